@@ -84,41 +84,35 @@ impl Drop for BlockCache {
     }
 }
 
-// BlockCacheManager 结构体表示多个块缓存的管理器。它维护一个具有指定限制的块缓存队列，并跟踪起始扇区。
+// 同一个块号在不同的设备(或分区)上是不同的块, 缓存按(设备, 块号)查找
+fn device_key(block_device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(block_device) as *const () as usize
+}
+
+// BlockCacheManager 结构体表示多个块缓存的管理器。它维护一个具有指定限制的块缓存队列。
 // 双缓存：数据块和索引块，Clock算法进行淘汰
 pub struct BlockCacheManager {
-    start_sec: usize,
     limit: usize,
-    queue: VecDeque<(usize, Arc<RwLock<BlockCache>>)>,
+    queue: VecDeque<((usize, usize), Arc<RwLock<BlockCache>>)>,
 }
 
 impl BlockCacheManager {
     pub fn new(limit: usize) -> Self {
         Self {
-            start_sec: 0,
             limit,
             queue: VecDeque::new(),
         }
     }
 
-    // 返回起始扇区
-    pub fn get_start_sec(&self)->usize {
-        self.start_sec
-    }
-
-    // 设置起始扇区的值。
-    pub fn set_start_sec(&mut self, new_start_sec: usize) {
-        self.start_sec = new_start_sec;
-    }
-
     pub fn read_block_cache(
         &self,
         block_id: usize,
-        //block_device: Arc<dyn BlockDevice>,
+        block_device: &Arc<dyn BlockDevice>,
     ) -> Option<Arc<RwLock<BlockCache>>>{
+        let key = (device_key(block_device), block_id);
         if let Some(pair) = self.queue
             .iter()
-            .find(|pair| pair.0 == block_id) {
+            .find(|pair| pair.0 == key) {
                 Some(Arc::clone(&pair.1))
         }else{
             None
@@ -132,9 +126,10 @@ impl BlockCacheManager {
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
     ) -> Arc<RwLock<BlockCache>> {
+        let key = (device_key(&block_device), block_id);
         if let Some(pair) = self.queue
             .iter()
-            .find(|pair| pair.0 == block_id) {
+            .find(|pair| pair.0 == key) {
                 Arc::clone(&pair.1)
         } else {
             // substitute
@@ -153,7 +148,7 @@ impl BlockCacheManager {
             let block_cache = Arc::new(RwLock::new(
                 BlockCache::new(block_id, Arc::clone(&block_device))
             ));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
+            self.queue.push_back((key, Arc::clone(&block_cache)));
             //println!("blkcache: {:?}", block_cache.read().cache);
             block_cache
         }
@@ -190,19 +185,18 @@ pub fn get_block_cache(
     block_device: Arc<dyn BlockDevice>,
     rw_mode: CacheMode,
 ) -> Arc<RwLock<BlockCache>> {
-    let phy_blk_id = DATA_BLOCK_CACHE_MANAGER.read().get_start_sec() + block_id;
     if rw_mode == CacheMode::READ {
         // make sure the blk is in cache
-        if let Some(blk) = INFO_CACHE_MANAGER.read().read_block_cache(phy_blk_id){
+        if let Some(blk) = INFO_CACHE_MANAGER.read().read_block_cache(block_id, &block_device){
             return blk
         }
-        DATA_BLOCK_CACHE_MANAGER.write().get_block_cache(phy_blk_id, block_device);
-        DATA_BLOCK_CACHE_MANAGER.read().read_block_cache(phy_blk_id).unwrap()
+        DATA_BLOCK_CACHE_MANAGER.write().get_block_cache(block_id, Arc::clone(&block_device));
+        DATA_BLOCK_CACHE_MANAGER.read().read_block_cache(block_id, &block_device).unwrap()
     } else {
-        if let Some(blk) = INFO_CACHE_MANAGER.read().read_block_cache(phy_blk_id){
+        if let Some(blk) = INFO_CACHE_MANAGER.read().read_block_cache(block_id, &block_device){
             return blk
         }
-        DATA_BLOCK_CACHE_MANAGER.write().get_block_cache(phy_blk_id, block_device)
+        DATA_BLOCK_CACHE_MANAGER.write().get_block_cache(block_id, block_device)
     }
 }
 
@@ -212,27 +206,21 @@ pub fn get_info_cache(
     block_device: Arc<dyn BlockDevice>,
     rw_mode: CacheMode,
 ) -> Arc<RwLock<BlockCache>> {
-    let phy_blk_id = INFO_CACHE_MANAGER.read().get_start_sec() + block_id;
     if rw_mode == CacheMode::READ {
         // make sure the blk is in cache
-        if let Some(blk) = DATA_BLOCK_CACHE_MANAGER.read().read_block_cache(phy_blk_id){
+        if let Some(blk) = DATA_BLOCK_CACHE_MANAGER.read().read_block_cache(block_id, &block_device){
             return blk
         }
-        INFO_CACHE_MANAGER.write().get_block_cache(phy_blk_id, block_device);
-        INFO_CACHE_MANAGER.read().read_block_cache(phy_blk_id).unwrap()
+        INFO_CACHE_MANAGER.write().get_block_cache(block_id, Arc::clone(&block_device));
+        INFO_CACHE_MANAGER.read().read_block_cache(block_id, &block_device).unwrap()
     } else {
-        if let Some(blk) = DATA_BLOCK_CACHE_MANAGER.read().read_block_cache(phy_blk_id){
+        if let Some(blk) = DATA_BLOCK_CACHE_MANAGER.read().read_block_cache(block_id, &block_device){
             return blk
         }
-        INFO_CACHE_MANAGER.write().get_block_cache(phy_blk_id, block_device)
+        INFO_CACHE_MANAGER.write().get_block_cache(block_id, block_device)
     }
 }
 
-pub fn set_start_sec(start_sec: usize){
-    INFO_CACHE_MANAGER.write().set_start_sec(start_sec);
-    DATA_BLOCK_CACHE_MANAGER.write().set_start_sec(start_sec);
-}
-
 pub fn write_to_dev(){  
     INFO_CACHE_MANAGER.write().drop_all();
     DATA_BLOCK_CACHE_MANAGER.write().drop_all();
@@ -4,12 +4,12 @@ use super::{
     get_info_cache,
     get_block_cache,
     write_to_dev,
-    set_start_sec,
     CacheMode,
     FSInfo, 
     FatBS, 
     FatExtBS,
     FAT,
+    BLOCK_SZ,
 };
 //#[macro_use]
 use crate::{ layout::*, VFile};
@@ -68,25 +68,12 @@ impl FAT32Manager {
 
     /* 打开现有的FAT32  */
     pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<RwLock<Self>>{
-        // 读入分区偏移
-        let start_sector:u32 = get_info_cache(
-            0, 
-            Arc::clone(&block_device),
-            CacheMode::READ )
-        .read()
-        .read(0x1c6, |ssec_bytes:&[u8;4]|{
-            // DEBUG
-            let mut start_sec:u32 = 0;
-            for i in 0..4 {
-                let tmp = ssec_bytes[i] as u32;
-                start_sec = start_sec + (tmp << (8*i));
-                //println!("start sec = {}, buf = {}", start_sec , ssec_bytes[i])
-            }
-            start_sec
-        });
-        
-        set_start_sec(start_sector as usize);
-        
+        Self::try_open(block_device).expect("Error loading fat32! Illegal signature")
+    }
+
+    /* 打开现有的FAT32, 设备上不是FAT32时返回None */
+    pub fn try_open(block_device: Arc<dyn BlockDevice>) -> Option<Arc<RwLock<Self>>>{
+        // 分区表由内核解析, 传进来的设备的第0块就是Boot Sector
         // 读入 Boot Sector
         let boot_sec:FatBS = get_info_cache(
             0, 
@@ -108,10 +95,19 @@ impl FAT32Manager {
         .read(36, |ebs:&FatExtBS|{
             *ebs // DEBUG
         });
+        // FSInfo在保留扇区里, 先排除明显不是FAT32的设备, 免得读到设备外面
+        if boot_sec.bytes_per_sector as usize != BLOCK_SZ
+            || boot_sec.sectors_per_cluster == 0
+            || ext_boot_sec.fat_info_sec() >= boot_sec.reserved_sector_count as u32
+        {
+            return None;
+        }
         // 读入 FSInfo
         let fsinfo = FSInfo::new(ext_boot_sec.fat_info_sec());
         // 校验签名
-        assert!(fsinfo.check_signature(Arc::clone(&block_device)),"Error loading fat32! Illegal signature");
+        if !fsinfo.check_signature(Arc::clone(&block_device)) {
+            return None;
+        }
         //println!("[fs]: first free cluster = {}", fsinfo.first_free_cluster(block_device.clone()) );
         
         let sectors_per_cluster = boot_sec.sectors_per_cluster as u32;
@@ -148,7 +144,7 @@ impl FAT32Manager {
             vroot_dirent: Arc::new(RwLock::new(root_dirent)),
         };
         
-        Arc::new(RwLock::new(fat32_manager))
+        Some(Arc::new(RwLock::new(fat32_manager)))
     }
 
     pub fn get_root_vfile(&self, fs_manager: &Arc<RwLock<Self>>)-> VFile {
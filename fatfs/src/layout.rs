@@ -7,6 +7,7 @@ use super::{
     fat32_manager::FAT32Manager,
     CacheMode,
     clone_into_array,
+    time::{fat_to_unix, now, unix_to_fat},
 };
 use alloc::sync::Arc;
 use alloc::string::String;
@@ -209,8 +210,8 @@ pub struct ShortDirEntry {
 }
 
 impl ShortDirEntry {
-    /* K210上无法获取现实的时间，因此没有修改时间字段的必要 */
-    
+    /* 时间戳来自内核注册的时间源, 见time.rs */
+
     /* 建一个空的，一般读取时用到 */
     // QUES 真的用得到？
     pub fn empty() -> Self {
@@ -241,21 +242,23 @@ impl ShortDirEntry {
     ) -> Self {
         let name: [u8; 8] = clone_into_array(&name_[0..8]);
         let extension: [u8; 3] = clone_into_array(&extension_[0..3]);
-        Self {
+        let mut ent = Self {
             name,
             extension,
             attribute,
             winnt_reserved: 0,
             creation_tenths: 0,
             creation_time: 0,
-            creation_date: 0x529c,
+            creation_date: 0,
             last_acc_date: 0,
             cluster_high: 0,
             modification_time: 0,
             modification_date: 0,
             cluster_low: 0,
             size: 0,
-        }
+        };
+        ent.set_creation_time(now());
+        ent
     }
     
     pub fn initialize(
@@ -273,7 +276,7 @@ impl ShortDirEntry {
             winnt_reserved: 0,
             creation_tenths: 0,
             creation_time: 0,
-            creation_date: 0x529c,
+            creation_date: 0,
             last_acc_date: 0,
             cluster_high: 0,
             modification_time: 0,
@@ -281,6 +284,7 @@ impl ShortDirEntry {
             cluster_low: 0,
             size: 0,
         };
+        self.set_creation_time(now());
     }
     
     /* 返回目前使用的簇的数量 */
@@ -317,6 +321,23 @@ impl ShortDirEntry {
         self.attribute
     }
     
+    /* 新建时三个时间都设为创建时间 */
+    pub fn set_creation_time(&mut self, secs: u64) {
+        let (date, time, tenths) = unix_to_fat(secs);
+        self.creation_date = date;
+        self.creation_time = time;
+        self.creation_tenths = tenths;
+        self.set_modification_time(secs);
+    }
+
+    /* 写文件时调用，访问日期一并更新 */
+    pub fn set_modification_time(&mut self, secs: u64) {
+        let (date, time, _) = unix_to_fat(secs);
+        self.modification_date = date;
+        self.modification_time = time;
+        self.last_acc_date = date;
+    }
+
     pub fn get_creation_time(&self) -> (u32, u32, u32, u32, u32, u32, u64) {
         // year-month-day-Hour-min-sec-long_sec
         let year: u32 = ((self.creation_date & 0xFE00) >> 9) as u32 + 1980;
@@ -325,7 +346,8 @@ impl ShortDirEntry {
         let hour: u32 = ((self.creation_time & 0xF800) >> 11) as u32;
         let min: u32 = ((self.creation_time & 0x07E0) >> 5) as u32;
         let sec: u32 = ((self.creation_time & 0x001F) << 1) as u32; // 秒数需要*2
-        let long_sec: u64 = ((((year - 1970) * 365 + month * 30 + day) * 24 + hour) * 3600 + min * 60 + sec) as u64;
+        let long_sec: u64 = fat_to_unix(self.creation_date, self.creation_time)
+            + (self.creation_tenths / 100) as u64;
         (year, month, day, hour, min, sec, long_sec)
     }
     
@@ -337,7 +359,7 @@ impl ShortDirEntry {
         let hour: u32 = ((self.modification_time & 0xF800) >> 11) as u32;
         let min: u32 = ((self.modification_time & 0x07E0) >> 5) as u32;
         let sec: u32 = ((self.modification_time & 0x001F) << 1) as u32; // 秒数需要*2
-        let long_sec: u64 = ((((year - 1970) * 365 + month * 30 + day) * 24 + hour) * 3600 + min * 60 + sec) as u64;
+        let long_sec: u64 = fat_to_unix(self.modification_date, self.modification_time);
         (year, month, day, hour, min, sec, long_sec)
     }
     
@@ -349,7 +371,7 @@ impl ShortDirEntry {
         let hour: u32 = 0;
         let min: u32 = 0;
         let sec: u32 = 0; // 没有相关信息，默认0
-        let long_sec: u64 = ((((year - 1970) * 365 + month * 30 + day) * 24 + hour) * 3600 + min * 60 + sec) as u64;
+        let long_sec: u64 = fat_to_unix(self.last_acc_date, 0);
         (year, month, day, hour, min, sec, long_sec)
     }
     
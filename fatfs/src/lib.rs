@@ -7,6 +7,7 @@ mod layout;
 mod fat32_manager;
 mod vfs;
 mod block_cache;
+mod time;
 pub const BLOCK_SZ:usize = 512;
 pub use block_dev::BlockDevice;
 pub use vfs::VFile;
@@ -14,7 +15,8 @@ pub use layout::ShortDirEntry;
 //pub use layout::NAME_LENGTH_LIMIT;
 pub use fat32_manager::FAT32Manager;
 pub use layout::*;
-use block_cache::{get_block_cache,get_info_cache,write_to_dev,set_start_sec, CacheMode};
+pub use time::set_time_source;
+use block_cache::{get_block_cache,get_info_cache,write_to_dev, CacheMode};
 /*
 pub trait w_field {
     fn set(&self, value:Self);
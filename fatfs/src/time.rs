@@ -0,0 +1,75 @@
+/* 目录项时间戳与unix时间之间的转换
+ * 文件系统自己拿不到时间，由内核通过set_time_source注册当前的unix时间(秒)
+ */
+use spin::RwLock;
+
+static TIME_SOURCE: RwLock<Option<fn() -> u64>> = RwLock::new(None);
+
+/* FAT能表示的最早时间 1980-01-01 00:00:00 */
+const FAT_EPOCH: u64 = 315532800;
+/* 年份字段只有7位，能表示的最晚时间 2107-12-31 23:59:59 */
+const FAT_MAX: u64 = 4354819199;
+const SECS_PER_DAY: u64 = 86400;
+
+pub fn set_time_source(source: fn() -> u64) {
+    *TIME_SOURCE.write() = Some(source);
+}
+
+/* 当前unix时间，没有注册时间源时返回FAT_EPOCH */
+pub fn now() -> u64 {
+    match *TIME_SOURCE.read() {
+        Some(source) => source().max(FAT_EPOCH),
+        None => FAT_EPOCH,
+    }
+}
+
+/* 1970-01-01以来的天数 -> (年, 月, 日) */
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/* (年, 月, 日) -> 1970-01-01以来的天数 */
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (if month > 2 { month - 3 } else { month + 9 }) as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/* unix时间 -> (FAT日期, FAT时间, 10ms为单位的余数)，超出1980..=2107的时间取边界 */
+pub fn unix_to_fat(secs: u64) -> (u16, u16, u8) {
+    let secs = secs.clamp(FAT_EPOCH, FAT_MAX);
+    let (year, month, day) = civil_from_days((secs / SECS_PER_DAY) as i64);
+    let rem = secs % SECS_PER_DAY;
+    let (hour, min, sec) = (rem / 3600, rem % 3600 / 60, rem % 60);
+    let date = (((year - 1980) as u16) << 9) | ((month as u16) << 5) | day as u16;
+    let time = ((hour as u16) << 11) | ((min as u16) << 5) | (sec as u16 >> 1);
+    // 时间字段只精确到2秒，奇数秒记在creation_tenths里
+    (date, time, ((sec & 1) * 100) as u8)
+}
+
+/* FAT日期和时间 -> unix时间，日期为0(从未设置)时返回0 */
+pub fn fat_to_unix(date: u16, time: u16) -> u64 {
+    if date == 0 {
+        return 0;
+    }
+    let year = ((date & 0xFE00) >> 9) as i64 + 1980;
+    let month = (((date & 0x01E0) >> 5) as u32).max(1);
+    let day = ((date & 0x001F) as u32).max(1);
+    let hour = ((time & 0xF800) >> 11) as u64;
+    let min = ((time & 0x07E0) >> 5) as u64;
+    let sec = ((time & 0x001F) << 1) as u64;
+    days_from_civil(year, month, day) as u64 * SECS_PER_DAY + hour * 3600 + min * 60 + sec
+}
@@ -4,6 +4,7 @@ use super::{
     layout::*,
     get_info_cache,
     CacheMode,
+    time::now,
 };
 use alloc::sync::Arc;
 use alloc::string::String;
@@ -78,6 +79,11 @@ impl VFile{
         self.fs.clone()
     }
 
+    // 短目录项的位置<sector, offset>, 文件存在期间不变
+    pub fn get_dirent_pos(&self) -> (usize, usize){
+        (self.short_sector, self.short_offset)
+    }
+
     pub fn is_dir(&self)->bool{
         if 0 != (self.attribute & ATTRIBUTE_DIRECTORY) {
             true
@@ -722,6 +728,7 @@ impl VFile{
     pub fn write_at(& self, offset: usize, buf: & [u8])->usize{
         self.increase_size((offset + buf.len()) as u32  );
         self.modify_short_dirent(|short_ent: &mut ShortDirEntry|{
+            short_ent.set_modification_time(now());
             short_ent.write_at(
                 offset,
                 buf,
@@ -7,33 +7,95 @@ pub const MMIO: &[(usize, usize)] = &[
     (0x10000000, 0x9000),     // VIRT_UART0 with GPU  in virt machine
 ];
 
+/// 驱动从设备树里找地址的设备, 它们的reg也要映射进内核地址空间
+pub const FDT_MMIO_COMPATIBLE: &[&str] = &["virtio,mmio", "riscv,plic0", "sifive,plic-1.0.0"];
+
 pub type BlockDeviceImpl = crate::drivers::block::VirtIOBlock;
 pub type CharDeviceImpl = crate::drivers::chardev::NS16550a<VIRT_UART>;
+pub type RtcDeviceImpl = crate::drivers::rtc::GoldfishRtc<VIRT_RTC>;
 
 pub const VIRT_PLIC: usize = 0xC00_0000;
 pub const VIRT_UART: usize = 0x1000_0000;
+pub const VIRT_RTC: usize = 0x10_1000;
 #[allow(unused)]
 pub const VIRTGPU_XRES: u32 = 1280;
 #[allow(unused)]
 pub const VIRTGPU_YRES: u32 = 800;
 
-use crate::drivers::block::BLOCK_DEVICE;
 use crate::drivers::chardev::{CharDevice, UART};
 use crate::drivers::plic::{IntrTargetPriority, PLIC};
-use crate::drivers::{KEYBOARD_DEVICE, MOUSE_DEVICE};
+use crate::drivers::NET_DEVICES;
+use crate::fdt::fdt;
+use crate::fs::console_interrupt;
+use crate::net::net_interrupt_handler;
+use crate::sync::UPIntrFreeCell;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+
+/// 设备树里没有串口节点时用qemu virt的中断号
+const VIRT_UART_IRQ: usize = 10;
+
+/// 启动时SBI在a0里传进来的hart id
+static BOOT_HART: AtomicUsize = AtomicUsize::new(0);
+
+pub type IrqHandler = Arc<dyn Fn() + Send + Sync>;
+
+lazy_static! {
+    /// PLIC的地址和串口的中断号以设备树为准
+    static ref PLIC_BASE: usize = fdt()
+        .and_then(|fdt| {
+            fdt.nodes().find(|node| {
+                node.is_compatible("riscv,plic0") || node.is_compatible("sifive,plic-1.0.0")
+            })
+        })
+        .and_then(|node| node.reg().next())
+        .map_or(VIRT_PLIC, |(base, _)| base);
+    static ref UART_IRQ: usize = fdt()
+        .and_then(|fdt| fdt.nodes().find(|node| node.is_compatible("ns16550a")))
+        .and_then(|node| node.property_u32("interrupts"))
+        .map_or(VIRT_UART_IRQ, |irq| irq as usize);
+    /// 外部中断号 -> 驱动登记的处理函数
+    static ref IRQ_HANDLERS: UPIntrFreeCell<BTreeMap<usize, IrqHandler>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+}
+
+pub fn init_boot_hart(hart_id: usize) {
+    BOOT_HART.store(hart_id, Ordering::Relaxed);
+}
+
+fn boot_hart() -> usize {
+    BOOT_HART.load(Ordering::Relaxed)
+}
+
+/// 驱动探测到设备后登记中断处理函数, 同时在PLIC上打开这个中断
+pub fn register_irq(irq: usize, handler: IrqHandler) {
+    let mut plic = unsafe { PLIC::new(*PLIC_BASE) };
+    plic.enable(boot_hart(), IntrTargetPriority::Supervisor, irq);
+    plic.set_priority(irq, 1);
+    IRQ_HANDLERS.exclusive_access().insert(irq, handler);
+}
 
 pub fn device_init() {
     use riscv::register::sie;
-    let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
-    let hart_id: usize = 0;
+    let mut plic = unsafe { PLIC::new(*PLIC_BASE) };
+    let hart_id = boot_hart();
     let supervisor = IntrTargetPriority::Supervisor;
     let machine = IntrTargetPriority::Machine;
     plic.set_threshold(hart_id, supervisor, 0);
     plic.set_threshold(hart_id, machine, 1);
-    //irq nums: 5 keyboard, 6 mouse, 8 block, 10 uart
-    for intr_src_id in [5usize, 6, 8, 10] {
-        plic.enable(hart_id, supervisor, intr_src_id);
-        plic.set_priority(intr_src_id, 1);
+    register_irq(
+        *UART_IRQ,
+        Arc::new(|| {
+            UART.handle_irq();
+            console_interrupt();
+        }),
+    );
+    // virtio块设备和输入设备在驱动初始化时自己登记
+    for net in NET_DEVICES.iter() {
+        let irq = net.irq;
+        register_irq(irq, Arc::new(move || net_interrupt_handler(irq)));
     }
     unsafe {
         sie::set_sext();
@@ -41,14 +103,17 @@ pub fn device_init() {
 }
 
 pub fn irq_handler() {
-    let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
-    let intr_src_id = plic.claim(0, IntrTargetPriority::Supervisor);
-    match intr_src_id {
-        5 => KEYBOARD_DEVICE.handle_irq(),
-        6 => MOUSE_DEVICE.handle_irq(),
-        8 => BLOCK_DEVICE.handle_irq(),
-        10 => UART.handle_irq(),
-        _ => panic!("unsupported IRQ {}", intr_src_id),
+    let hart_id = boot_hart();
+    let mut plic = unsafe { PLIC::new(*PLIC_BASE) };
+    let intr_src_id = plic.claim(hart_id, IntrTargetPriority::Supervisor);
+    // 先拿出来再调用, 处理函数里可能还会登记别的中断
+    let handler = IRQ_HANDLERS
+        .exclusive_access()
+        .get(&(intr_src_id as usize))
+        .cloned();
+    match handler {
+        Some(handler) => handler(),
+        None => panic!("unsupported IRQ {}", intr_src_id),
     }
-    plic.complete(0, IntrTargetPriority::Supervisor, intr_src_id);
+    plic.complete(hart_id, IntrTargetPriority::Supervisor, intr_src_id);
 }
@@ -3,6 +3,7 @@
 pub const USER_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_HEAP_SIZE: usize = 0x100_0000;
+// 没有设备树时才用, 见mm::memory_end
 pub const MEMORY_END: usize = 0x88000000;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
@@ -12,5 +13,8 @@ pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
 
 pub const USER_STACK_BASE: usize = 0x1_0000_0000; // 4GB
 pub const MEMORY_MAP_BASE: usize = 0x8000_0000; // 2GB
+// PIE程序和动态链接器的加载基址
+pub const ELF_DYN_BASE: usize = 0x1000_0000;
+pub const ELF_INTERP_BASE: usize = 0x6000_0000;
 
-pub use crate::board::{CLOCK_FREQ, MMIO};
+pub use crate::board::{CLOCK_FREQ, FDT_MMIO_COMPATIBLE, MMIO};
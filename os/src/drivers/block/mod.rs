@@ -1,14 +1,68 @@
+mod partition;
 mod virtio_blk;
 
+pub use partition::Partition;
 pub use virtio_blk::VirtIOBlock;
 
-use crate::board::BlockDeviceImpl;
+use crate::board::{register_irq, BlockDeviceImpl};
+use crate::drivers::bus::virtio::{virtio_mmio_devices, VIRTIO_DEVICE_BLOCK};
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use fatfs::BlockDevice;
 use lazy_static::*;
+use partition::read_partitions;
+
+/// vda到vdz
+const MAX_DISKS: usize = 26;
+
+/// 磁盘按总线上的顺序叫vda, vdb..., 分区按分区表里的序号叫vda1, vda2...
+pub struct BlockDeviceInfo {
+    pub name: String,
+    pub device: Arc<dyn BlockDevice>,
+}
 
 lazy_static! {
-    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+    pub static ref BLOCK_DEVICES: Vec<BlockDeviceInfo> = probe_block_devices();
+    /// 根文件系统: 第一个磁盘的第一个分区, 没有分区表时是整个磁盘
+    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = find_block_device("vda1")
+        .or_else(|| find_block_device("vda"))
+        .expect("no virtio block device");
+}
+
+fn probe_block_devices() -> Vec<BlockDeviceInfo> {
+    let mut devices = Vec::new();
+    let disks = virtio_mmio_devices(VIRTIO_DEVICE_BLOCK).take(MAX_DISKS);
+    for (index, device) in disks.enumerate() {
+        let disk = BlockDeviceImpl::new(device.base);
+        let sectors = disk.sectors();
+        let disk: Arc<dyn BlockDevice> = Arc::new(disk);
+        let handler = disk.clone();
+        register_irq(device.irq, Arc::new(move || handler.handle_irq()));
+        let name = format!("vd{}", (b'a' + index as u8) as char);
+        let partitions = read_partitions(&disk, sectors);
+        println!("KERN: {}: {} partitions", name, partitions.len());
+        devices.push(BlockDeviceInfo {
+            name: name.clone(),
+            device: disk.clone(),
+        });
+        for (slot, start, sectors) in partitions {
+            devices.push(BlockDeviceInfo {
+                name: format!("{}{}", name, slot + 1),
+                device: Arc::new(Partition::new(disk.clone(), start, sectors)),
+            });
+        }
+    }
+    devices
+}
+
+/// 按名字找块设备, 比如"vdb2"
+pub fn find_block_device(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES
+        .iter()
+        .find(|info| info.name == name)
+        .map(|info| info.device.clone())
 }
 
 #[allow(unused)]
@@ -0,0 +1,163 @@
+//! MBR和GPT分区表, 每个分区包装成一个独立的BlockDevice
+use super::BlockDevice;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use fatfs::BLOCK_SZ;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_PARTITION_TABLE: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_TYPE_EMPTY: u8 = 0x00;
+const MBR_TYPE_GPT: u8 = 0xee;
+/// 扩展分区里的逻辑分区不支持
+const MBR_TYPE_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// 头部的最小长度, 校验和只算头部自己记的长度
+const GPT_HEADER_MIN_SIZE: usize = 92;
+/// 分区项太多时只看前面这些
+const GPT_MAX_ENTRIES: usize = 128;
+
+/// 磁盘上的一段连续扇区
+pub struct Partition {
+    disk: Arc<dyn BlockDevice>,
+    start: usize,
+    sectors: usize,
+}
+
+impl Partition {
+    pub fn new(disk: Arc<dyn BlockDevice>, start: usize, sectors: usize) -> Self {
+        Self {
+            disk,
+            start,
+            sectors,
+        }
+    }
+}
+
+impl BlockDevice for Partition {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        assert!(block_id < self.sectors, "read beyond the end of partition");
+        self.disk.read_block(self.start + block_id, buf);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        assert!(block_id < self.sectors, "write beyond the end of partition");
+        self.disk.write_block(self.start + block_id, buf);
+    }
+
+    // 中断由整个磁盘处理
+    fn handle_irq(&self) {}
+}
+
+fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le64(bytes: &[u8]) -> u64 {
+    ((le32(&bytes[4..]) as u64) << 32) | le32(bytes) as u64
+}
+
+/// 分区表用的CRC32(IEEE)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+/// 没有分区表, 整个磁盘就是一个FAT文件系统
+fn is_fat_boot_sector(sector: &[u8]) -> bool {
+    (sector[0] == 0xeb || sector[0] == 0xe9)
+        && (&sector[82..87] == b"FAT32" || &sector[54..57] == b"FAT")
+        && le16(&sector[11..]) as usize == BLOCK_SZ
+}
+
+/// 读出`disk`的分区表, 返回每个分区的(表项序号, 起始扇区, 扇区数),
+/// 分区按序号命名. `disk_sectors`是磁盘的大小, 超出磁盘的分区不要.
+/// 没有分区表时返回空
+pub fn read_partitions(
+    disk: &Arc<dyn BlockDevice>,
+    disk_sectors: usize,
+) -> Vec<(usize, usize, usize)> {
+    let mut mbr = [0u8; BLOCK_SZ];
+    disk.read_block(0, &mut mbr);
+    if mbr[510..512] != MBR_SIGNATURE || is_fat_boot_sector(&mbr) {
+        return Vec::new();
+    }
+    let mut partitions = Vec::new();
+    for (i, entry) in mbr[MBR_PARTITION_TABLE..510]
+        .chunks(MBR_ENTRY_SIZE)
+        .enumerate()
+    {
+        let start = le32(&entry[8..]) as usize;
+        let sectors = le32(&entry[12..]) as usize;
+        match entry[4] {
+            MBR_TYPE_EMPTY => {}
+            // 保护MBR, 真正的分区表在GPT里
+            MBR_TYPE_GPT => return read_gpt(disk, disk_sectors),
+            kind if MBR_TYPE_EXTENDED.contains(&kind) => {}
+            _ if sectors > 0 && start + sectors <= disk_sectors => {
+                partitions.push((i, start, sectors))
+            }
+            _ => {}
+        }
+    }
+    partitions
+}
+
+fn read_gpt(disk: &Arc<dyn BlockDevice>, disk_sectors: usize) -> Vec<(usize, usize, usize)> {
+    let mut header = [0u8; BLOCK_SZ];
+    disk.read_block(1, &mut header);
+    if &header[0..8] != GPT_SIGNATURE {
+        return Vec::new();
+    }
+    // 校验和按CRC字段清零以后算
+    let header_size = le32(&header[12..]) as usize;
+    if !(GPT_HEADER_MIN_SIZE..=BLOCK_SZ).contains(&header_size) {
+        return Vec::new();
+    }
+    let header_crc = le32(&header[16..]);
+    header[16..20].fill(0);
+    if crc32(&header[..header_size]) != header_crc {
+        return Vec::new();
+    }
+    let entries_lba = le64(&header[72..]) as usize;
+    let entries = (le32(&header[80..]) as usize).min(GPT_MAX_ENTRIES);
+    let entry_size = le32(&header[84..]) as usize;
+    if entry_size < 128 || BLOCK_SZ % entry_size != 0 {
+        return Vec::new();
+    }
+    let per_block = BLOCK_SZ / entry_size;
+    // 分区项不能读到磁盘外面去
+    let entry_blocks = (entries + per_block - 1) / per_block;
+    if entries_lba < 2 || entries_lba.saturating_add(entry_blocks) > disk_sectors {
+        return Vec::new();
+    }
+    let mut partitions = Vec::new();
+    let mut block = [0u8; BLOCK_SZ];
+    for i in 0..entries {
+        if i % per_block == 0 {
+            disk.read_block(entries_lba + i / per_block, &mut block);
+        }
+        let entry = &block[i % per_block * entry_size..][..entry_size];
+        // 类型GUID全0的项没有使用
+        if entry[..16].iter().all(|&b| b == 0) {
+            continue;
+        }
+        let first = le64(&entry[32..]) as usize;
+        let last = le64(&entry[40..]) as usize;
+        if first <= last && last < disk_sectors {
+            partitions.push((i, first, last - first + 1));
+        }
+    }
+    partitions
+}
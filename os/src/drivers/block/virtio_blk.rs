@@ -10,16 +10,18 @@ use crate::DEV_NON_BLOCKING_ACCESS;
 use alloc::collections::BTreeMap;
 use virtio_drivers::{BlkResp, RespStatus, VirtIOBlk, VirtIOHeader};
 
-#[allow(unused)]
-const VIRTIO0: usize = 0x10008000;
-
 pub struct VirtIOBlock {
     // 表示VirtIO块设备对象。它是一个带有中断和自由访问权限的封装结构体。
     virtio_blk: UPIntrFreeCell<VirtIOBlk<'static, VirtioHal>>,
     // 表示条件变量的集合, 用于在非阻塞访问模式下等待I/O操作完成。
     condvars: BTreeMap<u16, Condvar>,
+    // 磁盘大小, 以512字节的扇区计
+    sectors: usize,
 }
 
+/// virtio-mmio的设备配置空间, 块设备的前8个字节是容量
+const VIRTIO_MMIO_CONFIG: usize = 0x100;
+
 impl BlockDevice for VirtIOBlock {
     /// 根据DEV_NON_BLOCKING_ACCESS的值，它可以选择阻塞或非阻塞模式进行访问。
     /// 如果处于非阻塞模式，它会发起一个异步读取请求，并通过条件变量等待请求完成后继续执行。
@@ -81,10 +83,11 @@ impl BlockDevice for VirtIOBlock {
 }
 
 impl VirtIOBlock {
-    pub fn new() -> Self {
+    /// `base`是探测到的virtio-mmio槽的地址
+    pub fn new(base: usize) -> Self {
         let virtio_blk = unsafe {
             UPIntrFreeCell::new(
-                VirtIOBlk::<VirtioHal>::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap(),
+                VirtIOBlk::<VirtioHal>::new(&mut *(base as *mut VirtIOHeader)).unwrap(),
             )
         };
         let mut condvars = BTreeMap::new();
@@ -93,9 +96,16 @@ impl VirtIOBlock {
             let condvar = Condvar::new();
             condvars.insert(i, condvar);
         }
+        let sectors =
+            unsafe { core::ptr::read_volatile((base + VIRTIO_MMIO_CONFIG) as *const u64) } as usize;
         Self {
             virtio_blk,
             condvars,
+            sectors,
         }
     }
+
+    pub fn sectors(&self) -> usize {
+        self.sectors
+    }
 }
@@ -1,26 +1,96 @@
+use crate::fdt::fdt;
 use crate::mm::{
-    frame_alloc_more, frame_dealloc, kernel_token, FrameTracker, PageTable, PhysAddr, PhysPageNum,
-    StepByOne, VirtAddr,
+    frame_alloc_dma, frame_dealloc, kernel_token, PageTable, PhysAddr, PhysPageNum, StepByOne,
+    VirtAddr,
 };
-use crate::sync::UPIntrFreeCell;
 use alloc::vec::Vec;
+use core::ptr::read_volatile;
 use lazy_static::*;
 use virtio_drivers::Hal;
 
+pub const VIRTIO_DEVICE_NET: u32 = 1;
+pub const VIRTIO_DEVICE_BLOCK: u32 = 2;
+pub const VIRTIO_DEVICE_GPU: u32 = 16;
+pub const VIRTIO_DEVICE_INPUT: u32 = 18;
+
+const VIRTIO_MAGIC: u32 = 0x7472_6976;
+const MMIO_MAGIC_VALUE: usize = 0x000;
+const MMIO_DEVICE_ID: usize = 0x008;
+
+/// 没有设备树时按qemu virt的布局扫描, 第i个槽的中断号是i + 1
+const VIRTIO_MMIO_BASE: usize = 0x10001000;
+const VIRTIO_MMIO_SLOTS: usize = 8;
+const VIRTIO_MMIO_STRIDE: usize = 0x1000;
+
+/// 总线上接了设备的一个virtio-mmio槽
+pub struct VirtioMmioDevice {
+    pub base: usize,
+    pub irq: usize,
+    pub device_id: u32,
+}
+
 lazy_static! {
-    static ref QUEUE_FRAMES: UPIntrFreeCell<Vec<FrameTracker>> =
-        unsafe { UPIntrFreeCell::new(Vec::new()) };
+    /// 按地址从高到低排列: qemu把第一个-device接在地址最高的槽上,
+    /// 这样就和命令行里-device的顺序一致
+    pub static ref VIRTIO_MMIO_DEVICES: Vec<VirtioMmioDevice> = probe_virtio_mmio();
+}
+
+fn mmio_read(base: usize, offset: usize) -> u32 {
+    unsafe { read_volatile((base + offset) as *const u32) }
+}
+
+/// 设备树里所有virtio,mmio节点, 读出槽上的设备类型
+fn probe_virtio_mmio() -> Vec<VirtioMmioDevice> {
+    let mut slots: Vec<(usize, usize)> = match fdt() {
+        Some(fdt) => fdt
+            .nodes()
+            .filter(|node| node.is_compatible("virtio,mmio"))
+            .filter_map(|node| {
+                let (base, _) = node.reg().next()?;
+                let irq = node.property_u32("interrupts")?;
+                Some((base, irq as usize))
+            })
+            .collect(),
+        None => (0..VIRTIO_MMIO_SLOTS)
+            .map(|slot| (VIRTIO_MMIO_BASE + slot * VIRTIO_MMIO_STRIDE, slot + 1))
+            .collect(),
+    };
+    slots.sort_unstable_by(|a, b| b.cmp(a));
+    let mut devices = Vec::new();
+    for (base, irq) in slots {
+        if mmio_read(base, MMIO_MAGIC_VALUE) != VIRTIO_MAGIC {
+            continue;
+        }
+        // 没有接设备的槽id是0
+        let device_id = mmio_read(base, MMIO_DEVICE_ID);
+        if device_id != 0 {
+            println!(
+                "KERN: virtio-mmio@{:#x}: device id {}, irq {}",
+                base, device_id, irq
+            );
+            devices.push(VirtioMmioDevice {
+                base,
+                irq,
+                device_id,
+            });
+        }
+    }
+    devices
+}
+
+/// 类型是`device_id`的virtio设备
+pub fn virtio_mmio_devices(device_id: u32) -> impl Iterator<Item = &'static VirtioMmioDevice> {
+    VIRTIO_MMIO_DEVICES
+        .iter()
+        .filter(move |device| device.device_id == device_id)
 }
 
 pub struct VirtioHal;
 
 impl Hal for VirtioHal {
+    /// 设备要的是4GiB以下物理连续的页, 由dma_dealloc逐页释放
     fn dma_alloc(pages: usize) -> usize {
-        let trakcers = frame_alloc_more(pages);
-        let ppn_base = trakcers.as_ref().unwrap().last().unwrap().ppn;
-        QUEUE_FRAMES
-            .exclusive_access()
-            .append(&mut trakcers.unwrap());
+        let ppn_base = frame_alloc_dma(pages).unwrap();
         let pa: PhysAddr = ppn_base.into();
         pa.0
     }
@@ -1,11 +1,10 @@
-use crate::drivers::bus::virtio::VirtioHal;
+use crate::drivers::bus::virtio::{virtio_mmio_devices, VirtioHal, VIRTIO_DEVICE_GPU};
 use crate::sync::UPIntrFreeCell;
 use alloc::{sync::Arc, vec::Vec};
 use core::any::Any;
 use embedded_graphics::pixelcolor::Rgb888;
 use tinybmp::Bmp;
 use virtio_drivers::{VirtIOGpu, VirtIOHeader};
-const VIRTIO7: usize = 0x10007000;
 pub trait GpuDevice: Send + Sync + Any {
     fn update_cursor(&self);
     fn get_framebuffer(&self) -> &mut [u8];
@@ -13,7 +12,10 @@ pub trait GpuDevice: Send + Sync + Any {
 }
 
 lazy_static::lazy_static!(
-    pub static ref GPU_DEVICE: Arc<dyn GpuDevice> = Arc::new(VirtIOGpuWrapper::new());
+    /// qemu命令行里没有virtio-gpu-device时是None
+    pub static ref GPU_DEVICE: Option<Arc<dyn GpuDevice>> = virtio_mmio_devices(VIRTIO_DEVICE_GPU)
+        .next()
+        .map(|device| Arc::new(VirtIOGpuWrapper::new(device.base)) as Arc<dyn GpuDevice>);
 );
 
 pub struct VirtIOGpuWrapper {
@@ -22,10 +24,10 @@ pub struct VirtIOGpuWrapper {
 }
 static BMP_DATA: &[u8] = include_bytes!("../../assert/mouse.bmp");
 impl VirtIOGpuWrapper {
-    pub fn new() -> Self {
+    pub fn new(base: usize) -> Self {
         unsafe {
             let mut virtio =
-                VirtIOGpu::<VirtioHal>::new(&mut *(VIRTIO7 as *mut VirtIOHeader)).unwrap();
+                VirtIOGpu::<VirtioHal>::new(&mut *(base as *mut VirtIOHeader)).unwrap();
 
             let fbuffer = virtio.setup_framebuffer().unwrap();
             let len = fbuffer.len();
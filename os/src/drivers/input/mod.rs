@@ -1,4 +1,5 @@
-use crate::drivers::bus::virtio::VirtioHal;
+use crate::board::register_irq;
+use crate::drivers::bus::virtio::{virtio_mmio_devices, VirtioHal, VIRTIO_DEVICE_INPUT};
 use crate::sync::{Condvar, UPIntrFreeCell};
 use crate::task::schedule;
 use alloc::collections::VecDeque;
@@ -6,9 +7,6 @@ use alloc::sync::Arc;
 use core::any::Any;
 use virtio_drivers::{VirtIOHeader, VirtIOInput};
 
-const VIRTIO5: usize = 0x10005000;
-const VIRTIO6: usize = 0x10006000;
-
 struct VirtIOInputInner {
     virtio_input: VirtIOInput<'static, VirtioHal>,
     events: VecDeque<u64>,
@@ -26,10 +24,19 @@ pub trait InputDevice: Send + Sync + Any {
 }
 
 lazy_static::lazy_static!(
-    pub static ref KEYBOARD_DEVICE: Arc<dyn InputDevice> = Arc::new(VirtIOInputWrapper::new(VIRTIO5));
-    pub static ref MOUSE_DEVICE: Arc<dyn InputDevice> = Arc::new(VirtIOInputWrapper::new(VIRTIO6));
+    pub static ref KEYBOARD_DEVICE: Option<Arc<dyn InputDevice>> = probe_input(0);
+    pub static ref MOUSE_DEVICE: Option<Arc<dyn InputDevice>> = probe_input(1);
 );
 
+/// 第`index`个virtio输入设备, 按qemu命令行的顺序先是键盘后是鼠标
+fn probe_input(index: usize) -> Option<Arc<dyn InputDevice>> {
+    let device = virtio_mmio_devices(VIRTIO_DEVICE_INPUT).nth(index)?;
+    let input: Arc<dyn InputDevice> = Arc::new(VirtIOInputWrapper::new(device.base));
+    let handler = input.clone();
+    register_irq(device.irq, Arc::new(move || handler.handle_irq()));
+    Some(input)
+}
+
 impl VirtIOInputWrapper {
     pub fn new(addr: usize) -> Self {
         let inner = VirtIOInputInner {
@@ -1,46 +1,46 @@
+mod loopback;
+mod virtio_net;
+
+pub use loopback::LoopbackDevice;
+pub use virtio_net::VirtIONetWrapper;
+
 use core::any::Any;
 
-use crate::drivers::virtio::VirtioHal;
-use crate::sync::UPIntrFreeCell;
+use crate::drivers::bus::virtio::{virtio_mmio_devices, VIRTIO_DEVICE_NET};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
-use virtio_drivers::{VirtIOHeader, VirtIONet};
-
-const VIRTIO8: usize = 0x10004000;
 
-lazy_static! {
-    pub static ref NET_DEVICE: Arc<dyn NetDevice> = Arc::new(VirtIONetWrapper::new());
+pub struct NetDeviceInfo {
+    pub irq: usize,
+    pub mac: [u8; 6],
+    pub device: Arc<dyn NetDevice>,
 }
 
-pub trait NetDevice: Send + Sync + Any {
-    fn transmit(&self, data: &[u8]);
-    fn receive(&self, data: &mut [u8]) -> usize;
+lazy_static! {
+    /// every virtio-net device found on the bus, in slot order
+    pub static ref NET_DEVICES: Vec<NetDeviceInfo> = probe_net_devices();
+    pub static ref LOOPBACK_DEVICE: Arc<LoopbackDevice> = Arc::new(LoopbackDevice::new());
 }
 
-pub struct VirtIONetWrapper(UPIntrFreeCell<VirtIONet<'static, VirtioHal>>);
-
-impl NetDevice for VirtIONetWrapper {
-    fn transmit(&self, data: &[u8]) {
-        self.0
-            .exclusive_access()
-            .send(data)
-            .expect("can't send data")
-    }
-
-    fn receive(&self, data: &mut [u8]) -> usize {
-        self.0
-            .exclusive_access()
-            .recv(data)
-            .expect("can't receive data")
+fn probe_net_devices() -> Vec<NetDeviceInfo> {
+    let mut devices = Vec::new();
+    for device in virtio_mmio_devices(VIRTIO_DEVICE_NET) {
+        if VirtIONetWrapper::probe(device.base) {
+            let net = VirtIONetWrapper::new(device.base);
+            devices.push(NetDeviceInfo {
+                irq: device.irq,
+                mac: net.mac(),
+                device: Arc::new(net),
+            });
+        }
     }
+    devices
 }
 
-impl VirtIONetWrapper {
-    pub fn new() -> Self {
-        unsafe {
-            let virtio = VirtIONet::<VirtioHal>::new(&mut *(VIRTIO8 as *mut VirtIOHeader))
-                .expect("can't create net device by virtio");
-            VirtIONetWrapper(UPIntrFreeCell::new(virtio))
-        }
-    }
+pub trait NetDevice: Send + Sync + Any {
+    fn transmit(&self, data: &[u8]);
+    /// take one received frame, returns 0 if there is none
+    fn receive(&self, data: &mut [u8]) -> usize;
+    fn handle_irq(&self);
 }
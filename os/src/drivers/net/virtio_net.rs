@@ -0,0 +1,351 @@
+//! virtio-net over the legacy mmio transport.
+//! The receive queue is kept full of buffers so the device can raise an
+//! interrupt for every frame, which virtio_drivers::VirtIONet can't do.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+use riscv::register::sstatus;
+use virtio_drivers::Hal;
+
+use super::NetDevice;
+use crate::drivers::bus::virtio::VirtioHal;
+use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::task::schedule;
+use crate::DEV_NON_BLOCKING_ACCESS;
+
+const MMIO_MAGIC_VALUE: usize = 0x000;
+const MMIO_VERSION: usize = 0x004;
+const MMIO_DEVICE_ID: usize = 0x008;
+const MMIO_HOST_FEATURES: usize = 0x010;
+const MMIO_HOST_FEATURES_SEL: usize = 0x014;
+const MMIO_GUEST_FEATURES: usize = 0x020;
+const MMIO_GUEST_FEATURES_SEL: usize = 0x024;
+const MMIO_GUEST_PAGE_SIZE: usize = 0x028;
+const MMIO_QUEUE_SEL: usize = 0x030;
+const MMIO_QUEUE_NUM_MAX: usize = 0x034;
+const MMIO_QUEUE_NUM: usize = 0x038;
+const MMIO_QUEUE_ALIGN: usize = 0x03c;
+const MMIO_QUEUE_PFN: usize = 0x040;
+const MMIO_QUEUE_NOTIFY: usize = 0x050;
+const MMIO_INTERRUPT_STATUS: usize = 0x060;
+const MMIO_INTERRUPT_ACK: usize = 0x064;
+const MMIO_STATUS: usize = 0x070;
+const MMIO_CONFIG: usize = 0x100;
+
+const VIRTIO_MAGIC: u32 = 0x7472_6976;
+const VIRTIO_DEVICE_NET: u32 = 1;
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+
+const VIRTIO_NET_F_MAC: u32 = 1 << 5;
+
+const PAGE_SIZE: usize = 4096;
+const QUEUE_SIZE: usize = 16;
+const QUEUE_RECEIVE: u32 = 0;
+const QUEUE_TRANSMIT: u32 = 1;
+/// legacy virtio_net_hdr without mergeable rx buffers
+const NET_HDR_LEN: usize = 10;
+const NET_BUFFER_SIZE: usize = 2048;
+/// frames waiting for the stack, newer ones are dropped beyond this
+const RX_BACKLOG: usize = 256;
+
+const VRING_DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// split virtqueue in the legacy layout, the used ring starts on a new page
+struct VirtQueue {
+    desc: usize,
+    avail: usize,
+    used: usize,
+    avail_idx: u16,
+    last_used_idx: u16,
+}
+
+impl VirtQueue {
+    fn pages() -> usize {
+        let used_offset = Self::used_offset();
+        (used_offset + 6 + 8 * QUEUE_SIZE + PAGE_SIZE - 1) / PAGE_SIZE
+    }
+
+    fn used_offset() -> usize {
+        let avail_end = 16 * QUEUE_SIZE + 6 + 2 * QUEUE_SIZE;
+        (avail_end + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE
+    }
+
+    fn new(base: usize) -> Self {
+        Self {
+            desc: base,
+            avail: base + 16 * QUEUE_SIZE,
+            used: base + Self::used_offset(),
+            avail_idx: 0,
+            last_used_idx: 0,
+        }
+    }
+
+    fn set_desc(&mut self, id: u16, addr: usize, len: usize, flags: u16) {
+        let desc = (self.desc + id as usize * 16) as *mut Descriptor;
+        unsafe {
+            write_volatile(
+                desc,
+                Descriptor {
+                    addr: addr as u64,
+                    len: len as u32,
+                    flags,
+                    next: 0,
+                },
+            );
+        }
+    }
+
+    fn push_avail(&mut self, id: u16) {
+        let slot = (self.avail + 4 + 2 * (self.avail_idx as usize % QUEUE_SIZE)) as *mut u16;
+        unsafe {
+            write_volatile(slot, id);
+        }
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe {
+            write_volatile((self.avail + 2) as *mut u16, self.avail_idx);
+        }
+        fence(Ordering::SeqCst);
+    }
+
+    /// (descriptor id, bytes written by the device)
+    fn pop_used(&mut self) -> Option<(u16, usize)> {
+        fence(Ordering::SeqCst);
+        let used_idx = unsafe { read_volatile((self.used + 2) as *const u16) };
+        if used_idx == self.last_used_idx {
+            return None;
+        }
+        let elem = self.used + 4 + 8 * (self.last_used_idx as usize % QUEUE_SIZE);
+        let (id, len) = unsafe {
+            (
+                read_volatile(elem as *const u32),
+                read_volatile((elem + 4) as *const u32),
+            )
+        };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        Some((id as u16, len as usize))
+    }
+}
+
+struct VirtIONetInner {
+    base: usize,
+    rx: VirtQueue,
+    tx: VirtQueue,
+    rx_buffers: usize,
+    tx_buffers: usize,
+    tx_free: Vec<u16>,
+    /// frames taken off the receive queue by the irq handler
+    received: VecDeque<Vec<u8>>,
+    mac: [u8; 6],
+}
+
+pub struct VirtIONetWrapper {
+    inner: UPIntrFreeCell<VirtIONetInner>,
+    /// senders waiting for a free transmit descriptor
+    tx_condvar: Condvar,
+}
+
+fn mmio_read(base: usize, offset: usize) -> u32 {
+    unsafe { read_volatile((base + offset) as *const u32) }
+}
+
+fn mmio_write(base: usize, offset: usize, value: u32) {
+    unsafe { write_volatile((base + offset) as *mut u32, value) }
+}
+
+impl VirtIONetInner {
+    fn notify(&self, queue: u32) {
+        mmio_write(self.base, MMIO_QUEUE_NOTIFY, queue);
+    }
+
+    fn setup_queue(base: usize, queue: u32) -> VirtQueue {
+        mmio_write(base, MMIO_QUEUE_SEL, queue);
+        assert!(mmio_read(base, MMIO_QUEUE_NUM_MAX) as usize >= QUEUE_SIZE);
+        mmio_write(base, MMIO_QUEUE_NUM, QUEUE_SIZE as u32);
+        mmio_write(base, MMIO_QUEUE_ALIGN, PAGE_SIZE as u32);
+        let pa = VirtioHal::dma_alloc(VirtQueue::pages());
+        mmio_write(base, MMIO_QUEUE_PFN, (pa / PAGE_SIZE) as u32);
+        VirtQueue::new(VirtioHal::phys_to_virt(pa))
+    }
+
+    /// move finished receive buffers into `received` and hand them back to the device
+    fn drain_rx(&mut self) {
+        let mut refilled = false;
+        while let Some((id, len)) = self.rx.pop_used() {
+            let buffer = self.rx_buffers + id as usize * NET_BUFFER_SIZE;
+            if len > NET_HDR_LEN {
+                let frame = unsafe {
+                    core::slice::from_raw_parts(
+                        (buffer + NET_HDR_LEN) as *const u8,
+                        len.min(NET_BUFFER_SIZE) - NET_HDR_LEN,
+                    )
+                };
+                if self.received.len() < RX_BACKLOG {
+                    self.received.push_back(frame.to_vec());
+                }
+            }
+            self.rx.push_avail(id);
+            refilled = true;
+        }
+        if refilled {
+            self.notify(QUEUE_RECEIVE);
+        }
+    }
+
+    /// returns whether any descriptor came back
+    fn reclaim_tx(&mut self) -> bool {
+        let mut reclaimed = false;
+        while let Some((id, _)) = self.tx.pop_used() {
+            self.tx_free.push(id);
+            reclaimed = true;
+        }
+        reclaimed
+    }
+
+    fn send(&mut self, id: u16, data: &[u8]) {
+        let len = data.len().min(NET_BUFFER_SIZE - NET_HDR_LEN);
+        let buffer = self.tx_buffers + id as usize * NET_BUFFER_SIZE;
+        unsafe {
+            let buf = core::slice::from_raw_parts_mut(buffer as *mut u8, NET_BUFFER_SIZE);
+            buf[..NET_HDR_LEN].fill(0);
+            buf[NET_HDR_LEN..NET_HDR_LEN + len].copy_from_slice(&data[..len]);
+        }
+        let pa = VirtioHal::virt_to_phys(buffer);
+        self.tx.set_desc(id, pa, NET_HDR_LEN + len, 0);
+        self.tx.push_avail(id);
+        self.notify(QUEUE_TRANSMIT);
+    }
+}
+
+impl VirtIONetWrapper {
+    /// is there a legacy virtio-net device behind `base`
+    pub fn probe(base: usize) -> bool {
+        mmio_read(base, MMIO_MAGIC_VALUE) == VIRTIO_MAGIC
+            && mmio_read(base, MMIO_VERSION) == 1
+            && mmio_read(base, MMIO_DEVICE_ID) == VIRTIO_DEVICE_NET
+    }
+
+    pub fn new(base: usize) -> Self {
+        assert_eq!(mmio_read(base, MMIO_MAGIC_VALUE), VIRTIO_MAGIC);
+        assert_eq!(mmio_read(base, MMIO_VERSION), 1, "only legacy virtio-mmio is supported");
+        assert_eq!(mmio_read(base, MMIO_DEVICE_ID), VIRTIO_DEVICE_NET);
+
+        mmio_write(base, MMIO_STATUS, 0);
+        mmio_write(base, MMIO_STATUS, STATUS_ACKNOWLEDGE);
+        mmio_write(base, MMIO_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        mmio_write(base, MMIO_HOST_FEATURES_SEL, 0);
+        let features = mmio_read(base, MMIO_HOST_FEATURES) & VIRTIO_NET_F_MAC;
+        mmio_write(base, MMIO_GUEST_FEATURES_SEL, 0);
+        mmio_write(base, MMIO_GUEST_FEATURES, features);
+        mmio_write(base, MMIO_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+
+        let rx = VirtIONetInner::setup_queue(base, QUEUE_RECEIVE);
+        let tx = VirtIONetInner::setup_queue(base, QUEUE_TRANSMIT);
+        let buffer_pages = QUEUE_SIZE * NET_BUFFER_SIZE / PAGE_SIZE;
+        let rx_buffers = VirtioHal::phys_to_virt(VirtioHal::dma_alloc(buffer_pages));
+        let tx_buffers = VirtioHal::phys_to_virt(VirtioHal::dma_alloc(buffer_pages));
+
+        let mut mac = [0u8; 6];
+        if features & VIRTIO_NET_F_MAC != 0 {
+            for (i, byte) in mac.iter_mut().enumerate() {
+                *byte = unsafe { read_volatile((base + MMIO_CONFIG + i) as *const u8) };
+            }
+        }
+
+        let mut inner = VirtIONetInner {
+            base,
+            rx,
+            tx,
+            rx_buffers,
+            tx_buffers,
+            tx_free: (0..QUEUE_SIZE as u16).collect(),
+            received: VecDeque::new(),
+            mac,
+        };
+        // every receive descriptor owns one buffer for its whole life
+        for id in 0..QUEUE_SIZE as u16 {
+            let buffer = VirtioHal::virt_to_phys(rx_buffers + id as usize * NET_BUFFER_SIZE);
+            inner
+                .rx
+                .set_desc(id, buffer, NET_BUFFER_SIZE, VRING_DESC_F_WRITE);
+            inner.rx.push_avail(id);
+        }
+        mmio_write(
+            base,
+            MMIO_STATUS,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+        );
+        inner.notify(QUEUE_RECEIVE);
+        VirtIONetWrapper {
+            inner: unsafe { UPIntrFreeCell::new(inner) },
+            tx_condvar: Condvar::new(),
+        }
+    }
+
+    pub fn mac(&self) -> [u8; 6] {
+        self.inner.exclusive_access().mac
+    }
+}
+
+impl NetDevice for VirtIONetWrapper {
+    /// waits for a free descriptor when the ring is full, irq handlers and
+    /// code holding other cells can't sleep and drop the frame instead
+    fn transmit(&self, data: &[u8]) {
+        let may_sleep = sstatus::read().sie() && *DEV_NON_BLOCKING_ACCESS.exclusive_access();
+        loop {
+            let task_cx_ptr = self.inner.exclusive_session(|inner| {
+                inner.reclaim_tx();
+                match inner.tx_free.pop() {
+                    Some(id) => {
+                        inner.send(id, data);
+                        None
+                    }
+                    None if may_sleep => Some(self.tx_condvar.wait_no_sched()),
+                    None => None,
+                }
+            });
+            match task_cx_ptr {
+                Some(task_cx_ptr) => schedule(task_cx_ptr),
+                None => return,
+            }
+        }
+    }
+
+    fn receive(&self, data: &mut [u8]) -> usize {
+        self.inner.exclusive_session(|inner| {
+            inner.drain_rx();
+            match inner.received.pop_front() {
+                Some(frame) => {
+                    let len = frame.len().min(data.len());
+                    data[..len].copy_from_slice(&frame[..len]);
+                    len
+                }
+                None => 0,
+            }
+        })
+    }
+
+    fn handle_irq(&self) {
+        self.inner.exclusive_session(|inner| {
+            let status = mmio_read(inner.base, MMIO_INTERRUPT_STATUS);
+            mmio_write(inner.base, MMIO_INTERRUPT_ACK, status);
+            inner.drain_rx();
+            if inner.reclaim_tx() {
+                self.tx_condvar.signal_all();
+            }
+        });
+    }
+}
@@ -0,0 +1,26 @@
+///! Ref: https://android.googlesource.com/platform/external/qemu/+/master/docs/GOLDFISH-VIRTUAL-HARDWARE.TXT
+use super::RtcDevice;
+use core::ptr::read_volatile;
+
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+
+/// qemu virt上的goldfish rtc, 只用到读时间
+pub struct GoldfishRtc<const BASE_ADDR: usize>;
+
+impl<const BASE_ADDR: usize> GoldfishRtc<BASE_ADDR> {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<const BASE_ADDR: usize> RtcDevice for GoldfishRtc<BASE_ADDR> {
+    fn read_time_ns(&self) -> u64 {
+        // 必须先读TIME_LOW, 设备在这时锁存TIME_HIGH
+        unsafe {
+            let low = read_volatile((BASE_ADDR + TIME_LOW) as *const u32) as u64;
+            let high = read_volatile((BASE_ADDR + TIME_HIGH) as *const u32) as u64;
+            (high << 32) | low
+        }
+    }
+}
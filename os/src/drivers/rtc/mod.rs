@@ -0,0 +1,15 @@
+mod goldfish;
+
+use crate::board::RtcDeviceImpl;
+use alloc::sync::Arc;
+pub use goldfish::GoldfishRtc;
+use lazy_static::*;
+
+pub trait RtcDevice {
+    /// 1970-01-01以来的纳秒数
+    fn read_time_ns(&self) -> u64;
+}
+
+lazy_static! {
+    pub static ref RTC: Arc<RtcDeviceImpl> = Arc::new(RtcDeviceImpl::new());
+}
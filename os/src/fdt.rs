@@ -0,0 +1,250 @@
+//! 扁平设备树(FDT)的只读解析, 启动时SBI把它的物理地址放在a1里传给内核.
+//! 不分配内存, 帧分配器初始化之前就要用它拿到物理内存的大小
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+/// 节点嵌套的最大深度
+const MAX_DEPTH: usize = 16;
+
+/// 设备树的物理地址, 0表示没有
+static DTB: AtomicUsize = AtomicUsize::new(0);
+
+/// 记下SBI传来的设备树地址, 在mm::init之前调用
+pub fn init(dtb: usize) {
+    if unsafe { Fdt::from_addr(dtb) }.is_some() {
+        DTB.store(dtb, Ordering::Relaxed);
+    } else {
+        println!("[kernel] no valid device tree at {:#x}", dtb);
+    }
+}
+
+/// 启动时传进来的设备树, 内核空间恒等映射了物理内存, 开启分页后也能直接读
+pub fn fdt() -> Option<Fdt> {
+    match DTB.load(Ordering::Relaxed) {
+        0 => None,
+        dtb => unsafe { Fdt::from_addr(dtb) },
+    }
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// 按`cells`个32位大端数读出一个数
+fn read_cells(bytes: &[u8], cells: usize) -> usize {
+    bytes[..cells * 4]
+        .chunks(4)
+        .fold(0, |value, chunk| (value << 32) | be32(chunk) as usize)
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+#[derive(Clone, Copy)]
+pub struct Fdt {
+    data: &'static [u8],
+    struct_off: usize,
+    strings_off: usize,
+}
+
+impl Fdt {
+    /// # Safety
+    /// `addr`必须可以直接访问, 头部校验失败时返回None
+    pub unsafe fn from_addr(addr: usize) -> Option<Self> {
+        if addr == 0 || addr % 4 != 0 {
+            return None;
+        }
+        let header = core::slice::from_raw_parts(addr as *const u8, 40);
+        if be32(&header[0..]) != FDT_MAGIC {
+            return None;
+        }
+        let total_size = be32(&header[4..]) as usize;
+        Some(Self {
+            data: core::slice::from_raw_parts(addr as *const u8, total_size),
+            struct_off: be32(&header[8..]) as usize,
+            strings_off: be32(&header[12..]) as usize,
+        })
+    }
+
+    /// 设备树本身占的物理内存, 分配物理页时要避开
+    pub fn range(&self) -> (usize, usize) {
+        let start = self.data.as_ptr() as usize;
+        (start, start + self.data.len())
+    }
+
+    fn token(&self, offset: usize) -> u32 {
+        be32(&self.data[offset..])
+    }
+
+    fn string(&self, offset: usize) -> &'static str {
+        let bytes = &self.data[offset..];
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        core::str::from_utf8(&bytes[..len]).unwrap_or("")
+    }
+
+    /// 按深度优先的顺序遍历所有节点
+    pub fn nodes(&self) -> FdtNodes {
+        FdtNodes {
+            fdt: *self,
+            offset: self.struct_off,
+            depth: 0,
+            cells: [(2, 1); MAX_DEPTH],
+        }
+    }
+
+    /// memory节点里包含`addr`的那一段物理内存
+    pub fn memory_region(&self, addr: usize) -> Option<(usize, usize)> {
+        self.nodes()
+            .filter(|node| {
+                node.property("device_type")
+                    .map_or(false, |device_type| device_type == b"memory\0")
+                    || node.base_name() == "memory"
+            })
+            .flat_map(|node| node.reg())
+            .map(|(start, size)| (start, start + size))
+            .find(|&(start, end)| start <= addr && addr < end)
+    }
+}
+
+pub struct FdtNode {
+    fdt: Fdt,
+    pub name: &'static str,
+    /// 第一个属性的偏移
+    props: usize,
+    /// 父节点的#address-cells和#size-cells, 解释reg用
+    address_cells: usize,
+    size_cells: usize,
+}
+
+impl FdtNode {
+    /// 去掉@后面单元地址的名字
+    pub fn base_name(&self) -> &'static str {
+        self.name.split('@').next().unwrap()
+    }
+
+    fn properties(&self) -> FdtProperties {
+        FdtProperties {
+            fdt: self.fdt,
+            offset: self.props,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<&'static [u8]> {
+        self.properties()
+            .find(|&(prop, _)| prop == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn property_u32(&self, name: &str) -> Option<u32> {
+        self.property(name)
+            .filter(|value| value.len() >= 4)
+            .map(be32)
+    }
+
+    /// compatible是一串以\0分隔的字符串
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.property("compatible").map_or(false, |value| {
+            value
+                .split(|&b| b == 0)
+                .any(|item| item == compatible.as_bytes())
+        })
+    }
+
+    /// reg里的(地址, 大小)
+    pub fn reg(&self) -> impl Iterator<Item = (usize, usize)> {
+        let (address_cells, size_cells) = (self.address_cells, self.size_cells);
+        let entry = (address_cells + size_cells) * 4;
+        let value = self.property("reg").unwrap_or(&[]);
+        value.chunks_exact(entry.max(4)).map(move |chunk| {
+            (
+                read_cells(chunk, address_cells),
+                read_cells(&chunk[address_cells * 4..], size_cells),
+            )
+        })
+    }
+}
+
+struct FdtProperties {
+    fdt: Fdt,
+    offset: usize,
+}
+
+impl Iterator for FdtProperties {
+    type Item = (&'static str, &'static [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.fdt.token(self.offset) {
+                FDT_NOP => self.offset += 4,
+                FDT_PROP => {
+                    let len = self.fdt.token(self.offset + 4) as usize;
+                    let name_off = self.fdt.token(self.offset + 8) as usize;
+                    let value = &self.fdt.data[self.offset + 12..self.offset + 12 + len];
+                    self.offset = align4(self.offset + 12 + len);
+                    return Some((self.fdt.string(self.fdt.strings_off + name_off), value));
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+pub struct FdtNodes {
+    fdt: Fdt,
+    offset: usize,
+    depth: usize,
+    /// 各层节点给子节点定的#address-cells和#size-cells
+    cells: [(usize, usize); MAX_DEPTH],
+}
+
+impl Iterator for FdtNodes {
+    type Item = FdtNode;
+
+    fn next(&mut self) -> Option<FdtNode> {
+        loop {
+            match self.fdt.token(self.offset) {
+                FDT_BEGIN_NODE => {
+                    let name = self.fdt.string(self.offset + 4);
+                    let props = align4(self.offset + 4 + name.len() + 1);
+                    let (address_cells, size_cells) = self.cells[self.depth.min(MAX_DEPTH - 1)];
+                    let node = FdtNode {
+                        fdt: self.fdt,
+                        name,
+                        props,
+                        address_cells,
+                        size_cells,
+                    };
+                    // 跳过属性, 下一次从子节点或者FDT_END_NODE开始
+                    let mut properties = node.properties();
+                    while properties.next().is_some() {}
+                    self.offset = properties.offset;
+                    self.depth += 1;
+                    if self.depth < MAX_DEPTH {
+                        self.cells[self.depth] = (
+                            node.property_u32("#address-cells").unwrap_or(2) as usize,
+                            node.property_u32("#size-cells").unwrap_or(1) as usize,
+                        );
+                    }
+                    return Some(node);
+                }
+                FDT_END_NODE => {
+                    self.depth = self.depth.saturating_sub(1);
+                    self.offset += 4;
+                }
+                FDT_NOP => self.offset += 4,
+                FDT_PROP => {
+                    let len = self.fdt.token(self.offset + 4) as usize;
+                    self.offset = align4(self.offset + 12 + len);
+                }
+                // FDT_END, 或者格式不对
+                _ => return None,
+            }
+        }
+    }
+}
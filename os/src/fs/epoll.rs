@@ -0,0 +1,155 @@
+use super::{File, FileDescriptor, PollEvents};
+use crate::mm::UserBuffer;
+use crate::sync::UPIntrFreeCell;
+use crate::task::current_process;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub const EPOLL_CTL_ADD: usize = 1;
+pub const EPOLL_CTL_DEL: usize = 2;
+pub const EPOLL_CTL_MOD: usize = 3;
+/// 只支持水平触发, EPOLLET(1 << 31)按水平触发处理
+pub const EPOLLONESHOT: u32 = 1 << 30;
+/// epoll嵌套监听的最大层数
+const EP_MAX_NESTS: usize = 4;
+
+/// struct epoll_event, riscv上不是packed的
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+/// epoll实例, 兴趣列表按fd号记录, 每次等待时到当前进程的fd表里找文件
+pub struct EpollFile {
+    interest: UPIntrFreeCell<BTreeMap<usize, EpollEvent>>,
+}
+
+impl EpollFile {
+    pub fn new() -> Self {
+        Self {
+            interest: unsafe { UPIntrFreeCell::new(BTreeMap::new()) },
+        }
+    }
+
+    pub fn ctl(&self, op: usize, fd: usize, event: EpollEvent) -> isize {
+        let mut interest = self.interest.exclusive_access();
+        match op {
+            EPOLL_CTL_ADD if !interest.contains_key(&fd) => {
+                interest.insert(fd, event);
+                0
+            }
+            EPOLL_CTL_MOD if interest.contains_key(&fd) => {
+                interest.insert(fd, event);
+                0
+            }
+            EPOLL_CTL_DEL if interest.remove(&fd).is_some() => 0,
+            _ => -1,
+        }
+    }
+
+    /// EPOLL_CTL_ADD能否监听`epoll`: 不能成环(包括自己), 也不能嵌套太深
+    pub fn may_watch(&self, epoll: &EpollFile) -> bool {
+        !epoll.reaches(self, 1)
+    }
+
+    /// 沿着兴趣列表里的epoll往下能否走到`target`, 超过EP_MAX_NESTS层也当作能走到
+    fn reaches(&self, target: &EpollFile, level: usize) -> bool {
+        if core::ptr::eq(self, target) || level > EP_MAX_NESTS {
+            return true;
+        }
+        let epolls: Vec<Arc<EpollFile>> = {
+            let interest = self.interest.exclusive_access();
+            let process = current_process();
+            let inner = process.inner_exclusive_access();
+            interest
+                .keys()
+                .filter_map(|&fd| match inner.fd_table.get(fd) {
+                    Some(Some(FileDescriptor::Epoll(epoll))) => Some(epoll.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+        epolls.iter().any(|epoll| epoll.reaches(target, level + 1))
+    }
+
+    /// epoll_wait取走最多`max`个就绪事件
+    pub fn ready_events(&self, max: usize) -> Vec<EpollEvent> {
+        self.collect(max, true)
+    }
+
+    /// `disarm`为false时只是查看, 不让EPOLLONESHOT失效; 已经关闭的fd顺便移出兴趣列表
+    fn collect(&self, max: usize, disarm: bool) -> Vec<EpollEvent> {
+        let files: Vec<(usize, EpollEvent, Option<FileDescriptor>)> = {
+            let interest = self.interest.exclusive_access();
+            let process = current_process();
+            let inner = process.inner_exclusive_access();
+            interest
+                .iter()
+                .map(|(&fd, &event)| (fd, event, inner.fd_table.get(fd).cloned().flatten()))
+                .collect()
+        };
+        let mut ready = Vec::new();
+        // poll会借用各自的表, 不能在借用fd表时调用
+        for (fd, event, file) in files {
+            if ready.len() == max {
+                break;
+            }
+            let file = match file {
+                Some(file) => file,
+                None => {
+                    self.interest.exclusive_access().remove(&fd);
+                    continue;
+                }
+            };
+            // 触发过的EPOLLONESHOT被清成0, 直到EPOLL_CTL_MOD重新设置
+            if event.events == 0 {
+                continue;
+            }
+            let requested = PollEvents::from_bits_truncate(event.events) | PollEvents::always();
+            let revents = file.poll() & requested;
+            if revents.is_empty() {
+                continue;
+            }
+            ready.push(EpollEvent {
+                events: revents.bits(),
+                data: event.data,
+            });
+            if disarm && event.events & EPOLLONESHOT != 0 {
+                if let Some(event) = self.interest.exclusive_access().get_mut(&fd) {
+                    event.events = 0;
+                }
+            }
+        }
+        ready
+    }
+}
+
+impl File for EpollFile {
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+
+    /// epoll可以被另一个epoll或poll监听
+    fn poll(&self) -> PollEvents {
+        if self.collect(1, false).is_empty() {
+            PollEvents::empty()
+        } else {
+            PollEvents::POLLIN
+        }
+    }
+}
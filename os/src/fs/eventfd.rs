@@ -0,0 +1,120 @@
+use super::{File, PollEvents, INTERRUPTED, WOULD_BLOCK};
+use crate::mm::UserBuffer;
+use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::task::{check_signals_of_current, schedule};
+
+pub const EFD_SEMAPHORE: usize = 1;
+pub const EFD_NONBLOCK: usize = 0o4000;
+
+/// 计数器最大值, 再写就要阻塞
+const EVENTFD_MAX: u64 = u64::MAX - 1;
+
+/// eventfd2, 读写的都是一个8字节的计数
+pub struct EventFd {
+    count: UPIntrFreeCell<u64>,
+    semaphore: bool,
+    nonblock: bool,
+    /// 读写双方都在这里等待
+    condvar: Condvar,
+}
+
+impl EventFd {
+    pub fn new(initval: u64, flags: usize) -> Self {
+        Self {
+            count: unsafe { UPIntrFreeCell::new(initval) },
+            semaphore: flags & EFD_SEMAPHORE != 0,
+            nonblock: flags & EFD_NONBLOCK != 0,
+            condvar: Condvar::new(),
+        }
+    }
+}
+
+/// 从用户缓冲区取出8字节的计数
+fn read_u64(buf: &UserBuffer) -> Option<u64> {
+    let mut bytes = [0u8; 8];
+    let mut len = 0;
+    for b in buf.buffers.iter() {
+        let n = b.len().min(8 - len);
+        bytes[len..len + n].copy_from_slice(&b[..n]);
+        len += n;
+        if len == 8 {
+            return Some(u64::from_ne_bytes(bytes));
+        }
+    }
+    None
+}
+
+impl File for EventFd {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    // 计数为0时阻塞, 非阻塞模式下返回错误
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        if buf.len() < 8 {
+            return 0;
+        }
+        loop {
+            let mut count = self.count.exclusive_access();
+            if *count > 0 {
+                let value = if self.semaphore { 1 } else { *count };
+                *count -= value;
+                drop(count);
+                self.condvar.signal_all();
+                return buf.write(&value.to_ne_bytes());
+            }
+            if self.nonblock {
+                return WOULD_BLOCK;
+            }
+            let task_cx_ptr = self.condvar.wait_no_sched();
+            drop(count);
+            schedule(task_cx_ptr);
+            // 等待时来了信号就回去处理, 不然kill -9也杀不掉
+            if check_signals_of_current().is_some() {
+                return INTERRUPTED;
+            }
+        }
+    }
+
+    // 计数会溢出时阻塞
+    fn write(&self, buf: UserBuffer) -> usize {
+        let value = match read_u64(&buf) {
+            Some(value) if value != u64::MAX => value,
+            _ => return 0,
+        };
+        loop {
+            let mut count = self.count.exclusive_access();
+            if EVENTFD_MAX - *count >= value {
+                *count += value;
+                drop(count);
+                self.condvar.signal_all();
+                return 8;
+            }
+            if self.nonblock {
+                return WOULD_BLOCK;
+            }
+            let task_cx_ptr = self.condvar.wait_no_sched();
+            drop(count);
+            schedule(task_cx_ptr);
+            if check_signals_of_current().is_some() {
+                return INTERRUPTED;
+            }
+        }
+    }
+
+    fn poll(&self) -> PollEvents {
+        let count = *self.count.exclusive_access();
+        let mut events = PollEvents::empty();
+        if count > 0 {
+            events |= PollEvents::POLLIN;
+        }
+        if count < EVENTFD_MAX {
+            events |= PollEvents::POLLOUT;
+        }
+        events
+    }
+}
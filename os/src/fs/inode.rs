@@ -2,6 +2,8 @@ use core::str::FromStr;
 
 use super::*;
 use crate::drivers::BLOCK_DEVICE;
+use alloc::collections::BTreeMap;
+use alloc::format;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
@@ -10,6 +12,9 @@ use lazy_static::lazy_static;
 use log::info;
 use spin::Mutex;
 use crate::fs::info::{VFSFlag, DTYPE_DIR, DTYPE_REG, DTYPE_UNKNOWN};
+use crate::task::{
+    current_add_signal, current_task, SignalFlags, MAY_EXEC, MAY_READ, MAY_WRITE, RLIMIT_FSIZE,
+};
 
 /// 表示进程中一个被打开的常规文件或目录
 pub struct OSInode {
@@ -50,6 +55,11 @@ impl OSInode {
         vec
     }
 
+    /// 从`offset`处读, 不改变文件的读写位置
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        self.inner.lock().inode.read_at(offset, buf)
+    }
+
     pub fn is_dir(&self) -> bool {
         let inner = self.inner.lock();
         inner.inode.is_dir()
@@ -62,7 +72,9 @@ impl OSInode {
     }
 
     pub fn delete(&self) -> usize {
-        self.inner.lock().inode.remove()
+        let inode = self.inner.lock().inode.clone();
+        FILE_OWNERS.lock().remove(&vfile_key(&inode));
+        inode.remove()
     }
 
     pub fn get_size(&self) -> usize {
@@ -82,35 +94,38 @@ impl OSInode {
         }
         let mut path_split: Vec<&str> = path.split('/').collect();
         let (readable, writable) = (true, true);
-        if let Some(target_inode) = inode.find_vfile_bypath(path_split.clone()) {
-            target_inode.remove();
-        }
         let filename = path_split.pop().unwrap();
 
-        //创建失败的条件包括: 目录不存在,存在文件但不是目录
-        match inode.find_vfile_bypath(path_split) {
-            None => None,
-            Some(vfile) => {
-                if !vfile.is_dir() {
-                    None
-                } else {
-                    let attr = _type.into();
-                    vfile
-                        .create(filename, attr)
-                        .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
-                }
-            }
+        //创建失败的条件包括: 目录不存在,存在文件但不是目录,没有写权限
+        let vfile = inode.find_vfile_bypath(path_split.clone())?;
+        if !vfile.is_dir() || !vfile_permits(&vfile, MAY_WRITE | MAY_EXEC) {
+            return None;
+        }
+        path_split.push(filename);
+        if let Some(target_inode) = inode.find_vfile_bypath(path_split) {
+            target_inode.remove();
         }
+        let attr = _type.into();
+        vfile
+            .create(filename, attr)
+            .map(|inode| Arc::new(OSInode::new(readable, writable, record_owner(inode))))
+    }
+
+    /// 当前进程对这个文件有没有`access`(MAY_*的组合)权限
+    pub fn permits(&self, access: u32) -> bool {
+        vfile_permits(&self.inner.lock().inode, access)
     }
 
     pub fn find(&self, path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
         let inner = self.inner.lock();
         let path_split = path.split('/').collect();
 
-        inner.inode.find_vfile_bypath(path_split).map(|inode| {
-            let (readable, writable) = flags.read_write();
-            Arc::new(OSInode::new(readable, writable, inode.clone()))
-        })
+        let inode = inner.inode.find_vfile_bypath(path_split)?;
+        if !vfile_permits(&inode, open_access(flags)) {
+            return None;
+        }
+        let (readable, writable) = flags.read_write();
+        Some(Arc::new(OSInode::new(readable, writable, inode)))
     }
 
     pub fn get_dirent(&self, dirent: &mut Dirent) -> isize {
@@ -144,14 +159,7 @@ impl OSInode {
         let vfile = self.inner.lock().inode.clone();
 
         let (size, access_t, modify_t, create_t, inode_num) = vfile.stat();
-        let st_mode = {
-            if vfile.is_dir() {
-                VFSFlag::create_flag(VFSFlag::S_IFDIR, VFSFlag::S_IRWXU, VFSFlag::S_IRWXG)
-            } else {
-                VFSFlag::create_flag(VFSFlag::S_IFREG, VFSFlag::S_IRWXU, VFSFlag::S_IRWXG)
-            }
-        }
-        .bits();
+        let st_mode = vfile_mode(&vfile);
 
         fstat.update(
             inode_num,
@@ -173,7 +181,25 @@ lazy_static! {
     };
 }
 
+/// 所有人都能在里面建文件, 带粘滞位, 删别人的文件不行
+const TMP_DIR: &str = "tmp";
+
 pub fn init() {
+    let tmp = match ROOT_INODE.find_vfile_bypath(alloc::vec![TMP_DIR]) {
+        Some(tmp) => Some(tmp),
+        None => ROOT_INODE.create(TMP_DIR, FileType::Dir.into()),
+    };
+    match tmp {
+        Some(tmp) if tmp.is_dir() => {
+            let owner = FileOwner {
+                uid: 0,
+                gid: 0,
+                perm: Some(VFSFlag::S_ISVTX.bits() | 0o777),
+            };
+            FILE_OWNERS.lock().insert(vfile_key(&tmp), owner);
+        }
+        _ => println!("[kernel] cannot create /{}", TMP_DIR),
+    }
     println!("/**** All Files  ****");
     list_apps(ROOT_INODE.clone());
     println!("**********************/");
@@ -268,36 +294,219 @@ pub fn open_file(
     flags: OpenFlags,
     _type: FileType,
 ) -> Option<Arc<OSInode>> {
-    let cur_inode = get_current_inode(work_path);
+    let (cur_inode, path) = resolve_path(work_path, path);
     let (readable, writable) = flags.read_write();
     let mut path_split: Vec<&str> = path.split('/').collect();
 
     //创建文件
     if flags.contains(OpenFlags::O_CREATE) {
+        let filename = path_split.pop().unwrap();
+        let dir = cur_inode.find_vfile_bypath(path_split.clone())?;
+        // 在目录里建文件要有写和执行权限
+        if !vfile_permits(&dir, MAY_WRITE | MAY_EXEC) {
+            return None;
+        }
         //如果文件存在删除对应文件
-        if let Some(inode) = cur_inode.find_vfile_bypath(path_split.clone()) {
+        path_split.push(filename);
+        if let Some(inode) = cur_inode.find_vfile_bypath(path_split) {
             inode.remove();
         }
-
-        let filename = path_split.pop().unwrap();
-        let dir = cur_inode.find_vfile_bypath(path_split).unwrap();
         let attr = _type.into();
         //创建文件
         dir.create(filename, attr)
-            .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
+            .map(|inode| Arc::new(OSInode::new(readable, writable, record_owner(inode))))
     } else {
-        cur_inode.find_vfile_bypath(path_split).map(|inode| {
-            if flags.contains(OpenFlags::O_TRUNC) {
-                inode.clear()
+        let inode = cur_inode.find_vfile_bypath(path_split)?;
+        if !vfile_permits(&inode, open_access(flags)) {
+            return None;
+        }
+        if flags.contains(OpenFlags::O_TRUNC) {
+            inode.clear()
+        }
+        Some(Arc::new(OSInode::new(readable, writable, inode)))
+    }
+}
+
+/// 按打开方式需要的权限, O_TRUNC也算写
+fn open_access(flags: OpenFlags) -> u32 {
+    let (readable, writable) = flags.read_write();
+    let mut access = 0;
+    if readable {
+        access |= MAY_READ;
+    }
+    if writable || flags.contains(OpenFlags::O_TRUNC) {
+        access |= MAY_WRITE;
+    }
+    access
+}
+
+/// 删除`path`要对它所在的目录有写和执行权限,
+/// 目录带粘滞位时还要是文件或者目录的属主
+pub fn may_unlink(work_path: &str, path: &str) -> bool {
+    let (cur_inode, path) = resolve_path(work_path, path);
+    let mut path_split: Vec<&str> = path.split('/').collect();
+    let file = match cur_inode.find_vfile_bypath(path_split.clone()) {
+        Some(file) => file,
+        None => return false,
+    };
+    path_split.pop();
+    let dir = match cur_inode.find_vfile_bypath(path_split) {
+        Some(dir) => dir,
+        None => return false,
+    };
+    if !vfile_permits(&dir, MAY_WRITE | MAY_EXEC) {
+        return false;
+    }
+    if vfile_mode(&dir) & VFSFlag::S_ISVTX.bits() == 0 {
+        return true;
+    }
+    match current_task() {
+        None => true,
+        Some(task) => {
+            let process = task.process.upgrade().unwrap();
+            let inner = process.inner_exclusive_access();
+            let cred = &inner.cred;
+            cred.is_privileged()
+                || [&file, &dir]
+                    .iter()
+                    .any(|vfile| vfile_owner(vfile).uid == cred.uid.effective)
+        }
+    }
+}
+
+/// FAT里没有的属主和权限位
+#[derive(Clone, Copy, Default)]
+struct FileOwner {
+    uid: u32,
+    gid: u32,
+    /// None时按默认的rwxr-xr-x
+    perm: Option<u32>,
+}
+
+lazy_static! {
+    /// 按文件系统和短目录项的位置记下属主, 只在内存里, 重启后都算root的.
+    /// 进程建的文件记下建它的人, /tmp记成所有人可写的粘滞目录
+    static ref FILE_OWNERS: Mutex<BTreeMap<(usize, usize, usize), FileOwner>> =
+        Mutex::new(BTreeMap::new());
+}
+
+fn vfile_key(vfile: &VFile) -> (usize, usize, usize) {
+    let (sector, offset) = vfile.get_dirent_pos();
+    (Arc::as_ptr(&vfile.get_fs()) as usize, sector, offset)
+}
+
+/// 没有记录的文件都算root的
+fn vfile_owner(vfile: &VFile) -> FileOwner {
+    FILE_OWNERS
+        .lock()
+        .get(&vfile_key(vfile))
+        .copied()
+        .unwrap_or_default()
+}
+
+/// 新建的文件属于当前进程的有效uid和gid, 覆盖目录项位置上以前的记录
+fn record_owner(vfile: Arc<VFile>) -> Arc<VFile> {
+    let owner = match current_task() {
+        None => FileOwner::default(),
+        Some(task) => {
+            let process = task.process.upgrade().unwrap();
+            let inner = process.inner_exclusive_access();
+            FileOwner {
+                uid: inner.cred.uid.effective,
+                gid: inner.cred.gid.effective,
+                perm: None,
             }
-            Arc::new(OSInode::new(readable, writable, inode))
-        })
+        }
+    };
+    FILE_OWNERS.lock().insert(vfile_key(&vfile), owner);
+    vfile
+}
+
+/// 权限位见vfile_owner, 默认rwxr-xr-x, 带只读属性的去掉写位
+fn vfile_mode(vfile: &VFile) -> u32 {
+    let file_type = if vfile.is_dir() {
+        VFSFlag::S_IFDIR
+    } else {
+        VFSFlag::S_IFREG
+    };
+    let mut perm = vfile_owner(vfile).perm.unwrap_or(0o755);
+    if vfile.get_attribute() & ATTRIBUTE_READ_ONLY != 0 {
+        perm &= !0o222;
+    }
+    file_type.bits() | perm
+}
+
+/// 按当前进程的身份检查权限; 没有当前任务时是内核自己在打开文件, 比如加载initproc
+fn vfile_permits(vfile: &VFile, access: u32) -> bool {
+    match current_task() {
+        None => true,
+        Some(task) => {
+            let process = task.process.upgrade().unwrap();
+            let inner = process.inner_exclusive_access();
+            let owner = vfile_owner(vfile);
+            inner.cred.permits(vfile_mode(vfile), owner.uid, owner.gid, access)
+        }
+    }
+}
+
+/// 当前进程RLIMIT_FSIZE的软限制, 内核自己写文件时不限制
+fn file_size_limit() -> usize {
+    match current_task() {
+        None => usize::MAX,
+        Some(task) => {
+            let process = task.process.upgrade().unwrap();
+            let inner = process.inner_exclusive_access();
+            inner.rlimits.cur(RLIMIT_FSIZE)
+        }
+    }
+}
+
+/// 以绝对路径创建一个新文件, 文件已存在或父目录不存在时返回None
+pub fn create_new_file(path: &str) -> Option<Arc<OSInode>> {
+    let (root, path) = resolve_path("/", path);
+    let mut path_split: Vec<&str> = path.split('/').collect();
+    if root.find_vfile_bypath(path_split.clone()).is_some() {
+        return None;
+    }
+    let filename = path_split.pop()?;
+    let dir = root.find_vfile_bypath(path_split)?;
+    if !dir.is_dir() {
+        return None;
     }
+    dir.create(filename, FileType::Regular.into())
+        .map(|inode| Arc::new(OSInode::new(true, true, record_owner(inode))))
+}
+
+/// 没有设置PATH时在这些目录里找
+const DEFAULT_PATH: &str = "/:/bin";
+
+/// 按PATH找可执行文件, 给shell用, execve本身不查PATH: 名字里带/就是路径本身,
+/// 否则依次在PATH的各个目录下找, 返回第一个存在的路径
+#[allow(unused)]
+pub fn lookup_path(work_path: &str, name: &str, path_var: Option<&str>) -> Option<String> {
+    if name.contains('/') {
+        return Some(String::from(name));
+    }
+    path_var
+        .unwrap_or(DEFAULT_PATH)
+        .split(':')
+        .map(|dir| match dir {
+            // 空的一项表示当前目录
+            "" => format!("{}/{}", work_path.trim_end_matches('/'), name),
+            _ => format!("{}/{}", dir.trim_end_matches('/'), name),
+        })
+        .find(|path| file_exists(path))
+}
+
+pub fn file_exists(path: &str) -> bool {
+    let (root, path) = resolve_path("/", path);
+    let path_split: Vec<&str> = path.split('/').collect();
+    root.find_vfile_bypath(path_split).is_some()
 }
 
 #[inline]
 pub fn ch_dir(curr_path: &str, path: &str) -> isize {
-    let curr_inode = get_current_inode(curr_path);
+    let (curr_inode, path) = resolve_path(curr_path, path);
     let path_split: Vec<&str> = path.split("/").collect();
     match curr_inode.find_vfile_bypath(path_split) {
         None => -1,
@@ -368,15 +577,24 @@ impl File for OSInode {
     }
 
     fn write(&self, buf: UserBuffer) -> usize {
+        let limit = file_size_limit();
         let mut inner = self.inner.lock();
-        let write_size = 0;
+        let mut write_size = 0;
         for buffer in buf.buffers.iter() {
-            let size = inner.inode.write_at(inner.offset, *buffer);
+            // 超过RLIMIT_FSIZE的部分不写, 一个字节都写不了时发SIGXFSZ
+            if inner.offset >= limit {
+                if write_size == 0 {
+                    current_add_signal(SignalFlags::SIGXFSZ);
+                }
+                break;
+            }
+            let len = buffer.len().min(limit - inner.offset);
+            let size = inner.inode.write_at(inner.offset, &buffer[..len]);
             if size == 0 {
                 break;
             }
             inner.offset += size;
-            let _ = write_size + size;
+            write_size += size;
         }
         write_size
     }
@@ -1,3 +1,5 @@
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
@@ -5,30 +7,73 @@ use fatfs::VFile;
 
 
 pub use info::{Dirent, Kstat};
-pub use inode::{ch_dir, list_apps, open_file, init};
+pub use inode::{ch_dir, create_new_file, file_exists, list_apps, lookup_path, may_unlink, open_file, init};
 pub use inode::{FileType, OpenFlags, OSInode};
 pub use pipe::{make_pipe, Pipe};
+pub use poll::PollEvents;
+pub use pty::{open_ptmx, open_pts, pts_index, PTMX_PATH};
+pub use epoll::{EpollEvent, EpollFile, EPOLL_CTL_ADD, EPOLL_CTL_DEL};
+pub use eventfd::EventFd;
+pub use signalfd::SignalFd;
+pub use procfs::open_proc;
+pub use timerfd::{ITimerSpec, TimerFd};
 pub use stdio::{Stdin, Stdout};
+pub use tty::{console_interrupt, CONSOLE};
 pub use mount::MNT_TABLE;
 use crate::fs::inode::ROOT_INODE;
 use crate::mm::UserBuffer;
+use crate::mm::shm::ShmFile;
+use crate::net::socket_fd::SocketFd;
 
 
 
+mod epoll;
+mod eventfd;
 mod info;
 mod inode;
 mod mount;
 mod pipe;
+mod poll;
+mod procfs;
+mod pty;
+mod signalfd;
 mod stdio;
+mod timerfd;
+mod tty;
+
+/// 非阻塞的文件读写不了时返回它, 系统调用转成isize就是-1(EAGAIN)
+pub const WOULD_BLOCK: usize = usize::MAX;
+/// 阻塞等待时被信号打断返回它, 系统调用转成isize也是-1(EINTR)
+pub const INTERRUPTED: usize = usize::MAX;
 
 pub trait File: Send + Sync {
     fn readable(&self) -> bool;
     fn writable(&self) -> bool;
     fn read(&self, buf: UserBuffer) -> usize;
     fn write(&self, buf: UserBuffer) -> usize;
+    /// 当前就绪的事件, 默认认为可读写的文件总是就绪
+    fn poll(&self) -> PollEvents {
+        let mut events = PollEvents::empty();
+        if self.readable() {
+            events |= PollEvents::POLLIN;
+        }
+        if self.writable() {
+            events |= PollEvents::POLLOUT;
+        }
+        events
+    }
+    /// 设备相关的控制命令, 默认不支持
+    fn ioctl(&self, _request: usize, _arg: usize) -> isize {
+        -1
+    }
 }
 
 pub fn get_current_inode(curr_path: &str) -> Arc<VFile> {
+    let mounted = MNT_TABLE.lock().lookup(curr_path);
+    if let Some((root, rest)) = mounted {
+        let path: Vec<&str> = rest.split("/").collect();
+        return root.find_vfile_bypath(path).unwrap();
+    }
     if curr_path == "/" || curr_path.contains("^/") {
         ROOT_INODE.clone()
     } else {
@@ -37,10 +82,33 @@ pub fn get_current_inode(curr_path: &str) -> Arc<VFile> {
     }
 }
 
+/// 相对路径接到工作目录后面
+pub fn absolute_path(work_path: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        String::from(path)
+    } else {
+        format!("{}/{}", work_path, path)
+    }
+}
+
+/// 找`path`从哪个目录开始查: 落在挂载点下面时换成挂载的根目录和挂载点下的剩余路径,
+/// 否则是当前工作目录和原来的路径
+pub fn resolve_path(work_path: &str, path: &str) -> (Arc<VFile>, String) {
+    let mounted = MNT_TABLE.lock().lookup(&absolute_path(work_path, path));
+    if let Some(found) = mounted {
+        return found;
+    }
+    (get_current_inode(work_path), String::from(path))
+}
+
 #[derive(Clone)]
 pub enum FileDescriptor {
     Regular(Arc<OSInode>),
     Abstract(Arc<dyn File + Send + Sync>),
+    Socket(Arc<SocketFd>),
+    Shm(Arc<ShmFile>),
+    TimerFd(Arc<TimerFd>),
+    Epoll(Arc<EpollFile>),
 }
 
 impl File for FileDescriptor {
@@ -48,6 +116,10 @@ impl File for FileDescriptor {
         match self {
             FileDescriptor::Regular(inode) => inode.readable(),
             FileDescriptor::Abstract(inode) => inode.readable(),
+            FileDescriptor::Socket(socket) => socket.readable(),
+            FileDescriptor::Shm(shm) => shm.readable(),
+            FileDescriptor::TimerFd(timer) => timer.readable(),
+            FileDescriptor::Epoll(epoll) => epoll.readable(),
         }
     }
     
@@ -55,6 +127,10 @@ impl File for FileDescriptor {
         match self {
             FileDescriptor::Regular(inode) => inode.writable(),
             FileDescriptor::Abstract(inode) => inode.writable(),
+            FileDescriptor::Socket(socket) => socket.writable(),
+            FileDescriptor::Shm(shm) => shm.writable(),
+            FileDescriptor::TimerFd(timer) => timer.writable(),
+            FileDescriptor::Epoll(epoll) => epoll.writable(),
         }
     }
     
@@ -62,6 +138,10 @@ impl File for FileDescriptor {
         match self {
             FileDescriptor::Regular(inode) => inode.read(buf),
             FileDescriptor::Abstract(inode) => inode.read(buf),
+            FileDescriptor::Socket(socket) => socket.read(buf),
+            FileDescriptor::Shm(shm) => shm.read(buf),
+            FileDescriptor::TimerFd(timer) => timer.read(buf),
+            FileDescriptor::Epoll(epoll) => epoll.read(buf),
         }
     }
     
@@ -69,6 +149,32 @@ impl File for FileDescriptor {
         match self {
             FileDescriptor::Regular(inode) => inode.write(buf),
             FileDescriptor::Abstract(inode) => inode.write(buf),
+            FileDescriptor::Socket(socket) => socket.write(buf),
+            FileDescriptor::Shm(shm) => shm.write(buf),
+            FileDescriptor::TimerFd(timer) => timer.write(buf),
+            FileDescriptor::Epoll(epoll) => epoll.write(buf),
+        }
+    }
+
+    fn poll(&self) -> PollEvents {
+        match self {
+            FileDescriptor::Regular(inode) => inode.poll(),
+            FileDescriptor::Abstract(inode) => inode.poll(),
+            FileDescriptor::Socket(socket) => socket.poll(),
+            FileDescriptor::Shm(shm) => shm.poll(),
+            FileDescriptor::TimerFd(timer) => timer.poll(),
+            FileDescriptor::Epoll(epoll) => epoll.poll(),
+        }
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> isize {
+        match self {
+            FileDescriptor::Regular(inode) => inode.ioctl(request, arg),
+            FileDescriptor::Abstract(inode) => inode.ioctl(request, arg),
+            FileDescriptor::Socket(socket) => socket.ioctl(request, arg),
+            FileDescriptor::Shm(shm) => shm.ioctl(request, arg),
+            FileDescriptor::TimerFd(timer) => timer.ioctl(request, arg),
+            FileDescriptor::Epoll(epoll) => epoll.ioctl(request, arg),
         }
     }
 }
@@ -8,32 +8,64 @@
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use fatfs::VFile;
 use lazy_static::*;
 use spin::Mutex;
 
 const MNT_MAXLEN: usize = 16;
 
 pub struct MountTable {
-    // <特殊设备、挂载目录和文件系统类型>
-    mnt_list: Vec<(String, String, String)>, // special, dir, fstype
+    // <特殊设备、挂载目录、文件系统类型和挂载的文件系统的根目录>
+    // 根目录持有FAT32Manager, 卸载时随表项一起释放
+    mnt_list: Vec<(String, String, String, Arc<VFile>)>, // special, dir, fstype, root
+}
+
+/// 路径按'/'拆开, 去掉空的和"."
+fn components(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect()
 }
 
 impl MountTable {
-    /// 用于将一个新的挂载点添加到挂载点表中。它接受特殊设备、挂载目录、文件系统类型和标志作为参数，并将其添加到mnt_list中。
-    /// 如果挂载点表已满，返回-1
-    /// 如果挂载目录已存在于表中，返回0即不需要挂载
+    /// 用于将一个新的挂载点添加到挂载点表中。它接受特殊设备、挂载目录(绝对路径)、文件系统类型、
+    /// 文件系统的根目录和标志作为参数，并将其添加到mnt_list中。
+    /// 如果挂载点表已满，或者设备或目录已经挂载过，返回-1
     /// 否则返回0表示挂载成功
-    pub fn mount(&mut self, special: String, dir: String, fstype: String, _flag: u32) -> isize {
+    pub fn mount(
+        &mut self,
+        special: String,
+        dir: String,
+        fstype: String,
+        root: Arc<VFile>,
+        _flag: u32,
+    ) -> isize {
         if self.mnt_list.len() == MNT_MAXLEN {
             return -1;
         }
-        if self.mnt_list.iter().find(|&(_, d, _)| *d == dir).is_some() {
-            return 0;
+        if self
+            .mnt_list
+            .iter()
+            .any(|(s, d, _, _)| *s == special || components(d) == components(&dir))
+        {
+            return -1;
         }
-        self.mnt_list.push((special, dir, fstype));
+        self.mnt_list.push((special, dir, fstype, root));
         return 0;
     }
 
+    /// 绝对路径`path`落在某个挂载点下面时, 返回挂载的根目录和挂载点下的剩余路径;
+    /// 挂载点套挂载点时取最长的那个
+    pub fn lookup(&self, path: &str) -> Option<(Arc<VFile>, String)> {
+        let parts = components(path);
+        self.mnt_list
+            .iter()
+            .map(|(_, dir, _, root)| (components(dir), root))
+            .filter(|(dir, _)| parts.starts_with(dir))
+            .max_by_key(|(dir, _)| dir.len())
+            .map(|(dir, root)| (root.clone(), parts[dir.len()..].join("/")))
+    }
+
     /// 从挂载点表中卸载一个挂载点。它接受特殊设备和标志作为参数，并遍历mnt_list查找匹配的挂载点，
     /// 如果找到匹配项，则将其从表中移除并返回0，
     /// 否则返回-1表示卸载失败。
@@ -41,7 +73,9 @@ impl MountTable {
         let len = self.mnt_list.len();
         for i in 0..len {
             //println!("[umount] in mntlist = {}", self.mnt_list[i].0);
-            if self.mnt_list[i].0 == special || self.mnt_list[i].1 == special {
+            if self.mnt_list[i].0 == special
+                || components(&self.mnt_list[i].1) == components(&special)
+            {
                 self.mnt_list.remove(i);
                 return 0;
             }
@@ -1,6 +1,6 @@
 use alloc::sync::{Arc, Weak};
 use spin::Mutex;
-use crate::fs::File;
+use crate::fs::{File, PollEvents};
 use crate::mm::UserBuffer;
 use crate::task::suspend_current_and_run_next;
 
@@ -170,6 +170,23 @@ impl File for Pipe {
             }
         }
     }
+
+    fn poll(&self) -> PollEvents {
+        let ring_buf = self.buffer.lock();
+        let mut events = PollEvents::empty();
+        if self.readable {
+            if ring_buf.available_read() > 0 {
+                events |= PollEvents::POLLIN;
+            }
+            if ring_buf.all_write_ends_closed() {
+                events |= PollEvents::POLLHUP;
+            }
+        }
+        if self.writable && ring_buf.available_write() > 0 {
+            events |= PollEvents::POLLOUT;
+        }
+        events
+    }
 }
 
 /// Return (read_end, write_end)
@@ -0,0 +1,18 @@
+// poll/epoll共用的事件位, 和linux的POLL*/EPOLL*取值一致
+bitflags! {
+    pub struct PollEvents: u32 {
+        const POLLIN   = 0x001;
+        const POLLPRI  = 0x002;
+        const POLLOUT  = 0x004;
+        const POLLERR  = 0x008;
+        const POLLHUP  = 0x010;
+        const POLLNVAL = 0x020;
+    }
+}
+
+impl PollEvents {
+    /// 即使没有请求也总会报告的事件
+    pub fn always() -> Self {
+        Self::POLLERR | Self::POLLHUP | Self::POLLNVAL
+    }
+}
@@ -0,0 +1,106 @@
+//! /proc下的几个只读伪文件, 打开时把内核的统计生成成文本
+use super::File;
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_stats, free_frames, heap_stats, slab_stats, UserBuffer};
+use crate::sync::UPIntrFreeCell;
+use alloc::string::String;
+use core::fmt::Write;
+
+pub const SLABINFO_PATH: &str = "/proc/slabinfo";
+pub const BUDDYINFO_PATH: &str = "/proc/buddyinfo";
+
+pub struct ProcFile {
+    text: String,
+    offset: UPIntrFreeCell<usize>,
+}
+
+/// 不是/proc下支持的文件时返回None
+pub fn open_proc(path: &str) -> Option<ProcFile> {
+    let text = match path {
+        SLABINFO_PATH => slabinfo(),
+        BUDDYINFO_PATH => buddyinfo(),
+        _ => return None,
+    };
+    Some(ProcFile {
+        text,
+        offset: unsafe { UPIntrFreeCell::new(0) },
+    })
+}
+
+fn slabinfo() -> String {
+    let mut text = String::new();
+    writeln!(text, "# size objperslab slabs active allocs").unwrap();
+    for stats in slab_stats().iter() {
+        writeln!(
+            text,
+            "kmalloc-{} {} {} {} {}",
+            stats.object_size, stats.objects_per_slab, stats.slabs, stats.in_use, stats.allocs
+        )
+        .unwrap();
+    }
+    let heap = heap_stats();
+    writeln!(
+        text,
+        "heap: {} bytes, {} allocated, {} requested, {} regions",
+        heap.total, heap.allocated, heap.requested, heap.regions
+    )
+    .unwrap();
+    let frames = free_frames();
+    writeln!(
+        text,
+        "free frames: {} ({} KiB)",
+        frames,
+        frames * PAGE_SIZE / 1024
+    )
+    .unwrap();
+    text
+}
+
+/// 和Linux一样每个zone一行, 依次是每一阶的空闲块数
+fn buddyinfo() -> String {
+    let stats = frame_stats();
+    let mut text = String::new();
+    for zone in stats.zones.iter().filter(|zone| zone.managed > 0) {
+        write!(text, "Node 0, zone {:>8}", zone.name).unwrap();
+        for count in zone.free_blocks.iter() {
+            write!(text, " {:>6}", count).unwrap();
+        }
+        writeln!(text).unwrap();
+    }
+    for zone in stats.zones.iter().filter(|zone| zone.managed > 0) {
+        writeln!(
+            text,
+            "zone {}: {} pages managed, {} free",
+            zone.name, zone.managed, zone.free
+        )
+        .unwrap();
+    }
+    writeln!(
+        text,
+        "allocs: {}, failures: {}",
+        stats.allocs, stats.failures
+    )
+    .unwrap();
+    text
+}
+
+impl File for ProcFile {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let mut offset = self.offset.exclusive_access();
+        let n = buf.write(&self.text.as_bytes()[*offset..]);
+        *offset += n;
+        n
+    }
+
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+}
@@ -0,0 +1,206 @@
+//! 伪终端: 打开/dev/ptmx得到master, /dev/pts/N是slave
+//! slave就是一个Tty, 它的输出进到master的读缓冲区, 写master相当于在终端上敲键盘
+use super::tty::{Tty, TtyDriver};
+use super::{File, PollEvents};
+use crate::mm::{translated_ref, translated_refmut, UserBuffer};
+use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::task::{current_user_token, schedule};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lazy_static::*;
+
+pub const PTMX_PATH: &str = "/dev/ptmx";
+const PTS_DIR: &str = "/dev/pts/";
+
+const TIOCGPTN: usize = 0x80045430;
+const TIOCSPTLCK: usize = 0x40045431;
+const TIOCGPTLCK: usize = 0x80045439;
+
+/// slave的输出, 等master来读
+struct PtyOutput {
+    buffer: UPIntrFreeCell<VecDeque<u8>>,
+    condvar: Condvar,
+}
+
+impl TtyDriver for PtyOutput {
+    fn write(&self, data: &[u8]) {
+        self.buffer.exclusive_access().extend(data.iter());
+        self.condvar.signal_all();
+    }
+}
+
+struct Pty {
+    index: usize,
+    tty: Arc<Tty>,
+    output: Arc<PtyOutput>,
+    /// unlockpt之前不能打开slave
+    locked: AtomicBool,
+    /// 打开着的slave个数, 都关掉以后master读到文件尾
+    slaves: AtomicUsize,
+    slave_opened: AtomicBool,
+}
+
+lazy_static! {
+    /// 编号 -> pty, 由master持有, master关掉后slave就打不开了
+    static ref PTYS: UPIntrFreeCell<BTreeMap<usize, Weak<Pty>>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+}
+
+/// `path`是/dev/pts/N时返回N
+pub fn pts_index(path: &str) -> Option<usize> {
+    path.strip_prefix(PTS_DIR)?.parse().ok()
+}
+
+/// 打开/dev/ptmx, 分配最小的空闲编号
+pub fn open_ptmx() -> Arc<PtyMaster> {
+    let mut ptys = PTYS.exclusive_access();
+    let index = (0..).find(|index| !ptys.contains_key(index)).unwrap();
+    let output = Arc::new(PtyOutput {
+        buffer: unsafe { UPIntrFreeCell::new(VecDeque::new()) },
+        condvar: Condvar::new(),
+    });
+    let pty = Arc::new(Pty {
+        index,
+        tty: Arc::new(Tty::new(output.clone(), None)),
+        output,
+        locked: AtomicBool::new(true),
+        slaves: AtomicUsize::new(0),
+        slave_opened: AtomicBool::new(false),
+    });
+    ptys.insert(index, Arc::downgrade(&pty));
+    Arc::new(PtyMaster { pty })
+}
+
+/// 打开/dev/pts/N, 不存在或者还没有unlockpt时返回None
+pub fn open_pts(index: usize) -> Option<Arc<PtySlave>> {
+    let pty = PTYS.exclusive_access().get(&index)?.upgrade()?;
+    if pty.locked.load(Ordering::Relaxed) {
+        return None;
+    }
+    pty.slaves.fetch_add(1, Ordering::Relaxed);
+    pty.slave_opened.store(true, Ordering::Relaxed);
+    Some(Arc::new(PtySlave { pty }))
+}
+
+pub struct PtyMaster {
+    pty: Arc<Pty>,
+}
+
+impl File for PtyMaster {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    // 没有输出时阻塞, slave全部关掉后返回0
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let output = &self.pty.output;
+        loop {
+            let mut buffer = output.buffer.exclusive_access();
+            if !buffer.is_empty() {
+                let n = buffer.len().min(buf.len());
+                let data: Vec<u8> = buffer.drain(..n).collect();
+                drop(buffer);
+                return buf.write(&data);
+            }
+            if self.pty.slave_opened.load(Ordering::Relaxed)
+                && self.pty.slaves.load(Ordering::Relaxed) == 0
+            {
+                return 0;
+            }
+            let task_cx_ptr = output.condvar.wait_no_sched();
+            drop(buffer);
+            schedule(task_cx_ptr);
+        }
+    }
+
+    // 写进来的数据经过slave的行规程, 和在终端上输入一样
+    fn write(&self, buf: UserBuffer) -> usize {
+        for buffer in buf.buffers.iter() {
+            for &ch in buffer.iter() {
+                self.pty.tty.receive(ch);
+            }
+        }
+        buf.len()
+    }
+
+    fn poll(&self) -> PollEvents {
+        let mut events = PollEvents::POLLOUT;
+        if !self.pty.output.buffer.exclusive_access().is_empty() {
+            events |= PollEvents::POLLIN;
+        }
+        if self.pty.slave_opened.load(Ordering::Relaxed)
+            && self.pty.slaves.load(Ordering::Relaxed) == 0
+        {
+            events |= PollEvents::POLLHUP;
+        }
+        events
+    }
+
+    /// ptsname/unlockpt用的命令, 其余的交给slave的tty, 比如TIOCSWINSZ
+    fn ioctl(&self, request: usize, arg: usize) -> isize {
+        let token = current_user_token();
+        match request {
+            TIOCGPTN => *translated_refmut(token, arg as *mut u32) = self.pty.index as u32,
+            TIOCSPTLCK => {
+                let lock = *translated_ref(token, arg as *const i32) != 0;
+                self.pty.locked.store(lock, Ordering::Relaxed);
+            }
+            TIOCGPTLCK => {
+                let lock = self.pty.locked.load(Ordering::Relaxed) as i32;
+                *translated_refmut(token, arg as *mut i32) = lock;
+            }
+            _ => return self.pty.tty.ioctl(request, arg),
+        }
+        0
+    }
+}
+
+impl Drop for PtyMaster {
+    fn drop(&mut self) {
+        PTYS.exclusive_access().remove(&self.pty.index);
+        self.pty.tty.hangup();
+    }
+}
+
+pub struct PtySlave {
+    pty: Arc<Pty>,
+}
+
+impl File for PtySlave {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, buf: UserBuffer) -> usize {
+        self.pty.tty.read(buf)
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        self.pty.tty.write(buf)
+    }
+
+    fn poll(&self) -> PollEvents {
+        self.pty.tty.poll()
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> isize {
+        self.pty.tty.ioctl(request, arg)
+    }
+}
+
+impl Drop for PtySlave {
+    fn drop(&mut self) {
+        self.pty.slaves.fetch_sub(1, Ordering::Relaxed);
+        self.pty.output.condvar.signal_all();
+    }
+}
@@ -0,0 +1,91 @@
+use super::{File, PollEvents, INTERRUPTED, WOULD_BLOCK};
+use crate::mm::UserBuffer;
+use crate::task::{
+    check_signals_of_current, current_process, suspend_current_and_run_next, SignalFlags,
+};
+use alloc::vec::Vec;
+
+pub const SFD_NONBLOCK: usize = 0o4000;
+
+/// sizeof(struct signalfd_siginfo)
+const SIGINFO_SIZE: usize = 128;
+
+/// signalfd4, 从读者所在进程的未决信号里取走mask中的信号
+pub struct SignalFd {
+    mask: SignalFlags,
+    nonblock: bool,
+}
+
+impl SignalFd {
+    /// SIGKILL和SIGSTOP不能通过signalfd取走, 和Linux一样从mask里去掉
+    pub fn new(mask: SignalFlags, flags: usize) -> Self {
+        Self {
+            mask: mask - SignalFlags::unblockable(),
+            nonblock: flags & SFD_NONBLOCK != 0,
+        }
+    }
+
+    fn pending(&self) -> SignalFlags {
+        current_process().inner_exclusive_access().signals & self.mask
+    }
+}
+
+impl File for SignalFd {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    // 每个信号一条signalfd_siginfo, 只填ssi_signo; 没有信号时和管道一样让出cpu等待
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let count = buf.len() / SIGINFO_SIZE;
+        if count == 0 {
+            return 0;
+        }
+        loop {
+            let process = current_process();
+            let mut inner = process.inner_exclusive_access();
+            let pending = inner.signals & self.mask;
+            if !pending.is_empty() {
+                let mut records = Vec::new();
+                for signo in 1..32 {
+                    if records.len() == count * SIGINFO_SIZE {
+                        break;
+                    }
+                    let signal = SignalFlags::from_bits_truncate(1 << signo);
+                    if !signal.is_empty() && pending.contains(signal) {
+                        inner.signals.remove(signal);
+                        let mut info = [0u8; SIGINFO_SIZE];
+                        info[..4].copy_from_slice(&(signo as u32).to_ne_bytes());
+                        records.extend_from_slice(&info);
+                    }
+                }
+                drop(inner);
+                return buf.write(&records);
+            }
+            drop(inner);
+            if self.nonblock {
+                return WOULD_BLOCK;
+            }
+            suspend_current_and_run_next();
+            if check_signals_of_current().is_some() {
+                return INTERRUPTED;
+            }
+        }
+    }
+
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+
+    fn poll(&self) -> PollEvents {
+        if self.pending().is_empty() {
+            PollEvents::empty()
+        } else {
+            PollEvents::POLLIN
+        }
+    }
+}
@@ -1,8 +1,8 @@
-use super::File;
-use crate::drivers::chardev::CharDevice;
-use crate::drivers::chardev::UART;
+use super::tty::CONSOLE;
+use super::{File, PollEvents};
 use crate::mm::UserBuffer;
 
+/// 标准输入输出都是串口控制台, 经过tty的行规程
 pub struct Stdin;
 pub struct Stdout;
 
@@ -13,18 +13,18 @@ impl File for Stdin {
     fn writable(&self) -> bool {
         false
     }
-    fn read(&self, mut user_buf: UserBuffer) -> usize {
-        assert_eq!(user_buf.len(), 1);
-        //println!("before UART.read() in Stdin::read()");
-        let ch = UART.read();
-        unsafe {
-            user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
-        }
-        1
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        CONSOLE.read(user_buf)
     }
     fn write(&self, _user_buf: UserBuffer) -> usize {
         panic!("Cannot write to stdin!");
     }
+    fn poll(&self) -> PollEvents {
+        CONSOLE.poll() & PollEvents::POLLIN
+    }
+    fn ioctl(&self, request: usize, arg: usize) -> isize {
+        CONSOLE.ioctl(request, arg)
+    }
 }
 
 impl File for Stdout {
@@ -38,9 +38,9 @@ impl File for Stdout {
         panic!("Cannot read from stdout!");
     }
     fn write(&self, user_buf: UserBuffer) -> usize {
-        for buffer in user_buf.buffers.iter() {
-            print!("{}", core::str::from_utf8(*buffer).unwrap());
-        }
-        user_buf.len()
+        CONSOLE.write(user_buf)
+    }
+    fn ioctl(&self, request: usize, arg: usize) -> isize {
+        CONSOLE.ioctl(request, arg)
     }
 }
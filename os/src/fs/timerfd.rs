@@ -0,0 +1,175 @@
+use super::{File, PollEvents, INTERRUPTED, WOULD_BLOCK};
+use crate::mm::UserBuffer;
+use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::task::{check_signals_of_current, schedule};
+use crate::timer::{add_timer_callback, get_realtime_ns, get_time_ms, get_time_ns, TimeSpec};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub const TFD_TIMER_ABSTIME: usize = 1;
+pub const TFD_NONBLOCK: usize = 0o4000;
+
+/// struct itimerspec
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct ITimerSpec {
+    pub it_interval: TimeSpec,
+    pub it_value: TimeSpec,
+}
+
+/// settime换掉定时器后, TIMERS里旧的回调靠id认出自己已经失效
+static NEXT_TIMER_ID: AtomicUsize = AtomicUsize::new(1);
+
+struct TimerFdInner {
+    /// 下次到期的时间, 0表示没有启动
+    expire_ms: usize,
+    interval_ms: usize,
+    /// 上次read之后到期的次数
+    ticks: u64,
+    timer_id: usize,
+}
+
+/// timerfd, 由timer.rs里的TIMERS驱动, 到期次数通过read取走
+pub struct TimerFd {
+    inner: UPIntrFreeCell<TimerFdInner>,
+    /// CLOCK_REALTIME, 只影响TFD_TIMER_ABSTIME的换算
+    realtime: bool,
+    nonblock: bool,
+    condvar: Condvar,
+}
+
+impl TimerFd {
+    pub fn new(realtime: bool, flags: usize) -> Self {
+        Self {
+            inner: unsafe {
+                UPIntrFreeCell::new(TimerFdInner {
+                    expire_ms: 0,
+                    interval_ms: 0,
+                    ticks: 0,
+                    timer_id: 0,
+                })
+            },
+            realtime,
+            nonblock: flags & TFD_NONBLOCK != 0,
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// timerfd_settime, 返回原来的设置; 到期时间统一换算成开机以来的毫秒
+    pub fn settime(self: &Arc<Self>, flags: usize, new_value: &ITimerSpec) -> ITimerSpec {
+        let old_value = self.gettime();
+        let now = get_time_ms();
+        let id = NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+        let mut inner = self.inner.exclusive_access();
+        inner.ticks = 0;
+        inner.timer_id = id;
+        inner.interval_ms = new_value.it_interval.to_ms();
+        if new_value.it_value.is_zero() {
+            inner.expire_ms = 0;
+            return old_value;
+        }
+        inner.expire_ms = if flags & TFD_TIMER_ABSTIME != 0 {
+            let clock_ns = if self.realtime {
+                get_realtime_ns()
+            } else {
+                get_time_ns()
+            };
+            // 已经过去的时间点在下个时钟中断到期
+            let remaining = TimeSpec::from_ns(new_value.it_value.to_ns().saturating_sub(clock_ns));
+            (now + remaining.to_ms()).max(1)
+        } else {
+            now + new_value.it_value.to_ms()
+        };
+        let expire_ms = inner.expire_ms;
+        drop(inner);
+        self.arm(expire_ms, id);
+        old_value
+    }
+
+    pub fn gettime(&self) -> ITimerSpec {
+        let inner = self.inner.exclusive_access();
+        let remaining = if inner.expire_ms == 0 {
+            0
+        } else {
+            inner.expire_ms.saturating_sub(get_time_ms()).max(1)
+        };
+        ITimerSpec {
+            it_interval: TimeSpec::from_ms(inner.interval_ms),
+            it_value: TimeSpec::from_ms(remaining),
+        }
+    }
+
+    fn arm(self: &Arc<Self>, expire_ms: usize, id: usize) {
+        let timer = Arc::downgrade(self);
+        add_timer_callback(expire_ms, move || {
+            if let Some(timer) = timer.upgrade() {
+                timer.expire(id);
+            }
+        });
+    }
+
+    /// 在时钟中断里运行, 周期定时器把错过的次数一起算上
+    fn expire(self: &Arc<Self>, id: usize) {
+        let mut inner = self.inner.exclusive_access();
+        if inner.timer_id != id || inner.expire_ms == 0 {
+            return;
+        }
+        if inner.interval_ms == 0 {
+            inner.ticks += 1;
+            inner.expire_ms = 0;
+        } else {
+            let missed = (get_time_ms().saturating_sub(inner.expire_ms)) / inner.interval_ms + 1;
+            inner.ticks += missed as u64;
+            inner.expire_ms += missed * inner.interval_ms;
+            let expire_ms = inner.expire_ms;
+            drop(inner);
+            self.arm(expire_ms, id);
+        }
+        self.condvar.signal_all();
+    }
+}
+
+impl File for TimerFd {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        if buf.len() < 8 {
+            return 0;
+        }
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            if inner.ticks > 0 {
+                let ticks = core::mem::take(&mut inner.ticks);
+                drop(inner);
+                return buf.write(&ticks.to_ne_bytes());
+            }
+            if self.nonblock {
+                return WOULD_BLOCK;
+            }
+            let task_cx_ptr = self.condvar.wait_no_sched();
+            drop(inner);
+            schedule(task_cx_ptr);
+            if check_signals_of_current().is_some() {
+                return INTERRUPTED;
+            }
+        }
+    }
+
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+
+    fn poll(&self) -> PollEvents {
+        if self.inner.exclusive_access().ticks > 0 {
+            PollEvents::POLLIN
+        } else {
+            PollEvents::empty()
+        }
+    }
+}
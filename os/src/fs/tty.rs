@@ -0,0 +1,574 @@
+//! 终端行规程: 回显, 规范模式的行编辑, VINTR/VSUSP等控制字符产生信号
+//! 输入由驱动通过`Tty::receive`推进来, 输出交给`TtyDriver`
+use super::{File, PollEvents};
+use crate::drivers::chardev::{CharDevice, UART};
+use crate::mm::{translated_ref, translated_refmut, UserBuffer};
+use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::task::{
+    check_signals_of_current, current_process, current_user_token, handle_stop_signals_of_current,
+    schedule, send_signal_to_group, SignalFlags,
+};
+use crate::timer::add_timer_callback;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+const TCGETS: usize = 0x5401;
+const TCSETS: usize = 0x5402;
+const TCSETSW: usize = 0x5403;
+const TCSETSF: usize = 0x5404;
+const TCFLSH: usize = 0x540B;
+const TIOCSCTTY: usize = 0x540E;
+const TIOCGPGRP: usize = 0x540F;
+const TIOCSPGRP: usize = 0x5410;
+const TIOCGWINSZ: usize = 0x5413;
+const TIOCSWINSZ: usize = 0x5414;
+const TIOCNOTTY: usize = 0x5422;
+
+// c_iflag
+const ISTRIP: u32 = 0o40;
+const INLCR: u32 = 0o100;
+const IGNCR: u32 = 0o200;
+const ICRNL: u32 = 0o400;
+const IXON: u32 = 0o2000;
+// c_oflag
+const OPOST: u32 = 0o1;
+const ONLCR: u32 = 0o4;
+// c_cflag: B38400 | CS8 | CREAD | HUPCL
+const DEFAULT_CFLAG: u32 = 0o17 | 0o60 | 0o200 | 0o2000;
+// c_lflag
+const ISIG: u32 = 0o1;
+const ICANON: u32 = 0o2;
+const ECHO: u32 = 0o10;
+const ECHOE: u32 = 0o20;
+const ECHOK: u32 = 0o40;
+const ECHONL: u32 = 0o100;
+const NOFLSH: u32 = 0o200;
+const TOSTOP: u32 = 0o400;
+const ECHOCTL: u32 = 0o1000;
+const ECHOKE: u32 = 0o4000;
+const IEXTEN: u32 = 0o100000;
+
+// c_cc的下标
+const VINTR: usize = 0;
+const VQUIT: usize = 1;
+const VERASE: usize = 2;
+const VKILL: usize = 3;
+const VEOF: usize = 4;
+const VMIN: usize = 6;
+const VSUSP: usize = 10;
+const VEOL: usize = 11;
+const VWERASE: usize = 14;
+
+const NCCS: usize = 19;
+
+/// 内核的struct termios, TCGETS/TCSETS用的就是这个布局
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Termios {
+    pub c_iflag: u32,
+    pub c_oflag: u32,
+    pub c_cflag: u32,
+    pub c_lflag: u32,
+    pub c_line: u8,
+    pub c_cc: [u8; NCCS],
+}
+
+impl Default for Termios {
+    /// 和linux新开的终端一样: 规范模式, 回显, 产生信号
+    fn default() -> Self {
+        Self {
+            c_iflag: ICRNL | IXON,
+            c_oflag: OPOST | ONLCR,
+            c_cflag: DEFAULT_CFLAG,
+            c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN,
+            c_line: 0,
+            c_cc: [
+                0x03, 0x1c, 0x7f, 0x15, 0x04, 0, 1, 0, 0x11, 0x13, 0x1a, 0, 0x12, 0x0f, 0x17, 0x16,
+                0, 0, 0,
+            ],
+        }
+    }
+}
+
+impl Termios {
+    fn lflag(&self, flag: u32) -> bool {
+        self.c_lflag & flag != 0
+    }
+
+    /// 控制字符为0表示禁用
+    fn is_cc(&self, index: usize, ch: u8) -> bool {
+        self.c_cc[index] != 0 && self.c_cc[index] == ch
+    }
+}
+
+/// struct winsize
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WinSize {
+    pub ws_row: u16,
+    pub ws_col: u16,
+    pub ws_xpixel: u16,
+    pub ws_ypixel: u16,
+}
+
+impl Default for WinSize {
+    fn default() -> Self {
+        Self {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+}
+
+/// 终端的输出端, 串口或者pty的master
+pub trait TtyDriver: Send + Sync {
+    fn write(&self, data: &[u8]);
+}
+
+struct TtyInner {
+    termios: Termios,
+    winsize: WinSize,
+    /// 以它为控制终端的会话和其中的前台进程组
+    session: Option<usize>,
+    foreground: usize,
+    /// 规范模式下正在编辑的一行
+    line: Vec<u8>,
+    /// 可以读的数据; 规范模式下一项是一行, 空的一项表示读到了VEOF
+    input: VecDeque<Vec<u8>>,
+    /// pty的master已经关掉
+    hung_up: bool,
+}
+
+impl TtyInner {
+    fn has_input(&self) -> bool {
+        !self.input.is_empty()
+    }
+
+    /// 非规范模式下的字符接在最后一项后面
+    fn push_raw(&mut self, ch: u8) {
+        match self.input.back_mut() {
+            Some(last) if !last.is_empty() => last.push(ch),
+            _ => self.input.push_back(alloc::vec![ch]),
+        }
+    }
+
+    /// 最多取出`len`字节, 规范模式下一次只读一行; None表示没有数据, 空Vec表示文件尾
+    fn take_input(&mut self, len: usize) -> Option<Vec<u8>> {
+        let canonical = self.termios.lflag(ICANON);
+        let mut data = Vec::new();
+        while data.len() < len {
+            let front = match self.input.front_mut() {
+                Some(front) => front,
+                None => break,
+            };
+            if front.is_empty() {
+                // VEOF只在规范模式下有意义, 前面已经读到数据时留给下一次
+                if data.is_empty() {
+                    self.input.pop_front();
+                    return Some(data);
+                }
+                break;
+            }
+            let n = front.len().min(len - data.len());
+            data.extend(front.drain(..n));
+            if front.is_empty() {
+                self.input.pop_front();
+                if canonical {
+                    break;
+                }
+            }
+        }
+        if data.is_empty() {
+            None
+        } else {
+            Some(data)
+        }
+    }
+
+    fn flush_input(&mut self) {
+        self.line.clear();
+        self.input.clear();
+    }
+}
+
+pub struct Tty {
+    inner: UPIntrFreeCell<TtyInner>,
+    driver: Arc<dyn TtyDriver>,
+    /// 等输入的读者
+    condvar: Condvar,
+}
+
+impl Tty {
+    pub fn new(driver: Arc<dyn TtyDriver>, session: Option<usize>) -> Self {
+        Self {
+            inner: unsafe {
+                UPIntrFreeCell::new(TtyInner {
+                    termios: Termios::default(),
+                    winsize: WinSize::default(),
+                    session,
+                    foreground: session.unwrap_or(0),
+                    line: Vec::new(),
+                    input: VecDeque::new(),
+                    hung_up: false,
+                })
+            },
+            driver,
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn has_input(&self) -> bool {
+        self.inner.exclusive_access().has_input()
+    }
+
+    /// 按c_oflag处理后交给驱动
+    fn output(&self, termios: &Termios, data: &[u8]) {
+        if termios.c_oflag & OPOST == 0 || termios.c_oflag & ONLCR == 0 {
+            self.driver.write(data);
+            return;
+        }
+        let mut out = Vec::with_capacity(data.len());
+        for &ch in data {
+            if ch == b'\n' {
+                out.push(b'\r');
+            }
+            out.push(ch);
+        }
+        self.driver.write(&out);
+    }
+
+    /// ECHOCTL时控制字符显示成^X
+    fn echo(&self, termios: &Termios, ch: u8) {
+        let is_ctl = (ch < 0x20 && ch != b'\n' && ch != b'\t') || ch == 0x7f;
+        if is_ctl && termios.lflag(ECHOCTL) {
+            self.output(termios, &[b'^', ch ^ 0x40]);
+        } else {
+            self.output(termios, &[ch]);
+        }
+    }
+
+    /// 回显里擦掉`n`个字符
+    fn echo_erase(&self, termios: &Termios, n: usize) {
+        for _ in 0..n {
+            self.output(termios, b"\x08 \x08");
+        }
+    }
+
+    /// 驱动收到一个字符, 可能在中断里调用
+    pub fn receive(&self, ch: u8) {
+        let mut inner = self.inner.exclusive_access();
+        let termios = inner.termios;
+        let mut ch = ch;
+        if termios.c_iflag & ISTRIP != 0 {
+            ch &= 0x7f;
+        }
+        if ch == b'\r' {
+            if termios.c_iflag & IGNCR != 0 {
+                return;
+            }
+            if termios.c_iflag & ICRNL != 0 {
+                ch = b'\n';
+            }
+        } else if ch == b'\n' && termios.c_iflag & INLCR != 0 {
+            ch = b'\r';
+        }
+        if termios.lflag(ISIG) {
+            let signal = if termios.is_cc(VINTR, ch) {
+                Some(SignalFlags::SIGINT)
+            } else if termios.is_cc(VQUIT, ch) {
+                Some(SignalFlags::SIGQUIT)
+            } else if termios.is_cc(VSUSP, ch) {
+                Some(SignalFlags::SIGTSTP)
+            } else {
+                None
+            };
+            if let Some(signal) = signal {
+                if !termios.lflag(NOFLSH) {
+                    inner.flush_input();
+                }
+                if termios.lflag(ECHO) {
+                    self.echo(&termios, ch);
+                }
+                let foreground = inner.foreground;
+                drop(inner);
+                send_signal_to_group(foreground, signal);
+                // 叫醒读者, 让它们去处理信号
+                self.condvar.signal_all();
+                return;
+            }
+        }
+        let echo = termios.lflag(ECHO);
+        if !termios.lflag(ICANON) {
+            inner.push_raw(ch);
+            if echo {
+                self.echo(&termios, ch);
+            }
+            drop(inner);
+            self.condvar.signal_all();
+            return;
+        }
+        if termios.is_cc(VERASE, ch) {
+            if inner.line.pop().is_some() && echo && termios.lflag(ECHOE) {
+                self.echo_erase(&termios, 1);
+            }
+        } else if termios.is_cc(VKILL, ch) {
+            let erased = inner.line.len();
+            inner.line.clear();
+            if echo && termios.lflag(ECHOKE) {
+                self.echo_erase(&termios, erased);
+            } else if echo && termios.lflag(ECHOK) {
+                self.echo(&termios, ch);
+                self.output(&termios, b"\n");
+            }
+        } else if termios.is_cc(VWERASE, ch) && termios.lflag(IEXTEN) {
+            let line = &mut inner.line;
+            let mut erased = 0;
+            while line.last() == Some(&b' ') {
+                line.pop();
+                erased += 1;
+            }
+            while line.last().map_or(false, |c| *c != b' ') {
+                line.pop();
+                erased += 1;
+            }
+            if echo && termios.lflag(ECHOE) {
+                self.echo_erase(&termios, erased);
+            }
+        } else if termios.is_cc(VEOF, ch) {
+            // 行里没有内容时就是一个空项, 读者读到0
+            let line = core::mem::take(&mut inner.line);
+            inner.input.push_back(line);
+            drop(inner);
+            self.condvar.signal_all();
+        } else {
+            inner.line.push(ch);
+            if echo || (ch == b'\n' && termios.lflag(ECHONL)) {
+                self.echo(&termios, ch);
+            }
+            if ch == b'\n' || termios.is_cc(VEOL, ch) {
+                let line = core::mem::take(&mut inner.line);
+                inner.input.push_back(line);
+                drop(inner);
+                self.condvar.signal_all();
+            }
+        }
+    }
+
+    /// 后台进程组读写终端时给整个组发`signal`, 等被SIGCONT后再试; 信号被屏蔽时返回false
+    fn wait_for_foreground(&self, signal: SignalFlags) -> bool {
+        loop {
+            let process = current_process();
+            let inner = process.inner_exclusive_access();
+            let (pgid, sid) = (inner.pgid, inner.sid);
+            let masked = inner.signal_mask.contains(signal);
+            drop(inner);
+            let tty = self.inner.exclusive_access();
+            if tty.session != Some(sid) || tty.foreground == pgid {
+                return true;
+            }
+            drop(tty);
+            if masked {
+                return false;
+            }
+            send_signal_to_group(pgid, signal);
+            handle_stop_signals_of_current();
+        }
+    }
+
+    /// pty的master关掉了: 之后读到文件尾, 前台进程组收到SIGHUP
+    pub fn hangup(&self) {
+        let mut inner = self.inner.exclusive_access();
+        inner.hung_up = true;
+        let foreground = inner.session.take().map(|_| inner.foreground);
+        drop(inner);
+        // master可能是在持有进程inner时被关掉的, 信号留到下次时钟中断再发
+        if let Some(foreground) = foreground {
+            add_timer_callback(0, move || {
+                send_signal_to_group(foreground, SignalFlags::SIGHUP | SignalFlags::SIGCONT);
+            });
+        }
+        self.condvar.signal_all();
+    }
+
+    /// TCSETS系列, TCSETSF还要先丢掉没读的输入
+    fn set_termios(&self, request: usize, termios: Termios) {
+        let mut inner = self.inner.exclusive_access();
+        if request == TCSETSF {
+            inner.flush_input();
+        }
+        // 离开规范模式时没写完的一行也可以读了
+        if !termios.lflag(ICANON) && !inner.line.is_empty() {
+            let line = core::mem::take(&mut inner.line);
+            inner.input.push_back(line);
+        }
+        inner.termios = termios;
+        drop(inner);
+        self.condvar.signal_all();
+    }
+}
+
+impl File for Tty {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        if buf.len() == 0 || !self.wait_for_foreground(SignalFlags::SIGTTIN) {
+            return 0;
+        }
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            if let Some(data) = inner.take_input(buf.len()) {
+                drop(inner);
+                return buf.write(&data);
+            }
+            // VMIN为0时不等待, VTIME没有实现
+            if inner.hung_up || (!inner.termios.lflag(ICANON) && inner.termios.c_cc[VMIN] == 0) {
+                return 0;
+            }
+            let task_cx_ptr = self.condvar.wait_no_sched();
+            drop(inner);
+            schedule(task_cx_ptr);
+            // 等待时被Ctrl-Z停下的话在这里停住, 被Ctrl-C的话回去退出
+            handle_stop_signals_of_current();
+            if check_signals_of_current().is_some() {
+                return 0;
+            }
+        }
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        let inner = self.inner.exclusive_access();
+        let (termios, hung_up) = (inner.termios, inner.hung_up);
+        drop(inner);
+        if hung_up {
+            return 0;
+        }
+        if termios.lflag(TOSTOP) && !self.wait_for_foreground(SignalFlags::SIGTTOU) {
+            return 0;
+        }
+        for buffer in buf.buffers.iter() {
+            self.output(&termios, buffer);
+        }
+        buf.len()
+    }
+
+    fn poll(&self) -> PollEvents {
+        let inner = self.inner.exclusive_access();
+        if inner.hung_up {
+            PollEvents::POLLIN | PollEvents::POLLHUP
+        } else if inner.has_input() {
+            PollEvents::POLLIN | PollEvents::POLLOUT
+        } else {
+            PollEvents::POLLOUT
+        }
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> isize {
+        let token = current_user_token();
+        let process = current_process();
+        let inner = process.inner_exclusive_access();
+        let (pid, pgid, sid) = (process.getpid(), inner.pgid, inner.sid);
+        drop(inner);
+        match request {
+            TCGETS => {
+                let termios = self.inner.exclusive_access().termios;
+                *translated_refmut(token, arg as *mut Termios) = termios;
+            }
+            TCSETS | TCSETSW | TCSETSF => {
+                let termios = *translated_ref(token, arg as *const Termios);
+                self.set_termios(request, termios);
+            }
+            // 只有输入队列可以丢
+            TCFLSH => {
+                if arg != 1 {
+                    self.inner.exclusive_access().flush_input();
+                }
+            }
+            TIOCGWINSZ => {
+                let winsize = self.inner.exclusive_access().winsize;
+                *translated_refmut(token, arg as *mut WinSize) = winsize;
+            }
+            TIOCSWINSZ => {
+                let winsize = *translated_ref(token, arg as *const WinSize);
+                let mut inner = self.inner.exclusive_access();
+                inner.winsize = winsize;
+                let foreground = inner.foreground;
+                drop(inner);
+                send_signal_to_group(foreground, SignalFlags::SIGWINCH);
+            }
+            TIOCGPGRP => {
+                let inner = self.inner.exclusive_access();
+                if inner.session != Some(sid) {
+                    return -1;
+                }
+                let foreground = inner.foreground as i32;
+                drop(inner);
+                *translated_refmut(token, arg as *mut i32) = foreground;
+            }
+            TIOCSPGRP => {
+                let foreground = *translated_ref(token, arg as *const i32);
+                let mut inner = self.inner.exclusive_access();
+                if inner.session != Some(sid) || foreground < 0 {
+                    return -1;
+                }
+                inner.foreground = foreground as usize;
+            }
+            // 只有会话首进程可以把还没有主人的终端设成自己的控制终端
+            TIOCSCTTY => {
+                let mut inner = self.inner.exclusive_access();
+                if sid != pid || inner.session.map_or(false, |session| session != sid) {
+                    return -1;
+                }
+                inner.session = Some(sid);
+                inner.foreground = pgid;
+            }
+            TIOCNOTTY => {
+                let mut inner = self.inner.exclusive_access();
+                if inner.session != Some(sid) {
+                    return -1;
+                }
+                if sid == pid {
+                    // 会话首进程放弃终端, 前台进程组收到SIGHUP
+                    let foreground = inner.foreground;
+                    inner.session = None;
+                    drop(inner);
+                    send_signal_to_group(foreground, SignalFlags::SIGHUP | SignalFlags::SIGCONT);
+                }
+            }
+            _ => return -1,
+        }
+        0
+    }
+}
+
+/// 控制台的输出直接写串口
+struct UartTtyDriver;
+
+impl TtyDriver for UartTtyDriver {
+    fn write(&self, data: &[u8]) {
+        for &ch in data {
+            UART.write(ch);
+        }
+    }
+}
+
+lazy_static! {
+    /// 串口上的控制台, 开机时交给initproc(pid 0)的会话
+    pub static ref CONSOLE: Arc<Tty> = Arc::new(Tty::new(Arc::new(UartTtyDriver), Some(0)));
+}
+
+/// 串口中断之后把收到的字符交给控制台的行规程
+pub fn console_interrupt() {
+    while !UART.read_buffer_is_empty() {
+        CONSOLE.receive(UART.read());
+    }
+}
@@ -4,7 +4,7 @@
 #![feature(alloc_error_handler)]
 
 //use crate::drivers::{GPU_DEVICE, KEYBOARD_DEVICE, MOUSE_DEVICE, INPUT_CONDVAR};
-use crate::drivers::{GPU_DEVICE, KEYBOARD_DEVICE, MOUSE_DEVICE};
+use crate::drivers::{GPU_DEVICE, KEYBOARD_DEVICE, MOUSE_DEVICE, NET_DEVICES};
 extern crate alloc;
 
 #[macro_use]
@@ -17,6 +17,7 @@ mod board;
 mod console;
 mod config;
 mod drivers;
+mod fdt;
 mod fs;
 mod lang_items;
 mod mm;
@@ -54,8 +55,10 @@ lazy_static! {
 }
 
 #[no_mangle]
-pub fn rust_main() -> ! {
+pub fn rust_main(hart_id: usize, dtb: usize) -> ! {
     clear_bss();
+    board::init_boot_hart(hart_id);
+    fdt::init(dtb);
     mm::init();
     UART.init();
     println!("KERN: init gpu");
@@ -64,11 +67,15 @@ pub fn rust_main() -> ! {
     let _keyboard = KEYBOARD_DEVICE.clone();
     println!("KERN: init mouse");
     let _mouse = MOUSE_DEVICE.clone();
+    println!("KERN: init net");
+    let _net = NET_DEVICES.len();
     println!("KERN: init trap");
     trap::init();
     trap::enable_timer_interrupt();
     timer::set_next_trigger();
     board::device_init();
+    timer::init_realtime();
+    net::init();
     fs::init();
     task::add_initproc();
     *DEV_NON_BLOCKING_ACCESS.exclusive_access() = true;
@@ -1,8 +1,9 @@
 use super::{PhysAddr, PhysPageNum};
-use crate::config::MEMORY_END;
+use crate::config::{MEMORY_END, PAGE_SIZE};
 use crate::sync::UPIntrFreeCell;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use lazy_static::*;
 
 pub struct FrameTracker {
@@ -35,97 +36,371 @@ impl Drop for FrameTracker {
 trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
-    fn alloc_more(&mut self, pages: usize) -> Option<Vec<PhysPageNum>>;
+    /// 连续的`pages`页, 返回第一页
+    fn alloc_contiguous(&mut self, pages: usize) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
 }
 
-pub struct StackFrameAllocator {
-    current: usize,
+/// 最大的块是2^MAX_ORDER页
+pub const MAX_ORDER: usize = 10;
+pub const NR_ZONES: usize = 2;
+/// 4GiB以下的物理内存, 只能访问32位地址的设备做DMA用
+pub const ZONE_DMA32: usize = 0;
+pub const ZONE_NORMAL: usize = 1;
+const ZONE_NAMES: [&str; NR_ZONES] = ["DMA32", "Normal"];
+const DMA32_END: usize = 0x1_0000_0000 / PAGE_SIZE;
+
+/// 页的元数据: 空闲块的第一页记PAGE_FREE | order, 其余的页是0
+const PAGE_FREE: u8 = 0x80;
+
+#[derive(Clone, Copy)]
+struct FreeArea {
+    /// 空闲块的双向链表头, 0表示空(物理内存不从0开始)
+    head: usize,
+    count: usize,
+}
+
+/// buddy分配器: 块按自身大小对齐, 释放时和伙伴块合并.
+/// 空闲块的链表指针存在块的第一页里, 每页一字节的元数据放在管理的内存开头,
+/// 分配器自己不用内核堆, 内核堆不够时才能从这里拿页
+pub struct BuddyFrameAllocator {
+    /// 元数据覆盖的ppn范围
+    start: usize,
     end: usize,
-    recycled: Vec<usize>,
+    meta: usize,
+    free_area: [[FreeArea; MAX_ORDER + 1]; NR_ZONES],
+    managed: [usize; NR_ZONES],
+    allocs: usize,
+    failures: usize,
 }
 
-impl StackFrameAllocator {
-    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l.0;
-        self.end = r.0;
-        // println!("last {} Physical Frames.", self.end - self.current);
+fn zone_of(ppn: usize) -> usize {
+    if ppn < DMA32_END {
+        ZONE_DMA32
+    } else {
+        ZONE_NORMAL
     }
 }
-impl FrameAllocator for StackFrameAllocator {
-    fn new() -> Self {
-        Self {
-            current: 0,
-            end: 0,
-            recycled: Vec::new(),
+
+/// 能放下`pages`页的最小阶
+fn order_of(pages: usize) -> usize {
+    pages.next_power_of_two().trailing_zeros() as usize
+}
+
+impl BuddyFrameAllocator {
+    /// 管理[l, r)的物理页, 元数据占掉开头的几页, 之后用add_range加入可用的页
+    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) -> PhysPageNum {
+        self.start = l.0;
+        self.end = r.0;
+        self.meta = PhysAddr::from(l).0;
+        let meta_pages = (self.end - self.start + PAGE_SIZE - 1) / PAGE_SIZE;
+        unsafe {
+            core::slice::from_raw_parts_mut(self.meta as *mut u8, self.end - self.start).fill(0);
         }
+        PhysPageNum(self.start + meta_pages)
     }
-    fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else if self.current == self.end {
-            None
+
+    /// 把[l, r)的页加入分配器
+    pub fn add_range(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        let (l, r) = (l.0.max(self.start), r.0.min(self.end));
+        if l >= r {
+            return;
+        }
+        self.managed[ZONE_DMA32] += r.min(DMA32_END).saturating_sub(l);
+        self.managed[ZONE_NORMAL] += r.saturating_sub(l.max(DMA32_END));
+        self.free_range(l, r);
+    }
+
+    fn meta(&self, ppn: usize) -> &'static mut u8 {
+        unsafe { &mut *((self.meta + ppn - self.start) as *mut u8) }
+    }
+
+    /// 空闲块里的(next, prev)
+    fn links(ppn: usize) -> &'static mut [usize; 2] {
+        let pa: PhysAddr = PhysPageNum(ppn).into();
+        unsafe { &mut *(pa.0 as *mut [usize; 2]) }
+    }
+
+    fn push(&mut self, ppn: usize, order: usize) {
+        let area = &mut self.free_area[zone_of(ppn)][order];
+        *Self::links(ppn) = [area.head, 0];
+        if area.head != 0 {
+            Self::links(area.head)[1] = ppn;
+        }
+        area.head = ppn;
+        area.count += 1;
+        *self.meta(ppn) = PAGE_FREE | order as u8;
+    }
+
+    fn remove(&mut self, ppn: usize, order: usize) {
+        let [next, prev] = *Self::links(ppn);
+        let area = &mut self.free_area[zone_of(ppn)][order];
+        if prev != 0 {
+            Self::links(prev)[0] = next;
         } else {
-            self.current += 1;
-            Some((self.current - 1).into())
+            area.head = next;
+        }
+        if next != 0 {
+            Self::links(next)[1] = prev;
+        }
+        area.count -= 1;
+        *self.meta(ppn) = 0;
+    }
+
+    /// 释放一个块, 能合并就和伙伴合并成更大的块.
+    /// 4GiB按最大的块对齐, 合并不会跨zone
+    fn free_block(&mut self, mut ppn: usize, mut order: usize) {
+        while order < MAX_ORDER {
+            let buddy = ppn ^ (1 << order);
+            if buddy < self.start
+                || buddy + (1 << order) > self.end
+                || *self.meta(buddy) != PAGE_FREE | order as u8
+            {
+                break;
+            }
+            self.remove(buddy, order);
+            ppn = ppn.min(buddy);
+            order += 1;
+        }
+        self.push(ppn, order);
+    }
+
+    /// 把[l, r)切成尽量大的对齐块释放
+    fn free_range(&mut self, mut l: usize, r: usize) {
+        while l < r {
+            let mut order = (l.trailing_zeros() as usize).min(MAX_ORDER);
+            while l + (1 << order) > r {
+                order -= 1;
+            }
+            self.free_block(l, order);
+            l += 1 << order;
+        }
+    }
+
+    /// 从`zone`里拿一个2^order页的块, 大块拆开后剩下的一半放回去
+    fn alloc_block(&mut self, zone: usize, order: usize) -> Option<usize> {
+        let mut current = (order..=MAX_ORDER).find(|&o| self.free_area[zone][o].head != 0)?;
+        let ppn = self.free_area[zone][current].head;
+        self.remove(ppn, current);
+        while current > order {
+            current -= 1;
+            self.push(ppn + (1 << current), current);
         }
+        Some(ppn)
     }
-    fn alloc_more(&mut self, pages: usize) -> Option<Vec<PhysPageNum>> {
-        if self.current + pages >= self.end {
+
+    /// 依次尝试`zones`, 多出来的尾部还回去, 每一页都可以单独释放
+    fn alloc_pages(&mut self, zones: &[usize], pages: usize) -> Option<PhysPageNum> {
+        let order = order_of(pages);
+        let ppn = if pages == 0 || order > MAX_ORDER {
             None
         } else {
-            self.current += pages;
-            let arr: Vec<usize> = (1..pages + 1).collect();
-            let v = arr.iter().map(|x| (self.current - x).into()).collect();
-            Some(v)
+            zones.iter().find_map(|&zone| self.alloc_block(zone, order))
+        };
+        match ppn {
+            Some(ppn) => {
+                self.free_range(ppn + pages, ppn + (1 << order));
+                self.allocs += 1;
+                Some(ppn.into())
+            }
+            None => {
+                self.failures += 1;
+                None
+            }
         }
     }
+
+    /// 只从DMA32里分配
+    pub fn alloc_dma(&mut self, pages: usize) -> Option<PhysPageNum> {
+        self.alloc_pages(&[ZONE_DMA32], pages)
+    }
+
+    /// 还没分配出去的页数
+    pub fn free_pages(&self) -> usize {
+        self.free_area
+            .iter()
+            .flat_map(|zone| zone.iter().enumerate())
+            .map(|(order, area)| area.count << order)
+            .sum()
+    }
+
+    fn stats(&self) -> FrameStats {
+        let mut stats = FrameStats {
+            zones: [ZoneStats::default(); NR_ZONES],
+            allocs: self.allocs,
+            failures: self.failures,
+        };
+        for (zone, zone_stats) in stats.zones.iter_mut().enumerate() {
+            zone_stats.name = ZONE_NAMES[zone];
+            zone_stats.managed = self.managed[zone];
+            for (order, area) in self.free_area[zone].iter().enumerate() {
+                zone_stats.free_blocks[order] = area.count;
+                zone_stats.free += area.count << order;
+            }
+        }
+        stats
+    }
+}
+
+impl FrameAllocator for BuddyFrameAllocator {
+    fn new() -> Self {
+        Self {
+            start: 0,
+            end: 0,
+            meta: 0,
+            free_area: [[FreeArea { head: 0, count: 0 }; MAX_ORDER + 1]; NR_ZONES],
+            managed: [0; NR_ZONES],
+            allocs: 0,
+            failures: 0,
+        }
+    }
+    fn alloc(&mut self) -> Option<PhysPageNum> {
+        self.alloc_contiguous(1)
+    }
+    /// 优先用Normal, 把DMA32留给设备
+    fn alloc_contiguous(&mut self, pages: usize) -> Option<PhysPageNum> {
+        self.alloc_pages(&[ZONE_NORMAL, ZONE_DMA32], pages)
+    }
     fn dealloc(&mut self, ppn: PhysPageNum) {
         let ppn = ppn.0;
-        // validity check
-        if ppn >= self.current || self.recycled.iter().any(|&v| v == ppn) {
+        // validity check, 空闲块中间的页不再逐个检查
+        if ppn < self.start || ppn >= self.end || *self.meta(ppn) & PAGE_FREE != 0 {
             panic!("Frame ppn={:#x} has not been allocated!", ppn);
         }
-        // recycle
-        self.recycled.push(ppn);
+        self.free_block(ppn, 0);
     }
 }
 
-type FrameAllocatorImpl = StackFrameAllocator;
+type FrameAllocatorImpl = BuddyFrameAllocator;
 
 lazy_static! {
     pub static ref FRAME_ALLOCATOR: UPIntrFreeCell<FrameAllocatorImpl> =
         unsafe { UPIntrFreeCell::new(FrameAllocatorImpl::new()) };
 }
 
+/// 物理内存的结束地址, 有设备树时以设备树为准
+static MEMORY_END_PA: AtomicUsize = AtomicUsize::new(MEMORY_END);
+
+pub fn memory_end() -> usize {
+    MEMORY_END_PA.load(Ordering::Relaxed)
+}
+
 pub fn init_frame_allocator() {
     extern "C" {
         fn ekernel();
     }
-    FRAME_ALLOCATOR.exclusive_access().init(
-        PhysAddr::from(ekernel as usize).ceil(),
-        PhysAddr::from(MEMORY_END).floor(),
+    let fdt = crate::fdt::fdt();
+    if let Some((_, end)) = fdt.and_then(|fdt| fdt.memory_region(ekernel as usize)) {
+        MEMORY_END_PA.store(end, Ordering::Relaxed);
+    }
+    let l = PhysAddr::from(ekernel as usize).ceil();
+    let r = PhysAddr::from(memory_end()).floor();
+    let mut allocator = FRAME_ALLOCATOR.exclusive_access();
+    let l = allocator.init(l, r);
+    // 设备树一般放在内存的末尾, 不能分配出去
+    match fdt.map(|fdt| fdt.range()) {
+        Some((dtb_start, dtb_end)) => {
+            let dtb_start = PhysAddr::from(dtb_start).floor().0.clamp(l.0, r.0);
+            let dtb_end = PhysAddr::from(dtb_end).ceil().0.clamp(l.0, r.0);
+            allocator.add_range(l, dtb_start.into());
+            allocator.add_range(dtb_end.into(), r);
+        }
+        None => allocator.add_range(l, r),
+    }
+    println!(
+        "[kernel] physical memory ends at {:#x}, {} frames free",
+        memory_end(),
+        allocator.free_pages()
     );
 }
 
+/// 分配物理页失败过, 回用户态前由OOM killer处理
+static OUT_OF_MEMORY: AtomicBool = AtomicBool::new(false);
+
+/// 取出并清掉分配失败的标记
+pub fn take_out_of_memory() -> bool {
+    OUT_OF_MEMORY.swap(false, Ordering::Relaxed)
+}
+
+/// 内核堆彻底分配不出来时标记
+pub fn set_out_of_memory() {
+    OUT_OF_MEMORY.store(true, Ordering::Relaxed);
+}
+
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR
+    let frame = FRAME_ALLOCATOR
         .exclusive_access()
         .alloc()
-        .map(FrameTracker::new)
+        .map(FrameTracker::new);
+    if frame.is_none() {
+        OUT_OF_MEMORY.store(true, Ordering::Relaxed);
+    }
+    frame
 }
 
+/// 连续的`num`页, 按ppn从高到低排列, 最后一个是第一页.
+/// `num`是2的幂时第一页按`num`页对齐
 pub fn frame_alloc_more(num: usize) -> Option<Vec<FrameTracker>> {
-    FRAME_ALLOCATOR
-        .exclusive_access()
-        .alloc_more(num)
-        .map(|x| x.iter().map(|&t| FrameTracker::new(t)).collect())
+    let base = FRAME_ALLOCATOR.exclusive_access().alloc_contiguous(num);
+    match base {
+        Some(base) => Some(
+            (0..num)
+                .rev()
+                .map(|i| FrameTracker::new(PhysPageNum(base.0 + i)))
+                .collect(),
+        ),
+        None => {
+            OUT_OF_MEMORY.store(true, Ordering::Relaxed);
+            None
+        }
+    }
 }
 
 pub fn frame_dealloc(ppn: PhysPageNum) {
     FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
 }
 
+/// 给内核堆和slab用的页, 不清零也不用FrameTracker管理, 用frame_dealloc还回来.
+/// 分配器这时不能再去分配内核堆, 所以这里不会递归.
+/// 失败时不标记OOM: slab拿不到页还能退回buddy堆, 由内核堆最终分配失败时标记
+pub fn frame_alloc_raw(pages: usize) -> Option<PhysPageNum> {
+    FRAME_ALLOCATOR.exclusive_access().alloc_contiguous(pages)
+}
+
+/// 设备DMA用的4GiB以下的连续页, 已清零, 用frame_dealloc逐页还回来
+pub fn frame_alloc_dma(pages: usize) -> Option<PhysPageNum> {
+    let base = FRAME_ALLOCATOR.exclusive_access().alloc_dma(pages)?;
+    unsafe {
+        core::ptr::write_bytes(PhysAddr::from(base).0 as *mut u8, 0, pages * PAGE_SIZE);
+    }
+    Some(base)
+}
+
+/// 帧分配器里剩余的页数
+pub fn free_frames() -> usize {
+    FRAME_ALLOCATOR.exclusive_access().free_pages()
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct ZoneStats {
+    pub name: &'static str,
+    pub managed: usize,
+    pub free: usize,
+    /// 每一阶的空闲块数, 同/proc/buddyinfo
+    pub free_blocks: [usize; MAX_ORDER + 1],
+}
+
+/// 调试用的统计, 见/proc/buddyinfo
+pub struct FrameStats {
+    pub zones: [ZoneStats; NR_ZONES],
+    pub allocs: usize,
+    pub failures: usize,
+}
+
+pub fn frame_stats() -> FrameStats {
+    FRAME_ALLOCATOR.exclusive_access().stats()
+}
+
 #[allow(unused)]
 pub fn frame_allocator_test() {
     let mut v: Vec<FrameTracker> = Vec::new();
@@ -144,9 +419,12 @@ pub fn frame_allocator_test() {
     println!("frame_allocator_test passed!");
 }
 
+/// 把不归分配器管的一页交给它, 比如启动时用完的内存
 #[allow(unused)]
 pub fn add_free(ppn: usize) {
-    FRAME_ALLOCATOR.exclusive_access().recycled.push(ppn)
+    FRAME_ALLOCATOR
+        .exclusive_access()
+        .add_range(PhysPageNum(ppn), PhysPageNum(ppn + 1))
 }
 
 #[allow(unused)]
@@ -158,7 +436,9 @@ pub fn frame_allocator_alloc_more_test() {
     }
     v.extend(frames);
     v.clear();
-    let frames = frame_alloc_more(5).unwrap();
+    let frames = frame_alloc_more(8).unwrap();
+    // 2的幂页按自身大小对齐
+    assert_eq!(frames.last().unwrap().ppn.0 % 8, 0);
     for frame in &frames {
         println!("{:?}", frame);
     }
@@ -1,8 +1,18 @@
-use crate::config::KERNEL_HEAP_SIZE;
-use buddy_system_allocator::LockedHeap;
+use super::frame_allocator::{frame_alloc_raw, set_out_of_memory, MAX_ORDER};
+use super::slab::{slab_alloc, slab_dealloc};
+use super::PhysAddr;
+use crate::config::{KERNEL_HEAP_SIZE, PAGE_SIZE};
+use buddy_system_allocator::Heap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
+use crate::sync::IntrFreeMutex;
+
+/// 小对象走slab, 其余的走buddy堆. buddy堆一开始是bss里的HEAP_SPACE,
+/// 不够时从帧分配器拿连续的页扩充
+struct KernelAllocator;
 
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: KernelAllocator = KernelAllocator;
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -11,11 +21,120 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
 
 static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 
+/// buddy堆每次至少扩充这么多页
+const HEAP_GROW_PAGES: usize = 256;
+/// 最多记这么多段不连续的内存
+const MAX_HEAP_REGIONS: usize = 64;
+
+struct KernelHeap {
+    buddy: Heap,
+    /// buddy堆管理的内存段, 释放时按地址区分是buddy的还是slab的
+    regions: [(usize, usize); MAX_HEAP_REGIONS],
+    nr_regions: usize,
+}
+
+static KERNEL_HEAP: IntrFreeMutex<KernelHeap> = IntrFreeMutex::new(KernelHeap {
+    buddy: Heap::empty(),
+    regions: [(0, 0); MAX_HEAP_REGIONS],
+    nr_regions: 0,
+});
+
+impl KernelHeap {
+    fn contains(&self, addr: usize) -> bool {
+        self.regions[..self.nr_regions]
+            .iter()
+            .any(|&(start, end)| start <= addr && addr < end)
+    }
+
+    /// 紧接着上一段的就合并成一段
+    unsafe fn add_region(&mut self, start: usize, end: usize) -> bool {
+        if self.nr_regions > 0 && self.regions[self.nr_regions - 1].1 == start {
+            self.regions[self.nr_regions - 1].1 = end;
+        } else if self.nr_regions < MAX_HEAP_REGIONS {
+            self.regions[self.nr_regions] = (start, end);
+            self.nr_regions += 1;
+        } else {
+            return false;
+        }
+        self.buddy.add_to_heap(start, end);
+        true
+    }
+
+    /// 从帧分配器拿一块连续的页加到buddy堆里, 拿到的页一定能切出`layout`.
+    /// 2的幂页的块按自身大小对齐, 块不比`layout`小就够了; 块最大2^MAX_ORDER页
+    fn grow(&mut self, layout: &Layout) -> bool {
+        let bytes = layout.size().max(layout.align()).next_power_of_two();
+        let pages = ((bytes + PAGE_SIZE - 1) / PAGE_SIZE).max(HEAP_GROW_PAGES);
+        if pages > 1 << MAX_ORDER {
+            return false;
+        }
+        let ppn = match frame_alloc_raw(pages) {
+            Some(ppn) => ppn,
+            None => return false,
+        };
+        let start = PhysAddr::from(ppn).0;
+        // 段表满了的话这些页就留在堆外, 不再还给帧分配器
+        unsafe { self.add_region(start, start + pages * PAGE_SIZE) }
+    }
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // 帧分配器还没初始化或者没有页了, 小对象也从buddy堆分配
+        if let Some(object) = slab_alloc(layout.size(), layout.align()) {
+            return object as *mut u8;
+        }
+        let mut heap = KERNEL_HEAP.lock();
+        if let Ok(ptr) = heap.buddy.alloc(layout) {
+            return ptr.as_ptr();
+        }
+        if heap.grow(&layout) {
+            if let Ok(ptr) = heap.buddy.alloc(layout) {
+                return ptr.as_ptr();
+            }
+        }
+        set_out_of_memory();
+        null_mut()
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut heap = KERNEL_HEAP.lock();
+        if heap.contains(ptr as usize) {
+            heap.buddy.dealloc(NonNull::new_unchecked(ptr), layout);
+        } else {
+            drop(heap);
+            slab_dealloc(ptr as usize, layout.size(), layout.align());
+        }
+    }
+}
+
 pub fn init_heap() {
     unsafe {
-        HEAP_ALLOCATOR
-            .lock()
-            .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
+        KERNEL_HEAP.lock().add_region(
+            HEAP_SPACE.as_ptr() as usize,
+            HEAP_SPACE.as_ptr() as usize + KERNEL_HEAP_SIZE,
+        );
+    }
+}
+
+/// buddy堆的统计, 见/proc/slabinfo
+pub struct HeapStats {
+    /// 堆里一共有多少字节, 包括扩充的
+    pub total: usize,
+    /// 按2的幂取整以后实际分出去的字节数
+    pub allocated: usize,
+    /// 调用者要求的字节数
+    pub requested: usize,
+    pub regions: usize,
+}
+
+pub fn heap_stats() -> HeapStats {
+    let heap = KERNEL_HEAP.lock();
+    HeapStats {
+        total: heap.buddy.stats_total_bytes(),
+        allocated: heap.buddy.stats_alloc_actual(),
+        requested: heap.buddy.stats_alloc_user(),
+        regions: heap.nr_regions,
     }
 }
 
@@ -28,9 +147,9 @@ pub fn heap_test() {
         fn ebss();
     }
     let bss_range = sbss as usize..ebss as usize;
+    // 小对象在slab里, 不在bss上
     let a = Box::new(5);
     assert_eq!(*a, 5);
-    assert!(bss_range.contains(&(a.as_ref() as *const _ as usize)));
     drop(a);
     let mut v: Vec<usize> = Vec::new();
     for i in 0..500 {
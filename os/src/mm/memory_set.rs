@@ -1,16 +1,22 @@
-use super::{frame_alloc, FrameTracker, UserBuffer, translated_byte_buffer};
+use super::{frame_alloc, memory_end, FrameTracker, UserBuffer, translated_byte_buffer};
 use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
-use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, USER_STACK_BASE};
-use crate::fs::{FileDescriptor, File};
+use super::{align_up, StepByOne, VPNRange};
+use crate::config::{
+    ELF_DYN_BASE, ELF_INTERP_BASE, FDT_MMIO_COMPATIBLE, MEMORY_MAP_BASE, MMIO, PAGE_SIZE,
+    TRAMPOLINE, USER_STACK_BASE,
+};
+use crate::fdt::fdt;
+use crate::fs::{open_file, File, FileDescriptor, FileType, OSInode, OpenFlags};
 use crate::sync::UPIntrFreeCell;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::arch::asm;
 use lazy_static::*;
 use riscv::register::satp;
+use xmas_elf::{header, program, ElfFile};
 
 extern "C" {
     fn stext();
@@ -34,6 +40,74 @@ pub fn kernel_token() -> usize {
     KERNEL_SPACE.exclusive_access().token()
 }
 
+// auxv里用到的类型
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3;
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_BASE: usize = 7;
+pub const AT_FLAGS: usize = 8;
+pub const AT_ENTRY: usize = 9;
+pub const AT_RANDOM: usize = 25;
+
+/// PT_INTERP里解释器路径的最大长度
+const PATH_MAX: usize = 4096;
+
+/// 加载elf得到的信息, exec用来设置入口和初始栈上的auxv
+pub struct ElfInfo {
+    /// 开始执行的地址, 动态链接时是解释器的入口
+    pub entry: usize,
+    /// 程序自己的入口, AT_ENTRY
+    pub prog_entry: usize,
+    /// 程序头表在用户空间的地址, AT_PHDR
+    pub phdr: usize,
+    pub phent: usize,
+    pub phnum: usize,
+    /// 解释器的加载地址, 没有解释器时为0, AT_BASE
+    pub interp_base: usize,
+    pub heap_base: usize,
+    pub ustack_base: usize,
+}
+
+impl ElfInfo {
+    /// 除AT_RANDOM以外的auxv项, AT_RANDOM要等随机字节放到栈上以后才知道地址
+    pub fn auxv(&self) -> Vec<(usize, usize)> {
+        vec![
+            (AT_PHDR, self.phdr),
+            (AT_PHENT, self.phent),
+            (AT_PHNUM, self.phnum),
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_BASE, self.interp_base),
+            (AT_FLAGS, 0),
+            (AT_ENTRY, self.prog_entry),
+        ]
+    }
+}
+
+/// 程序头表最多读这么多, 再大的elf就不认了
+const ELF_HEAD_MAX: usize = 0x10000;
+
+/// 读elf头和程序头表, 够xmas_elf解析程序头就行
+fn read_elf_head(file: &OSInode) -> Option<Vec<u8>> {
+    let mut head = vec![0u8; 64];
+    if file.read_at(0, &mut head) < head.len() {
+        return None;
+    }
+    let elf_header = header::parse_header(&head).ok()?;
+    if elf_header.pt1.magic != [0x7f, 0x45, 0x4c, 0x46] {
+        return None;
+    }
+    let ph_end = elf_header.pt2.ph_offset() as usize
+        + elf_header.pt2.ph_count() as usize * elf_header.pt2.ph_entry_size() as usize;
+    if ph_end > ELF_HEAD_MAX {
+        return None;
+    }
+    head.resize(ph_end.max(head.len()), 0);
+    file.read_at(0, &mut head);
+    Some(head)
+}
+
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
@@ -42,28 +116,68 @@ pub struct MemorySet {
 }
 
 impl MemorySet {
-    pub fn new_bare() -> Self {
-        Self {
-            page_table: PageTable::new(),
+    /// 连根页表都分配不出来时返回None
+    pub fn new_bare() -> Option<Self> {
+        Some(Self {
+            page_table: PageTable::new()?,
             areas: Vec::new(),
             heap: BTreeMap::new(),
             mmap_areas: Vec::new(),
-        }
+        })
     }
     pub fn token(&self) -> usize {
         self.page_table.token()
     }
-    /// Assume that no conflicts.
+    /// Assume that no conflicts. false if out of memory
     pub fn insert_framed_area(
         &mut self,
         start_va: VirtAddr,
         end_va: VirtAddr,
         permission: MapPermission,
-    ) {
+    ) -> bool {
         self.push(
             MapArea::new(start_va, end_va, MapType::Framed, permission),
             None,
-        );
+        )
+    }
+    /// map frames some other address space may map too, e.g. a shared memory segment
+    pub fn insert_shared_area(
+        &mut self,
+        start_va: VirtAddr,
+        frames: Vec<Arc<FrameTracker>>,
+        permission: MapPermission,
+    ) -> bool {
+        self.push(MapArea::new_shared(start_va, frames, permission), None)
+    }
+    /// undo insert_shared_area, false if no shared area starts at `start_vpn`
+    pub fn remove_shared_area(&mut self, start_vpn: VirtPageNum) -> bool {
+        if let Some(idx) = self.areas.iter().position(|area| {
+            area.map_type == MapType::Shared && area.vpn_range.get_start() == start_vpn
+        }) {
+            self.areas[idx].unmap(&mut self.page_table);
+            self.areas.remove(idx);
+            true
+        } else {
+            false
+        }
+    }
+    /// first frame of the shared area at `start_vpn`, tells which segment it is
+    pub fn shared_area_frame(&self, start_vpn: VirtPageNum) -> Option<PhysPageNum> {
+        self.areas
+            .iter()
+            .find(|area| {
+                area.map_type == MapType::Shared && area.vpn_range.get_start() == start_vpn
+            })
+            .and_then(|area| area.shared_frames.get(&start_vpn))
+            .map(|frame| frame.ppn)
+    }
+    /// whether any page of [start_vpn, end_vpn) is mapped already
+    pub fn is_range_mapped(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        VPNRange::new(start_vpn, end_vpn).into_iter().any(|vpn| {
+            self.page_table
+                .translate(vpn)
+                .map_or(false, |pte| pte.is_valid())
+        })
     }
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self
@@ -76,24 +190,28 @@ impl MemorySet {
             self.areas.remove(idx);
         }
     }
-    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+    /// 物理页不够时什么都不映射, 返回false
+    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) -> bool {
+        if !map_area.map(&mut self.page_table) {
+            return false;
+        }
         if let Some(data) = data {
             map_area.copy_data(&mut self.page_table, data);
         }
         self.areas.push(map_area);
+        true
     }
     /// Mention that trampoline is not collected by areas.
-    fn map_trampoline(&mut self) {
+    fn map_trampoline(&mut self) -> bool {
         self.page_table.map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
             PTEFlags::R | PTEFlags::X,
-        );
+        )
     }
     /// Without kernel stacks.
     pub fn new_kernel() -> Self {
-        let mut memory_set = Self::new_bare();
+        let mut memory_set = Self::new_bare().unwrap();
         // map trampoline
         memory_set.map_trampoline();
         // map kernel sections
@@ -148,7 +266,7 @@ impl MemorySet {
         memory_set.push(
             MapArea::new(
                 (ekernel as usize).into(),
-                MEMORY_END.into(),
+                memory_end().into(),
                 MapType::Identical,
                 MapPermission::R | MapPermission::W,
             ),
@@ -166,63 +284,268 @@ impl MemorySet {
                 None,
             );
         }
+        // 设备树里的地址不一定在上面的表里, 表里已经映射的页跳过
+        if let Some(fdt) = fdt() {
+            for node in fdt
+                .nodes()
+                .filter(|node| FDT_MMIO_COMPATIBLE.iter().any(|c| node.is_compatible(c)))
+            {
+                for (base, size) in node.reg() {
+                    memory_set.map_identical_unmapped(base, base + size);
+                }
+            }
+        }
         memory_set
     }
-    /// Include sections in elf and trampoline,
-    /// also returns user_sp_base and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize, usize) {
-        let mut memory_set = Self::new_bare();
+
+    /// 恒等映射[start, end)里还没有映射的页, 内核映射设备寄存器用
+    fn map_identical_unmapped(&mut self, start: usize, end: usize) {
+        let mut run_start: Option<VirtPageNum> = None;
+        let end_vpn = VirtAddr::from(end).ceil();
+        let mut vpn = VirtAddr::from(start).floor();
+        while vpn <= end_vpn {
+            let mapped =
+                vpn == end_vpn || self.translate(vpn).map_or(false, |pte| pte.is_valid());
+            match (mapped, run_start) {
+                (false, None) => run_start = Some(vpn),
+                (true, Some(first)) => {
+                    self.push(
+                        MapArea::new(
+                            first.into(),
+                            vpn.into(),
+                            MapType::Identical,
+                            MapPermission::R | MapPermission::W,
+                        ),
+                        None,
+                    );
+                    run_start = None;
+                }
+                _ => {}
+            }
+            vpn.step();
+        }
+    }
+    /// [start_va, end_va)所在的页里是否已经有`areas`里的映射
+    fn overlaps(areas: &[MapArea], start_va: usize, end_va: usize) -> bool {
+        let start_vpn = VirtAddr::from(start_va).floor();
+        let end_vpn = VirtAddr::from(end_va).ceil();
+        areas.iter().any(|area| {
+            area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+        })
+    }
+    /// 同一个elf里相邻的两个段可以共用边界上的一页: 如果`areas[first..]`里已经映射了`vpn`,
+    /// 就把这一页拿出来单独成为一个区域, 权限取两个段的并集, 已经加载的数据保留
+    fn share_boundary_page(
+        &mut self,
+        first: usize,
+        vpn: VirtPageNum,
+        perm: MapPermission,
+    ) -> bool {
+        let index = match self.areas[first..]
+            .iter()
+            .position(|area| area.vpn_range.contain(vpn))
+        {
+            Some(i) => first + i,
+            None => return false,
+        };
+        let area = &mut self.areas[index];
+        let frame = area.data_frames.remove(&vpn).unwrap();
+        let (start, end) = (area.vpn_range.get_start(), area.vpn_range.get_end());
+        // 段之间字节不重叠, 共用的只能是原来那段的第一页或最后一页
+        area.vpn_range = if vpn == start {
+            VPNRange::new(VirtPageNum(start.0 + 1), end)
+        } else {
+            VPNRange::new(start, vpn)
+        };
+        let map_perm = area.map_perm | perm;
+        if area.vpn_range.get_start() == area.vpn_range.get_end() {
+            self.areas.remove(index);
+        }
+        self.page_table.unmap(vpn);
+        self.page_table.map(vpn, frame.ppn, PTEFlags::from_bits(map_perm.bits).unwrap());
+        let mut page = MapArea::new(
+            vpn.into(),
+            VirtPageNum(vpn.0 + 1).into(),
+            MapType::Framed,
+            map_perm,
+        );
+        page.data_frames.insert(vpn, frame);
+        self.areas.push(page);
+        true
+    }
+    /// 把文件里[offset, offset + len)读到从`start_va`开始已经映射好的页里
+    fn load_segment(&self, file: &OSInode, offset: usize, len: usize, start_va: usize) {
+        let mut start: usize = 0;
+        let mut page_offset = start_va % PAGE_SIZE;
+        let mut current_vpn = VirtAddr::from(start_va).floor();
+        while start < len {
+            let n = (PAGE_SIZE - page_offset).min(len - start);
+            let dst = &mut self
+                .page_table
+                .translate(current_vpn)
+                .unwrap()
+                .ppn()
+                .get_bytes_array()[page_offset..page_offset + n];
+            file.read_at(offset + start, dst);
+            start += n;
+            page_offset = 0;
+            current_vpn.step();
+        }
+    }
+    /// 把elf的PT_LOAD段映射到`bias`之上, 返回最高的结束地址.
+    /// 段不合法(超出文件, 互相重叠, 越过mmap区域)或者物理页不够时返回None
+    fn map_elf(&mut self, elf: &ElfFile, file: &OSInode, bias: usize) -> Option<usize> {
+        let mut max_end_va = 0;
+        // 这个elf之前已经有的区域, 以及已经加载的段的字节范围
+        let first = self.areas.len();
+        let mut segments: Vec<(usize, usize)> = Vec::new();
+        for ph in elf.program_iter() {
+            if !matches!(ph.get_type(), Ok(program::Type::Load)) {
+                continue;
+            }
+            let offset = ph.offset() as usize;
+            let file_size = ph.file_size() as usize;
+            let mem_size = ph.mem_size() as usize;
+            if file_size > mem_size || offset.checked_add(file_size)? > file.get_size() {
+                return None;
+            }
+            let start_va = bias.checked_add(ph.virtual_addr() as usize)?;
+            let end_va = start_va.checked_add(mem_size)?;
+            // 再往上是mmap区域和用户栈. 段之间只有字节重叠才算冲突, 共用一页是允许的
+            if end_va > MEMORY_MAP_BASE
+                || Self::overlaps(&self.areas[..first], start_va, end_va)
+                || segments.iter().any(|&(start, end)| start < end_va && start_va < end)
+            {
+                return None;
+            }
+            if mem_size == 0 {
+                continue;
+            }
+            let mut map_perm = MapPermission::U;
+            let ph_flags = ph.flags();
+            if ph_flags.is_read() {
+                map_perm |= MapPermission::R;
+            }
+            if ph_flags.is_write() {
+                map_perm |= MapPermission::W;
+            }
+            if ph_flags.is_execute() {
+                map_perm |= MapPermission::X;
+            }
+            let mut start_vpn = VirtAddr::from(start_va).floor();
+            let mut end_vpn = VirtAddr::from(end_va).ceil();
+            if self.share_boundary_page(first, start_vpn, map_perm) {
+                start_vpn.step();
+            }
+            let last_vpn = VirtPageNum(end_vpn.0 - 1);
+            if start_vpn < end_vpn && self.share_boundary_page(first, last_vpn, map_perm) {
+                end_vpn = last_vpn;
+            }
+            if start_vpn < end_vpn {
+                let mut map_area =
+                    MapArea::new(start_vpn.into(), end_vpn.into(), MapType::Framed, map_perm);
+                if !map_area.map(&mut self.page_table) {
+                    return None;
+                }
+                self.areas.push(map_area);
+            }
+            // 段的起始地址不一定页对齐, 数据要从页内偏移处开始放
+            self.load_segment(file, offset, file_size, start_va);
+            segments.push((start_va, end_va));
+            max_end_va = max_end_va.max(end_va);
+        }
+        Some(max_end_va)
+    }
+    /// Include sections in elf and trampoline, also returns what exec needs
+    /// to build the initial stack. None if the elf or its interpreter is bad,
+    /// or if out of memory.
+    /// 段直接从文件读进映射好的页, 不把整个文件读到内核堆里
+    pub fn from_elf(elf_file: &OSInode) -> Option<(Self, ElfInfo)> {
+        let mut memory_set = Self::new_bare()?;
         // map trampoline
-        memory_set.map_trampoline();
+        if !memory_set.map_trampoline() {
+            return None;
+        }
         // map program headers of elf, with U flag
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+        let elf_head = read_elf_head(elf_file)?;
+        let elf = ElfFile::new(&elf_head).ok()?;
         let elf_header = elf.header;
-        let magic = elf_header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
-        let ph_count = elf_header.pt2.ph_count();
-        let mut max_end_vpn = VirtPageNum(0);
-        for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-                let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
-                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
-                let mut map_perm = MapPermission::U;
-                let ph_flags = ph.flags();
-                if ph_flags.is_read() {
-                    map_perm |= MapPermission::R;
-                }
-                if ph_flags.is_write() {
-                    map_perm |= MapPermission::W;
-                }
-                if ph_flags.is_execute() {
-                    map_perm |= MapPermission::X;
-                }
-                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
-                max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+        // PIE放到固定的基址上, 非PIE按elf里的地址放
+        let bias = match elf_header.pt2.type_().as_type() {
+            header::Type::SharedObject => ELF_DYN_BASE,
+            _ => 0,
+        };
+        let max_end_va = memory_set.map_elf(&elf, elf_file, bias)?;
+        // 程序头表的位置, 优先用PT_PHDR, 否则找包含它的PT_LOAD段
+        let ph_offset = elf_header.pt2.ph_offset() as usize;
+        let phdr = elf
+            .program_iter()
+            .find_map(|ph| match ph.get_type() {
+                Ok(program::Type::Phdr) => Some(ph.virtual_addr() as usize),
+                _ => None,
+            })
+            .or_else(|| {
+                elf.program_iter().find_map(|ph| {
+                    let offset = ph.offset() as usize;
+                    let in_segment = matches!(ph.get_type(), Ok(program::Type::Load))
+                        && offset <= ph_offset
+                        && ph_offset < offset + ph.file_size() as usize;
+                    in_segment.then(|| ph.virtual_addr() as usize + ph_offset - offset)
+                })
+            })
+            .map_or(0, |va| bias + va);
+        let prog_entry = bias + elf_header.pt2.entry_point() as usize;
+        let mut info = ElfInfo {
+            entry: prog_entry,
+            prog_entry,
+            phdr,
+            phent: elf_header.pt2.ph_entry_size() as usize,
+            phnum: elf_header.pt2.ph_count() as usize,
+            interp_base: 0,
+            heap_base: align_up(max_end_va) + PAGE_SIZE,
+            ustack_base: USER_STACK_BASE,
+        };
+        // 动态链接的程序先跳到解释器(ld-musl/ld-linux), 由它来加载依赖的库
+        if let Some(interp) = elf
+            .program_iter()
+            .find(|ph| matches!(ph.get_type(), Ok(program::Type::Interp)))
+        {
+            // 解释器路径是以\0结尾的字符串
+            let len = interp.file_size() as usize;
+            if len == 0 || len > PATH_MAX {
+                return None;
             }
+            let mut path = vec![0u8; len];
+            if elf_file.read_at(interp.offset() as usize, &mut path) != len || path[len - 1] != 0 {
+                return None;
+            }
+            let path = core::str::from_utf8(&path[..len - 1]).ok()?;
+            let interp_file = open_file("/", path, OpenFlags::O_RDONLY, FileType::Regular)?;
+            let interp_head = read_elf_head(&interp_file)?;
+            let interp_elf = ElfFile::new(&interp_head).ok()?;
+            memory_set.map_elf(&interp_elf, &interp_file, ELF_INTERP_BASE)?;
+            info.interp_base = ELF_INTERP_BASE;
+            info.entry = ELF_INTERP_BASE + interp_elf.header.pt2.entry_point() as usize;
         }
-        let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_heap_base: usize = max_end_va.into();
-        user_heap_base += PAGE_SIZE;
-        (
-            memory_set,
-            user_heap_base,
-            USER_STACK_BASE,
-            elf.header.pt2.entry_point() as usize,
-        )
+        Some((memory_set, info))
     }
-    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
-        let mut memory_set = Self::new_bare();
+    /// None if out of memory
+    pub fn from_existed_user(user_space: &MemorySet) -> Option<MemorySet> {
+        let mut memory_set = Self::new_bare()?;
         // map trampoline
-        memory_set.map_trampoline();
+        if !memory_set.map_trampoline() {
+            return None;
+        }
         // copy data sections/trap_context/user_stack
         for area in user_space.areas.iter() {
             let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
+            if !memory_set.push(new_area, None) {
+                return None;
+            }
+            // shared areas map the same frames in both spaces
+            if area.map_type == MapType::Shared {
+                continue;
+            }
             // copy data from another space
             for vpn in area.vpn_range {
                 let src_ppn = user_space.translate(vpn).unwrap().ppn();
@@ -232,7 +555,7 @@ impl MemorySet {
                     .copy_from_slice(src_ppn.get_bytes_array());
             }
         }
-        memory_set
+        Some(memory_set)
     }
     pub fn activate(&self) {
         let satp = self.page_table.token();
@@ -244,9 +567,12 @@ impl MemorySet {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// 进程退出时回收, 堆和mmap的页也一起放掉, 不用等到父进程wait
     pub fn recycle_data_pages(&mut self) {
         //*self = Self::new_bare();
         self.areas.clear();
+        self.heap.clear();
+        self.mmap_areas.clear();
     }
 
     pub fn insert_mmap_area(&mut self, mmap_area: MemoryMapArea) {
@@ -266,11 +592,45 @@ impl MemorySet {
         }
     }
 
+    /// 逻辑段和mmap区域一共占了多少字节的虚拟地址, 不含堆
+    pub fn mapped_size(&self) -> usize {
+        let pages: usize = self
+            .areas
+            .iter()
+            .map(|area| &area.vpn_range)
+            .chain(self.mmap_areas.iter().map(|area| &area.vpn_range))
+            .map(|range| range.get_end().0 - range.get_start().0)
+            .sum();
+        pages * PAGE_SIZE
+    }
+
+    /// 驻留的物理页数(RSS), 包括和别的地址空间共享的页, 不含页表本身
+    pub fn resident_pages(&self) -> usize {
+        let areas: usize = self
+            .areas
+            .iter()
+            .map(|area| area.data_frames.len() + area.shared_frames.len())
+            .sum();
+        let mmap_areas: usize = self
+            .mmap_areas
+            .iter()
+            .map(|area| area.data_frames.len())
+            .sum();
+        areas + mmap_areas + self.heap.len()
+    }
+
     pub fn lazy_alloc_heap(&mut self, va: VirtAddr) -> bool {
         let vpn: VirtPageNum = va.floor();
-        let frame = frame_alloc().unwrap();
-        let ppn = frame.ppn;
-        self.page_table.map(vpn, ppn, PTEFlags::U | PTEFlags::R | PTEFlags::W);
+        let frame = match frame_alloc() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        if !self
+            .page_table
+            .map(vpn, frame.ppn, PTEFlags::U | PTEFlags::R | PTEFlags::W)
+        {
+            return false;
+        }
         self.heap.insert(vpn, frame);
         true
     }
@@ -289,6 +649,8 @@ impl MemorySet {
 pub struct MapArea {
     vpn_range: VPNRange,
     data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    /// MapType::Shared only, the frames are owned together with other mappings
+    shared_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
     map_type: MapType,
     map_perm: MapPermission,
 }
@@ -305,43 +667,91 @@ impl MapArea {
         Self {
             vpn_range: VPNRange::new(start_vpn, end_vpn),
             data_frames: BTreeMap::new(),
+            shared_frames: BTreeMap::new(),
             map_type,
             map_perm,
         }
     }
+    pub fn new_shared(
+        start_va: VirtAddr,
+        frames: Vec<Arc<FrameTracker>>,
+        map_perm: MapPermission,
+    ) -> Self {
+        let start_vpn: VirtPageNum = start_va.floor();
+        let end_vpn = VirtPageNum(start_vpn.0 + frames.len());
+        let mut shared_frames = BTreeMap::new();
+        for (i, frame) in frames.into_iter().enumerate() {
+            shared_frames.insert(VirtPageNum(start_vpn.0 + i), frame);
+        }
+        Self {
+            vpn_range: VPNRange::new(start_vpn, end_vpn),
+            data_frames: BTreeMap::new(),
+            shared_frames,
+            map_type: MapType::Shared,
+            map_perm,
+        }
+    }
     pub fn from_another(another: &MapArea) -> Self {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frames: BTreeMap::new(),
+            shared_frames: another.shared_frames.clone(),
             map_type: another.map_type,
             map_perm: another.map_perm,
         }
     }
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+    /// 物理页不够时返回false, 这一页没有映射
+    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
         let ppn: PhysPageNum;
+        let mut frame = None;
         match self.map_type {
             MapType::Identical => {
                 ppn = PhysPageNum(vpn.0);
             }
             MapType::Framed => {
-                let frame = frame_alloc().unwrap();
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                let new_frame = match frame_alloc() {
+                    Some(frame) => frame,
+                    None => return false,
+                };
+                ppn = new_frame.ppn;
+                frame = Some(new_frame);
+            }
+            MapType::Shared => {
+                ppn = self.shared_frames.get(&vpn).unwrap().ppn;
             }
         }
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        if !page_table.map(vpn, ppn, pte_flags) {
+            return false;
+        }
+        if let Some(frame) = frame {
+            self.data_frames.insert(vpn, frame);
+        }
+        true
     }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        if self.map_type == MapType::Framed {
-            self.data_frames.remove(&vpn);
+        match self.map_type {
+            MapType::Framed => {
+                self.data_frames.remove(&vpn);
+            }
+            MapType::Shared => {
+                self.shared_frames.remove(&vpn);
+            }
+            MapType::Identical => {}
         }
         page_table.unmap(vpn);
     }
-    pub fn map(&mut self, page_table: &mut PageTable) {
+    /// 中途物理页不够时把已经映射的页撤掉, 返回false
+    pub fn map(&mut self, page_table: &mut PageTable) -> bool {
         for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+            if !self.map_one(page_table, vpn) {
+                for mapped in VPNRange::new(self.vpn_range.get_start(), vpn) {
+                    self.unmap_one(page_table, mapped);
+                }
+                return false;
+            }
         }
+        true
     }
     pub fn unmap(&mut self, page_table: &mut PageTable) {
         for vpn in self.vpn_range {
@@ -376,6 +786,8 @@ impl MapArea {
 pub enum MapType {
     Identical,
     Framed,
+    /// frames shared with other address spaces, fork maps them instead of copying
+    Shared,
 }
 
 bitflags! {
@@ -442,13 +854,16 @@ impl MemoryMapArea {
     }
 
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum, fd_table: Vec<Option<FileDescriptor>>) -> bool {
-        // 分配物理页
-        let ppn: PhysPageNum;
-        let frame = frame_alloc().unwrap();
-        ppn = frame.ppn;
-        self.data_frames.insert(vpn, frame);
+        // 分配物理页, 不够时返回false
+        let frame = match frame_alloc() {
+            Some(frame) => frame,
+            None => return false,
+        };
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        if !page_table.map(vpn, frame.ppn, pte_flags) {
+            return false;
+        }
+        self.data_frames.insert(vpn, frame);
 
         // 复制文件数据到内存
         if let Some(file_descriptor) = &fd_table[self.fd] {
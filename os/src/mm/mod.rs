@@ -3,13 +3,19 @@ mod frame_allocator;
 mod heap_allocator;
 mod memory_set;
 mod page_table;
+pub mod shm;
+mod slab;
 
 pub use address::VPNRange;
 pub use address::{PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum, align_up};
 pub use frame_allocator::{frame_alloc, frame_alloc_more, frame_dealloc, FrameTracker, add_free};
+pub use frame_allocator::{frame_alloc_dma, frame_stats, free_frames, memory_end, take_out_of_memory};
+pub use heap_allocator::{heap_stats, HeapStats};
 pub use memory_set::remap_test;
 pub use memory_set::{kernel_token, MapArea, MapPermission, MapType, MemorySet, KERNEL_SPACE, MemoryMapArea};
+pub use memory_set::{ElfInfo, AT_NULL, AT_RANDOM};
 use page_table::PTEFlags;
+pub use slab::{slab_stats, SlabStats};
 pub use page_table::{
     translated_byte_buffer, translated_ref, translated_refmut, translated_str, PageTable,
     PageTableEntry, UserBuffer, UserBufferIterator,
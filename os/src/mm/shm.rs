@@ -0,0 +1,406 @@
+//! shared memory segments. System V segments are found by id in SHM_TABLE,
+//! POSIX ones are files under /dev/shm. Frames are reference counted, so a segment
+//! lives on after it is removed until the last mapping goes away.
+
+use super::{frame_alloc, FrameTracker, PhysPageNum, UserBuffer};
+use crate::config::PAGE_SIZE;
+use crate::fs::File;
+use crate::sync::UPIntrFreeCell;
+use crate::task::{Credentials, MAY_READ, MAY_WRITE};
+use crate::timer::get_realtime_ns;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+
+pub const IPC_PRIVATE: usize = 0;
+pub const IPC_CREAT: usize = 0o1000;
+pub const IPC_EXCL: usize = 0o2000;
+pub const IPC_RMID: usize = 0;
+pub const IPC_SET: usize = 1;
+pub const IPC_STAT: usize = 2;
+pub const SHM_RDONLY: usize = 0o10000;
+pub const SHM_RND: usize = 0o20000;
+
+/// largest segment shmget hands out
+const SHMMAX: usize = 0x400_0000;
+
+pub struct ShmSegment {
+    frames: UPIntrFreeCell<Vec<Arc<FrameTracker>>>,
+}
+
+impl ShmSegment {
+    pub fn new(size: usize) -> Option<Self> {
+        let segment = Self {
+            frames: unsafe { UPIntrFreeCell::new(Vec::new()) },
+        };
+        if segment.resize(size) {
+            Some(segment)
+        } else {
+            None
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.frames.exclusive_access().len() * PAGE_SIZE
+    }
+
+    /// new pages are zeroed, pages cut off stay alive in existing mappings
+    pub fn resize(&self, size: usize) -> bool {
+        let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut frames = self.frames.exclusive_access();
+        while frames.len() < pages {
+            match frame_alloc() {
+                Some(frame) => frames.push(Arc::new(frame)),
+                None => return false,
+            }
+        }
+        frames.truncate(pages);
+        true
+    }
+
+    /// frames backing [offset, offset + len), offset is page aligned
+    pub fn frames(&self, offset: usize, len: usize) -> Option<Vec<Arc<FrameTracker>>> {
+        let frames = self.frames.exclusive_access();
+        let start = offset / PAGE_SIZE;
+        let end = (offset + len + PAGE_SIZE - 1) / PAGE_SIZE;
+        if offset % PAGE_SIZE != 0 || start >= end || end > frames.len() {
+            return None;
+        }
+        Some(frames[start..end].to_vec())
+    }
+
+    fn first_frame(&self) -> Option<PhysPageNum> {
+        self.frames.exclusive_access().first().map(|frame| frame.ppn)
+    }
+
+    /// every mapping holds a reference to each of its frames
+    fn attach_count(&self) -> usize {
+        self.frames
+            .exclusive_access()
+            .first()
+            .map_or(0, |frame| Arc::strong_count(frame) - 1)
+    }
+
+    /// copy out of the segment, for read on a /dev/shm file
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let frames = self.frames.exclusive_access();
+        let end = (offset + buf.len()).min(frames.len() * PAGE_SIZE);
+        let mut pos = offset;
+        while pos < end {
+            let page = &frames[pos / PAGE_SIZE].ppn.get_bytes_array()[pos % PAGE_SIZE..];
+            let len = page.len().min(end - pos);
+            buf[pos - offset..pos - offset + len].copy_from_slice(&page[..len]);
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let frames = self.frames.exclusive_access();
+        let end = (offset + buf.len()).min(frames.len() * PAGE_SIZE);
+        let mut pos = offset;
+        while pos < end {
+            let page = &mut frames[pos / PAGE_SIZE].ppn.get_bytes_array()[pos % PAGE_SIZE..];
+            let len = page.len().min(end - pos);
+            page[..len].copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+}
+
+/// struct ipc64_perm
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct IpcPerm {
+    pub key: i32,
+    pub uid: u32,
+    pub gid: u32,
+    pub cuid: u32,
+    pub cgid: u32,
+    pub mode: u32,
+    pub seq: u16,
+    pub pad: u16,
+    pub unused: [usize; 2],
+}
+
+/// struct shmid64_ds
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct ShmidDs {
+    pub shm_perm: IpcPerm,
+    pub shm_segsz: usize,
+    pub shm_atime: isize,
+    pub shm_dtime: isize,
+    pub shm_ctime: isize,
+    pub shm_cpid: i32,
+    pub shm_lpid: i32,
+    pub shm_nattch: usize,
+    pub unused: [usize; 2],
+}
+
+struct SysvShm {
+    segment: Arc<ShmSegment>,
+    ds: ShmidDs,
+}
+
+lazy_static! {
+    static ref SHM_TABLE: UPIntrFreeCell<BTreeMap<usize, SysvShm>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+    /// POSIX segments by name, without the /dev/shm/ prefix
+    static ref SHM_NAMES: UPIntrFreeCell<BTreeMap<String, Arc<ShmSegment>>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+}
+
+static NEXT_SHMID: AtomicUsize = AtomicUsize::new(1);
+
+fn now_secs() -> isize {
+    (get_realtime_ns() / 1_000_000_000) as isize
+}
+
+impl IpcPerm {
+    /// owner or creator
+    fn is_owner(&self, cred: &Credentials) -> bool {
+        cred.uid.effective == self.uid || cred.uid.effective == self.cuid
+    }
+
+    /// like linux ipcperms: the owner and the creator get the user bits,
+    /// the owner's and the creator's groups get the group bits
+    fn permits(&self, cred: &Credentials, access: u32) -> bool {
+        let uid = if self.is_owner(cred) {
+            cred.uid.effective
+        } else {
+            self.uid
+        };
+        let gid = if cred.in_group(self.cgid) {
+            self.cgid
+        } else {
+            self.gid
+        };
+        cred.permits(self.mode, uid, gid, access)
+    }
+
+    /// IPC_SET and IPC_RMID are for the owner, the creator and root
+    fn may_change(&self, cred: &Credentials) -> bool {
+        cred.is_privileged() || self.is_owner(cred)
+    }
+}
+
+/// the MAY_* bits asked for by the low mode bits of shmflg
+fn requested_access(shmflg: usize) -> u32 {
+    let mode = (shmflg & 0o777) as u32;
+    ((mode >> 6) | (mode >> 3) | mode) & (MAY_READ | MAY_WRITE)
+}
+
+pub fn shmget(key: usize, size: usize, shmflg: usize, pid: usize, cred: &Credentials) -> isize {
+    let mut table = SHM_TABLE.exclusive_access();
+    if key != IPC_PRIVATE {
+        if let Some((&shmid, shm)) = table
+            .iter()
+            .find(|(_, shm)| shm.ds.shm_perm.key == key as i32)
+        {
+            if shmflg & IPC_CREAT != 0 && shmflg & IPC_EXCL != 0 {
+                return -1;
+            }
+            // EACCES
+            if !shm.ds.shm_perm.permits(cred, requested_access(shmflg)) {
+                return -1;
+            }
+            if size > shm.ds.shm_segsz {
+                return -1;
+            }
+            return shmid as isize;
+        }
+        if shmflg & IPC_CREAT == 0 {
+            return -1;
+        }
+    }
+    if size == 0 || size > SHMMAX {
+        return -1;
+    }
+    let segment = match ShmSegment::new(size) {
+        Some(segment) => Arc::new(segment),
+        None => return -1,
+    };
+    let shmid = NEXT_SHMID.fetch_add(1, Ordering::Relaxed);
+    let ds = ShmidDs {
+        shm_perm: IpcPerm {
+            key: key as i32,
+            uid: cred.uid.effective,
+            gid: cred.gid.effective,
+            cuid: cred.uid.effective,
+            cgid: cred.gid.effective,
+            mode: (shmflg & 0o777) as u32,
+            seq: shmid as u16,
+            ..Default::default()
+        },
+        shm_segsz: size,
+        shm_ctime: now_secs(),
+        shm_cpid: pid as i32,
+        ..Default::default()
+    };
+    table.insert(shmid, SysvShm { segment, ds });
+    shmid as isize
+}
+
+/// the segment for shmat if the mode allows `access`, also records the attach
+pub fn shm_attach(
+    shmid: usize,
+    pid: usize,
+    cred: &Credentials,
+    access: u32,
+) -> Option<Arc<ShmSegment>> {
+    let mut table = SHM_TABLE.exclusive_access();
+    let shm = table.get_mut(&shmid)?;
+    if !shm.ds.shm_perm.permits(cred, access) {
+        return None;
+    }
+    shm.ds.shm_atime = now_secs();
+    shm.ds.shm_lpid = pid as i32;
+    Some(shm.segment.clone())
+}
+
+/// shmdt finds the segment by the first frame of the mapping
+pub fn shm_detach(first_frame: PhysPageNum, pid: usize) {
+    let mut table = SHM_TABLE.exclusive_access();
+    if let Some(shm) = table
+        .values_mut()
+        .find(|shm| shm.segment.first_frame() == Some(first_frame))
+    {
+        shm.ds.shm_dtime = now_secs();
+        shm.ds.shm_lpid = pid as i32;
+    }
+}
+
+/// IPC_STAT needs read permission
+pub fn shm_stat(shmid: usize, cred: &Credentials) -> Option<ShmidDs> {
+    let table = SHM_TABLE.exclusive_access();
+    let shm = table.get(&shmid)?;
+    if !shm.ds.shm_perm.permits(cred, MAY_READ) {
+        return None;
+    }
+    let mut ds = shm.ds;
+    ds.shm_nattch = shm.segment.attach_count();
+    Some(ds)
+}
+
+/// IPC_SET, only the permission bits can change
+pub fn shm_set_mode(shmid: usize, mode: u32, cred: &Credentials) -> bool {
+    match SHM_TABLE.exclusive_access().get_mut(&shmid) {
+        Some(shm) if shm.ds.shm_perm.may_change(cred) => {
+            shm.ds.shm_perm.mode = mode & 0o777;
+            shm.ds.shm_ctime = now_secs();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// IPC_RMID, attached processes keep their mappings
+pub fn shm_remove(shmid: usize, cred: &Credentials) -> bool {
+    let mut table = SHM_TABLE.exclusive_access();
+    match table.get(&shmid) {
+        Some(shm) if shm.ds.shm_perm.may_change(cred) => {
+            table.remove(&shmid);
+            true
+        }
+        _ => false,
+    }
+}
+
+pub const SHM_DIR: &str = "/dev/shm/";
+
+/// name of a POSIX segment if `path` is under /dev/shm
+pub fn shm_name(path: &str) -> Option<&str> {
+    let name = path.strip_prefix(SHM_DIR)?;
+    if name.is_empty() || name.contains('/') {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// shm_open, new segments start empty until ftruncate
+pub fn shm_open(name: &str, create: bool, excl: bool, trunc: bool) -> Option<Arc<ShmSegment>> {
+    let mut names = SHM_NAMES.exclusive_access();
+    if let Some(segment) = names.get(name) {
+        if create && excl {
+            return None;
+        }
+        if trunc {
+            segment.resize(0);
+        }
+        return Some(segment.clone());
+    }
+    if !create {
+        return None;
+    }
+    let segment = Arc::new(ShmSegment::new(0)?);
+    names.insert(String::from(name), segment.clone());
+    Some(segment)
+}
+
+/// shm_unlink, open fds and mappings keep the segment alive
+pub fn shm_unlink(name: &str) -> bool {
+    SHM_NAMES.exclusive_access().remove(name).is_some()
+}
+
+/// an open /dev/shm file
+pub struct ShmFile {
+    pub segment: Arc<ShmSegment>,
+    readable: bool,
+    writable: bool,
+    offset: UPIntrFreeCell<usize>,
+}
+
+impl ShmFile {
+    pub fn new(segment: Arc<ShmSegment>, readable: bool, writable: bool) -> Self {
+        Self {
+            segment,
+            readable,
+            writable,
+            offset: unsafe { UPIntrFreeCell::new(0) },
+        }
+    }
+}
+
+impl File for ShmFile {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+
+    fn writable(&self) -> bool {
+        self.writable
+    }
+
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let mut offset = self.offset.exclusive_access();
+        let mut total = 0;
+        for slice in buf.buffers.iter_mut() {
+            let len = self.segment.read_at(*offset, slice);
+            *offset += len;
+            total += len;
+            if len < slice.len() {
+                break;
+            }
+        }
+        total
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        let mut offset = self.offset.exclusive_access();
+        let mut total = 0;
+        for slice in buf.buffers.iter() {
+            let len = self.segment.write_at(*offset, slice);
+            *offset += len;
+            total += len;
+            if len < slice.len() {
+                break;
+            }
+        }
+        total
+    }
+}
@@ -0,0 +1,204 @@
+//! 内核小对象的slab分配器. 每个slab占一个物理页, 直接从帧分配器拿,
+//! 页开头放SlabHeader, 后面按对象大小切成等长的块.
+//! 对象按大小取整到2的幂, 所以块的地址天然按对象大小对齐
+use super::frame_allocator::{frame_alloc_raw, frame_dealloc};
+use super::PhysAddr;
+use crate::config::PAGE_SIZE;
+use core::mem::size_of;
+use crate::sync::IntrFreeMutex;
+
+/// 各个缓存的对象大小, 再大的对象走buddy堆
+const SLAB_SIZES: [usize; 7] = [16, 32, 64, 128, 256, 512, 1024];
+const NR_CACHES: usize = SLAB_SIZES.len();
+
+#[repr(C)]
+struct SlabHeader {
+    /// partial链表里的下一个slab, 0表示没有
+    next: usize,
+    /// 空闲块链表, 下一个空闲块的地址存在块的开头
+    free: usize,
+    in_use: usize,
+    on_partial: bool,
+}
+
+impl SlabHeader {
+    fn of(slab: usize) -> &'static mut Self {
+        unsafe { &mut *(slab as *mut Self) }
+    }
+}
+
+fn next_free(object: usize) -> &'static mut usize {
+    unsafe { &mut *(object as *mut usize) }
+}
+
+/// 一种大小的对象的缓存
+struct SlabCache {
+    object_size: usize,
+    /// 还有空闲块的slab链表
+    partial: usize,
+    slabs: usize,
+    in_use: usize,
+    allocs: usize,
+}
+
+impl SlabCache {
+    const fn new(object_size: usize) -> Self {
+        Self {
+            object_size,
+            partial: 0,
+            slabs: 0,
+            in_use: 0,
+            allocs: 0,
+        }
+    }
+
+    /// 第一个块的页内偏移, 头部占掉开头的若干块
+    fn first_offset(&self) -> usize {
+        let header = size_of::<SlabHeader>();
+        (header + self.object_size - 1) / self.object_size * self.object_size
+    }
+
+    fn objects_per_slab(&self) -> usize {
+        (PAGE_SIZE - self.first_offset()) / self.object_size
+    }
+
+    /// 从帧分配器拿一页做成新的slab, 放到partial链表上
+    fn grow(&mut self) -> bool {
+        let ppn = match frame_alloc_raw(1) {
+            Some(ppn) => ppn,
+            None => return false,
+        };
+        let slab = PhysAddr::from(ppn).0;
+        let mut free = 0;
+        for i in (0..self.objects_per_slab()).rev() {
+            let object = slab + self.first_offset() + i * self.object_size;
+            *next_free(object) = free;
+            free = object;
+        }
+        *SlabHeader::of(slab) = SlabHeader {
+            next: self.partial,
+            free,
+            in_use: 0,
+            on_partial: true,
+        };
+        self.partial = slab;
+        self.slabs += 1;
+        true
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        if self.partial == 0 && !self.grow() {
+            return None;
+        }
+        let slab = self.partial;
+        let header = SlabHeader::of(slab);
+        let object = header.free;
+        header.free = *next_free(object);
+        header.in_use += 1;
+        // 用完了就从partial链表上摘下来, 释放时再挂回去
+        if header.free == 0 {
+            self.partial = header.next;
+            header.on_partial = false;
+        }
+        self.in_use += 1;
+        self.allocs += 1;
+        Some(object)
+    }
+
+    fn dealloc(&mut self, object: usize) {
+        let slab = object & !(PAGE_SIZE - 1);
+        let header = SlabHeader::of(slab);
+        *next_free(object) = header.free;
+        header.free = object;
+        header.in_use -= 1;
+        self.in_use -= 1;
+        if !header.on_partial {
+            header.next = self.partial;
+            header.on_partial = true;
+            self.partial = slab;
+        }
+        // 空的slab留一个备用, 其余的还给帧分配器
+        if header.in_use == 0 && !(self.partial == slab && header.next == 0) {
+            self.unlink(slab);
+            frame_dealloc(PhysAddr::from(slab).floor());
+            self.slabs -= 1;
+        }
+    }
+
+    fn unlink(&mut self, slab: usize) {
+        let next = SlabHeader::of(slab).next;
+        if self.partial == slab {
+            self.partial = next;
+            return;
+        }
+        let mut prev = self.partial;
+        while SlabHeader::of(prev).next != slab {
+            prev = SlabHeader::of(prev).next;
+        }
+        SlabHeader::of(prev).next = next;
+    }
+}
+
+/// 调试用的统计, 见/proc/slabinfo
+#[derive(Clone, Copy, Default)]
+pub struct SlabStats {
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    /// 占用的页数
+    pub slabs: usize,
+    pub in_use: usize,
+    /// 累计分配过的次数
+    pub allocs: usize,
+}
+
+struct SlabAllocator {
+    caches: [SlabCache; NR_CACHES],
+}
+
+static SLAB_ALLOCATOR: IntrFreeMutex<SlabAllocator> = IntrFreeMutex::new(SlabAllocator {
+    caches: [
+        SlabCache::new(SLAB_SIZES[0]),
+        SlabCache::new(SLAB_SIZES[1]),
+        SlabCache::new(SLAB_SIZES[2]),
+        SlabCache::new(SLAB_SIZES[3]),
+        SlabCache::new(SLAB_SIZES[4]),
+        SlabCache::new(SLAB_SIZES[5]),
+        SlabCache::new(SLAB_SIZES[6]),
+    ],
+});
+
+/// 能放下`size`字节且按`align`对齐的对象的缓存, 太大时返回None
+fn cache_index(size: usize, align: usize) -> Option<usize> {
+    let size = size.max(align);
+    SLAB_SIZES
+        .iter()
+        .position(|&object_size| object_size >= size)
+}
+
+/// 不归slab管的大小, 或者帧分配器没有页了, 返回None
+pub fn slab_alloc(size: usize, align: usize) -> Option<usize> {
+    let index = cache_index(size, align)?;
+    SLAB_ALLOCATOR.lock().caches[index].alloc()
+}
+
+/// `object`必须是slab_alloc用同样的大小和对齐分配出来的
+pub fn slab_dealloc(object: usize, size: usize, align: usize) {
+    let index = cache_index(size, align).unwrap();
+    SLAB_ALLOCATOR.lock().caches[index].dealloc(object);
+}
+
+/// 统计数据先拷出来, 持有锁时不能再分配内存
+pub fn slab_stats() -> [SlabStats; NR_CACHES] {
+    let allocator = SLAB_ALLOCATOR.lock();
+    let mut stats = [SlabStats::default(); NR_CACHES];
+    for (stat, cache) in stats.iter_mut().zip(allocator.caches.iter()) {
+        *stat = SlabStats {
+            object_size: cache.object_size,
+            objects_per_slab: cache.objects_per_slab(),
+            slabs: cache.slabs,
+            in_use: cache.in_use,
+            allocs: cache.allocs,
+        };
+    }
+    stats
+}
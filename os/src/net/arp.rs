@@ -0,0 +1,222 @@
+//! arp cache (rfc 826), maps next hop addresses to ethernet addresses per interface.
+//! Frames for an unresolved next hop wait here until the reply shows up.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use lose_net_stack::IPv4;
+
+use crate::sync::UPIntrFreeCell;
+use crate::timer::{add_timer_callback, get_time_ms};
+
+use super::iface;
+use super::ip::{ETH_HEADER_LEN, ETH_TYPE_IPV4};
+
+pub const ETH_TYPE_ARP: u16 = 0x0806;
+const ARP_PACKET_LEN: usize = 28;
+const ARP_HTYPE_ETHER: u16 = 1;
+const ARP_OP_REQUEST: u16 = 1;
+const ARP_OP_REPLY: u16 = 2;
+
+const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+
+/// resolved entries are thrown away after this long
+const ARP_ENTRY_TIMEOUT_MS: usize = 60_000;
+const ARP_RETRY_MS: usize = 1000;
+const ARP_MAX_RETRIES: usize = 3;
+/// frames kept per unresolved address, older ones are dropped
+const ARP_QUEUE_LIMIT: usize = 16;
+
+enum ArpEntry {
+    Resolved { mac: [u8; 6], expire_ms: usize },
+    Pending { queue: Vec<Vec<u8>>, retries: usize },
+}
+
+lazy_static! {
+    /// keyed by (interface, ip)
+    static ref ARP_CACHE: UPIntrFreeCell<BTreeMap<(usize, u32), ArpEntry>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+}
+
+struct ArpPacket {
+    op: u16,
+    sender_mac: [u8; 6],
+    sender_ip: IPv4,
+    target_ip: IPv4,
+}
+
+fn parse_arp(frame: &[u8]) -> Option<ArpPacket> {
+    if frame.len() < ETH_HEADER_LEN + ARP_PACKET_LEN
+        || u16::from_be_bytes([frame[12], frame[13]]) != ETH_TYPE_ARP
+    {
+        return None;
+    }
+    let arp = &frame[ETH_HEADER_LEN..];
+    if u16::from_be_bytes([arp[0], arp[1]]) != ARP_HTYPE_ETHER
+        || u16::from_be_bytes([arp[2], arp[3]]) != ETH_TYPE_IPV4
+        || arp[4] != 6
+        || arp[5] != 4
+    {
+        return None;
+    }
+    let mut sender_mac = [0u8; 6];
+    sender_mac.copy_from_slice(&arp[8..14]);
+    Some(ArpPacket {
+        op: u16::from_be_bytes([arp[6], arp[7]]),
+        sender_mac,
+        sender_ip: IPv4::from_u32(u32::from_be_bytes([arp[14], arp[15], arp[16], arp[17]])),
+        target_ip: IPv4::from_u32(u32::from_be_bytes([arp[24], arp[25], arp[26], arp[27]])),
+    })
+}
+
+fn build_arp(
+    op: u16,
+    sender_mac: [u8; 6],
+    sender_ip: IPv4,
+    target_mac: [u8; 6],
+    target_ip: IPv4,
+    dst_mac: [u8; 6],
+) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(ETH_HEADER_LEN + ARP_PACKET_LEN);
+    frame.extend_from_slice(&dst_mac);
+    frame.extend_from_slice(&sender_mac);
+    frame.extend_from_slice(&ETH_TYPE_ARP.to_be_bytes());
+    frame.extend_from_slice(&ARP_HTYPE_ETHER.to_be_bytes());
+    frame.extend_from_slice(&ETH_TYPE_IPV4.to_be_bytes());
+    frame.extend_from_slice(&[6, 4]);
+    frame.extend_from_slice(&op.to_be_bytes());
+    frame.extend_from_slice(&sender_mac);
+    frame.extend_from_slice(&sender_ip.to_u32().to_be_bytes());
+    frame.extend_from_slice(&target_mac);
+    frame.extend_from_slice(&target_ip.to_u32().to_be_bytes());
+    frame
+}
+
+fn send_request(index: usize, target: IPv4) {
+    let (ip, _, _) = iface::get_config(index);
+    let frame = build_arp(
+        ARP_OP_REQUEST,
+        iface::mac(index),
+        ip,
+        [0; 6],
+        target,
+        BROADCAST_MAC,
+    );
+    iface::device(index).transmit(&frame);
+}
+
+fn set_macs(frame: &mut [u8], dst_mac: [u8; 6], src_mac: [u8; 6]) {
+    frame[0..6].copy_from_slice(&dst_mac);
+    frame[6..12].copy_from_slice(&src_mac);
+}
+
+/// send an ip frame out of `index` towards `next_hop`, queueing it while the address is resolved
+pub fn output(index: usize, next_hop: IPv4, frame: &[u8]) {
+    let src_mac = iface::mac(index);
+    let mut frame = frame.to_vec();
+    if iface::is_broadcast(index, next_hop) {
+        set_macs(&mut frame, BROADCAST_MAC, src_mac);
+        iface::device(index).transmit(&frame);
+        return;
+    }
+    let key = (index, next_hop.to_u32());
+    let now = get_time_ms();
+    let mut start_resolve = false;
+    let resolved = ARP_CACHE.exclusive_session(|cache| {
+        match cache.get_mut(&key) {
+            Some(ArpEntry::Resolved { mac, expire_ms }) if *expire_ms > now => return Some(*mac),
+            Some(ArpEntry::Pending { queue, .. }) => {
+                if queue.len() >= ARP_QUEUE_LIMIT {
+                    queue.remove(0);
+                }
+                queue.push(frame.clone());
+                return None;
+            }
+            _ => {}
+        }
+        // unknown or expired
+        let mut queue = Vec::new();
+        queue.push(frame.clone());
+        cache.insert(key, ArpEntry::Pending { queue, retries: 0 });
+        start_resolve = true;
+        None
+    });
+    if let Some(mac) = resolved {
+        set_macs(&mut frame, mac, src_mac);
+        iface::device(index).transmit(&frame);
+    } else if start_resolve {
+        send_request(index, next_hop);
+        add_timer_callback(now + ARP_RETRY_MS, move || on_retry(key));
+    }
+}
+
+fn on_retry(key: (usize, u32)) {
+    let retry = ARP_CACHE.exclusive_session(|cache| match cache.get_mut(&key) {
+        Some(ArpEntry::Pending { retries, .. }) => {
+            *retries += 1;
+            if *retries > ARP_MAX_RETRIES {
+                // host unreachable, drop whatever was waiting
+                cache.remove(&key);
+                false
+            } else {
+                true
+            }
+        }
+        _ => false,
+    });
+    if retry {
+        send_request(key.0, IPv4::from_u32(key.1));
+        add_timer_callback(get_time_ms() + ARP_RETRY_MS, move || on_retry(key));
+    }
+}
+
+/// learn from an arp packet and answer requests for our address
+pub fn handle_arp(index: usize, frame: &[u8]) {
+    let arp = match parse_arp(frame) {
+        Some(arp) => arp,
+        None => return,
+    };
+    let (ip, _, _) = iface::get_config(index);
+    let mac = iface::mac(index);
+    let for_us = ip != iface::unspecified() && arp.target_ip == ip;
+    let key = (index, arp.sender_ip.to_u32());
+
+    // rfc 826: update an existing entry, only add new ones when we are the target
+    let waiting = ARP_CACHE.exclusive_session(|cache| {
+        if !for_us && !cache.contains_key(&key) {
+            return Vec::new();
+        }
+        let entry = ArpEntry::Resolved {
+            mac: arp.sender_mac,
+            expire_ms: get_time_ms() + ARP_ENTRY_TIMEOUT_MS,
+        };
+        match cache.insert(key, entry) {
+            Some(ArpEntry::Pending { queue, .. }) => queue,
+            _ => Vec::new(),
+        }
+    });
+    let device = iface::device(index);
+    for mut frame in waiting {
+        set_macs(&mut frame, arp.sender_mac, mac);
+        device.transmit(&frame);
+    }
+
+    if for_us && arp.op == ARP_OP_REQUEST {
+        let reply = build_arp(
+            ARP_OP_REPLY,
+            mac,
+            ip,
+            arp.sender_mac,
+            arp.sender_ip,
+            arp.sender_mac,
+        );
+        device.transmit(&reply);
+    }
+}
+
+/// forget everything learned on an interface, its address changed
+pub fn flush(index: usize) {
+    ARP_CACHE
+        .exclusive_access()
+        .retain(|&(iface, _), _| iface != index);
+}
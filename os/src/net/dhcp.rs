@@ -0,0 +1,316 @@
+//! dhcp client (rfc 2131), one per interface.
+//! It runs entirely off packet arrival and the timer heap, nothing blocks.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use lose_net_stack::IPv4;
+
+use crate::sync::UPIntrFreeCell;
+use crate::timer::{add_timer_callback, get_time, get_time_ms};
+
+use super::iface;
+use super::ip::{build_ipv4_frame_from, build_udp, Ipv4Packet, IPPROTO_UDP, UDP_HEADER_LEN};
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const BOOTP_LEN: usize = 236;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAM_LIST: u8 = 55;
+const OPT_END: u8 = 255;
+
+const DHCP_INITIAL_TIMEOUT_MS: usize = 1000;
+const DHCP_MAX_TIMEOUT_MS: usize = 16_000;
+const DHCP_MAX_RETRIES: usize = 5;
+/// wait this long before starting over when no server answered
+const DHCP_RESTART_MS: usize = 60_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DhcpState {
+    Selecting,
+    Requesting,
+    Bound,
+    /// extending the lease we hold, see rfc 2131 4.3.2
+    Renewing,
+}
+
+struct DhcpClient {
+    state: DhcpState,
+    xid: u32,
+    offered: IPv4,
+    server: IPv4,
+    timeout_ms: usize,
+    retries: usize,
+    timer_id: usize,
+}
+
+lazy_static! {
+    /// keyed by interface index
+    static ref DHCP_CLIENTS: UPIntrFreeCell<BTreeMap<usize, DhcpClient>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+}
+
+static NEXT_TIMER_ID: AtomicUsize = AtomicUsize::new(1);
+
+fn ipv4_at(data: &[u8]) -> IPv4 {
+    IPv4::from_u32(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+}
+
+fn build_message(index: usize, client: &DhcpClient, msg_type: u8) -> Vec<u8> {
+    let mac = iface::mac(index);
+    // a renewing client already owns its address: ciaddr carries it and the server
+    // can answer by unicast, the options naming the offer must not be sent
+    let renewing = client.state == DhcpState::Renewing;
+    let mut msg = Vec::with_capacity(BOOTP_LEN + 64);
+    msg.extend_from_slice(&[BOOTREQUEST, 1, 6, 0]);
+    msg.extend_from_slice(&client.xid.to_be_bytes());
+    // secs, then the broadcast flag since we can't take unicast before having an address
+    msg.extend_from_slice(&[0, 0, if renewing { 0 } else { 0x80 }, 0]);
+    // ciaddr, yiaddr, siaddr, giaddr
+    if renewing {
+        msg.extend_from_slice(&client.offered.to_u32().to_be_bytes());
+    } else {
+        msg.extend_from_slice(&[0; 4]);
+    }
+    msg.extend_from_slice(&[0; 12]);
+    msg.extend_from_slice(&mac);
+    msg.resize(BOOTP_LEN, 0);
+    msg.extend_from_slice(&MAGIC_COOKIE);
+
+    msg.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, msg_type]);
+    if msg_type == DHCPREQUEST && !renewing {
+        msg.extend_from_slice(&[OPT_REQUESTED_IP, 4]);
+        msg.extend_from_slice(&client.offered.to_u32().to_be_bytes());
+        msg.extend_from_slice(&[OPT_SERVER_ID, 4]);
+        msg.extend_from_slice(&client.server.to_u32().to_be_bytes());
+    }
+    msg.extend_from_slice(&[
+        OPT_PARAM_LIST,
+        4,
+        OPT_SUBNET_MASK,
+        OPT_ROUTER,
+        OPT_DNS,
+        OPT_LEASE_TIME,
+    ]);
+    msg.push(OPT_END);
+    msg
+}
+
+fn send(index: usize, client: &DhcpClient, msg_type: u8) {
+    let udp = build_udp(
+        DHCP_CLIENT_PORT,
+        DHCP_SERVER_PORT,
+        &build_message(index, client, msg_type),
+    );
+    let source = if client.state == DhcpState::Renewing {
+        client.offered
+    } else {
+        IPv4::from_u32(0)
+    };
+    let frame = build_ipv4_frame_from(
+        source,
+        iface::mac(index),
+        IPv4::from_u32(u32::MAX),
+        [0xff; 6],
+        IPPROTO_UDP,
+        &udp,
+    );
+    iface::device(index).transmit(&frame);
+}
+
+fn arm_timer(index: usize, client: &mut DhcpClient, timeout_ms: usize) {
+    let id = NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+    client.timer_id = id;
+    add_timer_callback(get_time_ms() + timeout_ms, move || on_timeout(index, id));
+}
+
+/// broadcast a DHCPDISCOVER on the interface
+pub fn start(index: usize) {
+    DHCP_CLIENTS.exclusive_session(|clients| {
+        let mut client = DhcpClient {
+            state: DhcpState::Selecting,
+            xid: (get_time() as u32) ^ ((index as u32) << 24),
+            offered: IPv4::from_u32(0),
+            server: IPv4::from_u32(0),
+            timeout_ms: DHCP_INITIAL_TIMEOUT_MS,
+            retries: 0,
+            timer_id: 0,
+        };
+        send(index, &client, DHCPDISCOVER);
+        arm_timer(index, &mut client, client.timeout_ms);
+        clients.insert(index, client);
+    });
+}
+
+fn on_timeout(index: usize, timer_id: usize) {
+    let restart = DHCP_CLIENTS.exclusive_session(|clients| {
+        let client = match clients.get_mut(&index) {
+            Some(client) if client.timer_id == timer_id => client,
+            _ => return false,
+        };
+        match client.state {
+            // renewal time, ask the same server again
+            DhcpState::Bound => {
+                client.state = DhcpState::Renewing;
+                client.retries = 0;
+                client.timeout_ms = DHCP_INITIAL_TIMEOUT_MS;
+                send(index, client, DHCPREQUEST);
+                arm_timer(index, client, client.timeout_ms);
+                false
+            }
+            _ if client.retries >= DHCP_MAX_RETRIES => {
+                // no server, keep whatever address we have and try again later
+                client.timer_id = 0;
+                add_timer_callback(get_time_ms() + DHCP_RESTART_MS, move || start(index));
+                false
+            }
+            DhcpState::Selecting => {
+                client.retries += 1;
+                client.timeout_ms = (client.timeout_ms * 2).min(DHCP_MAX_TIMEOUT_MS);
+                send(index, client, DHCPDISCOVER);
+                arm_timer(index, client, client.timeout_ms);
+                false
+            }
+            DhcpState::Requesting | DhcpState::Renewing => {
+                client.retries += 1;
+                if client.retries >= DHCP_MAX_RETRIES {
+                    return true;
+                }
+                client.timeout_ms = (client.timeout_ms * 2).min(DHCP_MAX_TIMEOUT_MS);
+                send(index, client, DHCPREQUEST);
+                arm_timer(index, client, client.timeout_ms);
+                false
+            }
+        }
+    });
+    if restart {
+        start(index);
+    }
+}
+
+pub fn is_dhcp_reply(packet: &Ipv4Packet) -> bool {
+    packet.protocol == IPPROTO_UDP
+        && packet.payload.len() >= UDP_HEADER_LEN
+        && u16::from_be_bytes([packet.payload[2], packet.payload[3]]) == DHCP_CLIENT_PORT
+}
+
+struct DhcpReply {
+    msg_type: u8,
+    yiaddr: IPv4,
+    netmask: Option<IPv4>,
+    router: Option<IPv4>,
+    server: Option<IPv4>,
+    lease_secs: Option<u32>,
+}
+
+fn parse_reply(msg: &[u8], xid: u32) -> Option<DhcpReply> {
+    if msg.len() < BOOTP_LEN + 4
+        || msg[0] != BOOTREPLY
+        || u32::from_be_bytes([msg[4], msg[5], msg[6], msg[7]]) != xid
+        || msg[BOOTP_LEN..BOOTP_LEN + 4] != MAGIC_COOKIE
+    {
+        return None;
+    }
+    let mut reply = DhcpReply {
+        msg_type: 0,
+        yiaddr: ipv4_at(&msg[16..20]),
+        netmask: None,
+        router: None,
+        server: None,
+        lease_secs: None,
+    };
+    let mut options = &msg[BOOTP_LEN + 4..];
+    while let Some(&code) = options.first() {
+        match code {
+            0 => {
+                options = &options[1..];
+                continue;
+            }
+            OPT_END => break,
+            _ => {}
+        }
+        if options.len() < 2 || options.len() < 2 + options[1] as usize {
+            break;
+        }
+        let value = &options[2..2 + options[1] as usize];
+        match code {
+            OPT_MESSAGE_TYPE if value.len() == 1 => reply.msg_type = value[0],
+            OPT_SUBNET_MASK if value.len() == 4 => reply.netmask = Some(ipv4_at(value)),
+            OPT_ROUTER if value.len() >= 4 => reply.router = Some(ipv4_at(value)),
+            OPT_SERVER_ID if value.len() == 4 => reply.server = Some(ipv4_at(value)),
+            OPT_LEASE_TIME if value.len() == 4 => {
+                reply.lease_secs = Some(u32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+            }
+            _ => {}
+        }
+        options = &options[2 + value.len()..];
+    }
+    Some(reply)
+}
+
+pub fn handle_reply(index: usize, packet: &Ipv4Packet) {
+    let msg = &packet.payload[UDP_HEADER_LEN..];
+    let mut restart = false;
+    DHCP_CLIENTS.exclusive_session(|clients| {
+        let client = match clients.get_mut(&index) {
+            Some(client) => client,
+            None => return,
+        };
+        let reply = match parse_reply(msg, client.xid) {
+            Some(reply) => reply,
+            None => return,
+        };
+        match (client.state, reply.msg_type) {
+            (DhcpState::Selecting, DHCPOFFER) => {
+                client.offered = reply.yiaddr;
+                client.server = reply.server.unwrap_or(packet.src);
+                client.state = DhcpState::Requesting;
+                client.retries = 0;
+                client.timeout_ms = DHCP_INITIAL_TIMEOUT_MS;
+                send(index, client, DHCPREQUEST);
+                arm_timer(index, client, client.timeout_ms);
+            }
+            (DhcpState::Requesting | DhcpState::Renewing, DHCPACK) => {
+                iface::set_addr(index, reply.yiaddr);
+                if let Some(netmask) = reply.netmask {
+                    iface::set_netmask(index, netmask);
+                }
+                if let Some(router) = reply.router {
+                    iface::set_gateway(index, router);
+                }
+                client.offered = reply.yiaddr;
+                client.state = DhcpState::Bound;
+                // renew at T1, half of the lease
+                let lease_secs = reply.lease_secs.unwrap_or(u32::MAX) as usize;
+                let renew_ms = (lease_secs / 2).saturating_mul(1000).min(usize::MAX / 2);
+                arm_timer(index, client, renew_ms);
+            }
+            (DhcpState::Requesting | DhcpState::Renewing, DHCPNAK) => {
+                client.timer_id = 0;
+                restart = true;
+            }
+            _ => {}
+        }
+    });
+    if restart {
+        start(index);
+    }
+}
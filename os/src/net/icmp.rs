@@ -0,0 +1,206 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+use lazy_static::lazy_static;
+use lose_net_stack::IPv4;
+
+use crate::fs::{File, PollEvents};
+use crate::mm::UserBuffer;
+use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::task::schedule;
+
+use super::ip::{build_ipv4_frame, checksum, Ipv4Packet, IPPROTO_ICMP};
+use super::{is_local_addr, transmit_frame};
+
+pub const ICMP_ECHO_REPLY: u8 = 0;
+pub const ICMP_ECHO_REQUEST: u8 = 8;
+
+/// packets kept per socket before new ones are dropped
+const ICMP_QUEUE_SIZE: usize = 64;
+
+struct IcmpSocket {
+    /// SOCK_RAW gets the ip header too, a ping socket only gets echo replies
+    raw: bool,
+    /// echo identifier of a ping socket, every request it sends carries it
+    id: u16,
+    packets: VecDeque<(IPv4, Vec<u8>)>,
+    condvar: Arc<Condvar>,
+}
+
+lazy_static! {
+    static ref ICMP_TABLE: UPIntrFreeCell<Vec<Option<IcmpSocket>>> =
+        unsafe { UPIntrFreeCell::new(Vec::new()) };
+}
+
+static NEXT_PING_ID: AtomicU16 = AtomicU16::new(1);
+
+/// an echo identifier no other ping socket holds, like the port linux binds a ping socket to
+fn alloc_ping_id(table: &[Option<IcmpSocket>]) -> u16 {
+    let mut id = NEXT_PING_ID.load(Ordering::Relaxed);
+    // there can't be more ping sockets than open files, so a free id always turns up
+    for _ in 0..u16::MAX {
+        let in_use = table
+            .iter()
+            .flatten()
+            .any(|sock| !sock.raw && sock.id == id);
+        if !in_use {
+            break;
+        }
+        id = id.wrapping_add(1).max(1);
+    }
+    NEXT_PING_ID.store(id.wrapping_add(1).max(1), Ordering::Relaxed);
+    id
+}
+
+pub fn handle_icmp(packet: &Ipv4Packet) {
+    let icmp = packet.payload;
+    if icmp.len() < 8 || checksum(icmp) != 0 {
+        return;
+    }
+    if icmp[0] == ICMP_ECHO_REQUEST && is_local_addr(packet.dst) {
+        let mut reply = icmp.to_vec();
+        reply[0] = ICMP_ECHO_REPLY;
+        reply[2] = 0;
+        reply[3] = 0;
+        let sum = checksum(&reply);
+        reply[2..4].copy_from_slice(&sum.to_be_bytes());
+        transmit_frame(&build_ipv4_frame(packet.src, IPPROTO_ICMP, &reply));
+    }
+
+    ICMP_TABLE.exclusive_session(|table| {
+        for sock in table.iter_mut().flatten() {
+            if sock.packets.len() >= ICMP_QUEUE_SIZE {
+                continue;
+            }
+            if sock.raw {
+                sock.packets.push_back((packet.src, packet.packet.to_vec()));
+            } else if icmp[0] == ICMP_ECHO_REPLY
+                && sock.id == u16::from_be_bytes([icmp[4], icmp[5]])
+            {
+                sock.packets.push_back((packet.src, icmp.to_vec()));
+            } else {
+                continue;
+            }
+            sock.condvar.signal();
+        }
+    });
+}
+
+// icmp socket, SOCK_RAW or a SOCK_DGRAM ping socket
+pub struct ICMP {
+    pub socket_index: usize,
+    pub raw: bool,
+    /// default destination set by connect
+    pub peer: UPIntrFreeCell<Option<IPv4>>,
+}
+
+impl ICMP {
+    pub fn new(raw: bool) -> Self {
+        let mut table = ICMP_TABLE.exclusive_access();
+        let sock = IcmpSocket {
+            raw,
+            id: if raw { 0 } else { alloc_ping_id(&table) },
+            packets: VecDeque::new(),
+            condvar: Arc::new(Condvar::new()),
+        };
+        let index = match table.iter().position(|x| x.is_none()) {
+            Some(index) => {
+                table[index] = Some(sock);
+                index
+            }
+            None => {
+                table.push(Some(sock));
+                table.len() - 1
+            }
+        };
+        Self {
+            socket_index: index,
+            raw,
+            peer: unsafe { UPIntrFreeCell::new(None) },
+        }
+    }
+
+    pub fn send_to(&self, dest: IPv4, data: &[u8]) -> usize {
+        if data.len() < 8 {
+            return 0;
+        }
+        let mut icmp = data.to_vec();
+        if !self.raw {
+            // ping sockets only send echo requests, with the socket's own identifier
+            // and the checksum filled in by the kernel
+            if icmp[0] != ICMP_ECHO_REQUEST {
+                return 0;
+            }
+            let id = ICMP_TABLE.exclusive_access()[self.socket_index]
+                .as_ref()
+                .unwrap()
+                .id;
+            icmp[4..6].copy_from_slice(&id.to_be_bytes());
+            icmp[2] = 0;
+            icmp[3] = 0;
+            let sum = checksum(&icmp);
+            icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+        }
+        transmit_frame(&build_ipv4_frame(dest, IPPROTO_ICMP, &icmp));
+        data.len()
+    }
+
+    /// block until a packet arrives, returns the sender and the packet
+    pub fn recv_from(&self) -> (IPv4, Vec<u8>) {
+        loop {
+            let mut table = ICMP_TABLE.exclusive_access();
+            let sock = table[self.socket_index].as_mut().unwrap();
+            if let Some(packet) = sock.packets.pop_front() {
+                return packet;
+            }
+            let task_cx_ptr = sock.condvar.wait_no_sched();
+            drop(table);
+            schedule(task_cx_ptr);
+        }
+    }
+}
+
+impl File for ICMP {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let (_, packet) = self.recv_from();
+        buf.write(&packet)
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        let peer = *self.peer.exclusive_access();
+        match peer {
+            Some(dest) => {
+                let mut data = Vec::new();
+                for b in buf.buffers.iter() {
+                    data.extend_from_slice(b);
+                }
+                self.send_to(dest, &data)
+            }
+            None => 0,
+        }
+    }
+
+    fn poll(&self) -> PollEvents {
+        let table = ICMP_TABLE.exclusive_access();
+        if table[self.socket_index].as_ref().unwrap().packets.is_empty() {
+            PollEvents::POLLOUT
+        } else {
+            PollEvents::POLLIN | PollEvents::POLLOUT
+        }
+    }
+}
+
+impl Drop for ICMP {
+    fn drop(&mut self) {
+        ICMP_TABLE.exclusive_access()[self.socket_index] = None;
+    }
+}
@@ -0,0 +1,160 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use lose_net_stack::IPv4;
+
+use crate::drivers::{NetDevice, NET_DEVICES};
+use crate::sync::UPIntrFreeCell;
+
+use super::{arp, dhcp, LOSE_NET_STACK};
+
+pub struct NetInterface {
+    pub name: String,
+    pub device: Arc<dyn NetDevice>,
+    pub irq: usize,
+    pub mac: [u8; 6],
+    pub ip: IPv4,
+    pub netmask: IPv4,
+    /// 0.0.0.0 if this interface has no default route
+    pub gateway: IPv4,
+}
+
+lazy_static! {
+    static ref INTERFACES: UPIntrFreeCell<Vec<NetInterface>> =
+        unsafe { UPIntrFreeCell::new(Vec::new()) };
+}
+
+pub fn unspecified() -> IPv4 {
+    IPv4::from_u32(0)
+}
+
+/// one interface per virtio-net device, eth0 keeps the qemu usernet address
+/// until dhcp hands out a lease
+pub fn init() {
+    for (i, info) in NET_DEVICES.iter().enumerate() {
+        let (ip, netmask, gateway) = if i == 0 {
+            (
+                IPv4::new(10, 0, 2, 15),
+                IPv4::new(255, 255, 255, 0),
+                IPv4::new(10, 0, 2, 2),
+            )
+        } else {
+            (unspecified(), unspecified(), unspecified())
+        };
+        INTERFACES.exclusive_access().push(NetInterface {
+            name: format!("eth{}", i),
+            device: info.device.clone(),
+            irq: info.irq,
+            mac: info.mac,
+            ip,
+            netmask,
+            gateway,
+        });
+        sync_stack(i);
+        dhcp::start(i);
+    }
+}
+
+pub fn iface_count() -> usize {
+    INTERFACES.exclusive_access().len()
+}
+
+pub fn find_by_name(name: &str) -> Option<usize> {
+    INTERFACES
+        .exclusive_access()
+        .iter()
+        .position(|iface| iface.name == name)
+}
+
+pub fn find_by_irq(irq: usize) -> Option<usize> {
+    INTERFACES
+        .exclusive_access()
+        .iter()
+        .position(|iface| iface.irq == irq)
+}
+
+pub fn device(index: usize) -> Arc<dyn NetDevice> {
+    INTERFACES.exclusive_access()[index].device.clone()
+}
+
+pub fn mac(index: usize) -> [u8; 6] {
+    INTERFACES.exclusive_access()[index].mac
+}
+
+/// (address, netmask, gateway)
+pub fn get_config(index: usize) -> (IPv4, IPv4, IPv4) {
+    let ifaces = INTERFACES.exclusive_access();
+    (ifaces[index].ip, ifaces[index].netmask, ifaces[index].gateway)
+}
+
+pub fn set_addr(index: usize, ip: IPv4) {
+    INTERFACES.exclusive_access()[index].ip = ip;
+    sync_stack(index);
+    arp::flush(index);
+}
+
+pub fn set_netmask(index: usize, netmask: IPv4) {
+    INTERFACES.exclusive_access()[index].netmask = netmask;
+}
+
+pub fn set_gateway(index: usize, gateway: IPv4) {
+    INTERFACES.exclusive_access()[index].gateway = gateway;
+}
+
+/// lose-net-stack only knows about one address, keep it on eth0
+fn sync_stack(index: usize) {
+    if index != 0 {
+        return;
+    }
+    let (ip, mac) = {
+        let ifaces = INTERFACES.exclusive_access();
+        (ifaces[0].ip, ifaces[0].mac)
+    };
+    let mut stack = LOSE_NET_STACK.0.exclusive_access();
+    stack.ip = ip;
+    stack.mac = lose_net_stack::MacAddress::new(mac);
+}
+
+pub fn is_iface_addr(ip: IPv4) -> bool {
+    ip != unspecified()
+        && INTERFACES
+            .exclusive_access()
+            .iter()
+            .any(|iface| iface.ip == ip)
+}
+
+/// limited broadcast or the directed broadcast of the interface subnet
+pub fn is_broadcast(index: usize, ip: IPv4) -> bool {
+    let ifaces = INTERFACES.exclusive_access();
+    let iface = &ifaces[index];
+    ip.to_u32() == u32::MAX
+        || (iface.netmask.to_u32() != u32::MAX
+            && ip.to_u32() == iface.ip.to_u32() | !iface.netmask.to_u32())
+}
+
+fn same_subnet(a: IPv4, b: IPv4, netmask: IPv4) -> bool {
+    a.to_u32() & netmask.to_u32() == b.to_u32() & netmask.to_u32()
+}
+
+/// pick the outgoing interface and the next hop for `dest`
+pub fn route(dest: IPv4) -> Option<(usize, IPv4)> {
+    let ifaces = INTERFACES.exclusive_access();
+    if dest.to_u32() == u32::MAX {
+        return if ifaces.is_empty() { None } else { Some((0, dest)) };
+    }
+    // directly connected first
+    for (i, iface) in ifaces.iter().enumerate() {
+        if iface.ip != unspecified() && same_subnet(dest, iface.ip, iface.netmask) {
+            return Some((i, dest));
+        }
+    }
+    // then the default gateway
+    for (i, iface) in ifaces.iter().enumerate() {
+        if iface.gateway != unspecified() {
+            return Some((i, iface.gateway));
+        }
+    }
+    None
+}
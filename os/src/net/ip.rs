@@ -0,0 +1,122 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+use lose_net_stack::IPv4;
+
+use super::local_hw_addr_for;
+
+pub const ETH_HEADER_LEN: usize = 14;
+pub const ETH_TYPE_IPV4: u16 = 0x0800;
+pub const IPV4_HEADER_LEN: usize = 20;
+pub const UDP_HEADER_LEN: usize = 8;
+
+pub const IPPROTO_ICMP: u8 = 1;
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+
+const DEFAULT_TTL: u8 = 64;
+
+static NEXT_IDENT: AtomicU16 = AtomicU16::new(1);
+
+/// an ipv4 packet inside an ethernet frame
+pub struct Ipv4Packet<'a> {
+    pub src_mac: [u8; 6],
+    pub src: IPv4,
+    pub dst: IPv4,
+    pub protocol: u8,
+    /// ip header and payload, without ethernet padding
+    pub packet: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+pub fn parse_ipv4(frame: &[u8]) -> Option<Ipv4Packet> {
+    if frame.len() < ETH_HEADER_LEN + IPV4_HEADER_LEN
+        || u16::from_be_bytes([frame[12], frame[13]]) != ETH_TYPE_IPV4
+    {
+        return None;
+    }
+    let packet = &frame[ETH_HEADER_LEN..];
+    if packet[0] >> 4 != 4 {
+        return None;
+    }
+    let header_len = (packet[0] & 0xf) as usize * 4;
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if header_len < IPV4_HEADER_LEN || total_len < header_len || total_len > packet.len() {
+        return None;
+    }
+    let mut src_mac = [0u8; 6];
+    src_mac.copy_from_slice(&frame[6..12]);
+    Some(Ipv4Packet {
+        src_mac,
+        src: IPv4::from_u32(u32::from_be_bytes([packet[12], packet[13], packet[14], packet[15]])),
+        dst: IPv4::from_u32(u32::from_be_bytes([packet[16], packet[17], packet[18], packet[19]])),
+        protocol: packet[9],
+        packet: &packet[..total_len],
+        payload: &packet[header_len..total_len],
+    })
+}
+
+/// internet checksum, rfc 1071
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in data.chunks(2) {
+        let word = if chunk.len() == 2 {
+            u16::from_be_bytes([chunk[0], chunk[1]])
+        } else {
+            u16::from_be_bytes([chunk[0], 0])
+        };
+        sum += word as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// wrap `payload` into an ipv4 packet and an ethernet frame for `dst`
+pub fn build_ipv4_frame(dst: IPv4, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let (src, src_mac) = local_hw_addr_for(dst);
+    // the destination mac is filled in by arp on the way out
+    build_ipv4_frame_from(src, src_mac, dst, [0; 6], protocol, payload)
+}
+
+pub fn build_ipv4_frame_from(
+    src: IPv4,
+    src_mac: [u8; 6],
+    dst: IPv4,
+    dst_mac: [u8; 6],
+    protocol: u8,
+    payload: &[u8],
+) -> Vec<u8> {
+    let total_len = IPV4_HEADER_LEN + payload.len();
+    let mut frame = Vec::with_capacity(ETH_HEADER_LEN + total_len);
+    frame.extend_from_slice(&dst_mac);
+    frame.extend_from_slice(&src_mac);
+    frame.extend_from_slice(&ETH_TYPE_IPV4.to_be_bytes());
+
+    let mut header = [0u8; IPV4_HEADER_LEN];
+    header[0] = 0x45;
+    header[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+    let ident = NEXT_IDENT.fetch_add(1, Ordering::Relaxed);
+    header[4..6].copy_from_slice(&ident.to_be_bytes());
+    header[8] = DEFAULT_TTL;
+    header[9] = protocol;
+    header[12..16].copy_from_slice(&src.to_u32().to_be_bytes());
+    header[16..20].copy_from_slice(&dst.to_u32().to_be_bytes());
+    let sum = checksum(&header);
+    header[10..12].copy_from_slice(&sum.to_be_bytes());
+
+    frame.extend_from_slice(&header);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// udp header in front of `payload`, the checksum is optional over ipv4
+pub fn build_udp(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+    let mut udp = Vec::with_capacity(UDP_HEADER_LEN + payload.len());
+    udp.extend_from_slice(&src_port.to_be_bytes());
+    udp.extend_from_slice(&dst_port.to_be_bytes());
+    udp.extend_from_slice(&((UDP_HEADER_LEN + payload.len()) as u16).to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+    udp
+}
@@ -1,24 +1,35 @@
 #[allow(unused)]
 
 
+pub mod arp;
+pub mod dhcp;
+pub mod icmp;
+pub mod iface;
+pub mod ip;
 pub mod port_table;
 pub mod socket;
+pub mod socket_fd;
 pub mod tcp;
 pub mod udp;
+pub mod unix;
 
 pub use lose_net_stack::IPv4;
 
-use alloc::{sync::Arc, vec};
-use lose_net_stack::{results::Packet, LoseStack, MacAddress, TcpFlags};
+use alloc::{sync::Arc, vec, vec::Vec};
+use lose_net_stack::{results::Packet, LoseStack, MacAddress};
 
 use crate::{
-    drivers::NET_DEVICE,
+    drivers::{NetDevice, LOOPBACK_DEVICE},
     net::socket::{get_socket, push_data},
     sync::UPIntrFreeCell,
 };
+use core::sync::atomic::{AtomicBool, Ordering};
+use ip::{parse_ipv4, IPPROTO_ICMP};
 
-use self::{port_table::check_accept, socket::set_s_a_by_index};
+/// largest ethernet frame without vlan tag
+pub const MAX_FRAME_SIZE: usize = 1514;
 
+/// packet parser, iface::init replaces the address with the one of eth0
 pub struct NetStack(UPIntrFreeCell<LoseStack>);
 
 impl NetStack {
@@ -36,70 +47,149 @@ lazy_static::lazy_static! {
     static ref LOSE_NET_STACK: Arc<NetStack> = Arc::new(NetStack::new());
 }
 
-pub fn net_interrupt_handler() {
-    let mut recv_buf = vec![0u8; 1024];
+pub fn init() {
+    iface::init();
+}
 
-    let len = NET_DEVICE.receive(&mut recv_buf);
+pub fn is_loopback(ip: IPv4) -> bool {
+    ip.to_u32() >> 24 == 127
+}
 
-    let packet = LOSE_NET_STACK
-        .0
-        .exclusive_access()
-        .analysis(&recv_buf[..len]);
+/// source address and mac to use when talking to `dest`
+pub fn local_hw_addr_for(dest: IPv4) -> (IPv4, [u8; 6]) {
+    if is_loopback(dest) {
+        return (IPv4::new(127, 0, 0, 1), [0; 6]);
+    }
+    if iface::is_iface_addr(dest) {
+        return (dest, [0; 6]);
+    }
+    match iface::route(dest) {
+        Some((index, _)) => {
+            let (ip, _, _) = iface::get_config(index);
+            (ip, iface::mac(index))
+        }
+        None => (iface::unspecified(), [0; 6]),
+    }
+}
 
-    // println!("[kernel] receive a packet");
-    // hexdump(&recv_buf[..len]);
+pub fn local_addr_for(dest: IPv4) -> (IPv4, MacAddress) {
+    let (ip, mac) = local_hw_addr_for(dest);
+    (ip, MacAddress::new(mac))
+}
 
-    match packet {
-        Packet::ARP(arp_packet) => {
-            let lose_stack = LOSE_NET_STACK.0.exclusive_access();
-            let reply_packet = arp_packet
-                .reply_packet(lose_stack.ip, lose_stack.mac)
-                .expect("can't build reply");
-            let reply_data = reply_packet.build_data();
-            NET_DEVICE.transmit(&reply_data)
-        }
+pub fn is_local_addr(ip: IPv4) -> bool {
+    is_loopback(ip) || iface::is_iface_addr(ip)
+}
 
-        Packet::UDP(udp_packet) => {
-            let target = udp_packet.source_ip;
-            let lport = udp_packet.dest_port;
-            let rport = udp_packet.source_port;
+/// 127.0.0.0/8 and our own addresses go back into the receive path,
+/// everything else leaves through the interface the route points at,
+/// addressed to the next hop once arp knows it
+pub fn transmit_frame(frame: &[u8]) {
+    let dest = match parse_ipv4(frame) {
+        Some(packet) => packet.dst,
+        None => return,
+    };
+    if is_local_addr(dest) {
+        LOOPBACK_DEVICE.transmit(frame);
+        // the loopback device never interrupts, deliver right away
+        net_poll();
+    } else if let Some((index, next_hop)) = iface::route(dest) {
+        arp::output(index, next_hop, frame);
+    }
+}
 
-            if let Some(socket_index) = get_socket(target, lport, rport) {
-                push_data(socket_index, udp_packet.data.to_vec());
+/// send frames built while holding protocol tables, after the tables are released
+pub fn transmit_frames(frames: Vec<Vec<u8>>) {
+    for frame in frames {
+        transmit_frame(&frame);
+    }
+}
+
+/// set while some context is draining the devices, handlers may transmit again
+static POLLING: AtomicBool = AtomicBool::new(false);
+
+/// handle every frame waiting on the loopback and the NICs
+pub fn net_poll() {
+    let mut recv_buf = vec![0u8; MAX_FRAME_SIZE];
+    loop {
+        if POLLING.swap(true, Ordering::Acquire) {
+            return;
+        }
+        loop {
+            let mut received = false;
+            let len = LOOPBACK_DEVICE.receive(&mut recv_buf);
+            if len > 0 {
+                handle_frame(None, &recv_buf[..len]);
+                received = true;
+            }
+            for index in 0..iface::iface_count() {
+                let len = iface::device(index).receive(&mut recv_buf);
+                if len > 0 {
+                    handle_frame(Some(index), &recv_buf[..len]);
+                    received = true;
+                }
+            }
+            if !received {
+                break;
             }
         }
+        POLLING.store(false, Ordering::Release);
+        // a frame may have come in after the last check
+        if !LOOPBACK_DEVICE.pending() {
+            return;
+        }
+    }
+}
 
-        Packet::TCP(tcp_packet) => {
-            let target = tcp_packet.source_ip;
-            let lport = tcp_packet.dest_port;
-            let rport = tcp_packet.source_port;
-            let flags = tcp_packet.flags;
-
-            if flags.contains(TcpFlags::S) {
-                // if it has a port to accept, then response the request
-                if check_accept(lport, &tcp_packet).is_some() {
-                    let mut reply_packet = tcp_packet.ack();
-                    reply_packet.flags = TcpFlags::S | TcpFlags::A;
-                    NET_DEVICE.transmit(&reply_packet.build_data());
-                }
-                return;
-            } else if tcp_packet.flags.contains(TcpFlags::F) {
-                // tcp disconnected
-                let reply_packet = tcp_packet.ack();
-                NET_DEVICE.transmit(&reply_packet.build_data());
-
-                let mut end_packet = reply_packet.ack();
-                end_packet.flags |= TcpFlags::F;
-                NET_DEVICE.transmit(&end_packet.build_data());
-            } else if tcp_packet.flags.contains(TcpFlags::A) && tcp_packet.data_len == 0 {
+/// PLIC source of a virtio-net device
+pub fn net_interrupt_handler(irq: usize) {
+    if let Some(index) = iface::find_by_irq(irq) {
+        iface::device(index).handle_irq();
+    }
+    net_poll();
+}
+
+/// `iface` is None for frames from the loopback device
+fn handle_frame(iface: Option<usize>, frame: &[u8]) {
+    if frame.len() >= ip::ETH_HEADER_LEN
+        && u16::from_be_bytes([frame[12], frame[13]]) == arp::ETH_TYPE_ARP
+    {
+        if let Some(index) = iface {
+            arp::handle_arp(index, frame);
+        }
+        return;
+    }
+    if let Some(packet) = parse_ipv4(frame) {
+        // lose-net-stack knows nothing useful about icmp
+        if packet.protocol == IPPROTO_ICMP {
+            icmp::handle_icmp(&packet);
+            return;
+        }
+        if let Some(index) = iface {
+            if dhcp::is_dhcp_reply(&packet) {
+                dhcp::handle_reply(index, &packet);
                 return;
             }
+        }
+    }
+
+    let packet = LOSE_NET_STACK.0.exclusive_access().analysis(frame);
+
+    // println!("[kernel] receive a packet");
+    // hexdump(frame);
+
+    match packet {
+        Packet::UDP(udp_packet) => {
+            let target = udp_packet.source_ip;
+            let lport = udp_packet.dest_port;
+            let rport = udp_packet.source_port;
 
             if let Some(socket_index) = get_socket(target, lport, rport) {
-                push_data(socket_index, tcp_packet.data.to_vec());
-                set_s_a_by_index(socket_index, tcp_packet.seq, tcp_packet.ack);
+                push_data(socket_index, target, rport, udp_packet.data.to_vec());
             }
         }
+
+        Packet::TCP(tcp_packet) => tcp::handle_segment(&tcp_packet),
         _ => {}
     }
 }
@@ -1,20 +1,24 @@
-#[allow(unused)]
-
-
-use alloc::{sync::Arc, vec::Vec};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::lazy_static;
-use lose_net_stack::packets::tcp::TCPPacket;
 
-use crate::fs::File;
-use crate::sync::UPIntrFreeCell;
-use crate::task::TaskControlBlock;
+use crate::fs::{File, PollEvents};
+use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::task::schedule;
+
+use super::tcp;
 
-use super::tcp::TCP;
+/// max connections waiting in the accept queue when listen gives no backlog
+pub const DEFAULT_BACKLOG: usize = 16;
 
 pub struct Port {
     pub port: u16,
-    pub receivable: bool,
-    pub schedule: Option<Arc<TaskControlBlock>>,
+    pub backlog: usize,
+    /// established connections (tcb index) not accepted yet
+    pub established: VecDeque<usize>,
+    /// tasks blocked in accept
+    pub condvar: Arc<Condvar>,
 }
 
 lazy_static! {
@@ -22,8 +26,14 @@ lazy_static! {
         unsafe { UPIntrFreeCell::new(Vec::new()) };
 }
 
-pub fn listen(port: u16) -> Option<usize> {
+pub fn listen(port: u16, backlog: usize) -> Option<usize> {
     let mut listen_table = LISTEN_TABLE.exclusive_access();
+    if listen_table
+        .iter()
+        .any(|x| matches!(x, Some(p) if p.port == port))
+    {
+        return None;
+    }
     let mut index = usize::MAX;
     for i in 0..listen_table.len() {
         if listen_table[i].is_none() {
@@ -34,8 +44,9 @@ pub fn listen(port: u16) -> Option<usize> {
 
     let listen_port = Port {
         port,
-        receivable: false,
-        schedule: None,
+        backlog: if backlog == 0 { DEFAULT_BACKLOG } else { backlog },
+        established: VecDeque::new(),
+        condvar: Arc::new(Condvar::new()),
     };
 
     if index == usize::MAX {
@@ -47,71 +58,53 @@ pub fn listen(port: u16) -> Option<usize> {
     }
 }
 
-// can accept request
-pub fn accept(listen_index: usize, task: Arc<TaskControlBlock>) {
-    let mut listen_table = LISTEN_TABLE.exclusive_access();
-    assert!(listen_index < listen_table.len());
-    let listen_port = listen_table[listen_index].as_mut();
-    assert!(listen_port.is_some());
-    let listen_port = listen_port.unwrap();
-    listen_port.receivable = true;
-    listen_port.schedule = Some(task);
+/// some socket listens on `port`, whether or not its queue is full
+pub fn is_listening(port: u16) -> bool {
+    LISTEN_TABLE
+        .exclusive_access()
+        .iter()
+        .any(|x| matches!(x, Some(p) if p.port == port))
 }
 
-pub fn port_acceptable(listen_index: usize) -> bool {
-    let mut listen_table = LISTEN_TABLE.exclusive_access();
-    assert!(listen_index < listen_table.len());
-
-    let listen_port = listen_table[listen_index].as_mut();
-    listen_port.map_or(false, |x| x.receivable)
+/// find the listening port a SYN should go to, None if nobody listens or the queue is full
+pub fn find_listener(port: u16) -> Option<usize> {
+    let listen_table = LISTEN_TABLE.exclusive_access();
+    listen_table.iter().position(|x| match x {
+        Some(p) => p.port == port && p.established.len() < p.backlog,
+        None => false,
+    })
 }
 
-// check whether it can accept request
-pub fn check_accept(port: u16, tcp_packet: &TCPPacket) -> Option<()> {
-    LISTEN_TABLE.exclusive_session(|listen_table| {
-        let mut listen_ports: Vec<&mut Option<Port>> = listen_table
-            .iter_mut()
-            .filter(|x| match x {
-                Some(t) => t.port == port && t.receivable == true,
-                None => false,
-            })
-            .collect();
-        if listen_ports.len() == 0 {
-            None
-        } else {
-            let listen_port = listen_ports[0].as_mut().unwrap();
-            let task = listen_port.schedule.clone().unwrap();
-            // wakeup_task(Arc::clone(&listen_port.schedule.clone().unwrap()));
-            listen_port.schedule = None;
-            listen_port.receivable = false;
-
-            accept_connection(port, tcp_packet, task);
-            Some(())
+/// a passive open finished the handshake, queue it for accept
+pub fn push_established(listen_index: usize, port: u16, tcb_index: usize) -> bool {
+    let mut listen_table = LISTEN_TABLE.exclusive_access();
+    match listen_table.get_mut(listen_index) {
+        Some(Some(p)) if p.port == port => {
+            p.established.push_back(tcb_index);
+            p.condvar.signal();
+            true
         }
-    })
+        _ => false,
+    }
 }
 
-pub fn accept_connection(_port: u16, tcp_packet: &TCPPacket, task: Arc<TaskControlBlock>) {
-    let process = task.process.upgrade().unwrap();
-    let mut inner = process.inner_exclusive_access();
-    let fd = inner.alloc_fd();
-
-    let tcp_socket = TCP::new(
-        tcp_packet.source_ip,
-        tcp_packet.dest_port,
-        tcp_packet.source_port,
-        tcp_packet.seq,
-        tcp_packet.ack,
-    );
-
-    //inner.fd_table[fd] = Some(Arc::new(tcp_socket));
-
-    let cx = task.inner_exclusive_access().get_trap_cx();
-    cx.x[10] = fd;
+// take an established connection, block until there is one
+pub fn accept(listen_index: usize) -> Option<usize> {
+    loop {
+        let mut listen_table = LISTEN_TABLE.exclusive_access();
+        assert!(listen_index < listen_table.len());
+        let port = listen_table[listen_index].as_mut()?;
+        if let Some(tcb_index) = port.established.pop_front() {
+            return Some(tcb_index);
+        }
+        let task_cx_ptr = port.condvar.wait_no_sched();
+        drop(listen_table);
+        schedule(task_cx_ptr);
+    }
 }
 
 // store in the fd_table, delete the listen table when close the application.
-pub struct PortFd(usize);
+pub struct PortFd(pub usize);
 
 impl PortFd {
     pub fn new(port_index: usize) -> Self {
@@ -121,7 +114,13 @@ impl PortFd {
 
 impl Drop for PortFd {
     fn drop(&mut self) {
-        LISTEN_TABLE.exclusive_access()[self.0] = None
+        let port = LISTEN_TABLE.exclusive_access()[self.0].take();
+        // reset the connections nobody accepted
+        if let Some(port) = port {
+            for tcb_index in port.established {
+                tcp::abort(tcb_index);
+            }
+        }
     }
 }
 
@@ -141,4 +140,13 @@ impl File for PortFd {
     fn write(&self, _buf: crate::mm::UserBuffer) -> usize {
         0
     }
+
+    /// readable once a connection waits for accept
+    fn poll(&self) -> PollEvents {
+        let listen_table = LISTEN_TABLE.exclusive_access();
+        match &listen_table[self.0] {
+            Some(port) if !port.established.is_empty() => PollEvents::POLLIN,
+            _ => PollEvents::empty(),
+        }
+    }
 }
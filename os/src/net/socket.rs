@@ -1,18 +1,19 @@
 use alloc::collections::VecDeque;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use lazy_static::lazy_static;
 use lose_net_stack::IPv4;
 
-use crate::sync::UPIntrFreeCell;
+use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::task::schedule;
 
-// TODO: specify the protocol, TCP or UDP
+// udp sockets, tcp connections live in the tcp module
 pub struct Socket {
-    pub raddr: IPv4,                // remote address
-    pub lport: u16,                 // local port
-    pub rport: u16,                 // rempote port
-    pub buffers: VecDeque<Vec<u8>>, // datas
-    pub seq: u32,
-    pub ack: u32,
+    pub raddr: IPv4,                             // remote address
+    pub lport: u16,                              // local port
+    pub rport: u16,                              // rempote port, 0 until connected
+    pub buffers: VecDeque<(IPv4, u16, Vec<u8>)>, // datas and where they came from
+    pub condvar: Arc<Condvar>,                   // readers waiting for datas
 }
 
 lazy_static! {
@@ -20,30 +21,8 @@ lazy_static! {
         unsafe { UPIntrFreeCell::new(Vec::new()) };
 }
 
-/// get the seq and ack by socket index
-pub fn get_s_a_by_index(index: usize) -> Option<(u32, u32)> {
-    let socket_table = SOCKET_TABLE.exclusive_access();
-
-    assert!(index < socket_table.len());
-
-    socket_table.get(index).map_or(None, |x| match x {
-        Some(x) => Some((x.seq, x.ack)),
-        None => None,
-    })
-}
-
-pub fn set_s_a_by_index(index: usize, seq: u32, ack: u32) {
-    let mut socket_table = SOCKET_TABLE.exclusive_access();
-
-    assert!(socket_table.len() > index);
-    assert!(socket_table[index].is_some());
-
-    let sock = socket_table[index].as_mut().unwrap();
-
-    sock.ack = ack;
-    sock.seq = seq;
-}
-
+/// the socket on `lport` taking datagrams from (raddr, rport),
+/// a connected socket only takes the ones from its peer
 pub fn get_socket(raddr: IPv4, lport: u16, rport: u16) -> Option<usize> {
     let socket_table = SOCKET_TABLE.exclusive_access();
     for i in 0..socket_table.len() {
@@ -53,19 +32,24 @@ pub fn get_socket(raddr: IPv4, lport: u16, rport: u16) -> Option<usize> {
         }
 
         let sock = sock.as_ref().unwrap();
-        if sock.raddr == raddr && sock.lport == lport && sock.rport == rport {
+        if sock.lport == lport && (sock.rport == 0 || sock.raddr == raddr && sock.rport == rport) {
             return Some(i);
         }
     }
     None
 }
 
-pub fn add_socket(raddr: IPv4, lport: u16, rport: u16) -> Option<usize> {
-    if get_socket(raddr, lport, rport).is_some() {
+/// None if `lport` is already in use
+pub fn add_socket(lport: u16) -> Option<usize> {
+    let mut socket_table = SOCKET_TABLE.exclusive_access();
+    if socket_table
+        .iter()
+        .flatten()
+        .any(|sock| sock.lport == lport)
+    {
         return None;
     }
 
-    let mut socket_table = SOCKET_TABLE.exclusive_access();
     let mut index = usize::MAX;
     for i in 0..socket_table.len() {
         if socket_table[i].is_none() {
@@ -75,12 +59,11 @@ pub fn add_socket(raddr: IPv4, lport: u16, rport: u16) -> Option<usize> {
     }
 
     let socket = Socket {
-        raddr,
+        raddr: IPv4::from_u32(0),
         lport,
-        rport,
+        rport: 0,
         buffers: VecDeque::new(),
-        seq: 0,
-        ack: 0,
+        condvar: Arc::new(Condvar::new()),
     };
 
     if index == usize::MAX {
@@ -92,6 +75,17 @@ pub fn add_socket(raddr: IPv4, lport: u16, rport: u16) -> Option<usize> {
     }
 }
 
+pub fn connect_socket(index: usize, raddr: IPv4, rport: u16) {
+    let mut socket_table = SOCKET_TABLE.exclusive_access();
+
+    assert!(socket_table.len() > index);
+    assert!(socket_table[index].is_some());
+
+    let sock = socket_table[index].as_mut().unwrap();
+    sock.raddr = raddr;
+    sock.rport = rport;
+}
+
 pub fn remove_socket(index: usize) {
     let mut socket_table = SOCKET_TABLE.exclusive_access();
 
@@ -100,20 +94,18 @@ pub fn remove_socket(index: usize) {
     socket_table[index] = None;
 }
 
-pub fn push_data(index: usize, data: Vec<u8>) {
+pub fn push_data(index: usize, raddr: IPv4, rport: u16, data: Vec<u8>) {
     let mut socket_table = SOCKET_TABLE.exclusive_access();
 
     assert!(socket_table.len() > index);
     assert!(socket_table[index].is_some());
 
-    socket_table[index]
-        .as_mut()
-        .unwrap()
-        .buffers
-        .push_back(data);
+    let sock = socket_table[index].as_mut().unwrap();
+    sock.buffers.push_back((raddr, rport, data));
+    sock.condvar.signal();
 }
 
-pub fn pop_data(index: usize) -> Option<Vec<u8>> {
+pub fn pop_data(index: usize) -> Option<(IPv4, u16, Vec<u8>)> {
     let mut socket_table = SOCKET_TABLE.exclusive_access();
 
     assert!(socket_table.len() > index);
@@ -121,3 +113,26 @@ pub fn pop_data(index: usize) -> Option<Vec<u8>> {
 
     socket_table[index].as_mut().unwrap().buffers.pop_front()
 }
+
+pub fn has_data(index: usize) -> bool {
+    let socket_table = SOCKET_TABLE.exclusive_access();
+    matches!(&socket_table[index], Some(sock) if !sock.buffers.is_empty())
+}
+
+/// pop data and its sender, block until there is some
+pub fn wait_data(index: usize) -> (IPv4, u16, Vec<u8>) {
+    loop {
+        let mut socket_table = SOCKET_TABLE.exclusive_access();
+
+        assert!(socket_table.len() > index);
+        assert!(socket_table[index].is_some());
+
+        let sock = socket_table[index].as_mut().unwrap();
+        if let Some(data) = sock.buffers.pop_front() {
+            return data;
+        }
+        let task_cx_ptr = sock.condvar.wait_no_sched();
+        drop(socket_table);
+        schedule(task_cx_ptr);
+    }
+}
@@ -0,0 +1,169 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+
+use crate::fs::{File, PollEvents};
+use crate::mm::UserBuffer;
+use crate::sync::{UPIntrFreeCell, UPIntrRefMut};
+
+use super::icmp::ICMP;
+use super::port_table::PortFd;
+use super::tcp::TCP;
+use super::udp::UDP;
+use super::unix::{self, UnixDatagram, UnixListener, UnixStream};
+
+pub const AF_UNIX: usize = unix::AF_UNIX;
+pub const AF_INET: usize = 2;
+
+pub const SOCK_STREAM: usize = 1;
+pub const SOCK_DGRAM: usize = 2;
+pub const SOCK_RAW: usize = 3;
+/// SOCK_NONBLOCK and SOCK_CLOEXEC share the type argument of socket
+pub const SOCK_TYPE_MASK: usize = 0xf;
+
+pub const IPPROTO_ICMP: usize = 1;
+
+#[derive(Clone)]
+pub enum SocketState {
+    Unconnected,
+    Listening(Arc<PortFd>),
+    Stream(Arc<TCP>),
+    Datagram(Arc<UDP>),
+    Icmp(Arc<ICMP>),
+    UnixListening(Arc<UnixListener>),
+    UnixStream(Arc<UnixStream>),
+    UnixDatagram(Arc<UnixDatagram>),
+}
+
+/// what socket() hands out, becomes a listener or a connection later on
+pub struct SocketFd {
+    pub domain: usize,
+    pub sock_type: usize,
+    inner: UPIntrFreeCell<SocketFdInner>,
+}
+
+pub struct SocketFdInner {
+    pub local_port: Option<u16>,
+    /// AF_UNIX name given to bind, released when the socket goes away
+    pub unix_name: Option<String>,
+    pub state: SocketState,
+}
+
+impl SocketFd {
+    pub fn new(domain: usize, sock_type: usize) -> Self {
+        Self {
+            domain,
+            sock_type,
+            inner: unsafe {
+                UPIntrFreeCell::new(SocketFdInner {
+                    local_port: None,
+                    unix_name: None,
+                    state: SocketState::Unconnected,
+                })
+            },
+        }
+    }
+
+    /// socket for a connection returned by accept
+    pub fn from_stream(local_port: u16, tcp: TCP) -> Self {
+        let socket = Self::new(AF_INET, SOCK_STREAM);
+        {
+            let mut inner = socket.inner_exclusive_access();
+            inner.local_port = Some(local_port);
+            inner.state = SocketState::Stream(Arc::new(tcp));
+        }
+        socket
+    }
+
+    /// SOCK_RAW or SOCK_DGRAM with IPPROTO_ICMP, the latter is a ping socket
+    pub fn new_icmp(sock_type: usize) -> Self {
+        let socket = Self::new(AF_INET, sock_type);
+        socket.inner_exclusive_access().state =
+            SocketState::Icmp(Arc::new(ICMP::new(sock_type == SOCK_RAW)));
+        socket
+    }
+
+    /// AF_UNIX socket, a datagram one gets its receive queue right away
+    pub fn new_unix(sock_type: usize) -> Self {
+        let socket = Self::new(AF_UNIX, sock_type);
+        if sock_type == SOCK_DGRAM {
+            socket.inner_exclusive_access().state =
+                SocketState::UnixDatagram(Arc::new(UnixDatagram::new()));
+        }
+        socket
+    }
+
+    /// accept and socketpair on AF_UNIX
+    pub fn from_unix_stream(stream: UnixStream) -> Self {
+        let socket = Self::new(AF_UNIX, SOCK_STREAM);
+        socket.inner_exclusive_access().state = SocketState::UnixStream(Arc::new(stream));
+        socket
+    }
+
+    pub fn from_unix_datagram(datagram: Arc<UnixDatagram>) -> Self {
+        let socket = Self::new(AF_UNIX, SOCK_DGRAM);
+        socket.inner_exclusive_access().state = SocketState::UnixDatagram(datagram);
+        socket
+    }
+
+    pub fn inner_exclusive_access(&self) -> UPIntrRefMut<'_, SocketFdInner> {
+        self.inner.exclusive_access()
+    }
+
+    pub fn state(&self) -> SocketState {
+        self.inner.exclusive_access().state.clone()
+    }
+}
+
+impl File for SocketFd {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, buf: UserBuffer) -> usize {
+        // clone the state out, read may block
+        match self.state() {
+            SocketState::Stream(tcp) => tcp.read(buf),
+            SocketState::Datagram(udp) => udp.read(buf),
+            SocketState::Icmp(icmp) => icmp.read(buf),
+            SocketState::UnixStream(stream) => stream.read(buf),
+            SocketState::UnixDatagram(datagram) => datagram.read(buf),
+            _ => 0,
+        }
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        match self.state() {
+            SocketState::Stream(tcp) => tcp.write(buf),
+            SocketState::Datagram(udp) => udp.write(buf),
+            SocketState::Icmp(icmp) => icmp.write(buf),
+            SocketState::UnixStream(stream) => stream.write(buf),
+            SocketState::UnixDatagram(datagram) => datagram.write(buf),
+            _ => 0,
+        }
+    }
+
+    fn poll(&self) -> PollEvents {
+        match self.state() {
+            SocketState::Unconnected => PollEvents::empty(),
+            SocketState::Listening(port) => port.poll(),
+            SocketState::Stream(tcp) => tcp.poll(),
+            SocketState::Datagram(udp) => udp.poll(),
+            SocketState::Icmp(icmp) => icmp.poll(),
+            SocketState::UnixListening(listener) => listener.poll(),
+            SocketState::UnixStream(stream) => stream.poll(),
+            SocketState::UnixDatagram(datagram) => datagram.poll(),
+        }
+    }
+}
+
+impl Drop for SocketFd {
+    fn drop(&mut self) {
+        if let Some(name) = self.inner.exclusive_access().unix_name.take() {
+            unix::unbind(&name);
+        }
+    }
+}
@@ -1,116 +1,883 @@
-#[allow(unused)]
-
-
-use alloc::vec;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::hash::Hasher;
+use core::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use lazy_static::lazy_static;
 use lose_net_stack::packets::tcp::TCPPacket;
 use lose_net_stack::IPv4;
 use lose_net_stack::MacAddress;
 use lose_net_stack::TcpFlags;
 
-use crate::{drivers::NET_DEVICE, fs::File};
+use crate::fs::{File, PollEvents};
+use crate::mm::UserBuffer;
+use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::task::schedule;
+use crate::timer::{add_timer_callback, get_time, get_time_ms, get_time_ns};
 
-use super::socket::get_s_a_by_index;
-use super::{
-    net_interrupt_handler,
-    socket::{add_socket, pop_data, remove_socket},
-    LOSE_NET_STACK,
-};
+use super::port_table::{find_listener, is_listening, push_established};
+use super::{local_addr_for, transmit_frames};
 
-// add tcp packet info to this structure
-pub struct TCP {
-    pub target: IPv4,
-    pub sport: u16,
-    pub dport: u16,
-    pub seq: u32,
-    pub ack: u32,
-    pub socket_index: usize,
+/// we never send mss option, so the peer will assume the rfc 879 default
+pub const TCP_MSS: usize = 536;
+pub const TCP_SEND_BUFFER_SIZE: usize = 16 * 1024;
+pub const TCP_RECV_BUFFER_SIZE: usize = 16 * 1024;
+/// at most this many out of order segments are kept for reassembly
+const TCP_MAX_OUT_OF_ORDER: usize = 32;
+const TCP_INITIAL_RTO_MS: usize = 1000;
+const TCP_MAX_RTO_MS: usize = 60_000;
+const TCP_MAX_RETRIES: usize = 8;
+/// 2 * MSL, kept short since there is nothing but qemu usernet between the peers
+const TCP_TIME_WAIT_MS: usize = 2000;
+/// a closed connection waiting in FIN_WAIT_2 for the peer's FIN gives up after this
+const TCP_FIN_WAIT2_TIMEOUT_MS: usize = 60_000;
+
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
 }
 
-impl TCP {
-    pub fn new(target: IPv4, sport: u16, dport: u16, seq: u32, ack: u32) -> Self {
-        let index = add_socket(target, sport, dport).expect("can't add socket");
+/// `a < b` in sequence space
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
 
+/// lose-net-stack hands us the rest of the frame, drop the ethernet padding
+fn segment_payload<'a>(packet: &TCPPacket<'a>) -> &'a [u8] {
+    let data = packet.data;
+    &data[..packet.data_len.min(data.len())]
+}
+
+/// transmission control block, one per connection
+pub struct TcpControlBlock {
+    pub state: TcpState,
+    pub local_port: u16,
+    pub remote_ip: IPv4,
+    pub remote_port: u16,
+    /// listening port this connection came from, for passive open
+    pub listen_index: Option<usize>,
+    /// the connection was reset or timed out
+    pub reset: bool,
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_wnd: u32,
+    rcv_nxt: u32,
+    /// data not acked by the peer yet, starting at the byte `snd_una` refers to
+    send_buffer: VecDeque<u8>,
+    recv_buffer: VecDeque<u8>,
+    out_of_order: BTreeMap<u32, Vec<u8>>,
+    /// the user closed the connection, send FIN after the buffered data
+    fin_pending: bool,
+    fin_sent: bool,
+    fin_received: bool,
+    rto_ms: usize,
+    retries: usize,
+    /// id of the armed timer, stale timers find a different id and do nothing
+    timer_id: usize,
+    /// no file refers to this connection any more
+    detached: bool,
+    /// readers, writers and connect wait here for the next segment
+    pub condvar: Arc<Condvar>,
+}
+
+lazy_static! {
+    static ref TCP_TABLE: UPIntrFreeCell<Vec<Option<TcpControlBlock>>> =
+        unsafe { UPIntrFreeCell::new(Vec::new()) };
+}
+
+lazy_static! {
+    /// key for the initial sequence numbers. there is no entropy source, so it comes from
+    /// the cycle counter at the moment the first connection opens
+    static ref ISS_SECRET: (u64, u64) = {
+        let seed = get_time() as u64;
+        (seed, seed.rotate_left(32) ^ 0x9e37_79b9_7f4a_7c15)
+    };
+}
+
+static NEXT_TIMER_ID: AtomicUsize = AtomicUsize::new(1);
+static NEXT_EPHEMERAL_PORT: AtomicU16 = AtomicU16::new(EPHEMERAL_PORT_START);
+
+/// rfc 6528: a 4 microsecond clock plus a keyed hash of the 4-tuple, so an off-path
+/// attacker can't predict the sequence numbers of somebody else's connection
+#[allow(deprecated)]
+fn initial_sequence_number(local_port: u16, remote_ip: IPv4, remote_port: u16) -> u32 {
+    let (local_ip, _) = local_addr_for(remote_ip);
+    let mut hasher = core::hash::SipHasher::new_with_keys(ISS_SECRET.0, ISS_SECRET.1);
+    hasher.write_u32(local_ip.to_u32());
+    hasher.write_u16(local_port);
+    hasher.write_u32(remote_ip.to_u32());
+    hasher.write_u16(remote_port);
+    let clock = (get_time_ns() / 4000) as u32;
+    clock.wrapping_add(hasher.finish() as u32)
+}
+
+impl TcpControlBlock {
+    fn new(local_port: u16, remote_ip: IPv4, remote_port: u16, state: TcpState) -> Self {
+        let iss = initial_sequence_number(local_port, remote_ip, remote_port);
         Self {
-            target,
-            sport,
-            dport,
+            state,
+            local_port,
+            remote_ip,
+            remote_port,
+            listen_index: None,
+            reset: false,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss.wrapping_add(1),
+            snd_wnd: 0,
+            rcv_nxt: 0,
+            send_buffer: VecDeque::new(),
+            recv_buffer: VecDeque::new(),
+            out_of_order: BTreeMap::new(),
+            fin_pending: false,
+            fin_sent: false,
+            fin_received: false,
+            rto_ms: TCP_INITIAL_RTO_MS,
+            retries: 0,
+            timer_id: 0,
+            detached: false,
+            condvar: Arc::new(Condvar::new()),
+        }
+    }
+
+    fn recv_window(&self) -> usize {
+        TCP_RECV_BUFFER_SIZE - self.recv_buffer.len()
+    }
+
+    fn build_segment(&self, seq: u32, flags: TcpFlags, payload: &[u8]) -> Vec<u8> {
+        let (ip, mac) = local_addr_for(self.remote_ip);
+        let ack = if flags.contains(TcpFlags::A) {
+            self.rcv_nxt
+        } else {
+            0
+        };
+        let packet = TCPPacket {
+            source_ip: ip,
+            source_mac: mac,
+            source_port: self.local_port,
+            dest_ip: self.remote_ip,
+            // filled in by arp on the way out
+            dest_mac: MacAddress::new([0; 6]),
+            dest_port: self.remote_port,
+            data_len: payload.len(),
             seq,
             ack,
-            socket_index: index,
+            flags,
+            win: self.recv_window().min(u16::MAX as usize) as u16,
+            urg: 0,
+            data: payload,
+        };
+        packet.build_data()
+    }
+
+    fn ack_segment(&self) -> Vec<u8> {
+        self.build_segment(self.snd_nxt, TcpFlags::A, &[])
+    }
+
+    fn syn_segment(&self) -> Vec<u8> {
+        match self.state {
+            TcpState::SynReceived => self.build_segment(self.iss, TcpFlags::S | TcpFlags::A, &[]),
+            _ => self.build_segment(self.iss, TcpFlags::S, &[]),
         }
     }
-}
 
-impl File for TCP {
-    fn readable(&self) -> bool {
-        true
+    fn arm_timer(&mut self, index: usize, timeout_ms: usize) {
+        let id = NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+        self.timer_id = id;
+        add_timer_callback(get_time_ms() + timeout_ms, move || {
+            on_timeout(index, id);
+        });
     }
 
-    fn writable(&self) -> bool {
+    fn cancel_timer(&mut self) {
+        self.timer_id = 0;
+    }
+
+    /// send as much buffered data as the peer's window allows, then FIN if requested
+    fn output(&mut self, index: usize, frames: &mut Vec<Vec<u8>>) {
+        match self.state {
+            TcpState::Established | TcpState::CloseWait => {}
+            _ => return,
+        }
+        while !self.fin_sent {
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let unsent = self.send_buffer.len() - in_flight;
+            let usable = (self.snd_wnd as usize).saturating_sub(in_flight);
+            let len = unsent.min(usable).min(TCP_MSS);
+            if len == 0 {
+                if unsent == 0 && self.fin_pending {
+                    frames.push(self.build_segment(self.snd_nxt, TcpFlags::F | TcpFlags::A, &[]));
+                    self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                    self.fin_sent = true;
+                    self.state = match self.state {
+                        TcpState::CloseWait => TcpState::LastAck,
+                        _ => TcpState::FinWait1,
+                    };
+                }
+                break;
+            }
+            let payload: Vec<u8> = self
+                .send_buffer
+                .range(in_flight..in_flight + len)
+                .copied()
+                .collect();
+            frames.push(self.build_segment(self.snd_nxt, TcpFlags::A | TcpFlags::P, &payload));
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+        }
+        if self.timer_id == 0 && (self.snd_nxt != self.snd_una || !self.send_buffer.is_empty()) {
+            // retransmission timer, doubles as the persist timer for a zero window
+            self.arm_timer(index, self.rto_ms);
+        }
+    }
+
+    /// retransmit the earliest unacknowledged segment
+    fn retransmit(&mut self, frames: &mut Vec<Vec<u8>>) {
+        match self.state {
+            TcpState::SynSent | TcpState::SynReceived => frames.push(self.syn_segment()),
+            _ => {
+                let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+                if in_flight == 0 {
+                    if !self.send_buffer.is_empty() && self.snd_wnd == 0 {
+                        // zero window probe
+                        let probe = [self.send_buffer[0]];
+                        frames.push(self.build_segment(self.snd_nxt, TcpFlags::A, &probe));
+                        self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                    }
+                    return;
+                }
+                let len = in_flight.min(self.send_buffer.len()).min(TCP_MSS);
+                if len > 0 {
+                    let payload: Vec<u8> = self.send_buffer.range(..len).copied().collect();
+                    frames.push(self.build_segment(
+                        self.snd_una,
+                        TcpFlags::A | TcpFlags::P,
+                        &payload,
+                    ));
+                } else if self.fin_sent {
+                    frames.push(self.build_segment(self.snd_una, TcpFlags::F | TcpFlags::A, &[]));
+                }
+            }
+        }
+    }
+
+    fn enter_time_wait(&mut self, index: usize) {
+        self.state = TcpState::TimeWait;
+        self.arm_timer(index, TCP_TIME_WAIT_MS);
+    }
+
+    /// our FIN is acked; once nobody can read the connection, don't wait for the peer forever
+    fn enter_fin_wait2(&mut self, index: usize) {
+        self.state = TcpState::FinWait2;
+        self.cancel_timer();
+        if self.detached {
+            self.arm_timer(index, TCP_FIN_WAIT2_TIMEOUT_MS);
+        }
+    }
+
+    /// process an acceptable ack, returns whether the connection just became established
+    fn process_ack(&mut self, index: usize, ack: u32, win: u16) -> bool {
+        let mut established = false;
+        if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+            let mut acked = ack.wrapping_sub(self.snd_una) as usize;
+            if matches!(self.state, TcpState::SynSent | TcpState::SynReceived) {
+                // the SYN takes one sequence number
+                acked -= 1;
+                self.state = TcpState::Established;
+                established = true;
+            }
+            let data_acked = acked.min(self.send_buffer.len());
+            self.send_buffer.drain(..data_acked);
+            if acked > data_acked && self.fin_sent {
+                // our FIN is acked
+                match self.state {
+                    TcpState::FinWait1 => self.enter_fin_wait2(index),
+                    TcpState::Closing => self.enter_time_wait(index),
+                    TcpState::LastAck => self.state = TcpState::Closed,
+                    _ => {}
+                }
+            }
+            self.snd_una = ack;
+            self.retries = 0;
+            self.rto_ms = TCP_INITIAL_RTO_MS;
+            if !matches!(self.state, TcpState::TimeWait | TcpState::FinWait2) {
+                self.cancel_timer();
+                if self.snd_una != self.snd_nxt {
+                    self.arm_timer(index, self.rto_ms);
+                }
+            }
+        }
+        if seq_le(self.snd_una, ack) {
+            self.snd_wnd = win as u32;
+        }
+        established
+    }
+
+    /// queue in-window data, returns true if anything new was accepted
+    fn process_data(&mut self, seq: u32, payload: &[u8]) -> bool {
+        if payload.is_empty() {
+            return false;
+        }
+        let window = self.recv_window() as u32;
+        let end = seq.wrapping_add(payload.len() as u32);
+        if seq_le(end, self.rcv_nxt) || !seq_lt(seq, self.rcv_nxt.wrapping_add(window)) {
+            // duplicate or beyond the window
+            return false;
+        }
+        if seq_lt(self.rcv_nxt, seq) {
+            if self.out_of_order.len() < TCP_MAX_OUT_OF_ORDER {
+                self.out_of_order.insert(seq, payload.to_vec());
+            }
+            return false;
+        }
+        let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+        self.append_in_order(&payload[skip..]);
+        // reassemble whatever became contiguous
+        while let Some((&seg_seq, _)) = self.out_of_order.iter().next() {
+            if seq_lt(self.rcv_nxt, seg_seq) {
+                break;
+            }
+            let data = self.out_of_order.remove(&seg_seq).unwrap();
+            let seg_end = seg_seq.wrapping_add(data.len() as u32);
+            if seq_lt(self.rcv_nxt, seg_end) {
+                let skip = self.rcv_nxt.wrapping_sub(seg_seq) as usize;
+                self.append_in_order(&data[skip..]);
+            }
+        }
         true
     }
 
-    fn read(&self, mut buf: crate::mm::UserBuffer) -> usize {
-        loop {
-            if let Some(data) = pop_data(self.socket_index) {
-                let data_len = data.len();
-                let mut left = 0;
-                for i in 0..buf.buffers.len() {
-                    let buffer_i_len = buf.buffers[i].len().min(data_len - left);
-
-                    buf.buffers[i][..buffer_i_len]
-                        .copy_from_slice(&data[left..(left + buffer_i_len)]);
-
-                    left += buffer_i_len;
-                    if left == data_len {
-                        break;
+    fn append_in_order(&mut self, data: &[u8]) {
+        let len = data.len().min(self.recv_window());
+        self.recv_buffer.extend(&data[..len]);
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(len as u32);
+    }
+}
+
+fn alloc_tcb(table: &mut Vec<Option<TcpControlBlock>>, tcb: TcpControlBlock) -> usize {
+    if let Some(index) = table.iter().position(|x| x.is_none()) {
+        table[index] = Some(tcb);
+        index
+    } else {
+        table.push(Some(tcb));
+        table.len() - 1
+    }
+}
+
+fn find_tcb(
+    table: &Vec<Option<TcpControlBlock>>,
+    remote_ip: IPv4,
+    local_port: u16,
+    remote_port: u16,
+) -> Option<usize> {
+    table.iter().position(|x| match x {
+        Some(tcb) => {
+            tcb.remote_ip == remote_ip
+                && tcb.local_port == local_port
+                && tcb.remote_port == remote_port
+        }
+        None => false,
+    })
+}
+
+/// free the tcb once nobody refers to it and the connection is over
+fn try_free(table: &mut Vec<Option<TcpControlBlock>>, index: usize) {
+    if let Some(tcb) = &table[index] {
+        if tcb.detached && tcb.state == TcpState::Closed {
+            table[index] = None;
+        }
+    }
+}
+
+fn reset_segment(packet: &TCPPacket) -> Vec<u8> {
+    let payload_len = segment_payload(packet).len() as u32;
+    let mut reply = packet.ack();
+    reply.data = &[];
+    reply.data_len = 0;
+    if packet.flags.contains(TcpFlags::A) {
+        reply.seq = packet.ack;
+        reply.flags = TcpFlags::R;
+    } else {
+        let mut len = payload_len;
+        if packet.flags.contains(TcpFlags::S) {
+            len += 1;
+        }
+        if packet.flags.contains(TcpFlags::F) {
+            len += 1;
+        }
+        reply.seq = 0;
+        reply.ack = packet.seq.wrapping_add(len);
+        reply.flags = TcpFlags::R | TcpFlags::A;
+    }
+    reply.build_data()
+}
+
+fn on_timeout(index: usize, timer_id: usize) {
+    let mut frames = Vec::new();
+    let mut wake = None;
+    TCP_TABLE.exclusive_session(|table| {
+        let tcb = match table.get_mut(index) {
+            Some(Some(tcb)) if tcb.timer_id == timer_id => tcb,
+            _ => return,
+        };
+        wake = Some(tcb.condvar.clone());
+        tcb.timer_id = 0;
+        if matches!(tcb.state, TcpState::TimeWait | TcpState::FinWait2) {
+            tcb.state = TcpState::Closed;
+            try_free(table, index);
+            return;
+        }
+        if tcb.snd_una == tcb.snd_nxt && tcb.send_buffer.is_empty() {
+            return;
+        }
+        tcb.retries += 1;
+        if tcb.retries > TCP_MAX_RETRIES {
+            // give up on the peer
+            frames.push(tcb.build_segment(tcb.snd_nxt, TcpFlags::R, &[]));
+            tcb.state = TcpState::Closed;
+            tcb.reset = true;
+            try_free(table, index);
+            return;
+        }
+        tcb.retransmit(&mut frames);
+        tcb.rto_ms = (tcb.rto_ms * 2).min(TCP_MAX_RTO_MS);
+        tcb.arm_timer(index, tcb.rto_ms);
+    });
+    if let Some(condvar) = wake {
+        condvar.signal_all();
+    }
+    transmit_frames(frames);
+}
+
+/// handle an incoming segment, following the "segment arrives" part of rfc 793
+pub fn handle_segment(packet: &TCPPacket) {
+    let payload = segment_payload(packet);
+    let flags = packet.flags;
+    let mut frames = Vec::new();
+    let mut established = None;
+    let mut wake = None;
+
+    TCP_TABLE.exclusive_session(|table| {
+        let index = match find_tcb(table, packet.source_ip, packet.dest_port, packet.source_port)
+        {
+            Some(index) => index,
+            None => {
+                if flags.contains(TcpFlags::R) {
+                    return;
+                }
+                // passive open
+                if flags.contains(TcpFlags::S) && !flags.contains(TcpFlags::A) {
+                    if let Some(listen_index) = find_listener(packet.dest_port) {
+                        let mut tcb = TcpControlBlock::new(
+                            packet.dest_port,
+                            packet.source_ip,
+                            packet.source_port,
+                            TcpState::SynReceived,
+                        );
+                        tcb.listen_index = Some(listen_index);
+                        tcb.rcv_nxt = packet.seq.wrapping_add(1);
+                        tcb.snd_wnd = packet.win as u32;
+                        frames.push(tcb.syn_segment());
+                        let index = alloc_tcb(table, tcb);
+                        let tcb = table[index].as_mut().unwrap();
+                        tcb.arm_timer(index, tcb.rto_ms);
+                        return;
                     }
                 }
-                return left;
+                frames.push(reset_segment(packet));
+                return;
+            }
+        };
+        let tcb = table[index].as_mut().unwrap();
+        wake = Some(tcb.condvar.clone());
+
+        if tcb.state == TcpState::SynSent {
+            if flags.contains(TcpFlags::A)
+                && !(seq_lt(tcb.iss, packet.ack) && seq_le(packet.ack, tcb.snd_nxt))
+            {
+                if !flags.contains(TcpFlags::R) {
+                    frames.push(reset_segment(packet));
+                }
+                return;
+            }
+            if flags.contains(TcpFlags::R) {
+                if flags.contains(TcpFlags::A) {
+                    // connection refused
+                    tcb.state = TcpState::Closed;
+                    tcb.reset = true;
+                    tcb.cancel_timer();
+                    try_free(table, index);
+                }
+                return;
+            }
+            if flags.contains(TcpFlags::S) {
+                tcb.rcv_nxt = packet.seq.wrapping_add(1);
+                if flags.contains(TcpFlags::A) {
+                    tcb.process_ack(index, packet.ack, packet.win);
+                    frames.push(tcb.ack_segment());
+                    tcb.output(index, &mut frames);
+                } else {
+                    // simultaneous open
+                    tcb.state = TcpState::SynReceived;
+                    frames.push(tcb.syn_segment());
+                }
+            }
+            return;
+        }
+
+        // sequence number check, an unacceptable segment only gets an ack back
+        let window = tcb.recv_window() as u32;
+        let acceptable = if payload.is_empty() {
+            if window == 0 {
+                packet.seq == tcb.rcv_nxt
+            } else {
+                seq_le(tcb.rcv_nxt, packet.seq)
+                    && seq_lt(packet.seq, tcb.rcv_nxt.wrapping_add(window))
+            }
+        } else {
+            let end = packet.seq.wrapping_add(payload.len() as u32 - 1);
+            window > 0
+                && (seq_le(tcb.rcv_nxt, packet.seq)
+                    && seq_lt(packet.seq, tcb.rcv_nxt.wrapping_add(window))
+                    || seq_le(tcb.rcv_nxt, end)
+                        && seq_lt(end, tcb.rcv_nxt.wrapping_add(window)))
+        };
+        let fin_retransmit = flags.contains(TcpFlags::F)
+            && packet.seq.wrapping_add(payload.len() as u32).wrapping_add(1) == tcb.rcv_nxt;
+        if !acceptable && !fin_retransmit {
+            if !flags.contains(TcpFlags::R) {
+                frames.push(tcb.ack_segment());
+            }
+            return;
+        }
+
+        if flags.contains(TcpFlags::R) {
+            tcb.state = TcpState::Closed;
+            tcb.reset = true;
+            tcb.cancel_timer();
+            try_free(table, index);
+            return;
+        }
+        if flags.contains(TcpFlags::S) {
+            if tcb.state == TcpState::SynReceived && packet.seq.wrapping_add(1) == tcb.rcv_nxt {
+                // our SYN-ACK got lost
+                frames.push(tcb.syn_segment());
             } else {
-                net_interrupt_handler();
+                frames.push(tcb.ack_segment());
             }
+            return;
+        }
+        if !flags.contains(TcpFlags::A) {
+            return;
+        }
+        if tcb.state == TcpState::SynReceived
+            && !(seq_lt(tcb.snd_una, packet.ack) && seq_le(packet.ack, tcb.snd_nxt))
+        {
+            frames.push(reset_segment(packet));
+            return;
+        }
+        if tcb.process_ack(index, packet.ack, packet.win) && tcb.listen_index.is_some() {
+            established = Some((tcb.listen_index.unwrap(), index));
         }
+        if tcb.state == TcpState::Closed {
+            // last ack of our FIN
+            try_free(table, index);
+            return;
+        }
+
+        let mut need_ack = false;
+        if matches!(
+            tcb.state,
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+        ) {
+            need_ack = tcb.process_data(packet.seq, payload) || !payload.is_empty();
+        }
+
+        if flags.contains(TcpFlags::F) {
+            let fin_seq = packet.seq.wrapping_add(payload.len() as u32);
+            if fin_seq == tcb.rcv_nxt && !tcb.fin_received {
+                tcb.fin_received = true;
+                tcb.rcv_nxt = tcb.rcv_nxt.wrapping_add(1);
+                match tcb.state {
+                    TcpState::SynReceived | TcpState::Established => {
+                        tcb.state = TcpState::CloseWait
+                    }
+                    TcpState::FinWait1 => tcb.state = TcpState::Closing,
+                    TcpState::FinWait2 => tcb.enter_time_wait(index),
+                    _ => {}
+                }
+            } else if tcb.state == TcpState::TimeWait {
+                // the peer lost our ack, restart the 2 MSL timeout
+                tcb.enter_time_wait(index);
+            }
+            need_ack = true;
+        }
+        if need_ack {
+            frames.push(tcb.ack_segment());
+        }
+        tcb.output(index, &mut frames);
+    });
+
+    if let Some((listen_index, index)) = established {
+        if !push_established(listen_index, packet.dest_port, index) {
+            // the listener went away before the handshake finished
+            abort(index);
+        }
+    }
+    if let Some(condvar) = wake {
+        condvar.signal_all();
     }
+    transmit_frames(frames);
+}
 
-    fn write(&self, buf: crate::mm::UserBuffer) -> usize {
-        let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
+/// active open
+pub fn connect(remote_ip: IPv4, remote_port: u16, local_port: u16) -> Option<usize> {
+    let mut frames = Vec::new();
+    let index = TCP_TABLE.exclusive_session(|table| {
+        if find_tcb(table, remote_ip, local_port, remote_port).is_some() {
+            return None;
+        }
+        let mut tcb = TcpControlBlock::new(local_port, remote_ip, remote_port, TcpState::SynSent);
+        frames.push(tcb.syn_segment());
+        let index = alloc_tcb(table, tcb);
+        let tcb = table[index].as_mut().unwrap();
+        tcb.arm_timer(index, tcb.rto_ms);
+        Some(index)
+    })?;
+    transmit_frames(frames);
+    Some(index)
+}
 
-        let mut data = vec![0u8; buf.len()];
+/// pick a port for an implicit bind that no connection or listener holds,
+/// None once the whole ephemeral range is taken
+pub fn alloc_ephemeral_port() -> Option<u16> {
+    let range = (u16::MAX - EPHEMERAL_PORT_START) as usize + 1;
+    let mut next = NEXT_EPHEMERAL_PORT.load(Ordering::Relaxed);
+    for _ in 0..range {
+        let port = next;
+        next = if port == u16::MAX {
+            EPHEMERAL_PORT_START
+        } else {
+            port + 1
+        };
+        let in_use = TCP_TABLE
+            .exclusive_access()
+            .iter()
+            .any(|x| matches!(x, Some(tcb) if tcb.local_port == port));
+        if !in_use && !is_listening(port) {
+            NEXT_EPHEMERAL_PORT.store(next, Ordering::Relaxed);
+            return Some(port);
+        }
+    }
+    None
+}
 
-        let mut left = 0;
-        for i in 0..buf.buffers.len() {
-            data[left..(left + buf.buffers[i].len())].copy_from_slice(buf.buffers[i]);
-            left += buf.buffers[i].len();
+/// block until the handshake of an active open finishes, false if it failed
+pub fn wait_established(index: usize) -> bool {
+    loop {
+        let mut task_cx_ptr = None;
+        let state = TCP_TABLE.exclusive_session(|table| {
+            let tcb = table[index].as_mut().unwrap();
+            if matches!(tcb.state, TcpState::SynSent | TcpState::SynReceived) {
+                task_cx_ptr = Some(tcb.condvar.wait_no_sched());
+            }
+            tcb.state
+        });
+        match task_cx_ptr {
+            Some(task_cx_ptr) => schedule(task_cx_ptr),
+            None => return state != TcpState::Closed,
         }
+    }
+}
 
-        let len = data.len();
+pub fn get_state(index: usize) -> TcpState {
+    TCP_TABLE.exclusive_access()[index]
+        .as_ref()
+        .map_or(TcpState::Closed, |tcb| tcb.state)
+}
 
-        // get sock and sequence
-        let (ack, seq) = get_s_a_by_index(self.socket_index).map_or((0, 0), |x| x);
+pub fn get_peer(index: usize) -> Option<(IPv4, u16)> {
+    TCP_TABLE.exclusive_access()[index]
+        .as_ref()
+        .map(|tcb| (tcb.remote_ip, tcb.remote_port))
+}
 
-        let tcp_packet = TCPPacket {
-            source_ip: lose_net_stack.ip,
-            source_mac: lose_net_stack.mac,
-            source_port: self.sport,
-            dest_ip: self.target,
-            dest_mac: MacAddress::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
-            dest_port: self.dport,
-            data_len: len,
-            seq,
-            ack,
-            flags: TcpFlags::A,
-            win: 65535,
-            urg: 0,
-            data: data.as_ref(),
+/// user close, FIN goes out once the send buffer drains
+pub fn close(index: usize) {
+    let mut frames = Vec::new();
+    TCP_TABLE.exclusive_session(|table| {
+        let tcb = match table[index].as_mut() {
+            Some(tcb) => tcb,
+            None => return,
         };
-        NET_DEVICE.transmit(&tcp_packet.build_data());
-        len
+        tcb.detached = true;
+        match tcb.state {
+            TcpState::SynSent | TcpState::Closed => {
+                tcb.state = TcpState::Closed;
+                tcb.cancel_timer();
+            }
+            TcpState::SynReceived | TcpState::Established | TcpState::CloseWait => {
+                tcb.fin_pending = true;
+                if tcb.state == TcpState::SynReceived {
+                    // FIN may only follow the handshake
+                    return;
+                }
+                tcb.output(index, &mut frames);
+            }
+            _ => {}
+        }
+        try_free(table, index);
+    });
+    transmit_frames(frames);
+}
+
+/// drop the connection with a reset
+pub fn abort(index: usize) {
+    let mut frames = Vec::new();
+    TCP_TABLE.exclusive_session(|table| {
+        if let Some(tcb) = table[index].as_mut() {
+            if tcb.state != TcpState::Closed {
+                frames.push(tcb.build_segment(tcb.snd_nxt, TcpFlags::R | TcpFlags::A, &[]));
+            }
+            tcb.state = TcpState::Closed;
+            tcb.detached = true;
+            tcb.cancel_timer();
+        }
+        try_free(table, index);
+    });
+    transmit_frames(frames);
+}
+
+// a connected tcp stream, refers to a tcb in the TCP_TABLE
+pub struct TCP {
+    pub socket_index: usize,
+}
+
+impl TCP {
+    pub fn new(socket_index: usize) -> Self {
+        Self { socket_index }
+    }
+}
+
+impl File for TCP {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let index = self.socket_index;
+        loop {
+            let mut frames = Vec::new();
+            let mut task_cx_ptr = None;
+            let data = TCP_TABLE.exclusive_session(|table| {
+                let tcb = table[index].as_mut().unwrap();
+                if !tcb.recv_buffer.is_empty() {
+                    let old_window = tcb.recv_window();
+                    let len = tcb.recv_buffer.len().min(buf.len());
+                    let data: Vec<u8> = tcb.recv_buffer.drain(..len).collect();
+                    // window update once the reader made room for a full segment
+                    if old_window < TCP_MSS && tcb.recv_window() >= TCP_MSS {
+                        frames.push(tcb.ack_segment());
+                    }
+                    Some(data)
+                } else if tcb.fin_received || tcb.state == TcpState::Closed {
+                    Some(Vec::new())
+                } else {
+                    task_cx_ptr = Some(tcb.condvar.wait_no_sched());
+                    None
+                }
+            });
+            transmit_frames(frames);
+            if let Some(data) = data {
+                return buf.write(&data);
+            }
+            schedule(task_cx_ptr.unwrap());
+        }
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        let index = self.socket_index;
+        let mut data: Vec<u8> = Vec::new();
+        for b in buf.buffers.iter() {
+            data.extend_from_slice(b);
+        }
+        let mut written = 0;
+        while written < data.len() {
+            let mut frames = Vec::new();
+            let mut task_cx_ptr = None;
+            let closed = TCP_TABLE.exclusive_session(|table| {
+                let tcb = table[index].as_mut().unwrap();
+                if !matches!(tcb.state, TcpState::Established | TcpState::CloseWait)
+                    || tcb.fin_pending
+                {
+                    return true;
+                }
+                let room = TCP_SEND_BUFFER_SIZE - tcb.send_buffer.len();
+                let len = room.min(data.len() - written);
+                tcb.send_buffer.extend(&data[written..written + len]);
+                written += len;
+                tcb.output(index, &mut frames);
+                if written < data.len() {
+                    // send buffer is full, wait for acks
+                    task_cx_ptr = Some(tcb.condvar.wait_no_sched());
+                }
+                false
+            });
+            transmit_frames(frames);
+            if closed {
+                break;
+            }
+            if let Some(task_cx_ptr) = task_cx_ptr {
+                schedule(task_cx_ptr);
+            }
+        }
+        written
+    }
+
+    fn poll(&self) -> PollEvents {
+        let table = TCP_TABLE.exclusive_access();
+        let tcb = table[self.socket_index].as_ref().unwrap();
+        let mut events = PollEvents::empty();
+        if !tcb.recv_buffer.is_empty() || tcb.fin_received || tcb.state == TcpState::Closed {
+            events |= PollEvents::POLLIN;
+        }
+        if matches!(tcb.state, TcpState::Established | TcpState::CloseWait)
+            && !tcb.fin_pending
+            && tcb.send_buffer.len() < TCP_SEND_BUFFER_SIZE
+        {
+            events |= PollEvents::POLLOUT;
+        }
+        if tcb.state == TcpState::Closed {
+            events |= PollEvents::POLLHUP;
+        }
+        if tcb.reset {
+            events |= PollEvents::POLLERR;
+        }
+        events
     }
 }
 
 impl Drop for TCP {
     fn drop(&mut self) {
-        remove_socket(self.socket_index)
+        close(self.socket_index)
     }
 }
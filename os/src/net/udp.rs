@@ -1,32 +1,62 @@
 #[allow(unused)]
 
-use super::net_interrupt_handler;
-use super::socket::{add_socket, pop_data, remove_socket};
-use super::LOSE_NET_STACK;
-use super::NET_DEVICE;
-use crate::fs::File;
+use super::socket::{add_socket, connect_socket, has_data, remove_socket, wait_data};
+use super::{local_addr_for, transmit_frame};
+use crate::fs::{File, PollEvents};
+use crate::sync::UPIntrFreeCell;
 use alloc::vec;
+use alloc::vec::Vec;
 use lose_net_stack::packets::udp::UDPPacket;
 use lose_net_stack::IPv4;
 use lose_net_stack::MacAddress;
 
 pub struct UDP {
-    pub target: IPv4,
     pub sport: u16,
-    pub dport: u16,
+    /// default destination set by connect
+    pub peer: UPIntrFreeCell<Option<(IPv4, u16)>>,
     pub socket_index: usize,
 }
 
 impl UDP {
-    pub fn new(target: IPv4, sport: u16, dport: u16) -> Self {
-        let index = add_socket(target, sport, dport).expect("can't add socket");
+    /// None if `sport` is already in use
+    pub fn new(sport: u16) -> Option<Self> {
+        let index = add_socket(sport)?;
 
-        Self {
-            target,
+        Some(Self {
             sport,
-            dport,
+            peer: unsafe { UPIntrFreeCell::new(None) },
             socket_index: index,
-        }
+        })
+    }
+
+    /// after this only datagrams from (target, dport) are received
+    pub fn connect(&self, target: IPv4, dport: u16) {
+        connect_socket(self.socket_index, target, dport);
+        *self.peer.exclusive_access() = Some((target, dport));
+    }
+
+    /// block until a datagram arrives, returns the sender and the data
+    pub fn recv_from(&self) -> (IPv4, u16, Vec<u8>) {
+        wait_data(self.socket_index)
+    }
+
+    pub fn send_to(&self, target: IPv4, dport: u16, data: &[u8]) -> usize {
+        let (ip, mac) = local_addr_for(target);
+        let len = data.len();
+
+        let udp_packet = UDPPacket::new(
+            ip,
+            mac,
+            self.sport,
+            target,
+            // filled in by arp on the way out
+            MacAddress::new([0; 6]),
+            dport,
+            len,
+            data,
+        );
+        transmit_frame(&udp_packet.build_data());
+        len
     }
 }
 
@@ -40,30 +70,28 @@ impl File for UDP {
     }
 
     fn read(&self, mut buf: crate::mm::UserBuffer) -> usize {
-        loop {
-            if let Some(data) = pop_data(self.socket_index) {
-                let data_len = data.len();
-                let mut left = 0;
-                for i in 0..buf.buffers.len() {
-                    let buffer_i_len = buf.buffers[i].len().min(data_len - left);
-
-                    buf.buffers[i][..buffer_i_len]
-                        .copy_from_slice(&data[left..(left + buffer_i_len)]);
-
-                    left += buffer_i_len;
-                    if left == data_len {
-                        break;
-                    }
-                }
-                return left;
-            } else {
-                net_interrupt_handler();
+        let (_, _, data) = self.recv_from();
+        let data_len = data.len();
+        let mut left = 0;
+        for i in 0..buf.buffers.len() {
+            let buffer_i_len = buf.buffers[i].len().min(data_len - left);
+
+            buf.buffers[i][..buffer_i_len].copy_from_slice(&data[left..(left + buffer_i_len)]);
+
+            left += buffer_i_len;
+            if left == data_len {
+                break;
             }
         }
+        left
     }
 
     fn write(&self, buf: crate::mm::UserBuffer) -> usize {
-        let lose_net_stack = LOSE_NET_STACK.0.exclusive_access();
+        let peer = *self.peer.exclusive_access();
+        let (target, dport) = match peer {
+            Some(peer) => peer,
+            None => return 0,
+        };
 
         let mut data = vec![0u8; buf.len()];
 
@@ -73,20 +101,15 @@ impl File for UDP {
             left += buf.buffers[i].len();
         }
 
-        let len = data.len();
+        self.send_to(target, dport, &data)
+    }
 
-        let udp_packet = UDPPacket::new(
-            lose_net_stack.ip,
-            lose_net_stack.mac,
-            self.sport,
-            self.target,
-            MacAddress::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
-            self.dport,
-            len,
-            data.as_ref(),
-        );
-        NET_DEVICE.transmit(&udp_packet.build_data());
-        len
+    fn poll(&self) -> PollEvents {
+        if has_data(self.socket_index) {
+            PollEvents::POLLIN | PollEvents::POLLOUT
+        } else {
+            PollEvents::POLLOUT
+        }
     }
 }
 
@@ -0,0 +1,519 @@
+//! AF_UNIX sockets. A stream is a pair of byte queues, a datagram socket owns one message queue.
+//! Every name lives in UNIX_NAMES, path names also get a file so they show up in the fs.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+
+use crate::fs::{create_new_file, File, FileDescriptor, PollEvents};
+use crate::mm::UserBuffer;
+use crate::sync::{Condvar, UPIntrFreeCell, UPIntrRefMut};
+use crate::task::schedule;
+
+pub const AF_UNIX: usize = 1;
+pub const UNIX_PATH_MAX: usize = 108;
+
+/// bytes a stream direction may hold before writers block
+const UNIX_STREAM_BUFFER: usize = 64 * 1024;
+/// datagrams queued on one socket before senders block
+const UNIX_DGRAM_QUEUE: usize = 64;
+
+pub struct UnixMessage {
+    pub data: Vec<u8>,
+    /// SCM_RIGHTS, installed into the receiver's fd table by recvmsg
+    pub fds: Vec<FileDescriptor>,
+    /// name of the sending datagram socket
+    pub from: Option<String>,
+}
+
+impl UnixMessage {
+    fn empty() -> Self {
+        Self {
+            data: Vec::new(),
+            fds: Vec::new(),
+            from: None,
+        }
+    }
+}
+
+struct UnixQueue {
+    messages: VecDeque<UnixMessage>,
+    bytes: usize,
+    /// every writer is gone, readers get eof
+    write_closed: bool,
+    /// the reader is gone, writers fail
+    read_closed: bool,
+}
+
+/// one direction of a connection, or the receive queue of a datagram socket
+struct UnixChannel {
+    queue: UPIntrFreeCell<UnixQueue>,
+    /// readers and writers both wait here
+    condvar: Condvar,
+}
+
+impl UnixChannel {
+    fn new() -> Self {
+        Self {
+            queue: unsafe {
+                UPIntrFreeCell::new(UnixQueue {
+                    messages: VecDeque::new(),
+                    bytes: 0,
+                    write_closed: false,
+                    read_closed: false,
+                })
+            },
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// queue as much of `data` as fits, block while the buffer is full
+    fn send_stream(&self, data: &[u8], fds: Vec<FileDescriptor>) -> isize {
+        let mut fds = Some(fds);
+        let mut sent = 0;
+        loop {
+            let mut queue = self.queue.exclusive_access();
+            if queue.read_closed {
+                return if sent > 0 { sent as isize } else { -1 };
+            }
+            let space = UNIX_STREAM_BUFFER - queue.bytes;
+            if space == 0 {
+                let task_cx_ptr = self.condvar.wait_no_sched();
+                drop(queue);
+                schedule(task_cx_ptr);
+                continue;
+            }
+            let len = space.min(data.len() - sent);
+            let fds = fds.take().unwrap_or_default();
+            // an empty write only goes through if it carries fds
+            if len > 0 || !fds.is_empty() {
+                queue.bytes += len;
+                queue.messages.push_back(UnixMessage {
+                    data: data[sent..sent + len].to_vec(),
+                    fds,
+                    from: None,
+                });
+                self.condvar.signal_all();
+            }
+            sent += len;
+            if sent == data.len() {
+                return sent as isize;
+            }
+        }
+    }
+
+    fn send_datagram(&self, message: UnixMessage) -> isize {
+        loop {
+            let mut queue = self.queue.exclusive_access();
+            if queue.read_closed {
+                return -1;
+            }
+            if queue.messages.len() < UNIX_DGRAM_QUEUE {
+                let len = message.data.len();
+                queue.bytes += len;
+                queue.messages.push_back(message);
+                self.condvar.signal_all();
+                return len as isize;
+            }
+            let task_cx_ptr = self.condvar.wait_no_sched();
+            drop(queue);
+            schedule(task_cx_ptr);
+        }
+    }
+
+    /// block until something is queued, an empty message means eof
+    fn wait_message(&self) -> Option<UPIntrRefMut<'_, UnixQueue>> {
+        loop {
+            let queue = self.queue.exclusive_access();
+            if !queue.messages.is_empty() {
+                return Some(queue);
+            }
+            if queue.write_closed {
+                return None;
+            }
+            let task_cx_ptr = self.condvar.wait_no_sched();
+            drop(queue);
+            schedule(task_cx_ptr);
+        }
+    }
+
+    /// up to `len` bytes, fds only come along with the first byte they were sent with
+    fn recv_stream(&self, len: usize) -> UnixMessage {
+        let mut queue = match self.wait_message() {
+            Some(queue) => queue,
+            None => return UnixMessage::empty(),
+        };
+        let mut result = UnixMessage::empty();
+        while result.data.len() < len {
+            let message = match queue.messages.front_mut() {
+                Some(message) => message,
+                None => break,
+            };
+            if !message.fds.is_empty() {
+                if !result.data.is_empty() || !result.fds.is_empty() {
+                    break;
+                }
+                result.fds = core::mem::take(&mut message.fds);
+            }
+            let take = message.data.len().min(len - result.data.len());
+            result.data.extend(message.data.drain(..take));
+            if message.data.is_empty() {
+                queue.messages.pop_front();
+            }
+        }
+        queue.bytes -= result.data.len();
+        self.condvar.signal_all();
+        result
+    }
+
+    /// one whole datagram, the caller truncates it
+    fn recv_datagram(&self) -> UnixMessage {
+        let mut queue = match self.wait_message() {
+            Some(queue) => queue,
+            None => return UnixMessage::empty(),
+        };
+        let message = queue.messages.pop_front().unwrap();
+        queue.bytes -= message.data.len();
+        self.condvar.signal_all();
+        message
+    }
+
+    /// readiness of the reading side, eof counts as readable
+    fn poll_read(&self) -> PollEvents {
+        let queue = self.queue.exclusive_access();
+        let mut events = PollEvents::empty();
+        if !queue.messages.is_empty() || queue.write_closed {
+            events |= PollEvents::POLLIN;
+        }
+        if queue.write_closed {
+            events |= PollEvents::POLLHUP;
+        }
+        events
+    }
+
+    /// readiness of the writing side, `stream` picks which limit applies
+    fn poll_write(&self, stream: bool) -> PollEvents {
+        let queue = self.queue.exclusive_access();
+        if queue.read_closed {
+            PollEvents::POLLERR
+        } else if (stream && queue.bytes < UNIX_STREAM_BUFFER)
+            || (!stream && queue.messages.len() < UNIX_DGRAM_QUEUE)
+        {
+            PollEvents::POLLOUT
+        } else {
+            PollEvents::empty()
+        }
+    }
+
+    fn close_read(&self) {
+        let mut queue = self.queue.exclusive_access();
+        queue.read_closed = true;
+        // fds in flight can't be received anymore, close them after the borrow ends
+        let messages = core::mem::take(&mut queue.messages);
+        queue.bytes = 0;
+        drop(queue);
+        self.condvar.signal_all();
+        drop(messages);
+    }
+
+    fn close_write(&self) {
+        self.queue.exclusive_access().write_closed = true;
+        self.condvar.signal_all();
+    }
+}
+
+/// one end of a connected stream
+pub struct UnixStream {
+    rx: Arc<UnixChannel>,
+    tx: Arc<UnixChannel>,
+}
+
+impl UnixStream {
+    pub fn pair() -> (Self, Self) {
+        let a = Arc::new(UnixChannel::new());
+        let b = Arc::new(UnixChannel::new());
+        (
+            Self {
+                rx: a.clone(),
+                tx: b.clone(),
+            },
+            Self { rx: b, tx: a },
+        )
+    }
+
+    pub fn send(&self, data: &[u8], fds: Vec<FileDescriptor>) -> isize {
+        self.tx.send_stream(data, fds)
+    }
+
+    pub fn recv(&self, len: usize) -> UnixMessage {
+        self.rx.recv_stream(len)
+    }
+}
+
+impl Drop for UnixStream {
+    fn drop(&mut self) {
+        self.rx.close_read();
+        self.tx.close_write();
+    }
+}
+
+impl File for UnixStream {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        if buf.len() == 0 {
+            return 0;
+        }
+        let message = self.recv(buf.len());
+        if message.data.is_empty() {
+            return 0;
+        }
+        buf.write(&message.data)
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        let data = user_buffer_data(&buf);
+        let sent = self.send(&data, Vec::new());
+        if sent < 0 {
+            0
+        } else {
+            sent as usize
+        }
+    }
+
+    fn poll(&self) -> PollEvents {
+        self.rx.poll_read() | self.tx.poll_write(true)
+    }
+}
+
+pub struct UnixListener {
+    backlog: usize,
+    /// server ends of connections nobody accepted yet
+    pending: UPIntrFreeCell<VecDeque<UnixStream>>,
+    condvar: Condvar,
+}
+
+impl UnixListener {
+    pub fn new(backlog: usize) -> Self {
+        Self {
+            backlog: backlog.max(1),
+            pending: unsafe { UPIntrFreeCell::new(VecDeque::new()) },
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// block until a client connects
+    pub fn accept(&self) -> UnixStream {
+        loop {
+            let mut pending = self.pending.exclusive_access();
+            if let Some(stream) = pending.pop_front() {
+                return stream;
+            }
+            let task_cx_ptr = self.condvar.wait_no_sched();
+            drop(pending);
+            schedule(task_cx_ptr);
+        }
+    }
+
+    /// readable once a connection waits for accept
+    pub fn poll(&self) -> PollEvents {
+        if self.pending.exclusive_access().is_empty() {
+            PollEvents::empty()
+        } else {
+            PollEvents::POLLIN
+        }
+    }
+
+    /// the client end, None if the backlog is full
+    fn connect(&self) -> Option<UnixStream> {
+        let mut pending = self.pending.exclusive_access();
+        if pending.len() >= self.backlog {
+            return None;
+        }
+        let (client, server) = UnixStream::pair();
+        pending.push_back(server);
+        self.condvar.signal();
+        Some(client)
+    }
+}
+
+pub struct UnixDatagram {
+    rx: Arc<UnixChannel>,
+    /// set by connect, target of write and send without an address
+    pub peer: UPIntrFreeCell<Option<Weak<UnixDatagram>>>,
+}
+
+impl UnixDatagram {
+    pub fn new() -> Self {
+        Self {
+            rx: Arc::new(UnixChannel::new()),
+            peer: unsafe { UPIntrFreeCell::new(None) },
+        }
+    }
+
+    /// two datagram sockets connected to each other, for socketpair
+    pub fn pair() -> (Arc<Self>, Arc<Self>) {
+        let a = Arc::new(Self::new());
+        let b = Arc::new(Self::new());
+        *a.peer.exclusive_access() = Some(Arc::downgrade(&b));
+        *b.peer.exclusive_access() = Some(Arc::downgrade(&a));
+        (a, b)
+    }
+
+    /// queue a datagram on this socket
+    pub fn deliver(&self, message: UnixMessage) -> isize {
+        self.rx.send_datagram(message)
+    }
+
+    /// send to the connected peer
+    pub fn send(&self, message: UnixMessage) -> isize {
+        let peer = self.peer.exclusive_access().as_ref().and_then(|p| p.upgrade());
+        match peer {
+            Some(peer) => peer.deliver(message),
+            None => -1,
+        }
+    }
+
+    pub fn recv(&self) -> UnixMessage {
+        self.rx.recv_datagram()
+    }
+}
+
+impl Drop for UnixDatagram {
+    fn drop(&mut self) {
+        self.rx.close_read();
+    }
+}
+
+impl File for UnixDatagram {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let message = self.recv();
+        if buf.len() == 0 || message.data.is_empty() {
+            return 0;
+        }
+        buf.write(&message.data)
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        let message = UnixMessage {
+            data: user_buffer_data(&buf),
+            fds: Vec::new(),
+            from: None,
+        };
+        let sent = self.send(message);
+        if sent < 0 {
+            0
+        } else {
+            sent as usize
+        }
+    }
+
+    fn poll(&self) -> PollEvents {
+        // an unconnected socket can still sendto anybody
+        let peer = self.peer.exclusive_access().as_ref().and_then(|p| p.upgrade());
+        let writable = match peer {
+            Some(peer) => peer.rx.poll_write(false),
+            None => PollEvents::POLLOUT,
+        };
+        (self.rx.poll_read() - PollEvents::POLLHUP) | writable
+    }
+}
+
+pub fn user_buffer_data(buf: &UserBuffer) -> Vec<u8> {
+    let mut data = Vec::with_capacity(buf.len());
+    for b in buf.buffers.iter() {
+        data.extend_from_slice(b);
+    }
+    data
+}
+
+enum UnixBinding {
+    /// bound but not listening yet
+    Reserved,
+    Listener(Weak<UnixListener>),
+    Datagram(Weak<UnixDatagram>),
+}
+
+lazy_static! {
+    /// absolute path, or NUL followed by the abstract name
+    static ref UNIX_NAMES: UPIntrFreeCell<BTreeMap<String, UnixBinding>> =
+        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+}
+
+pub fn is_abstract(name: &str) -> bool {
+    name.starts_with('\0')
+}
+
+/// claim a name, path names need a fresh file like on linux
+pub fn bind(name: &str, datagram: Option<&Arc<UnixDatagram>>) -> bool {
+    if UNIX_NAMES.exclusive_access().contains_key(name) {
+        return false;
+    }
+    // no table borrow across fs io, it may block
+    if !is_abstract(name) && create_new_file(name).is_none() {
+        return false;
+    }
+    let binding = match datagram {
+        Some(datagram) => UnixBinding::Datagram(Arc::downgrade(datagram)),
+        None => UnixBinding::Reserved,
+    };
+    UNIX_NAMES
+        .exclusive_access()
+        .insert(String::from(name), binding);
+    true
+}
+
+/// a bound stream socket starts listening
+pub fn listen(name: &str, listener: &Arc<UnixListener>) {
+    UNIX_NAMES
+        .exclusive_access()
+        .insert(String::from(name), UnixBinding::Listener(Arc::downgrade(listener)));
+}
+
+/// the socket is closed, the file of a path name stays until unlink
+pub fn unbind(name: &str) {
+    UNIX_NAMES.exclusive_access().remove(name);
+}
+
+/// path names only count while their file still exists
+fn lookup(name: &str) -> Option<UnixBinding> {
+    if !is_abstract(name) && !crate::fs::file_exists(name) {
+        return None;
+    }
+    match UNIX_NAMES.exclusive_access().get(name)? {
+        UnixBinding::Reserved => Some(UnixBinding::Reserved),
+        UnixBinding::Listener(listener) => Some(UnixBinding::Listener(listener.clone())),
+        UnixBinding::Datagram(datagram) => Some(UnixBinding::Datagram(datagram.clone())),
+    }
+}
+
+/// the client end of a new connection to the listener bound at `name`
+pub fn connect(name: &str) -> Option<UnixStream> {
+    match lookup(name)? {
+        UnixBinding::Listener(listener) => listener.upgrade()?.connect(),
+        _ => None,
+    }
+}
+
+pub fn find_datagram(name: &str) -> Option<Arc<UnixDatagram>> {
+    match lookup(name)? {
+        UnixBinding::Datagram(datagram) => datagram.upgrade(),
+        _ => None,
+    }
+}
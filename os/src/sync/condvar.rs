@@ -31,6 +31,13 @@ impl Condvar {
         }
     }
 
+    pub fn signal_all(&self) {
+        let mut inner = self.inner.exclusive_access();
+        while let Some(task) = inner.wait_queue.pop_front() {
+            wakeup_task(task);
+        }
+    }
+
     /*
     pub fn wait(&self) {
         let mut inner = self.inner.exclusive_access();
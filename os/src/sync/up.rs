@@ -138,3 +138,43 @@ impl<'a, T> DerefMut for UPIntrRefMut<'a, T> {
         self.0.as_mut().unwrap().deref_mut()
     }
 }
+
+/// 关中断的自旋锁. 全局分配器在中断处理里也会被用到, 又要能在static里const初始化,
+/// 不能用UPIntrFreeCell; 单核上拿着锁时来中断再去拿同一把锁就死锁了, 所以拿锁前关中断
+pub struct IntrFreeMutex<T> {
+    inner: spin::Mutex<T>,
+}
+
+pub struct IntrFreeMutexGuard<'a, T>(Option<spin::MutexGuard<'a, T>>);
+
+impl<T> IntrFreeMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: spin::Mutex::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> IntrFreeMutexGuard<'_, T> {
+        INTR_MASKING_INFO.get_mut().enter();
+        IntrFreeMutexGuard(Some(self.inner.lock()))
+    }
+}
+
+impl<'a, T> Drop for IntrFreeMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.0 = None;
+        INTR_MASKING_INFO.get_mut().exit();
+    }
+}
+
+impl<'a, T> Deref for IntrFreeMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref().unwrap().deref()
+    }
+}
+impl<'a, T> DerefMut for IntrFreeMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0.as_mut().unwrap().deref_mut()
+    }
+}
@@ -0,0 +1,200 @@
+use crate::fs::{
+    EpollEvent, EpollFile, EventFd, File, FileDescriptor, ITimerSpec, PollEvents, SignalFd,
+    TimerFd, EPOLL_CTL_ADD, EPOLL_CTL_DEL,
+};
+use crate::mm::{translated_ref, translated_refmut};
+use crate::task::{
+    check_signals_of_current, current_process, current_user_token, suspend_current_and_run_next,
+    SignalFlags,
+};
+use crate::timer::{get_time_ms, TimeSpec};
+use alloc::sync::Arc;
+
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+
+/// struct pollfd
+#[repr(C)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+
+fn install_fd(file: FileDescriptor) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    match inner.alloc_fd() {
+        Some(fd) => {
+            inner.fd_table[fd] = Some(file);
+            fd as isize
+        }
+        None => -1,
+    }
+}
+
+fn get_file(fd: usize) -> Option<FileDescriptor> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    inner.fd_table.get(fd).cloned().flatten()
+}
+
+pub fn sys_eventfd2(initval: usize, flags: usize) -> isize {
+    let eventfd = EventFd::new(initval as u64, flags);
+    install_fd(FileDescriptor::Abstract(Arc::new(eventfd)))
+}
+
+pub fn sys_timerfd_create(clockid: usize, flags: usize) -> isize {
+    if clockid != CLOCK_REALTIME && clockid != CLOCK_MONOTONIC {
+        return -1;
+    }
+    let timer = TimerFd::new(clockid == CLOCK_REALTIME, flags);
+    install_fd(FileDescriptor::TimerFd(Arc::new(timer)))
+}
+
+pub fn sys_timerfd_settime(
+    fd: usize,
+    flags: usize,
+    new_value: *const ITimerSpec,
+    old_value: *mut ITimerSpec,
+) -> isize {
+    let token = current_user_token();
+    let timer = match get_file(fd) {
+        Some(FileDescriptor::TimerFd(timer)) => timer,
+        _ => return -1,
+    };
+    let new_value = *translated_ref(token, new_value);
+    let old = timer.settime(flags, &new_value);
+    if !old_value.is_null() {
+        *translated_refmut(token, old_value) = old;
+    }
+    0
+}
+
+pub fn sys_timerfd_gettime(fd: usize, curr_value: *mut ITimerSpec) -> isize {
+    let token = current_user_token();
+    match get_file(fd) {
+        Some(FileDescriptor::TimerFd(timer)) => {
+            *translated_refmut(token, curr_value) = timer.gettime();
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// 只支持新建, 不能修改已有signalfd的mask
+pub fn sys_signalfd4(fd: isize, mask: *const u64, _sizemask: usize, flags: usize) -> isize {
+    if fd != -1 {
+        return -1;
+    }
+    let mask = SignalFlags::from_sigset(*translated_ref(current_user_token(), mask));
+    let signalfd = SignalFd::new(mask, flags);
+    install_fd(FileDescriptor::Abstract(Arc::new(signalfd)))
+}
+
+/// 忙等: 查一遍所有fd, 都没就绪就让出cpu; 没有给信号处理函数, 不支持sigmask
+pub fn sys_ppoll(
+    fds: *mut PollFd,
+    nfds: usize,
+    timeout: *const TimeSpec,
+    _sigmask: usize,
+) -> isize {
+    let token = current_user_token();
+    let deadline = if timeout.is_null() {
+        None
+    } else {
+        Some(get_time_ms() + translated_ref(token, timeout).to_ms())
+    };
+    loop {
+        let mut ready = 0;
+        for i in 0..nfds {
+            let pollfd = translated_refmut(token, unsafe { fds.add(i) });
+            pollfd.revents = 0;
+            if pollfd.fd < 0 {
+                continue;
+            }
+            let revents = match get_file(pollfd.fd as usize) {
+                Some(file) => {
+                    let requested = PollEvents::from_bits_truncate(pollfd.events as u16 as u32);
+                    file.poll() & (requested | PollEvents::always())
+                }
+                None => PollEvents::POLLNVAL,
+            };
+            if !revents.is_empty() {
+                pollfd.revents = revents.bits() as i16;
+                ready += 1;
+            }
+        }
+        if ready > 0 || deadline.map_or(false, |deadline| get_time_ms() >= deadline) {
+            return ready;
+        }
+        if check_signals_of_current().is_some() {
+            return -1;
+        }
+        suspend_current_and_run_next();
+    }
+}
+
+pub fn sys_epoll_create1(_flags: usize) -> isize {
+    install_fd(FileDescriptor::Epoll(Arc::new(EpollFile::new())))
+}
+
+pub fn sys_epoll_ctl(epfd: usize, op: usize, fd: usize, event: *const EpollEvent) -> isize {
+    let epoll = match get_file(epfd) {
+        Some(FileDescriptor::Epoll(epoll)) => epoll,
+        _ => return -1,
+    };
+    match get_file(fd) {
+        // 不能监听自己, 也不能成环(ELOOP)
+        Some(FileDescriptor::Epoll(target)) if op == EPOLL_CTL_ADD && !epoll.may_watch(&target) => {
+            return -1
+        }
+        Some(_) => {}
+        None => return -1,
+    }
+    let event = if op == EPOLL_CTL_DEL {
+        EpollEvent::default()
+    } else {
+        *translated_ref(current_user_token(), event)
+    };
+    epoll.ctl(op, fd, event)
+}
+
+/// `timeout`是毫秒, -1表示一直等
+pub fn sys_epoll_pwait(
+    epfd: usize,
+    events: *mut EpollEvent,
+    maxevents: usize,
+    timeout: isize,
+    _sigmask: usize,
+) -> isize {
+    let token = current_user_token();
+    let epoll = match get_file(epfd) {
+        Some(FileDescriptor::Epoll(epoll)) => epoll,
+        _ => return -1,
+    };
+    if maxevents == 0 {
+        return -1;
+    }
+    let deadline = if timeout < 0 {
+        None
+    } else {
+        Some(get_time_ms() + timeout as usize)
+    };
+    loop {
+        let ready = epoll.ready_events(maxevents);
+        if !ready.is_empty() {
+            for (i, event) in ready.iter().enumerate() {
+                *translated_refmut(token, unsafe { events.add(i) }) = *event;
+            }
+            return ready.len() as isize;
+        }
+        if deadline.map_or(false, |deadline| get_time_ms() >= deadline) {
+            return 0;
+        }
+        if check_signals_of_current().is_some() {
+            return -1;
+        }
+        suspend_current_and_run_next();
+    }
+}
@@ -1,8 +1,12 @@
+use crate::drivers::block::find_block_device;
+use crate::drivers::BLOCK_DEVICE;
 use crate::fs::*;
+use crate::mm::shm::{shm_name, shm_open, shm_unlink, ShmFile};
 use crate::mm::{translated_byte_buffer, translated_refmut, translated_str, UserBuffer};
-use crate::task::{current_process, current_user_token, WorkPath};
+use crate::task::{current_process, current_user_token, WorkPath, RLIMIT_NOFILE};
 use alloc::string::ToString;
-use fatfs::DIRENT_SZ;
+use alloc::sync::Arc;
+use fatfs::{FAT32Manager, DIRENT_SZ};
 
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = current_user_token();
@@ -60,13 +64,29 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
 //     }
 // }
 
-const FD_LIMIT: usize = 128;
 pub fn sys_openat(fd: isize, path: *const u8, flags: u32) -> isize {
     let process = current_process();
     let token = current_user_token();
     let path = translated_str(token, path);
     
     let flags = unsafe { OpenFlags::from_bits_unchecked(flags) };
+    // shm_open打开的是/dev/shm下的共享内存对象
+    if let Some(name) = shm_name(&path) {
+        return open_shm(name, flags);
+    }
+    // 伪终端, 不会自动成为控制终端, 要用TIOCSCTTY
+    if path == PTMX_PATH {
+        return install_file(FileDescriptor::Abstract(open_ptmx()));
+    }
+    if let Some(file) = open_proc(&path) {
+        return install_file(FileDescriptor::Abstract(Arc::new(file)));
+    }
+    if let Some(index) = pts_index(&path) {
+        return match open_pts(index) {
+            Some(slave) => install_file(FileDescriptor::Abstract(slave)),
+            None => -1,
+        };
+    }
     //获取要打开文件的inode
     match if WorkPath::is_abs_path(&path) {
         open_file("/", &path, flags, FileType::Regular)
@@ -99,12 +119,53 @@ pub fn sys_openat(fd: isize, path: *const u8, flags: u32) -> isize {
         None => -1,
         Some(os_inode) => {
             //alloc fd and push into fd table
-            let mut inner = process.inner_exclusive_access();
-            let ret_fd = inner.alloc_fd();
-            inner.fd_table[ret_fd] = Some(FileDescriptor::Regular(os_inode));
-            assert!(inner.fd_table[ret_fd].is_some());
-            ret_fd as isize
+            install_file(FileDescriptor::Regular(os_inode))
+        }
+    }
+}
+
+fn install_file(file: FileDescriptor) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    match inner.alloc_fd() {
+        Some(fd) => {
+            inner.fd_table[fd] = Some(file);
+            fd as isize
+        }
+        // 超过了RLIMIT_NOFILE
+        None => -1,
+    }
+}
+
+fn open_shm(name: &str, flags: OpenFlags) -> isize {
+    let segment = match shm_open(
+        name,
+        flags.contains(OpenFlags::O_CREATE),
+        flags.contains(OpenFlags::O_EXCL),
+        flags.contains(OpenFlags::O_TRUNC),
+    ) {
+        Some(segment) => segment,
+        None => return -1,
+    };
+    let (readable, writable) = flags.read_write();
+    install_file(FileDescriptor::Shm(Arc::new(ShmFile::new(
+        segment, readable, writable,
+    ))))
+}
+
+/// 目前只支持共享内存对象, 用来设置其大小
+pub fn sys_ftruncate(fd: usize, len: usize) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    match inner.fd_table.get(fd) {
+        Some(Some(FileDescriptor::Shm(shm))) if shm.writable() => {
+            if shm.segment.resize(len) {
+                0
+            } else {
+                -1
+            }
         }
+        _ => -1,
     }
 }
 
@@ -125,9 +186,18 @@ pub fn sys_pipe(pipe: *mut u32, flag: usize) -> isize {
     let mut inner = process.inner_exclusive_access();
     _ = flag;
     let (pipe_read, pipe_write) = make_pipe();
-    let read_fd = inner.alloc_fd();
+    let read_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return -1,
+    };
     inner.fd_table[read_fd] = Some(FileDescriptor::Abstract(pipe_read));
-    let write_fd = inner.alloc_fd();
+    let write_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => {
+            inner.fd_table[read_fd] = None;
+            return -1;
+        }
+    };
     inner.fd_table[write_fd] = Some(FileDescriptor::Abstract(pipe_write));
     *translated_refmut(token, pipe as *mut [u32; 2]) = [read_fd as u32, write_fd as u32];
     0
@@ -165,7 +235,10 @@ pub fn sys_pipe(pipe: *mut u32, flag: usize) -> isize {
         return -1;
     }
     //查找空闲的文件描述符
-    let new_fd = inner.alloc_fd();
+    let new_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return -1,
+    };
     //分配文件描述符
     inner.fd_table[new_fd] = Some(inner.fd_table[fd].as_ref().unwrap().clone());
     new_fd as isize
@@ -175,7 +248,7 @@ pub fn sys_dup3(old_fd: usize, new_fd: usize) -> isize {
     let process = current_process();
     let mut inner = process.inner_exclusive_access();
     // 如果旧文件描述符超出了长度或者新文件描述符超过了限制，则返回-1表示错误。
-    if old_fd >= inner.fd_table.len() || new_fd > FD_LIMIT {
+    if old_fd >= inner.fd_table.len() || new_fd >= inner.rlimits.cur(RLIMIT_NOFILE) {
         return -1;
     }
     // 检查旧文件描述符对应的文件是否存在。
@@ -219,7 +292,10 @@ pub fn sys_mkdir(dir_fd: isize, path: *const u8, mode: u32) -> isize {
             return -1;
         }
 
-        if let Some(FileDescriptor::Regular(os_inode)) = inner.fd_table[fd_usize].clone() {
+        // create会检查权限, 再次借用进程控制块, 这里要先放掉
+        let res = inner.fd_table[fd_usize].clone();
+        drop(inner);
+        if let Some(FileDescriptor::Regular(os_inode)) = res {
             if !os_inode.is_dir() {
                 return -1;
             }
@@ -458,25 +534,54 @@ pub fn sys_mount(
 
     _ = data;
 
-    MNT_TABLE.lock().mount(special,dir,fstype,flags as u32)
+    // special要是/dev下登记过的块设备, 比如/dev/vdb1; 根文件系统所在的设备不能再挂一次
+    let device = match special.strip_prefix("/dev/").and_then(find_block_device) {
+        Some(device) if !Arc::ptr_eq(&device, &*BLOCK_DEVICE) => device,
+        _ => return -1,
+    };
+    // 挂载点要是已有的目录, 表里记绝对路径
+    let work_path = current_process().inner_exclusive_access().work_path.to_string();
+    match open_file(&work_path, &dir, OpenFlags::O_RDONLY, FileType::Dir) {
+        Some(inode) if inode.is_dir() => {}
+        _ => return -1,
+    }
+    let dir = absolute_path(&work_path, &dir);
+    let fat_manager = match FAT32Manager::try_open(device) {
+        Some(fat_manager) => fat_manager,
+        None => return -1,
+    };
+    let root = Arc::new(fat_manager.read().get_root_vfile(&fat_manager));
 
+    MNT_TABLE.lock().mount(special, dir, fstype, root, flags as u32)
 }
 
 pub fn sys_umount(special: *const u8, flags: usize) -> isize {
     let token = current_user_token();
     let special = translated_str(token, special);
+    let work_path = current_process().inner_exclusive_access().work_path.to_string();
+    let special = absolute_path(&work_path, &special);
     MNT_TABLE.lock().umount(special, flags as u32)
 }
 pub fn sys_unlink(fd: isize, path: *const u8, flags: u32) -> isize {
     let token = current_user_token();
     let path = translated_str(token, path);
     let pcb = current_process();
+    if let Some(name) = shm_name(&path) {
+        return if shm_unlink(name) { 0 } else { -1 };
+    }
     
+    // 删除看的是所在目录的权限, 文件本身只读也能删
     match if WorkPath::is_abs_path(&path) {
-        open_file("/", &path, OpenFlags::O_RDWR, FileType::Regular)
+        if !may_unlink("/", &path) {
+            return -1;
+        }
+        open_file("/", &path, OpenFlags::O_RDONLY, FileType::Regular)
     } else if fd == AT_FD_CWD {
         let work_path = pcb.inner_exclusive_access().work_path.to_string();
-        open_file(&work_path, &path, OpenFlags::O_RDWR, FileType::Regular)
+        if !may_unlink(&work_path, &path) {
+            return -1;
+        }
+        open_file(&work_path, &path, OpenFlags::O_RDONLY, FileType::Regular)
     } else {
         let fd_usize = fd as usize;
         let mut inner = pcb.inner_exclusive_access();
@@ -6,20 +6,20 @@ pub fn sys_event_get() -> isize {
     let mouse = MOUSE_DEVICE.clone();
     //let input=INPUT_CONDVAR.clone();
     //read_input_event() as isize
-    if !kb.is_empty() {
+    if let Some(kb) = kb.filter(|kb| !kb.is_empty()) {
         kb.read_event() as isize
-    } else if !mouse.is_empty() {
+    } else if let Some(mouse) = mouse.filter(|mouse| !mouse.is_empty()) {
         mouse.read_event() as isize
     } else {
         0
     }
 }
 
-use crate::drivers::chardev::UART;
+use crate::fs::CONSOLE;
 
-/// check UART's read-buffer is empty or not
+/// check console has input to read or not
 pub fn sys_key_pressed() -> isize {
-    let res = !UART.read_buffer_is_empty();
+    let res = CONSOLE.has_input();
     if res {
         1
     } else {
@@ -0,0 +1,93 @@
+use crate::config::PAGE_SIZE;
+use crate::mm::shm::{
+    shm_attach, shm_detach, shm_remove, shm_set_mode, shm_stat, shmget, ShmidDs, IPC_RMID,
+    IPC_SET, IPC_STAT, SHM_RDONLY, SHM_RND,
+};
+use crate::mm::{translated_ref, translated_refmut, MapPermission, VirtAddr};
+use crate::task::{current_process, current_user_token, Credentials, MAY_READ, MAY_WRITE};
+
+fn current_cred() -> Credentials {
+    current_process().inner_exclusive_access().cred.clone()
+}
+
+pub fn sys_shmget(key: usize, size: usize, shmflg: usize) -> isize {
+    shmget(key, size, shmflg, current_process().getpid(), &current_cred())
+}
+
+pub fn sys_shmat(shmid: usize, shmaddr: usize, shmflg: usize) -> isize {
+    let process = current_process();
+    let start = if shmaddr == 0 {
+        None
+    } else if shmflg & SHM_RND != 0 {
+        Some(shmaddr & !(PAGE_SIZE - 1))
+    } else if shmaddr % PAGE_SIZE != 0 {
+        return -1;
+    } else {
+        Some(shmaddr)
+    };
+    let mut access = MAY_READ;
+    if shmflg & SHM_RDONLY == 0 {
+        access |= MAY_WRITE;
+    }
+    let segment = match shm_attach(shmid, process.getpid(), &current_cred(), access) {
+        Some(segment) => segment,
+        None => return -1,
+    };
+    let frames = match segment.frames(0, segment.size()) {
+        Some(frames) => frames,
+        None => return -1,
+    };
+    let mut map_perm = MapPermission::R;
+    if shmflg & SHM_RDONLY == 0 {
+        map_perm |= MapPermission::W;
+    }
+    let mut inner = process.inner_exclusive_access();
+    match inner.mmap_shared(start, frames, map_perm) {
+        Some(start) => start as isize,
+        None => -1,
+    }
+}
+
+pub fn sys_shmdt(shmaddr: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let start_vpn = VirtAddr::from(shmaddr).floor();
+    let first_frame = match inner.memory_set.shared_area_frame(start_vpn) {
+        Some(ppn) => ppn,
+        None => return -1,
+    };
+    inner.memory_set.remove_shared_area(start_vpn);
+    drop(inner);
+    shm_detach(first_frame, process.getpid());
+    0
+}
+
+pub fn sys_shmctl(shmid: usize, cmd: usize, buf: *mut ShmidDs) -> isize {
+    let token = current_user_token();
+    let cred = current_cred();
+    match cmd {
+        IPC_STAT => match shm_stat(shmid, &cred) {
+            Some(ds) => {
+                *translated_refmut(token, buf) = ds;
+                0
+            }
+            None => -1,
+        },
+        IPC_SET => {
+            let mode = translated_ref(token, buf).shm_perm.mode;
+            if shm_set_mode(shmid, mode, &cred) {
+                0
+            } else {
+                -1
+            }
+        }
+        IPC_RMID => {
+            if shm_remove(shmid, &cred) {
+                0
+            } else {
+                -1
+            }
+        }
+        _ => -1,
+    }
+}
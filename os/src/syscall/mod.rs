@@ -1,15 +1,18 @@
 const SYSCALL_GETCWD: usize = 17; // new
+const SYSCALL_EVENTFD2: usize = 19;
+const SYSCALL_EPOLL_CREATE1: usize = 20;
+const SYSCALL_EPOLL_CTL: usize = 21;
+const SYSCALL_EPOLL_PWAIT: usize = 22;
 const SYSCALL_DUP: usize = 23;
 const SYSCALL_DUP3: usize = 24; // new
-// const SYSCALL_CONNECT: usize = 29;
-// const SYSCALL_LISTEN: usize = 30;
-const SYSCALL_ACCEPT: usize = 31;
+const SYSCALL_IOCTL: usize = 29;
 const SYSCALL_MKDIRAT: usize = 34; // new
 const SYSCALL_UNLINKAT: usize = 35; // new
 // const SYSCALL_LINK_AT: usize = 37; // new
 const SYSCALL_UMOUNT2: usize = 39; // new
 const SYSCALL_MOUNT: usize = 40; // new
 // const SYSCALL_STATFS: usize = 43; // new
+const SYSCALL_FTRUNCATE: usize = 46;
 const SYSCALL_CHDIR: usize = 49; // new
 const SYSCALL_OPEN: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
@@ -18,16 +21,62 @@ const SYSCALL_GETDENTS64: usize = 61; // new
 // const SYSCALL_LSEEK: usize = 62; // new
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_PPOLL: usize = 73;
+const SYSCALL_SIGNALFD4: usize = 74;
 const SYSCALL_FSTAT: usize = 80; // new
+const SYSCALL_TIMERFD_CREATE: usize = 85;
+const SYSCALL_TIMERFD_SETTIME: usize = 86;
+const SYSCALL_TIMERFD_GETTIME: usize = 87;
 const SYSCALL_EXIT: usize = 93;
-const SYSCALL_SLEEP: usize = 101;
+const SYSCALL_EXIT_GROUP: usize = 94;
+const SYSCALL_NANOSLEEP: usize = 101;
+const SYSCALL_GETITIMER: usize = 102;
+const SYSCALL_SETITIMER: usize = 103;
+const SYSCALL_CLOCK_GETTIME: usize = 113;
+const SYSCALL_CLOCK_GETRES: usize = 114;
+const SYSCALL_CLOCK_NANOSLEEP: usize = 115;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_KILL: usize = 129;
+const SYSCALL_RT_SIGPROCMASK: usize = 135;
+const SYSCALL_SETREGID: usize = 143;
+const SYSCALL_SETGID: usize = 144;
+const SYSCALL_SETREUID: usize = 145;
+const SYSCALL_SETUID: usize = 146;
+const SYSCALL_SETRESUID: usize = 147;
+const SYSCALL_GETRESUID: usize = 148;
+const SYSCALL_SETRESGID: usize = 149;
+const SYSCALL_GETRESGID: usize = 150;
 const SYSCALL_TIMES: usize = 153;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETPGID: usize = 155;
+const SYSCALL_GETSID: usize = 156;
+const SYSCALL_SETSID: usize = 157;
+const SYSCALL_GETGROUPS: usize = 158;
+const SYSCALL_SETGROUPS: usize = 159;
 const SYSCALL_UNAME: usize = 160;
-const SYSCALL_GET_TIME: usize = 169;
+const SYSCALL_GETRLIMIT: usize = 163;
+const SYSCALL_SETRLIMIT: usize = 164;
+const SYSCALL_GETTIMEOFDAY: usize = 169;
 const SYSCALL_GETPID: usize = 172;
 const SYSCALL_GET_PPID: usize = 173;
+const SYSCALL_GETUID: usize = 174;
+const SYSCALL_GETEUID: usize = 175;
+const SYSCALL_GETGID: usize = 176;
+const SYSCALL_GETEGID: usize = 177;
+const SYSCALL_SHMGET: usize = 194;
+const SYSCALL_SHMCTL: usize = 195;
+const SYSCALL_SHMAT: usize = 196;
+const SYSCALL_SHMDT: usize = 197;
+const SYSCALL_SOCKET: usize = 198;
+const SYSCALL_SOCKETPAIR: usize = 199;
+const SYSCALL_BIND: usize = 200;
+const SYSCALL_LISTEN: usize = 201;
+const SYSCALL_ACCEPT: usize = 202;
+const SYSCALL_CONNECT: usize = 203;
+const SYSCALL_SENDTO: usize = 206;
+const SYSCALL_RECVFROM: usize = 207;
+const SYSCALL_SENDMSG: usize = 211;
+const SYSCALL_RECVMSG: usize = 212;
 const SYSCALL_BRK: usize = 214;
 const SYSCALL_MUNMAP: usize = 215;
 //const SYSCALL_FORK: usize = 220;
@@ -35,6 +84,7 @@ const SYSCALL_CLONE: usize = 220;
 const SYSCALL_EXEC: usize = 221;
 const SYSCALL_MMAP: usize = 222;
 const SYSCALL_WAITPID: usize = 260;
+const SYSCALL_PRLIMIT64: usize = 261;
 const SYSCALL_THREAD_CREATE: usize = 1000;
 const SYSCALL_GETTID: usize = 1001;
 const SYSCALL_WAITTID: usize = 1002;
@@ -52,30 +102,44 @@ const SYSCALL_CONDVAR_WAIT: usize = 1032;
 const SYSCALL_EVENT_GET: usize = 3000;
 const SYSCALL_KEY_PRESSED: usize = 3001;
 
+mod event;
 mod fs;
 mod gui;
 mod input;
+mod ipc;
 mod net;
 mod process;
 mod sync;
 mod thread;
+mod time;
 
+use event::*;
 use fs::*;
 // use gui::*;
 use input::*;
+use ipc::*;
 use net::*;
 use process::*;
 use sync::*;
 use thread::*;
+use time::*;
 
 pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
     match syscall_id {
         SYSCALL_GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
+        SYSCALL_EVENTFD2 => sys_eventfd2(args[0], args[1]),
+        SYSCALL_EPOLL_CREATE1 => sys_epoll_create1(args[0]),
+        SYSCALL_EPOLL_CTL => sys_epoll_ctl(args[0], args[1], args[2], args[3] as _),
+        SYSCALL_EPOLL_PWAIT => sys_epoll_pwait(
+            args[0],
+            args[1] as _,
+            args[2],
+            args[3] as isize,
+            args[4],
+        ),
         SYSCALL_DUP => sys_dup(args[0]),
         SYSCALL_DUP3 => sys_dup3(args[0], args[1]),
-        // SYSCALL_CONNECT => sys_connect(args[0] as _, args[1] as _, args[2] as _),
-        // SYSCALL_LISTEN => sys_listen(args[0] as _),
-        SYSCALL_ACCEPT => sys_accept(args[0] as _),
+        SYSCALL_IOCTL => sys_ioctl(args[0], args[1], args[2]),
         SYSCALL_MKDIRAT => sys_mkdir(args[0] as isize, args[1] as *const u8, args[2] as u32),
         SYSCALL_UNLINKAT => sys_unlink(args[0] as isize, args[1] as *const u8, args[2] as u32),
         SYSCALL_UMOUNT2 => sys_umount(args[0] as *const u8, args[1]),
@@ -87,6 +151,7 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
             args[4] as *const u8,
         ),
         // SYSCALL_FSTAT => sys_fstat(args[0] as isize, args[1] as *const u8),
+        SYSCALL_FTRUNCATE => sys_ftruncate(args[0], args[1]),
         SYSCALL_CHDIR => sys_chdir(args[0] as *const u8),
         SYSCALL_OPEN => sys_openat(args[0] as isize, args[1] as *const u8, args[2] as u32),
         SYSCALL_CLOSE => sys_close(args[0]),
@@ -95,24 +160,89 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
         SYSCALL_GETDENTS64 => sys_getdents64(args[0] as isize, args[1] as *mut u8, args[2]),
+        SYSCALL_PPOLL => sys_ppoll(args[0] as _, args[1], args[2] as _, args[3]),
+        SYSCALL_SIGNALFD4 => sys_signalfd4(args[0] as isize, args[1] as _, args[2], args[3]),
         SYSCALL_FSTAT => sys_fstat(args[0] as isize, args[1] as *mut u8),
+        SYSCALL_TIMERFD_CREATE => sys_timerfd_create(args[0], args[1]),
+        SYSCALL_TIMERFD_SETTIME => sys_timerfd_settime(args[0], args[1], args[2] as _, args[3] as _),
+        SYSCALL_TIMERFD_GETTIME => sys_timerfd_gettime(args[0], args[1] as _),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
-        SYSCALL_SLEEP => sys_sleep(args[0]),
+        SYSCALL_EXIT_GROUP => sys_exit_group(args[0] as i32),
+        SYSCALL_NANOSLEEP => sys_nanosleep(args[0] as _, args[1] as _),
+        SYSCALL_GETITIMER => sys_getitimer(args[0], args[1] as _),
+        SYSCALL_SETITIMER => sys_setitimer(args[0], args[1] as _, args[2] as _),
+        SYSCALL_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as _),
+        SYSCALL_CLOCK_GETRES => sys_clock_getres(args[0], args[1] as _),
+        SYSCALL_CLOCK_NANOSLEEP => sys_clock_nanosleep(args[0], args[1], args[2] as _, args[3] as _),
         SYSCALL_YIELD => sys_yield(),
-        SYSCALL_KILL => sys_kill(args[0], args[1] as u32),
-        SYSCALL_TIMES => sys_get_time(),
+        SYSCALL_KILL => sys_kill(args[0] as isize, args[1] as u32),
+        SYSCALL_RT_SIGPROCMASK => sys_rt_sigprocmask(args[0], args[1] as _, args[2] as _),
+        SYSCALL_SETREGID => sys_setregid(args[0], args[1]),
+        SYSCALL_SETGID => sys_setgid(args[0]),
+        SYSCALL_SETREUID => sys_setreuid(args[0], args[1]),
+        SYSCALL_SETUID => sys_setuid(args[0]),
+        SYSCALL_SETRESUID => sys_setresuid(args[0], args[1], args[2]),
+        SYSCALL_GETRESUID => sys_getresuid(args[0] as _, args[1] as _, args[2] as _),
+        SYSCALL_SETRESGID => sys_setresgid(args[0], args[1], args[2]),
+        SYSCALL_GETRESGID => sys_getresgid(args[0] as _, args[1] as _, args[2] as _),
+        SYSCALL_TIMES => sys_times(args[0] as _),
+        SYSCALL_SETPGID => sys_setpgid(args[0], args[1]),
+        SYSCALL_GETPGID => sys_getpgid(args[0]),
+        SYSCALL_GETSID => sys_getsid(args[0]),
+        SYSCALL_SETSID => sys_setsid(),
+        SYSCALL_GETGROUPS => sys_getgroups(args[0], args[1] as _),
+        SYSCALL_SETGROUPS => sys_setgroups(args[0], args[1] as _),
         SYSCALL_UNAME => sys_uname(args[0] as *const u8),
-        SYSCALL_GET_TIME => sys_get_time(),
+        SYSCALL_GETRLIMIT => sys_getrlimit(args[0], args[1] as _),
+        SYSCALL_SETRLIMIT => sys_setrlimit(args[0], args[1] as _),
+        SYSCALL_GETTIMEOFDAY => sys_gettimeofday(args[0] as _, args[1]),
         SYSCALL_GETPID => sys_getpid(),
         SYSCALL_GET_PPID => sys_getppid(),
+        SYSCALL_GETUID => sys_getuid(),
+        SYSCALL_GETEUID => sys_geteuid(),
+        SYSCALL_GETGID => sys_getgid(),
+        SYSCALL_GETEGID => sys_getegid(),
+        SYSCALL_SHMGET => sys_shmget(args[0], args[1], args[2]),
+        SYSCALL_SHMCTL => sys_shmctl(args[0], args[1], args[2] as _),
+        SYSCALL_SHMAT => sys_shmat(args[0], args[1], args[2]),
+        SYSCALL_SHMDT => sys_shmdt(args[0]),
+        SYSCALL_SOCKET => sys_socket(args[0], args[1], args[2]),
+        SYSCALL_SOCKETPAIR => sys_socketpair(args[0], args[1], args[2], args[3] as _),
+        SYSCALL_BIND => sys_bind(args[0], args[1] as _, args[2]),
+        SYSCALL_LISTEN => sys_listen(args[0], args[1]),
+        SYSCALL_ACCEPT => sys_accept(args[0], args[1] as _, args[2] as _),
+        SYSCALL_CONNECT => sys_connect(args[0], args[1] as _, args[2]),
+        SYSCALL_SENDTO => sys_sendto(
+            args[0],
+            args[1] as *const u8,
+            args[2],
+            args[3],
+            args[4] as _,
+            args[5],
+        ),
+        SYSCALL_RECVFROM => sys_recvfrom(
+            args[0],
+            args[1] as *mut u8,
+            args[2],
+            args[3],
+            args[4] as _,
+            args[5] as _,
+        ),
+        SYSCALL_SENDMSG => sys_sendmsg(args[0], args[1] as _, args[2]),
+        SYSCALL_RECVMSG => sys_recvmsg(args[0], args[1] as _, args[2]),
         SYSCALL_BRK => sys_brk(args[0]),
         SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
         // SYSCALL_FORK => sys_fork(),
         SYSCALL_CLONE => sys_clone(args[0], args[1], args[2], args[3], args[4]),
-        SYSCALL_EXEC => sys_execve(args[0] as *const u8, args[1] as *const usize),
+        SYSCALL_EXEC => sys_execve(
+            args[0] as *const u8,
+            args[1] as *const usize,
+            args[2] as *const usize,
+        ),
         SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
         //SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
         SYSCALL_WAITPID => sys_wait4(args[0] as isize, args[1] as *mut i32, args[2] as isize),
+        SYSCALL_PRLIMIT64 => sys_prlimit64(args[0], args[1], args[2] as _, args[3] as _),
         SYSCALL_THREAD_CREATE => sys_thread_create(args[0], args[1]),
         SYSCALL_GETTID => sys_gettid(),
         SYSCALL_WAITTID => sys_waittid(args[0]) as isize,
@@ -1,53 +1,837 @@
-use crate::net::port_table::{accept, listen, port_acceptable, PortFd};
+use super::fs::sys_close;
+use crate::fs::FileDescriptor;
+use crate::fs::File;
+use crate::mm::{translated_byte_buffer, translated_ref, translated_refmut, UserBuffer};
+use crate::net::port_table::{accept, listen, PortFd, DEFAULT_BACKLOG};
+use crate::net::socket_fd::{
+    SocketFd, SocketState, AF_INET, AF_UNIX, IPPROTO_ICMP, SOCK_DGRAM, SOCK_RAW, SOCK_STREAM,
+    SOCK_TYPE_MASK,
+};
+use crate::net::tcp::{self, TCP};
 use crate::net::udp::UDP;
-use crate::net::{net_interrupt_handler, IPv4};
-use crate::task::{current_process, current_task, current_trap_cx};
+use crate::net::unix::{
+    self, user_buffer_data, UnixDatagram, UnixListener, UnixMessage, UnixStream, UNIX_PATH_MAX,
+};
+use crate::net::{iface, IPv4};
+use crate::task::{current_process, current_user_token};
+use alloc::string::{String, ToString};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 
-// just support udp
-// pub fn sys_connect(raddr: u32, lport: u16, rport: u16) -> isize {
-//     let process = current_process();
-//     let mut inner = process.inner_exclusive_access();
-//     let fd = inner.alloc_fd();
-//     let udp_node = UDP::new(IPv4::from_u32(raddr), lport, rport);
-//     inner.fd_table[fd] = Some(Arc::new(udp_node));
-//     fd as isize
-// }
-
-// // listen a port
-// pub fn sys_listen(port: u16) -> isize {
-//     match listen(port) {
-//         Some(port_index) => {
-//             let process = current_process();
-//             let mut inner = process.inner_exclusive_access();
-//             let fd = inner.alloc_fd();
-//             let port_fd = PortFd::new(port_index);
-//             inner.fd_table[fd] = Some(Arc::new(port_fd));
-
-//             // NOTICE: this return the port index, not the fd
-//             port_index as isize
-//         }
-//         None => -1,
-//     }
-// }
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SockAddrIn {
+    pub sin_family: u16,
+    /// network byte order
+    pub sin_port: u16,
+    /// network byte order
+    pub sin_addr: u32,
+    pub sin_zero: [u8; 8],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SockAddrUn {
+    pub sun_family: u16,
+    pub sun_path: [u8; UNIX_PATH_MAX],
+}
+
+fn install_socket(socket: SocketFd) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    match inner.alloc_fd() {
+        Some(fd) => {
+            inner.fd_table[fd] = Some(FileDescriptor::Socket(Arc::new(socket)));
+            fd as isize
+        }
+        None => -1,
+    }
+}
+
+fn get_socket_fd(fd: usize) -> Option<Arc<SocketFd>> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    match inner.fd_table.get(fd) {
+        Some(Some(FileDescriptor::Socket(socket))) => Some(socket.clone()),
+        _ => None,
+    }
+}
+
+fn read_sockaddr(addr: *const SockAddrIn, addrlen: usize) -> Option<(IPv4, u16)> {
+    if addr.is_null() || addrlen < core::mem::size_of::<SockAddrIn>() {
+        return None;
+    }
+    let sockaddr = *translated_ref(current_user_token(), addr);
+    if sockaddr.sin_family as usize != AF_INET {
+        return None;
+    }
+    Some((
+        IPv4::from_u32(u32::from_be(sockaddr.sin_addr)),
+        u16::from_be(sockaddr.sin_port),
+    ))
+}
+
+/// relative paths are taken from the cwd, `.` and `..` are resolved
+fn absolute_path(path: &str) -> String {
+    let mut parts: Vec<String> = if path.starts_with('/') {
+        Vec::new()
+    } else {
+        let process = current_process();
+        let inner = process.inner_exclusive_access();
+        // path[0] is the root "/"
+        inner.work_path.path.iter().skip(1).cloned().collect()
+    };
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part.to_string()),
+        }
+    }
+    if parts.is_empty() {
+        return String::from("/");
+    }
+    let mut abs_path = String::new();
+    for part in parts {
+        abs_path.push('/');
+        abs_path.push_str(&part);
+    }
+    abs_path
+}
+
+/// an absolute path, or NUL followed by the name in the abstract namespace
+fn read_sockaddr_un(addr: *const u8, addrlen: usize) -> Option<String> {
+    if addr.is_null() || addrlen <= 2 || addrlen > core::mem::size_of::<SockAddrUn>() {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(addrlen);
+    for b in translated_byte_buffer(current_user_token(), addr, addrlen) {
+        bytes.extend_from_slice(b);
+    }
+    if u16::from_ne_bytes([bytes[0], bytes[1]]) as usize != AF_UNIX {
+        return None;
+    }
+    let path = &bytes[2..];
+    if path[0] == 0 {
+        // abstract names use every byte of addrlen
+        return String::from_utf8(path.to_vec()).ok();
+    }
+    let len = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+    let path = core::str::from_utf8(&path[..len]).ok()?;
+    Some(absolute_path(path))
+}
+
+/// unnamed sockets only get the family
+fn sockaddr_un_bytes(name: Option<&str>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&(AF_UNIX as u16).to_ne_bytes());
+    if let Some(name) = name {
+        bytes.extend_from_slice(name.as_bytes());
+        if !unix::is_abstract(name) {
+            bytes.push(0);
+        }
+    }
+    bytes
+}
+
+fn write_sockaddr_un(addr: *mut u8, addrlen: *mut u32, name: Option<&str>) {
+    if addr.is_null() {
+        return;
+    }
+    let token = current_user_token();
+    let bytes = sockaddr_un_bytes(name);
+    UserBuffer::new(translated_byte_buffer(token, addr, bytes.len())).write(&bytes);
+    if !addrlen.is_null() {
+        *translated_refmut(token, addrlen) = bytes.len() as u32;
+    }
+}
+
+fn write_sockaddr(addr: *mut SockAddrIn, addrlen: *mut u32, ip: IPv4, port: u16) {
+    if addr.is_null() {
+        return;
+    }
+    let token = current_user_token();
+    *translated_refmut(token, addr) = SockAddrIn {
+        sin_family: AF_INET as u16,
+        sin_port: port.to_be(),
+        sin_addr: ip.to_u32().to_be(),
+        sin_zero: [0; 8],
+    };
+    if !addrlen.is_null() {
+        *translated_refmut(token, addrlen) = core::mem::size_of::<SockAddrIn>() as u32;
+    }
+}
+
+pub fn sys_socket(domain: usize, sock_type: usize, protocol: usize) -> isize {
+    let sock_type = sock_type & SOCK_TYPE_MASK;
+    let socket = match (domain, sock_type, protocol) {
+        (AF_UNIX, SOCK_STREAM, _) | (AF_UNIX, SOCK_DGRAM, _) => SocketFd::new_unix(sock_type),
+        (AF_INET, SOCK_RAW, IPPROTO_ICMP) | (AF_INET, SOCK_DGRAM, IPPROTO_ICMP) => {
+            SocketFd::new_icmp(sock_type)
+        }
+        (AF_INET, SOCK_STREAM, _) | (AF_INET, SOCK_DGRAM, _) => SocketFd::new(domain, sock_type),
+        _ => return -1,
+    };
+    install_socket(socket)
+}
+
+pub fn sys_socketpair(
+    domain: usize,
+    sock_type: usize,
+    _protocol: usize,
+    sv: *mut [u32; 2],
+) -> isize {
+    if domain != AF_UNIX {
+        return -1;
+    }
+    let (a, b) = match sock_type & SOCK_TYPE_MASK {
+        SOCK_STREAM => {
+            let (a, b) = UnixStream::pair();
+            (SocketFd::from_unix_stream(a), SocketFd::from_unix_stream(b))
+        }
+        SOCK_DGRAM => {
+            let (a, b) = UnixDatagram::pair();
+            (SocketFd::from_unix_datagram(a), SocketFd::from_unix_datagram(b))
+        }
+        _ => return -1,
+    };
+    let fd0 = install_socket(a);
+    if fd0 < 0 {
+        return -1;
+    }
+    let fd1 = install_socket(b);
+    if fd1 < 0 {
+        sys_close(fd0 as usize);
+        return -1;
+    }
+    *translated_refmut(current_user_token(), sv) = [fd0 as u32, fd1 as u32];
+    0
+}
+
+fn bind_unix(socket: &SocketFd, addr: *const u8, addrlen: usize) -> isize {
+    let name = match read_sockaddr_un(addr, addrlen) {
+        Some(name) => name,
+        None => return -1,
+    };
+    if socket.inner_exclusive_access().unix_name.is_some() {
+        return -1;
+    }
+    let datagram = match socket.state() {
+        SocketState::UnixDatagram(datagram) => Some(datagram),
+        _ => None,
+    };
+    if !unix::bind(&name, datagram.as_ref()) {
+        return -1;
+    }
+    socket.inner_exclusive_access().unix_name = Some(name);
+    0
+}
+
+pub fn sys_bind(fd: usize, addr: *const SockAddrIn, addrlen: usize) -> isize {
+    let socket = match get_socket_fd(fd) {
+        Some(socket) => socket,
+        None => return -1,
+    };
+    if socket.domain == AF_UNIX {
+        return bind_unix(&socket, addr as *const u8, addrlen);
+    }
+    let (_, port) = match read_sockaddr(addr, addrlen) {
+        Some(x) => x,
+        None => return -1,
+    };
+    let mut inner = socket.inner_exclusive_access();
+    if inner.local_port.is_some() {
+        return -1;
+    }
+    let port = if port == 0 {
+        match tcp::alloc_ephemeral_port() {
+            Some(port) => port,
+            None => return -1,
+        }
+    } else {
+        port
+    };
+    // datagrams may arrive before connect, so udp takes the port right away
+    if socket.sock_type == SOCK_DGRAM {
+        match UDP::new(port) {
+            Some(udp) => inner.state = SocketState::Datagram(Arc::new(udp)),
+            // EADDRINUSE
+            None => return -1,
+        }
+    }
+    inner.local_port = Some(port);
+    0
+}
+
+// listen a port
+pub fn sys_listen(fd: usize, backlog: usize) -> isize {
+    let socket = match get_socket_fd(fd) {
+        Some(socket) => socket,
+        None => return -1,
+    };
+    if socket.sock_type != SOCK_STREAM {
+        return -1;
+    }
+    let mut inner = socket.inner_exclusive_access();
+    if !matches!(inner.state, SocketState::Unconnected) {
+        return -1;
+    }
+    if socket.domain == AF_UNIX {
+        // unlike linux there is no autobind, the socket must have a name already
+        let name = match &inner.unix_name {
+            Some(name) => name.clone(),
+            None => return -1,
+        };
+        let listener = Arc::new(UnixListener::new(if backlog == 0 {
+            DEFAULT_BACKLOG
+        } else {
+            backlog
+        }));
+        unix::listen(&name, &listener);
+        inner.state = SocketState::UnixListening(listener);
+        return 0;
+    }
+    let port = match inner.local_port.or_else(tcp::alloc_ephemeral_port) {
+        Some(port) => port,
+        None => return -1,
+    };
+    match listen(port, backlog) {
+        Some(port_index) => {
+            inner.local_port = Some(port);
+            inner.state = SocketState::Listening(Arc::new(PortFd::new(port_index)));
+            0
+        }
+        None => -1,
+    }
+}
 
 // accept a tcp connection
-pub fn sys_accept(port_index: usize) -> isize {
-    println!("accepting port {}", port_index);
+pub fn sys_accept(fd: usize, addr: *mut SockAddrIn, addrlen: *mut u32) -> isize {
+    let socket = match get_socket_fd(fd) {
+        Some(socket) => socket,
+        None => return -1,
+    };
+    if let SocketState::UnixListening(listener) = socket.state() {
+        let stream = listener.accept();
+        // clients are usually unnamed
+        write_sockaddr_un(addr as *mut u8, addrlen, None);
+        return install_socket(SocketFd::from_unix_stream(stream));
+    }
+    let (port_fd, local_port) = {
+        let inner = socket.inner_exclusive_access();
+        match &inner.state {
+            SocketState::Listening(port_fd) => (port_fd.clone(), inner.local_port.unwrap()),
+            _ => return -1,
+        }
+    };
 
-    let task = current_task().unwrap();
-    accept(port_index, task);
-    // block_current_and_run_next();
+    let tcb_index = match accept(port_fd.0) {
+        Some(tcb_index) => tcb_index,
+        None => return -1,
+    };
 
-    // NOTICE: There does not have interrupt handler, just call it munually.
-    loop {
-        net_interrupt_handler();
+    if let Some((ip, port)) = tcp::get_peer(tcb_index) {
+        write_sockaddr(addr, addrlen, ip, port);
+    }
+    install_socket(SocketFd::from_stream(local_port, TCP::new(tcb_index)))
+}
 
-        if !port_acceptable(port_index) {
-            break;
+fn connect_unix(socket: &SocketFd, addr: *const u8, addrlen: usize) -> isize {
+    let name = match read_sockaddr_un(addr, addrlen) {
+        Some(name) => name,
+        None => return -1,
+    };
+    match socket.state() {
+        SocketState::UnixDatagram(datagram) => match unix::find_datagram(&name) {
+            Some(peer) => {
+                *datagram.peer.exclusive_access() = Some(Arc::downgrade(&peer));
+                0
+            }
+            None => -1,
+        },
+        SocketState::Unconnected => match unix::connect(&name) {
+            Some(stream) => {
+                socket.inner_exclusive_access().state = SocketState::UnixStream(Arc::new(stream));
+                0
+            }
+            None => -1,
+        },
+        _ => -1,
+    }
+}
+
+pub fn sys_connect(fd: usize, addr: *const SockAddrIn, addrlen: usize) -> isize {
+    let socket = match get_socket_fd(fd) {
+        Some(socket) => socket,
+        None => return -1,
+    };
+    if socket.domain == AF_UNIX {
+        return connect_unix(&socket, addr as *const u8, addrlen);
+    }
+    let (ip, port) = match read_sockaddr(addr, addrlen) {
+        Some(x) => x,
+        None => return -1,
+    };
+    if let SocketState::Icmp(icmp) = socket.state() {
+        *icmp.peer.exclusive_access() = Some(ip);
+        return 0;
+    }
+    if socket.sock_type == SOCK_DGRAM {
+        return match udp_socket(&socket) {
+            Some(udp) => {
+                udp.connect(ip, port);
+                0
+            }
+            None => -1,
+        };
+    }
+    let local_port = {
+        let mut inner = socket.inner_exclusive_access();
+        if !matches!(inner.state, SocketState::Unconnected) {
+            return -1;
+        }
+        match inner.local_port.or_else(tcp::alloc_ephemeral_port) {
+            Some(port) => *inner.local_port.insert(port),
+            // EADDRNOTAVAIL
+            None => return -1,
+        }
+    };
+
+    let tcb_index = match tcp::connect(ip, port, local_port) {
+        Some(tcb_index) => tcb_index,
+        None => return -1,
+    };
+    // wait for the handshake, the SYN is retransmitted by the tcp timer
+    if !tcp::wait_established(tcb_index) {
+        tcp::close(tcb_index);
+        return -1;
+    }
+    socket.inner_exclusive_access().state = SocketState::Stream(Arc::new(TCP::new(tcb_index)));
+    0
+}
+
+/// the udp socket behind `socket`, an unbound one gets an ephemeral port.
+/// None if the port is in use
+fn udp_socket(socket: &SocketFd) -> Option<Arc<UDP>> {
+    let mut inner = socket.inner_exclusive_access();
+    match &inner.state {
+        SocketState::Datagram(udp) => return Some(udp.clone()),
+        SocketState::Unconnected => {}
+        _ => return None,
+    }
+    let port = inner.local_port.or_else(tcp::alloc_ephemeral_port)?;
+    let udp = Arc::new(UDP::new(port)?);
+    inner.local_port = Some(port);
+    inner.state = SocketState::Datagram(udp.clone());
+    Some(udp)
+}
+
+/// datagrams without an address go to the connected peer
+fn send_unix_datagram(
+    socket: &SocketFd,
+    datagram: &UnixDatagram,
+    addr: *const u8,
+    addrlen: usize,
+    data: Vec<u8>,
+    fds: Vec<FileDescriptor>,
+) -> isize {
+    let message = UnixMessage {
+        data,
+        fds,
+        from: socket.inner_exclusive_access().unix_name.clone(),
+    };
+    if addr.is_null() {
+        return datagram.send(message);
+    }
+    match read_sockaddr_un(addr, addrlen).and_then(|name| unix::find_datagram(&name)) {
+        Some(target) => target.deliver(message),
+        None => -1,
+    }
+}
+
+pub fn sys_sendto(
+    fd: usize,
+    buf: *const u8,
+    len: usize,
+    _flags: usize,
+    addr: *const SockAddrIn,
+    addrlen: usize,
+) -> isize {
+    let socket = match get_socket_fd(fd) {
+        Some(socket) => socket,
+        None => return -1,
+    };
+    let token = current_user_token();
+    match socket.state() {
+        SocketState::Icmp(icmp) => {
+            let dest = match read_sockaddr(addr, addrlen) {
+                Some((ip, _)) => ip,
+                None => match *icmp.peer.exclusive_access() {
+                    Some(ip) => ip,
+                    None => return -1,
+                },
+            };
+            let mut data = Vec::new();
+            for b in translated_byte_buffer(token, buf, len) {
+                data.extend_from_slice(b);
+            }
+            icmp.send_to(dest, &data) as isize
+        }
+        SocketState::UnixDatagram(datagram) => {
+            let data = user_buffer_data(&UserBuffer::new(translated_byte_buffer(token, buf, len)));
+            send_unix_datagram(&socket, &datagram, addr as *const u8, addrlen, data, Vec::new())
+        }
+        // udp sends to the address if there is one, binding an ephemeral port first
+        SocketState::Unconnected | SocketState::Datagram(_)
+            if socket.sock_type == SOCK_DGRAM && !addr.is_null() =>
+        {
+            let (ip, port) = match read_sockaddr(addr, addrlen) {
+                Some(x) => x,
+                None => return -1,
+            };
+            let udp = match udp_socket(&socket) {
+                Some(udp) => udp,
+                None => return -1,
+            };
+            let data = user_buffer_data(&UserBuffer::new(translated_byte_buffer(token, buf, len)));
+            udp.send_to(ip, port, &data) as isize
+        }
+        // connected sockets ignore the address
+        _ => socket.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize,
+    }
+}
+
+pub fn sys_recvfrom(
+    fd: usize,
+    buf: *mut u8,
+    len: usize,
+    _flags: usize,
+    addr: *mut SockAddrIn,
+    addrlen: *mut u32,
+) -> isize {
+    let socket = match get_socket_fd(fd) {
+        Some(socket) => socket,
+        None => return -1,
+    };
+    let token = current_user_token();
+    match socket.state() {
+        SocketState::Icmp(icmp) => {
+            let (src, packet) = icmp.recv_from();
+            write_sockaddr(addr, addrlen, src, 0);
+            let mut buf = UserBuffer::new(translated_byte_buffer(token, buf, len));
+            buf.write(&packet) as isize
+        }
+        SocketState::Stream(tcp) => {
+            if let Some((ip, port)) = tcp::get_peer(tcp.socket_index) {
+                write_sockaddr(addr, addrlen, ip, port);
+            }
+            tcp.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        }
+        SocketState::Datagram(udp) => {
+            let (src, port, data) = udp.recv_from();
+            write_sockaddr(addr, addrlen, src, port);
+            if len == 0 {
+                return 0;
+            }
+            let mut buf = UserBuffer::new(translated_byte_buffer(token, buf, len));
+            buf.write(&data) as isize
+        }
+        SocketState::UnixStream(stream) => {
+            stream.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        }
+        SocketState::UnixDatagram(datagram) => {
+            let message = datagram.recv();
+            write_sockaddr_un(addr as *mut u8, addrlen, message.from.as_deref());
+            if len == 0 {
+                return 0;
+            }
+            UserBuffer::new(translated_byte_buffer(token, buf, len)).write(&message.data) as isize
+        }
+        _ => -1,
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct IoVec {
+    pub iov_base: *mut u8,
+    pub iov_len: usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MsgHdr {
+    pub msg_name: *mut u8,
+    pub msg_namelen: u32,
+    pub msg_iov: *const IoVec,
+    pub msg_iovlen: usize,
+    pub msg_control: *mut u8,
+    pub msg_controllen: usize,
+    pub msg_flags: i32,
+}
+
+/// struct cmsghdr { size_t cmsg_len; int cmsg_level; int cmsg_type; }
+const CMSG_HDR_LEN: usize = 16;
+const SOL_SOCKET: i32 = 1;
+const SCM_RIGHTS: i32 = 1;
+const MSG_CTRUNC: i32 = 0x8;
+const MSG_TRUNC: i32 = 0x20;
+
+fn cmsg_align(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+fn iov_buffer(token: usize, msg: &MsgHdr) -> UserBuffer {
+    let mut buffers = Vec::new();
+    for i in 0..msg.msg_iovlen {
+        let iov = *translated_ref(token, msg.msg_iov.wrapping_add(i));
+        if iov.iov_len > 0 {
+            buffers.extend(translated_byte_buffer(token, iov.iov_base, iov.iov_len));
+        }
+    }
+    UserBuffer::new(buffers)
+}
+
+/// the files named by SCM_RIGHTS, None if the control data or a fd is bad
+fn read_scm_rights(token: usize, msg: &MsgHdr) -> Option<Vec<FileDescriptor>> {
+    let mut files = Vec::new();
+    if msg.msg_control.is_null() || msg.msg_controllen == 0 {
+        return Some(files);
+    }
+    let mut control = Vec::with_capacity(msg.msg_controllen);
+    for b in translated_byte_buffer(token, msg.msg_control, msg.msg_controllen) {
+        control.extend_from_slice(b);
+    }
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let mut offset = 0;
+    while offset + CMSG_HDR_LEN <= control.len() {
+        let cmsg_len = usize::from_ne_bytes(control[offset..offset + 8].try_into().unwrap());
+        let level = i32::from_ne_bytes(control[offset + 8..offset + 12].try_into().unwrap());
+        let cmsg_type = i32::from_ne_bytes(control[offset + 12..offset + 16].try_into().unwrap());
+        if cmsg_len < CMSG_HDR_LEN || offset + cmsg_len > control.len() {
+            return None;
+        }
+        if level == SOL_SOCKET && cmsg_type == SCM_RIGHTS {
+            for fd in control[offset + CMSG_HDR_LEN..offset + cmsg_len].chunks_exact(4) {
+                let fd = i32::from_ne_bytes(fd.try_into().unwrap()) as usize;
+                match inner.fd_table.get(fd) {
+                    Some(Some(file)) => files.push(file.clone()),
+                    _ => return None,
+                }
+            }
         }
+        offset += cmsg_align(cmsg_len);
     }
+    Some(files)
+}
 
-    let cx = current_trap_cx();
-    cx.x[10] as isize
+/// install received files as new fds and describe them in the control buffer
+fn write_scm_rights(token: usize, msg: &mut MsgHdr, mut files: Vec<FileDescriptor>) {
+    let room = if msg.msg_control.is_null() || msg.msg_controllen < CMSG_HDR_LEN {
+        0
+    } else {
+        (msg.msg_controllen - CMSG_HDR_LEN) / 4
+    };
+    if room < files.len() {
+        msg.msg_flags |= MSG_CTRUNC;
+    }
+    // files that don't fit are closed, like on linux
+    let mut dropped = files.split_off(room.min(files.len()));
+    let mut fds = Vec::with_capacity(files.len());
+    {
+        let process = current_process();
+        let mut inner = process.inner_exclusive_access();
+        for file in files {
+            match inner.alloc_fd() {
+                Some(fd) => {
+                    inner.fd_table[fd] = Some(file);
+                    fds.push(fd);
+                }
+                // out of RLIMIT_NOFILE, the rest are closed too
+                None => {
+                    msg.msg_flags |= MSG_CTRUNC;
+                    dropped.push(file);
+                }
+            }
+        }
+    }
+    drop(dropped);
+    if fds.is_empty() {
+        msg.msg_controllen = 0;
+        return;
+    }
+    let cmsg_len = CMSG_HDR_LEN + 4 * fds.len();
+    let mut cmsg = Vec::with_capacity(cmsg_len);
+    cmsg.extend_from_slice(&cmsg_len.to_ne_bytes());
+    cmsg.extend_from_slice(&SOL_SOCKET.to_ne_bytes());
+    cmsg.extend_from_slice(&SCM_RIGHTS.to_ne_bytes());
+    for fd in fds {
+        cmsg.extend_from_slice(&(fd as i32).to_ne_bytes());
+    }
+    UserBuffer::new(translated_byte_buffer(token, msg.msg_control, cmsg.len())).write(&cmsg);
+    msg.msg_controllen = cmsg_align(cmsg_len).min(msg.msg_controllen);
+}
+
+pub fn sys_sendmsg(fd: usize, msg: *const MsgHdr, _flags: usize) -> isize {
+    let socket = match get_socket_fd(fd) {
+        Some(socket) => socket,
+        None => return -1,
+    };
+    let token = current_user_token();
+    let msg = *translated_ref(token, msg);
+    let files = match read_scm_rights(token, &msg) {
+        Some(files) => files,
+        None => return -1,
+    };
+    match socket.state() {
+        SocketState::UnixStream(stream) => {
+            stream.send(&user_buffer_data(&iov_buffer(token, &msg)), files)
+        }
+        SocketState::UnixDatagram(datagram) => send_unix_datagram(
+            &socket,
+            &datagram,
+            msg.msg_name,
+            msg.msg_namelen as usize,
+            user_buffer_data(&iov_buffer(token, &msg)),
+            files,
+        ),
+        // ancillary data only means something on AF_UNIX
+        _ if !files.is_empty() => -1,
+        _ => socket.write(iov_buffer(token, &msg)) as isize,
+    }
+}
+
+pub fn sys_recvmsg(fd: usize, msg: *mut MsgHdr, _flags: usize) -> isize {
+    let socket = match get_socket_fd(fd) {
+        Some(socket) => socket,
+        None => return -1,
+    };
+    let token = current_user_token();
+    let msg = translated_refmut(token, msg);
+    let mut buf = iov_buffer(token, msg);
+    let len = buf.len();
+    msg.msg_flags = 0;
+    let message = match socket.state() {
+        SocketState::UnixStream(stream) => stream.recv(len),
+        SocketState::UnixDatagram(datagram) => datagram.recv(),
+        _ => {
+            msg.msg_controllen = 0;
+            return socket.read(buf) as isize;
+        }
+    };
+    if !msg.msg_name.is_null() {
+        let name = sockaddr_un_bytes(message.from.as_deref());
+        let name_len = name.len().min(msg.msg_namelen as usize);
+        if name_len > 0 {
+            UserBuffer::new(translated_byte_buffer(token, msg.msg_name, name_len)).write(&name);
+        }
+        msg.msg_namelen = name.len() as u32;
+    }
+    if message.data.len() > len {
+        msg.msg_flags |= MSG_TRUNC;
+    }
+    write_scm_rights(token, msg, message.fds);
+    if len == 0 {
+        return 0;
+    }
+    buf.write(&message.data) as isize
+}
+
+const SIOCGIFADDR: usize = 0x8915;
+const SIOCSIFADDR: usize = 0x8916;
+const SIOCGIFNETMASK: usize = 0x891b;
+const SIOCSIFNETMASK: usize = 0x891c;
+const SIOCGIFHWADDR: usize = 0x8927;
+/// linux has no ifreq request for the default route, these are in the private range
+const SIOCGIFGATEWAY: usize = 0x89f0;
+const SIOCSIFGATEWAY: usize = 0x89f1;
+
+const IFNAMSIZ: usize = 16;
+const ARPHRD_ETHER: u16 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct IfReq {
+    pub ifr_name: [u8; IFNAMSIZ],
+    pub ifr_addr: SockAddrIn,
+    pub ifr_pad: [u8; 8],
+}
+
+fn ioctl_iface(request: usize, arg: *mut IfReq) -> isize {
+    if arg.is_null() {
+        return -1;
+    }
+    // changing the interface configuration needs root, EPERM
+    let set = matches!(request, SIOCSIFADDR | SIOCSIFNETMASK | SIOCSIFGATEWAY);
+    if set && !current_process().inner_exclusive_access().cred.is_privileged() {
+        return -1;
+    }
+    let ifreq = translated_refmut(current_user_token(), arg);
+    let name_len = ifreq
+        .ifr_name
+        .iter()
+        .position(|&c| c == 0)
+        .unwrap_or(IFNAMSIZ);
+    let name = match core::str::from_utf8(&ifreq.ifr_name[..name_len]) {
+        Ok(name) => name,
+        Err(_) => return -1,
+    };
+    let index = match iface::find_by_name(name) {
+        Some(index) => index,
+        None => return -1,
+    };
+    let (ip, netmask, gateway) = iface::get_config(index);
+    let new_addr = IPv4::from_u32(u32::from_be(ifreq.ifr_addr.sin_addr));
+    let get = |addr: IPv4| SockAddrIn {
+        sin_family: AF_INET as u16,
+        sin_port: 0,
+        sin_addr: addr.to_u32().to_be(),
+        sin_zero: [0; 8],
+    };
+    match request {
+        SIOCGIFADDR => ifreq.ifr_addr = get(ip),
+        SIOCGIFNETMASK => ifreq.ifr_addr = get(netmask),
+        SIOCGIFGATEWAY => ifreq.ifr_addr = get(gateway),
+        SIOCSIFADDR => iface::set_addr(index, new_addr),
+        SIOCSIFNETMASK => iface::set_netmask(index, new_addr),
+        SIOCSIFGATEWAY => iface::set_gateway(index, new_addr),
+        SIOCGIFHWADDR => {
+            // struct sockaddr { sa_family, sa_data[14] }, the mac is at the start of sa_data
+            let sockaddr = &mut ifreq.ifr_addr as *mut SockAddrIn as *mut u8;
+            let sockaddr = unsafe {
+                core::slice::from_raw_parts_mut(sockaddr, core::mem::size_of::<SockAddrIn>())
+            };
+            sockaddr.fill(0);
+            sockaddr[..2].copy_from_slice(&ARPHRD_ETHER.to_ne_bytes());
+            sockaddr[2..8].copy_from_slice(&iface::mac(index));
+        }
+        _ => return -1,
+    }
+    0
+}
+
+/// interface configuration goes through the socket layer, other requests go to the file itself
+pub fn sys_ioctl(fd: usize, request: usize, arg: usize) -> isize {
+    match request {
+        SIOCGIFADDR | SIOCSIFADDR | SIOCGIFNETMASK | SIOCSIFNETMASK | SIOCGIFHWADDR
+        | SIOCGIFGATEWAY | SIOCSIFGATEWAY => {
+            if get_socket_fd(fd).is_none() {
+                return -1;
+            }
+            ioctl_iface(request, arg as *mut IfReq)
+        }
+        _ => {
+            let process = current_process();
+            let inner = process.inner_exclusive_access();
+            let file = match inner.fd_table.get(fd) {
+                Some(Some(file)) => file.clone(),
+                _ => return -1,
+            };
+            drop(inner);
+            file.ioctl(request, arg)
+        }
+    }
 }
@@ -1,25 +1,28 @@
-use crate::fs::{OpenFlags, open_file, FileType};
-use crate::mm::{translated_ref, translated_refmut, translated_str, align_up, translated_byte_buffer, UserBuffer};
+use crate::fs::{OpenFlags, open_file, FileType, FileDescriptor, OSInode};
+use crate::mm::{translated_ref, translated_refmut, translated_str, align_up, translated_byte_buffer, UserBuffer, MapPermission};
 use crate::task::*;
-use crate::timer::get_time_ms;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 
+/// 只结束当前线程
 pub fn sys_exit(exit_code: i32) -> ! {
     exit_current_and_run_next(exit_code);
     panic!("Unreachable in sys_exit!");
 }
 
+/// 结束整个进程
+pub fn sys_exit_group(exit_code: i32) -> ! {
+    exit_group_and_run_next(exit_code);
+    panic!("Unreachable in sys_exit_group!");
+}
+
 pub fn sys_yield() -> isize {
     suspend_current_and_run_next();
     0
 }
 
-pub fn sys_get_time() -> isize {
-    get_time_ms() as isize
-}
-
 pub fn sys_getpid() -> isize {
     current_task().unwrap().process.upgrade().unwrap().getpid() as isize
 }
@@ -37,14 +40,32 @@ pub fn sys_getppid() -> isize {
     
 }
 
+/// RLIMIT_NPROC限制同一个实际uid的进程数, root不受限制
+fn may_fork(process: &ProcessControlBlock) -> bool {
+    let (uid, limit) = {
+        let inner = process.inner_exclusive_access();
+        if inner.cred.is_privileged() {
+            return true;
+        }
+        (inner.cred.uid.real, inner.rlimits.cur(RLIMIT_NPROC))
+    };
+    find_processes(|other| other.inner_exclusive_access().cred.uid.real == uid).len() < limit
+}
+
 #[allow(unused)]
 pub fn sys_fork() -> isize {
     let current_process = current_process();
-    let new_process = current_process.fork();
+    if !may_fork(&current_process) {
+        return -1;
+    }
+    let new_process = match current_process.fork() {
+        Some(process) => process,
+        None => return -1,
+    };
     let new_pid = new_process.getpid();
     // modify trap context of new_task, because it returns immediately after switching
     let new_process_inner = new_process.inner_exclusive_access();
-    let task = new_process_inner.tasks[0].as_ref().unwrap();
+    let task = new_process_inner.tasks.iter().flatten().next().unwrap();
     let trap_cx = task.inner_exclusive_access().get_trap_cx();
     // we do not have to move to next instruction since we have done it before
     // for child process, fork returns 0
@@ -56,7 +77,13 @@ pub fn sys_fork() -> isize {
 pub fn sys_clone(flags: usize, stack_ptr: usize, _ptid: usize, _tls: usize, _ctid: usize) -> isize {
     let pcb = current_process();
     let flags = unsafe { CloneFlag::from_bits_unchecked(flags) };
-    let child_pcb = pcb.fork();
+    if !may_fork(&pcb) {
+        return -1;
+    }
+    let child_pcb = match pcb.fork() {
+        Some(process) => process,
+        None => return -1,
+    };
     let child_pid = child_pcb.getpid();
     
     if !flags.contains(CloneFlag::CLONE_SIGHLD) {
@@ -66,7 +93,8 @@ pub fn sys_clone(flags: usize, stack_ptr: usize, _ptid: usize, _tls: usize, _cti
     if flags.contains(CloneFlag::CLONE_CHILD_SETTID) {}
     
     let child_inner = child_pcb.inner_exclusive_access();
-    let child_task = child_inner.tasks[0].as_ref().unwrap();
+    // 子进程唯一的线程沿用了调用者的tid, 不一定是0
+    let child_task = child_inner.tasks.iter().flatten().next().unwrap();
     let child_trap_cx = child_task.inner.exclusive_access().get_trap_cx();
     
     //更改
@@ -77,43 +105,88 @@ pub fn sys_clone(flags: usize, stack_ptr: usize, _ptid: usize, _tls: usize, _cti
     child_pid as isize
 }
 
-/// ### 将当前进程的地址空间清空并加载一个特定的可执行文件，返回用户态后开始它的执行。
-/// - 参数：
-///     - `path` 给出了要加载的可执行文件的名字
-///     - `args` 数组中的每个元素都是一个命令行参数字符串的起始地址，以地址为0表示参数尾
-/// - 返回值：如果出错的话（如找不到名字相符的可执行文件）则返回 -1，否则返回参数个数 `argc`。
-pub fn sys_execve(path: *const u8, mut args: *const usize) -> isize {
-    let token = current_user_token();
-    let path = translated_str(token, path);
-    let mut args_vec: Vec<String> = Vec::new();
+/// 读用户态以0结尾的字符串指针数组, 比如argv和envp, 空指针当作空数组
+fn translated_str_array(token: usize, mut ptr: *const usize) -> Vec<String> {
+    let mut strs = Vec::new();
+    if ptr.is_null() {
+        return strs;
+    }
     loop {
-        let arg_str_ptr = *translated_ref(token, args);
-        if arg_str_ptr == 0 {
+        let str_ptr = *translated_ref(token, ptr);
+        if str_ptr == 0 {
             break;
         }
-        args_vec.push(translated_str(token, arg_str_ptr as *const u8));
+        strs.push(translated_str(token, str_ptr as *const u8));
         unsafe {
-            args = args.add(1);
+            ptr = ptr.add(1);
         }
     }
+    strs
+}
+
+/// #!脚本最多套这么多层, 和Linux一样
+const MAX_SHEBANG_DEPTH: usize = 4;
+/// #!行最长读这么多
+const SHEBANG_MAX: usize = 256;
+
+/// 打开要执行的文件. 遇到`#!interpreter [arg]`脚本时换成解释器,
+/// 参数变成 interpreter [arg] 脚本路径 原来的argv[1..]
+fn open_executable(work_path: &str, path: &str, args: &mut Vec<String>) -> Option<Arc<OSInode>> {
+    let mut path = String::from(path);
+    for _ in 0..=MAX_SHEBANG_DEPTH {
+        let file = open_file(work_path, &path, OpenFlags::O_RDONLY, FileType::Regular)?;
+        // 脚本和解释器都要有执行权限
+        if file.is_dir() || !file.permits(MAY_EXEC) {
+            return None;
+        }
+        let mut head = [0u8; SHEBANG_MAX];
+        let len = file.read_at(0, &mut head);
+        if !head[..len].starts_with(b"#!") {
+            return Some(file);
+        }
+        let line = head[2..len].split(|&c| c == b'\n').next().unwrap();
+        let line = core::str::from_utf8(line).ok()?.trim();
+        // 解释器后面的内容整个作为一个参数
+        let (interp, arg) = match line.split_once(|c: char| c == ' ' || c == '\t') {
+            Some((interp, arg)) => (interp, arg.trim()),
+            None => (line, ""),
+        };
+        if interp.is_empty() {
+            return None;
+        }
+        let mut new_args = vec![String::from(interp)];
+        if !arg.is_empty() {
+            new_args.push(String::from(arg));
+        }
+        new_args.push(path);
+        new_args.extend(args.drain(..).skip(1));
+        *args = new_args;
+        path = String::from(interp);
+    }
+    None
+}
+
+/// ### 将当前进程的地址空间清空并加载一个特定的可执行文件，返回用户态后开始它的执行。
+/// - 参数：
+///     - `path` 给出了要加载的可执行文件的路径, 相对路径从当前工作目录找, 不查PATH
+///     - `args` 数组中的每个元素都是一个命令行参数字符串的起始地址，以地址为0表示参数尾
+///     - `envs` 环境变量数组, 格式同`args`, 原样放到新程序的栈上
+/// - 返回值：如果出错的话（如找不到名字相符的可执行文件）则返回 -1，否则返回参数个数 `argc`。
+pub fn sys_execve(path: *const u8, args: *const usize, envs: *const usize) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let mut args_vec = translated_str_array(token, args);
+    let envs_vec = translated_str_array(token, envs);
 
     //获取当前工作目录
     let work_path = current_process()
         .inner_exclusive_access()
         .work_path
         .to_string();
-    if let Some(app_inode) = open_file(
-        &work_path,
-        path.as_str(),
-        OpenFlags::O_RDONLY,
-        FileType::Regular,
-    ) {
-        let all_data = app_inode.read_all();
+    if let Some(app_inode) = open_executable(&work_path, &path, &mut args_vec) {
         let process = current_process();
-        let argc = args_vec.len();
-        process.exec(all_data.as_slice(), args_vec);
         // return argc because cx.x[10] will be covered with it later
-        argc as isize
+        process.exec(&app_inode, args_vec, envs_vec)
     } else {
         -1
     }
@@ -136,12 +209,9 @@ pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
         }
     }
     if let Some(app_inode) = open_file("/", path.as_str(), OpenFlags::O_RDONLY, FileType::Regular) {
-        let all_data = app_inode.read_all();
         let process = current_process();
-        let argc = args_vec.len();
-        process.exec(all_data.as_slice(), args_vec);
         // return argc because cx.x[10] will be covered with it later
-        argc as isize
+        process.exec(&app_inode, args_vec, Vec::new())
     } else {
         -1
     }
@@ -177,6 +247,8 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         // ++++ temporarily access child PCB exclusively
         let exit_code = child.inner_exclusive_access().exit_code;
         // ++++ release child PCB
+        let child_times = child.inner_exclusive_access().total_times();
+        inner.children_times += child_times;
         *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
         found_pid as isize
     } else {
@@ -185,28 +257,40 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     // ---- release current PCB automatically
 }
 
+const WNOHANG: isize = 1;
+const WUNTRACED: isize = 2;
+const WCONTINUED: isize = 8;
+
+/// wait4的pid: -1任意子进程, 0同一进程组, 小于-1是指定的进程组
+fn wait_target(pid: isize, pgid: usize, child: &ProcessControlBlock) -> bool {
+    match pid {
+        -1 => true,
+        0 => child.inner_exclusive_access().pgid == pgid,
+        pid if pid < -1 => child.inner_exclusive_access().pgid == (-pid) as usize,
+        pid => child.getpid() == pid as usize,
+    }
+}
+
 pub fn sys_wait4(pid: isize, status: *mut i32, options: isize) -> isize {
     //参数options提供了一些另外的选项来控制waitpid()函数的行为。如果不想使用这些选项，则可以把这个参数设为0。
-    if options != 0{
-        panic!{"Extended option not support yet..."};
+    if options & !(WNOHANG | WUNTRACED | WCONTINUED) != 0 {
+        return -1;
     }
     loop {
         let process = current_process();
         // find a child process
         //failed return-1
         let mut inner = process.inner_exclusive_access();
-        if !inner
-            .children
-            .iter()
-            .any(|p| pid == -1 || pid as usize == p.getpid())
-        {
+        let pgid = inner.pgid;
+        if !inner.children.iter().any(|p| wait_target(pid, pgid, p)) {
             return -1;
             // ---- release current PCB
         }
         let pair = inner.children.iter().enumerate().find(|(_, p)| {
             // ++++ temporarily access child PCB exclusively
-            p.inner_exclusive_access().is_zombie && (pid == -1 || pid as usize == p.getpid())
+            let is_zombie = p.inner_exclusive_access().is_zombie;
             // ++++ release child PCB
+            is_zombie && wait_target(pid, pgid, p)
         });
         if let Some((idx, _)) = pair {
             let child = inner.children.remove(idx);
@@ -216,6 +300,8 @@ pub fn sys_wait4(pid: isize, status: *mut i32, options: isize) -> isize {
             // ++++ temporarily access child PCB exclusively
             let exit_code = child.inner_exclusive_access().exit_code;
             // ++++ release child PCB
+            let child_times = child.inner_exclusive_access().total_times();
+            inner.children_times += child_times;
 
             //mjqadd
             let sstatus = exit_code << 8;
@@ -223,28 +309,402 @@ pub fn sys_wait4(pid: isize, status: *mut i32, options: isize) -> isize {
                 *translated_refmut(inner.memory_set.token(), status) = sstatus;
             }
             return found_pid as isize;
-        } else {
-            drop(inner);
-            drop(process);
-            suspend_current_and_run_next();
         }
+        // 停下或者继续运行的子进程, 每次状态变化只报告一次
+        let job = inner
+            .children
+            .iter()
+            .filter(|p| wait_target(pid, pgid, p))
+            .find_map(|p| {
+                let mut child_inner = p.inner_exclusive_access();
+                let wanted = match child_inner.job_status {
+                    Some(WAIT_CONTINUED) => options & WCONTINUED != 0,
+                    Some(_) => options & WUNTRACED != 0,
+                    None => false,
+                };
+                if wanted {
+                    child_inner.job_status.take().map(|s| (p.getpid(), s))
+                } else {
+                    None
+                }
+            });
+        if let Some((found_pid, sstatus)) = job {
+            if (status as usize) != 0 {
+                *translated_refmut(inner.memory_set.token(), status) = sstatus;
+            }
+            return found_pid as isize;
+        }
+        if options & WNOHANG != 0 {
+            return 0;
+        }
+        drop(inner);
+        drop(process);
+        suspend_current_and_run_next();
     }
     // ---- release current PCB automatically
 }
 
-pub fn sys_kill(pid: usize, signal: u32) -> isize {
-    if let Some(process) = pid2process(pid) {
-        if let Some(flag) = SignalFlags::from_bits(signal) {
-            process.inner_exclusive_access().signals |= flag;
-            0
-        } else {
-            -1
+/// pid大于0发给单个进程, 0发给自己的进程组, -1发给除initproc和自己外的所有进程,
+/// 小于-1发给进程组-pid; 信号0只检查目标是否存在. 只能发给uid相同的进程, root除外
+pub fn sys_kill(pid: isize, signum: u32) -> isize {
+    let signal = if signum == 0 {
+        SignalFlags::empty()
+    } else {
+        match SignalFlags::from_signum(signum) {
+            Some(signal) => signal,
+            None => return -1,
+        }
+    };
+    let process = current_process();
+    let (cred, pgid) = {
+        let inner = process.inner_exclusive_access();
+        (inner.cred.clone(), inner.pgid)
+    };
+    let in_group = |pgid: usize| find_processes(|p| p.inner_exclusive_access().pgid == pgid);
+    let targets = match pid {
+        0 => in_group(pgid),
+        // 除了init和自己
+        -1 => find_processes(|p| {
+            p.getpid() != INITPROC.getpid() && p.getpid() != process.getpid()
+        }),
+        pid if pid < -1 => in_group((-pid) as usize),
+        pid => pid2process(pid as usize).into_iter().collect(),
+    };
+    // 一个也发不出去时返回-1(ESRCH或者EPERM)
+    let mut sent = false;
+    for target in targets.iter() {
+        let allowed = cred.may_signal(&target.inner_exclusive_access().cred);
+        if allowed {
+            send_signal(target, signal);
+            sent = true;
+        }
+    }
+    if sent {
+        0
+    } else {
+        -1
+    }
+}
+
+/// pid为0表示当前进程
+fn pid_or_current(pid: usize) -> Option<Arc<ProcessControlBlock>> {
+    if pid == 0 {
+        Some(current_process())
+    } else {
+        pid2process(pid)
+    }
+}
+
+/// 只能改自己或者同一会话里的子进程, 会话首进程不能换组;
+/// 要加入的组必须已经在本会话里存在
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+    let process = current_process();
+    let target = if pid == 0 || pid == process.getpid() {
+        process.clone()
+    } else {
+        let inner = process.inner_exclusive_access();
+        match inner.children.iter().find(|p| p.getpid() == pid) {
+            Some(child) => child.clone(),
+            None => return -1,
         }
+    };
+    let target_pid = target.getpid();
+    let pgid = if pgid == 0 { target_pid } else { pgid };
+    let sid = process.inner_exclusive_access().sid;
+    let target_sid = target.inner_exclusive_access().sid;
+    if target_sid != sid || target_sid == target_pid {
+        return -1;
+    }
+    if pgid != target_pid {
+        let group = find_processes(|p| {
+            let inner = p.inner_exclusive_access();
+            inner.pgid == pgid && inner.sid == sid
+        });
+        if group.is_empty() {
+            return -1;
+        }
+    }
+    target.inner_exclusive_access().pgid = pgid;
+    0
+}
+
+pub fn sys_getpgid(pid: usize) -> isize {
+    match pid_or_current(pid) {
+        Some(process) => process.inner_exclusive_access().pgid as isize,
+        None => -1,
+    }
+}
+
+pub fn sys_getsid(pid: usize) -> isize {
+    match pid_or_current(pid) {
+        Some(process) => process.inner_exclusive_access().sid as isize,
+        None => -1,
+    }
+}
+
+/// 新会话没有控制终端; 进程组组长不能建新会话
+pub fn sys_setsid() -> isize {
+    let process = current_process();
+    let pid = process.getpid();
+    let mut inner = process.inner_exclusive_access();
+    if inner.pgid == pid {
+        return -1;
+    }
+    inner.pgid = pid;
+    inner.sid = pid;
+    pid as isize
+}
+
+pub fn sys_getuid() -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    inner.cred.uid.real as isize
+}
+
+pub fn sys_geteuid() -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    inner.cred.uid.effective as isize
+}
+
+pub fn sys_getgid() -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    inner.cred.gid.real as isize
+}
+
+pub fn sys_getegid() -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    inner.cred.gid.effective as isize
+}
+
+/// -1表示这一项不改
+fn optional_id(id: usize) -> Option<u32> {
+    match id as u32 {
+        u32::MAX => None,
+        id => Some(id),
+    }
+}
+
+pub fn sys_setuid(uid: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let privileged = inner.cred.is_privileged();
+    if inner.cred.uid.set(uid as u32, privileged) {
+        0
     } else {
         -1
     }
 }
 
+pub fn sys_setgid(gid: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let privileged = inner.cred.is_privileged();
+    if inner.cred.gid.set(gid as u32, privileged) {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_setreuid(ruid: usize, euid: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let privileged = inner.cred.is_privileged();
+    if inner
+        .cred
+        .uid
+        .set_re(optional_id(ruid), optional_id(euid), privileged)
+    {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_setregid(rgid: usize, egid: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let privileged = inner.cred.is_privileged();
+    if inner
+        .cred
+        .gid
+        .set_re(optional_id(rgid), optional_id(egid), privileged)
+    {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_setresuid(ruid: usize, euid: usize, suid: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let privileged = inner.cred.is_privileged();
+    let (ruid, euid, suid) = (optional_id(ruid), optional_id(euid), optional_id(suid));
+    if inner.cred.uid.set_res(ruid, euid, suid, privileged) {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_setresgid(rgid: usize, egid: usize, sgid: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let privileged = inner.cred.is_privileged();
+    let (rgid, egid, sgid) = (optional_id(rgid), optional_id(egid), optional_id(sgid));
+    if inner.cred.gid.set_res(rgid, egid, sgid, privileged) {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_getresuid(ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> isize {
+    let token = current_user_token();
+    let uid = current_process().inner_exclusive_access().cred.uid;
+    *translated_refmut(token, ruid) = uid.real;
+    *translated_refmut(token, euid) = uid.effective;
+    *translated_refmut(token, suid) = uid.saved;
+    0
+}
+
+pub fn sys_getresgid(rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> isize {
+    let token = current_user_token();
+    let gid = current_process().inner_exclusive_access().cred.gid;
+    *translated_refmut(token, rgid) = gid.real;
+    *translated_refmut(token, egid) = gid.effective;
+    *translated_refmut(token, sgid) = gid.saved;
+    0
+}
+
+/// size为0时只返回附加组的个数
+pub fn sys_getgroups(size: usize, list: *mut u32) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let groups = process.inner_exclusive_access().cred.groups.clone();
+    if size == 0 {
+        return groups.len() as isize;
+    }
+    if size < groups.len() {
+        return -1;
+    }
+    for (i, &gid) in groups.iter().enumerate() {
+        *translated_refmut(token, unsafe { list.add(i) }) = gid;
+    }
+    groups.len() as isize
+}
+
+/// 只有特权进程能设置附加组
+pub fn sys_setgroups(size: usize, list: *const u32) -> isize {
+    if size > NGROUPS_MAX {
+        return -1;
+    }
+    let token = current_user_token();
+    let groups: Vec<u32> = (0..size)
+        .map(|i| *translated_ref(token, unsafe { list.add(i) }))
+        .collect();
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if !inner.cred.is_privileged() {
+        return -1;
+    }
+    inner.cred.groups = groups;
+    0
+}
+
+pub fn sys_getrlimit(resource: usize, rlim: *mut RLimit) -> isize {
+    sys_prlimit64(0, resource, core::ptr::null(), rlim)
+}
+
+pub fn sys_setrlimit(resource: usize, rlim: *const RLimit) -> isize {
+    sys_prlimit64(0, resource, rlim, core::ptr::null_mut())
+}
+
+/// pid为0时是当前进程. 改别的进程的限制要求是root,
+/// 或者调用者的实际uid/gid和对方的三个uid/gid都相同
+pub fn sys_prlimit64(
+    pid: usize,
+    resource: usize,
+    new_limit: *const RLimit,
+    old_limit: *mut RLimit,
+) -> isize {
+    let token = current_user_token();
+    let caller = current_process();
+    let target = if pid == 0 || pid == caller.getpid() {
+        caller.clone()
+    } else {
+        match pid2process(pid) {
+            Some(target) => target,
+            None => return -1,
+        }
+    };
+    let cred = caller.inner_exclusive_access().cred.clone();
+    let privileged = cred.is_privileged();
+    if !Arc::ptr_eq(&caller, &target) && !privileged {
+        let target_inner = target.inner_exclusive_access();
+        let uid = &target_inner.cred.uid;
+        let gid = &target_inner.cred.gid;
+        let same_user = [uid.real, uid.effective, uid.saved]
+            .iter()
+            .all(|&id| id == cred.uid.real)
+            && [gid.real, gid.effective, gid.saved]
+                .iter()
+                .all(|&id| id == cred.gid.real);
+        if !same_user {
+            return -1;
+        }
+    }
+    let new_limit = if new_limit.is_null() {
+        None
+    } else {
+        Some(*translated_ref(token, new_limit))
+    };
+    let mut target_inner = target.inner_exclusive_access();
+    let old = match target_inner.rlimits.get(resource) {
+        Some(old) => old,
+        None => return -1,
+    };
+    if let Some(new_limit) = new_limit {
+        if !target_inner.rlimits.set(resource, new_limit, privileged) {
+            return -1;
+        }
+    }
+    drop(target_inner);
+    if !old_limit.is_null() {
+        *translated_refmut(token, old_limit) = old;
+    }
+    0
+}
+
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
+/// 屏蔽字是整个进程共用的
+pub fn sys_rt_sigprocmask(how: usize, set: *const u64, oldset: *mut u64) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if !oldset.is_null() {
+        *translated_refmut(token, oldset) = inner.signal_mask.to_sigset();
+    }
+    if set.is_null() {
+        return 0;
+    }
+    let set = SignalFlags::from_sigset(*translated_ref(token, set)) - SignalFlags::unblockable();
+    match how {
+        SIG_BLOCK => inner.signal_mask |= set,
+        SIG_UNBLOCK => inner.signal_mask -= set,
+        SIG_SETMASK => inner.signal_mask = set,
+        _ => return -1,
+    }
+    0
+}
+
 pub fn sys_brk(addr: usize) -> isize {
     let process = current_process();
     let mut inner = process.inner_exclusive_access();
@@ -253,11 +713,20 @@ pub fn sys_brk(addr: usize) -> isize {
     } else if addr < inner.heap_base.0 {
         -1
     } else {
+        // 堆的大小受RLIMIT_DATA限制, 增长的部分还要算进RLIMIT_AS
+        if addr - inner.heap_base.0 > inner.rlimits.cur(RLIMIT_DATA) {
+            return -1;
+        }
+        if addr > inner.heap_end.0 && !inner.may_grow_vm(addr - inner.heap_end.0) {
+            return -1;
+        }
         inner.heap_end = addr.into();
         addr as isize
     }
 }
 
+const MAP_SHARED: usize = 0x01;
+
 pub fn sys_mmap(
     _start: usize,
     len: usize,
@@ -266,8 +735,33 @@ pub fn sys_mmap(
     fd: usize,
     offset: usize,
 ) -> isize {
+    if flags & MAP_SHARED != 0 {
+        let process = current_process();
+        let mut inner = process.inner_exclusive_access();
+        // /dev/shm files map their segment, other files still get a private copy
+        if let Some(Some(FileDescriptor::Shm(shm))) = inner.fd_table.get(fd).cloned() {
+            let frames = match shm.segment.frames(offset, len) {
+                Some(frames) => frames,
+                None => return -1,
+            };
+            if !inner.may_grow_vm(align_up(len)) {
+                return -1;
+            }
+            let map_perm = MapPermission::from_bits_truncate(((prot & 0x7) << 1) as u8);
+            return match inner.mmap_shared(None, frames, map_perm) {
+                Some(start) => start as isize,
+                None => -1,
+            };
+        }
+    }
     let align_start = align_up(current_process().inner_exclusive_access().mmap_area_end.0);
     let align_len = align_up(len);
+    if !current_process()
+        .inner_exclusive_access()
+        .may_grow_vm(align_len)
+    {
+        return -1;
+    }
     current_process().inner_exclusive_access().mmap(
         align_start,
         align_len,
@@ -292,22 +786,6 @@ pub fn sys_munmap(start: usize, len: usize) -> isize {
     }
 }
 
-// pub fn sys_nanosleep(buf:*mut u8) -> isize {
-//     //获取当前时间
-//     let start = get_time_ms();
-//     let token = current_user_token();
-//     let sleep_time = translated_refmut(token, buf as *mut TimeVal);
-//     let time = sleep_time.sec*1000 + sleep_time.usec / 1000;
-//     loop{
-//         let end = get_time_ms();
-//         if end - start >= time {
-//             break;
-//         }
-//     };
-//     0
-// }
-
-
 pub fn sys_uname(buf:*const u8) -> isize{
     //取出正在执行的用户地址空间
     let token = current_user_token();
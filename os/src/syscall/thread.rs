@@ -8,16 +8,17 @@ use alloc::sync::Arc;
 pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
     let task = current_task().unwrap();
     let process = task.process.upgrade().unwrap();
-    // create a new thread
-    let new_task = Arc::new(TaskControlBlock::new(
-        Arc::clone(&process),
-        task.inner_exclusive_access()
-            .res
-            .as_ref()
-            .unwrap()
-            .ustack_base,
-        true,
-    ));
+    let ustack_base = task
+        .inner_exclusive_access()
+        .res
+        .as_ref()
+        .unwrap()
+        .ustack_base;
+    // create a new thread, 物理页不够时失败
+    let new_task = match TaskControlBlock::new(Arc::clone(&process), ustack_base, true) {
+        Some(new_task) => Arc::new(new_task),
+        None => return -1,
+    };
     // add new task to scheduler
     add_task(Arc::clone(&new_task));
     let new_task_inner = new_task.inner_exclusive_access();
@@ -65,7 +66,7 @@ pub fn sys_waittid(tid: usize) -> i32 {
         return -1;
     }
     let mut exit_code: Option<i32> = None;
-    let waited_task = process_inner.tasks[tid].as_ref();
+    let waited_task = process_inner.tasks.get(tid).and_then(|task| task.as_ref());
     if let Some(waited_task) = waited_task {
         if let Some(waited_exit_code) = waited_task.inner_exclusive_access().exit_code {
             exit_code = Some(waited_exit_code);
@@ -76,7 +77,9 @@ pub fn sys_waittid(tid: usize) -> i32 {
     }
     if let Some(exit_code) = exit_code {
         // dealloc the exited thread
-        process_inner.tasks[tid] = None;
+        if let Some(waited_task) = process_inner.tasks[tid].take() {
+            process_inner.exited_times += waited_task.inner_exclusive_access().times;
+        }
         exit_code
     } else {
         // waited thread has not exited
@@ -0,0 +1,195 @@
+use crate::mm::{translated_ref, translated_refmut};
+use crate::task::{
+    arm_real_timer, block_current_and_run_next, current_account_system_time, current_process,
+    current_task, current_user_token, suspend_current_and_run_next, ITimerVal, ITIMER_PROF,
+};
+use crate::timer::{
+    add_timer, clock_resolution_ns, get_realtime_ns, get_time, get_time_ns, ticks_to_clock_t,
+    ticks_to_ns, TimeSpec, TimeVal,
+};
+
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+const CLOCK_PROCESS_CPUTIME_ID: usize = 2;
+const CLOCK_THREAD_CPUTIME_ID: usize = 3;
+const CLOCK_MONOTONIC_RAW: usize = 4;
+const CLOCK_REALTIME_COARSE: usize = 5;
+const CLOCK_MONOTONIC_COARSE: usize = 6;
+const CLOCK_BOOTTIME: usize = 7;
+
+const TIMER_ABSTIME: usize = 1;
+
+/// struct tms, 单位是CLK_TCK
+#[repr(C)]
+pub struct Tms {
+    pub tms_utime: usize,
+    pub tms_stime: usize,
+    pub tms_cutime: usize,
+    pub tms_cstime: usize,
+}
+
+/// 各个时钟当前的纳秒数, 不支持的时钟返回None
+fn clock_now_ns(clockid: usize) -> Option<usize> {
+    match clockid {
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => Some(get_realtime_ns()),
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
+            Some(get_time_ns())
+        }
+        CLOCK_PROCESS_CPUTIME_ID => {
+            // 先把这次系统调用里已经用掉的时间记上
+            current_account_system_time();
+            let times = current_process().inner_exclusive_access().cpu_times();
+            Some(ticks_to_ns(times.utime + times.stime))
+        }
+        CLOCK_THREAD_CPUTIME_ID => {
+            current_account_system_time();
+            let times = current_task().unwrap().inner_exclusive_access().times;
+            Some(ticks_to_ns(times.utime + times.stime))
+        }
+        _ => None,
+    }
+}
+
+/// 睡眠`ns`纳秒, 由TIMERS按毫秒唤醒, 不足1ms的部分向上取整
+fn sleep_ns(ns: usize) {
+    if ns == 0 {
+        suspend_current_and_run_next();
+        return;
+    }
+    let expire_ms = (get_time_ns() + ns + 999_999) / 1_000_000;
+    add_timer(expire_ms, current_task().unwrap());
+    block_current_and_run_next();
+}
+
+pub fn sys_clock_gettime(clockid: usize, tp: *mut TimeSpec) -> isize {
+    match clock_now_ns(clockid) {
+        Some(ns) => {
+            *translated_refmut(current_user_token(), tp) = TimeSpec::from_ns(ns);
+            0
+        }
+        None => -1,
+    }
+}
+
+pub fn sys_clock_getres(clockid: usize, res: *mut TimeSpec) -> isize {
+    if clock_now_ns(clockid).is_none() {
+        return -1;
+    }
+    if !res.is_null() {
+        *translated_refmut(current_user_token(), res) = TimeSpec::from_ns(clock_resolution_ns());
+    }
+    0
+}
+
+pub fn sys_nanosleep(req: *const TimeSpec, _rem: *mut TimeSpec) -> isize {
+    let req = *translated_ref(current_user_token(), req);
+    if !req.is_valid() {
+        return -1;
+    }
+    sleep_ns(req.to_ns());
+    0
+}
+
+/// 不能按cpu时间睡眠; TIMER_ABSTIME时换算成相对时间
+pub fn sys_clock_nanosleep(
+    clockid: usize,
+    flags: usize,
+    req: *const TimeSpec,
+    _rem: *mut TimeSpec,
+) -> isize {
+    if clockid == CLOCK_PROCESS_CPUTIME_ID || clockid == CLOCK_THREAD_CPUTIME_ID {
+        return -1;
+    }
+    let now = match clock_now_ns(clockid) {
+        Some(now) => now,
+        None => return -1,
+    };
+    let req = *translated_ref(current_user_token(), req);
+    if !req.is_valid() {
+        return -1;
+    }
+    if flags & TIMER_ABSTIME != 0 {
+        let deadline = req.to_ns();
+        if deadline > now {
+            sleep_ns(deadline - now);
+        }
+    } else {
+        sleep_ns(req.to_ns());
+    }
+    0
+}
+
+/// 不支持时区, tz被忽略
+pub fn sys_gettimeofday(tv: *mut TimeVal, _tz: usize) -> isize {
+    if !tv.is_null() {
+        *translated_refmut(current_user_token(), tv) = TimeVal::from_ns(get_realtime_ns());
+    }
+    0
+}
+
+/// 返回开机以来的时钟滴答数
+pub fn sys_times(buf: *mut Tms) -> isize {
+    current_account_system_time();
+    if !buf.is_null() {
+        let token = current_user_token();
+        let process = current_process();
+        let inner = process.inner_exclusive_access();
+        let times = inner.cpu_times();
+        *translated_refmut(token, buf) = Tms {
+            tms_utime: ticks_to_clock_t(times.utime),
+            tms_stime: ticks_to_clock_t(times.stime),
+            tms_cutime: ticks_to_clock_t(inner.children_times.utime),
+            tms_cstime: ticks_to_clock_t(inner.children_times.stime),
+        };
+    }
+    ticks_to_clock_t(get_time()) as isize
+}
+
+pub fn sys_getitimer(which: usize, curr_value: *mut ITimerVal) -> isize {
+    if which > ITIMER_PROF {
+        return -1;
+    }
+    let token = current_user_token();
+    current_account_system_time();
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let now = inner.cpu_times();
+    let expired = inner.itimers.charge(now);
+    inner.signals |= expired;
+    let value = inner.itimers.get(which);
+    drop(inner);
+    *translated_refmut(token, curr_value) = value;
+    0
+}
+
+/// alarm在libc里用ITIMER_REAL实现, 所以没有单独的系统调用
+pub fn sys_setitimer(
+    which: usize,
+    new_value: *const ITimerVal,
+    old_value: *mut ITimerVal,
+) -> isize {
+    if which > ITIMER_PROF {
+        return -1;
+    }
+    let token = current_user_token();
+    let new_value = *translated_ref(token, new_value);
+    if !new_value.it_value.is_valid() || !new_value.it_interval.is_valid() {
+        return -1;
+    }
+    current_account_system_time();
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    // 先按旧的设置推进到现在, 新的cpu定时器从这里开始算
+    let now = inner.cpu_times();
+    let expired = inner.itimers.charge(now);
+    inner.signals |= expired;
+    let (old, real) = inner.itimers.set(which, &new_value);
+    drop(inner);
+    if let Some((expire_ms, id)) = real {
+        arm_real_timer(process.getpid(), expire_ms, id);
+    }
+    if !old_value.is_null() {
+        *translated_refmut(token, old_value) = old;
+    }
+    0
+}
@@ -0,0 +1,139 @@
+//! 进程的用户和组身份, 以及按文件的mode位检查权限
+use alloc::vec::Vec;
+
+/// 要求的权限, 和mode里每组三位的排列一样, 可以组合
+pub const MAY_READ: u32 = 4;
+pub const MAY_WRITE: u32 = 2;
+pub const MAY_EXEC: u32 = 1;
+
+/// 附加组最多这么多个, 同Linux的NGROUPS_MAX
+pub const NGROUPS_MAX: usize = 65536;
+
+const S_IFMT: u32 = 0o170_000;
+const S_IFDIR: u32 = 0o040_000;
+
+/// 实际/有效/保存的id, uid和gid各一组
+#[derive(Clone, Copy, Default)]
+pub struct IdSet {
+    pub real: u32,
+    pub effective: u32,
+    pub saved: u32,
+}
+
+impl IdSet {
+    fn contains(&self, id: u32) -> bool {
+        id == self.real || id == self.effective || id == self.saved
+    }
+
+    /// setuid/setgid: 特权进程三个都改, 否则只能把有效id改成实际或保存的id
+    pub fn set(&mut self, id: u32, privileged: bool) -> bool {
+        if privileged {
+            *self = Self {
+                real: id,
+                effective: id,
+                saved: id,
+            };
+        } else if id == self.real || id == self.saved {
+            self.effective = id;
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// setreuid/setregid, None表示不改.
+    /// 改了实际id或者有效id改成了实际id以外的值时, 保存的id跟着变成新的有效id
+    pub fn set_re(&mut self, real: Option<u32>, effective: Option<u32>, privileged: bool) -> bool {
+        if !privileged {
+            let real_ok = real.map_or(true, |id| id == self.real || id == self.effective);
+            let effective_ok = effective.map_or(true, |id| self.contains(id));
+            if !real_ok || !effective_ok {
+                return false;
+            }
+        }
+        let old_real = self.real;
+        if let Some(id) = real {
+            self.real = id;
+        }
+        if let Some(id) = effective {
+            self.effective = id;
+        }
+        if real.is_some() || effective.map_or(false, |id| id != old_real) {
+            self.saved = self.effective;
+        }
+        true
+    }
+
+    /// setresuid/setresgid, None表示不改; 非特权进程只能在现有的三个id之间换
+    pub fn set_res(
+        &mut self,
+        real: Option<u32>,
+        effective: Option<u32>,
+        saved: Option<u32>,
+        privileged: bool,
+    ) -> bool {
+        if !privileged
+            && ![real, effective, saved]
+                .iter()
+                .flatten()
+                .all(|&id| self.contains(id))
+        {
+            return false;
+        }
+        if let Some(id) = real {
+            self.real = id;
+        }
+        if let Some(id) = effective {
+            self.effective = id;
+        }
+        if let Some(id) = saved {
+            self.saved = id;
+        }
+        true
+    }
+}
+
+/// fork时继承, exec时不变(FAT上没有set-user-ID位)
+#[derive(Clone, Default)]
+pub struct Credentials {
+    pub uid: IdSet,
+    pub gid: IdSet,
+    /// 附加组
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    /// 有效uid是0的进程可以改身份, 也不受读写权限位的限制
+    pub fn is_privileged(&self) -> bool {
+        self.uid.effective == 0
+    }
+
+    /// kill的权限检查: root, 或者自己的真实/有效uid是对方的真实/保存uid
+    pub fn may_signal(&self, target: &Credentials) -> bool {
+        self.is_privileged()
+            || [self.uid.real, self.uid.effective]
+                .iter()
+                .any(|&uid| uid == target.uid.real || uid == target.uid.saved)
+    }
+
+    /// 有效gid或者附加组里有`gid`
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid.effective == gid || self.groups.contains(&gid)
+    }
+
+    /// 按文件的mode和属主检查是否允许`access`(MAY_*的组合).
+    /// root也要求普通文件至少有一个x位才能执行
+    pub fn permits(&self, mode: u32, uid: u32, gid: u32, access: u32) -> bool {
+        if self.is_privileged() {
+            return access & MAY_EXEC == 0 || mode & S_IFMT == S_IFDIR || mode & 0o111 != 0;
+        }
+        let bits = if self.uid.effective == uid {
+            mode >> 6
+        } else if self.in_group(gid) {
+            mode >> 3
+        } else {
+            mode
+        };
+        bits & access == access
+    }
+}
@@ -1,6 +1,6 @@
 use super::ProcessControlBlock;
 use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT_BASE, USER_STACK_SIZE};
-use crate::mm::{MapPermission, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::mm::{MapPermission, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
 use crate::sync::UPIntrFreeCell;
 use alloc::{
     sync::{Arc, Weak},
@@ -30,6 +30,13 @@ impl RecycleAllocator {
             self.current - 1
         }
     }
+    // 下一次alloc得到id, 比它小的都当作已经回收了
+    pub fn starting_at(id: usize) -> Self {
+        RecycleAllocator {
+            current: id + 1,
+            recycled: (0..=id).collect(),
+        }
+    }
     // 回收一个描述符
     pub fn dealloc(&mut self, id: usize) {
         assert!(id < self.current);
@@ -81,15 +88,19 @@ pub fn kernel_stack_position(kstack_id: usize) -> (usize, usize) {
 // 线程独占的内核栈
 pub struct KernelStack(pub usize);
 
-pub fn kstack_alloc() -> KernelStack {
+/// 物理页不够时返回None
+pub fn kstack_alloc() -> Option<KernelStack> {
     let kstack_id = KSTACK_ALLOCATOR.exclusive_access().alloc();
     let (kstack_bottom, kstack_top) = kernel_stack_position(kstack_id);
-    KERNEL_SPACE.exclusive_access().insert_framed_area(
+    if !KERNEL_SPACE.exclusive_access().insert_framed_area(
         kstack_bottom.into(),
         kstack_top.into(),
         MapPermission::R | MapPermission::W,
-    );
-    KernelStack(kstack_id)
+    ) {
+        KSTACK_ALLOCATOR.exclusive_access().dealloc(kstack_id);
+        return None;
+    }
+    Some(KernelStack(kstack_id))
 }
 
 impl Drop for KernelStack {
@@ -139,12 +150,48 @@ fn ustack_bottom_from_tid(ustack_base: usize, tid: usize) -> usize {
     ustack_base + tid * (PAGE_SIZE + USER_STACK_SIZE)
 }
 
+/// 从地址空间里去掉线程`tid`的用户栈和 Trap 上下文
+/// 映射线程的用户栈和trap上下文, 物理页不够时什么都不映射, 返回false
+pub fn map_user_res(memory_set: &mut MemorySet, ustack_base: usize, tid: usize) -> bool {
+    // alloc user stack
+    let ustack_bottom = ustack_bottom_from_tid(ustack_base, tid);
+    let ustack_top = ustack_bottom + USER_STACK_SIZE;
+    if !memory_set.insert_framed_area(
+        ustack_bottom.into(),
+        ustack_top.into(),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    ) {
+        return false;
+    }
+    // alloc trap_cx
+    let trap_cx_bottom = trap_cx_bottom_from_tid(tid);
+    let trap_cx_top = trap_cx_bottom + PAGE_SIZE;
+    if !memory_set.insert_framed_area(
+        trap_cx_bottom.into(),
+        trap_cx_top.into(),
+        MapPermission::R | MapPermission::W,
+    ) {
+        memory_set.remove_area_with_start_vpn(VirtAddr::from(ustack_bottom).into());
+        return false;
+    }
+    true
+}
+
+pub fn remove_user_res(memory_set: &mut MemorySet, ustack_base: usize, tid: usize) {
+    // dealloc ustack manually
+    let ustack_bottom_va: VirtAddr = ustack_bottom_from_tid(ustack_base, tid).into();
+    memory_set.remove_area_with_start_vpn(ustack_bottom_va.into());
+    // dealloc trap_cx manually
+    let trap_cx_bottom_va: VirtAddr = trap_cx_bottom_from_tid(tid).into();
+    memory_set.remove_area_with_start_vpn(trap_cx_bottom_va.into());
+}
+
 impl TaskUserRes {
     pub fn new(
         process: Arc<ProcessControlBlock>,
         ustack_base: usize,
         alloc_user_res: bool,
-    ) -> Self {
+    ) -> Option<Self> {
         let tid = process.inner_exclusive_access().alloc_tid();
         let task_user_res = Self {
             tid,
@@ -154,32 +201,18 @@ impl TaskUserRes {
         // 如果为假，就不必再分配一次用户栈和 Trap 上下文
         // 即在 fork 子进程并创建子进程的主线程的时候
         // 因为子进程拷贝了父进程的地址空间，这些内容已经被映射过了
-        if alloc_user_res {
-            task_user_res.alloc_user_res();
+        // 物理页不够时返回None, tid随task_user_res一起回收
+        if alloc_user_res && !task_user_res.alloc_user_res() {
+            return None;
         }
-        task_user_res
+        Some(task_user_res)
     }
 
     /// 在进程地址空间中实际映射线程的用户栈和 Trap 上下文。
-    pub fn alloc_user_res(&self) {
+    pub fn alloc_user_res(&self) -> bool {
         let process = self.process.upgrade().unwrap();
         let mut process_inner = process.inner_exclusive_access();
-        // alloc user stack
-        let ustack_bottom = ustack_bottom_from_tid(self.ustack_base, self.tid);
-        let ustack_top = ustack_bottom + USER_STACK_SIZE;
-        process_inner.memory_set.insert_framed_area(
-            ustack_bottom.into(),
-            ustack_top.into(),
-            MapPermission::R | MapPermission::W | MapPermission::U,
-        );
-        // alloc trap_cx
-        let trap_cx_bottom = trap_cx_bottom_from_tid(self.tid);
-        let trap_cx_top = trap_cx_bottom + PAGE_SIZE;
-        process_inner.memory_set.insert_framed_area(
-            trap_cx_bottom.into(),
-            trap_cx_top.into(),
-            MapPermission::R | MapPermission::W,
-        );
+        map_user_res(&mut process_inner.memory_set, self.ustack_base, self.tid)
     }
 
     // 当线程退出之后， TaskUserRes 会随着线程控制块一起被回收
@@ -188,16 +221,7 @@ impl TaskUserRes {
         // dealloc tid
         let process = self.process.upgrade().unwrap();
         let mut process_inner = process.inner_exclusive_access();
-        // dealloc ustack manually
-        let ustack_bottom_va: VirtAddr = ustack_bottom_from_tid(self.ustack_base, self.tid).into();
-        process_inner
-            .memory_set
-            .remove_area_with_start_vpn(ustack_bottom_va.into());
-        // dealloc trap_cx manually
-        let trap_cx_bottom_va: VirtAddr = trap_cx_bottom_from_tid(self.tid).into();
-        process_inner
-            .memory_set
-            .remove_area_with_start_vpn(trap_cx_bottom_va.into());
+        remove_user_res(&mut process_inner.memory_set, self.ustack_base, self.tid);
     }
 
     #[allow(unused)]
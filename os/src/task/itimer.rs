@@ -0,0 +1,153 @@
+//! setitimer的三个间隔定时器
+//! ITIMER_REAL挂在timer.rs的TIMERS上, VIRTUAL和PROF在时钟中断里按进程的cpu时间推进
+use super::{pid2process, CpuTimes, SignalFlags};
+use crate::timer::{add_timer_callback, get_time_ms, ns_to_ticks, ticks_to_ns, TimeSpec, TimeVal};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub const ITIMER_REAL: usize = 0;
+pub const ITIMER_VIRTUAL: usize = 1;
+pub const ITIMER_PROF: usize = 2;
+
+/// struct itimerval
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct ITimerVal {
+    pub it_interval: TimeVal,
+    pub it_value: TimeVal,
+}
+
+/// 换掉ITIMER_REAL后, TIMERS里旧的回调靠id认出自己已经失效
+static NEXT_REAL_TIMER_ID: AtomicUsize = AtomicUsize::new(1);
+
+#[derive(Default)]
+pub struct IntervalTimers {
+    /// ITIMER_REAL到期的时刻(开机以来的毫秒), 0表示没有启动
+    real_expire_ms: usize,
+    real_interval_ms: usize,
+    real_id: usize,
+    /// VIRTUAL和PROF还剩多少cpu时间到期, 单位是time寄存器的计数, 0表示没有启动
+    cpu_value: [usize; 2],
+    cpu_interval: [usize; 2],
+    /// 上次推进时进程的cpu时间
+    cpu_mark: CpuTimes,
+}
+
+fn to_ms(tv: &TimeVal) -> usize {
+    TimeSpec::from_ns(tv.to_ns()).to_ms()
+}
+
+impl IntervalTimers {
+    pub fn cpu_timer_armed(&self) -> bool {
+        self.cpu_value.iter().any(|value| *value != 0)
+    }
+
+    pub fn get(&self, which: usize) -> ITimerVal {
+        if which == ITIMER_REAL {
+            let remaining = if self.real_expire_ms == 0 {
+                0
+            } else {
+                self.real_expire_ms.saturating_sub(get_time_ms()).max(1)
+            };
+            ITimerVal {
+                it_interval: TimeVal::from_ns(self.real_interval_ms * 1_000_000),
+                it_value: TimeVal::from_ns(remaining * 1_000_000),
+            }
+        } else {
+            let i = which - ITIMER_VIRTUAL;
+            ITimerVal {
+                it_interval: TimeVal::from_ns(ticks_to_ns(self.cpu_interval[i])),
+                it_value: TimeVal::from_ns(ticks_to_ns(self.cpu_value[i])),
+            }
+        }
+    }
+
+    /// 返回原来的设置, ITIMER_REAL还返回(到期时刻, id), 由调用者放下borrow后arm_real_timer
+    pub fn set(
+        &mut self,
+        which: usize,
+        new_value: &ITimerVal,
+    ) -> (ITimerVal, Option<(usize, usize)>) {
+        let old_value = self.get(which);
+        if which == ITIMER_REAL {
+            self.real_id = NEXT_REAL_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+            self.real_interval_ms = to_ms(&new_value.it_interval);
+            if new_value.it_value.is_zero() {
+                self.real_expire_ms = 0;
+                return (old_value, None);
+            }
+            self.real_expire_ms = get_time_ms() + to_ms(&new_value.it_value);
+            (old_value, Some((self.real_expire_ms, self.real_id)))
+        } else {
+            let i = which - ITIMER_VIRTUAL;
+            self.cpu_interval[i] = ns_to_ticks(new_value.it_interval.to_ns());
+            self.cpu_value[i] = ns_to_ticks(new_value.it_value.to_ns());
+            (old_value, None)
+        }
+    }
+
+    /// 按进程当前的cpu时间推进VIRTUAL和PROF, 返回到期要发出的信号
+    pub fn charge(&mut self, now: CpuTimes) -> SignalFlags {
+        let mark = core::mem::replace(&mut self.cpu_mark, now);
+        let user = now.utime.saturating_sub(mark.utime);
+        let system = now.stime.saturating_sub(mark.stime);
+        let used = [user, user + system];
+        let signals = [SignalFlags::SIGVTALRM, SignalFlags::SIGPROF];
+        let mut expired = SignalFlags::empty();
+        for i in 0..2 {
+            let value = self.cpu_value[i];
+            if value == 0 {
+                continue;
+            }
+            if value > used[i] {
+                self.cpu_value[i] -= used[i];
+                continue;
+            }
+            expired |= signals[i];
+            let interval = self.cpu_interval[i];
+            self.cpu_value[i] = if interval == 0 {
+                0
+            } else {
+                // 一次tick内多次到期只算一次, 信号本来也不排队
+                interval - (used[i] - value) % interval
+            };
+        }
+        expired
+    }
+
+    /// ITIMER_REAL的回调, 周期定时器顺便算好下次到期的时刻
+    fn expire_real(&mut self, id: usize) -> bool {
+        if self.real_id != id || self.real_expire_ms == 0 {
+            return false;
+        }
+        if self.real_interval_ms == 0 {
+            self.real_expire_ms = 0;
+        } else {
+            let late = get_time_ms().saturating_sub(self.real_expire_ms);
+            self.real_expire_ms += (late / self.real_interval_ms + 1) * self.real_interval_ms;
+        }
+        true
+    }
+}
+
+/// 在TIMERS里挂上pid进程的ITIMER_REAL, 只记pid, 进程退出后回调什么也不做
+pub fn arm_real_timer(pid: usize, expire_ms: usize, id: usize) {
+    add_timer_callback(expire_ms, move || {
+        let process = match pid2process(pid) {
+            Some(process) => process,
+            None => return,
+        };
+        let mut inner = process.inner_exclusive_access();
+        if inner.is_zombie {
+            return;
+        }
+        if !inner.itimers.expire_real(id) {
+            return;
+        }
+        inner.signals |= SignalFlags::SIGALRM;
+        let next_ms = inner.itimers.real_expire_ms;
+        drop(inner);
+        if next_ms != 0 {
+            arm_real_timer(pid, next_ms, id);
+        }
+    });
+}
@@ -0,0 +1,69 @@
+//! 进程组, 会话和作业控制
+//! 停止信号不会结束进程, 而是让它的所有线程在回用户态之前让出cpu, 直到收到SIGCONT
+use super::manager::{find_processes, wakeup_task};
+use super::{current_process, suspend_current_and_run_next, ProcessControlBlock, SignalFlags};
+use alloc::vec::Vec;
+
+/// wait4里被SIGCONT继续的子进程的status
+pub const WAIT_CONTINUED: i32 = 0xffff;
+
+/// wait4里被信号停下的子进程的status
+fn wait_stopped(signal: SignalFlags) -> i32 {
+    ((signal.signum() as i32) << 8) | 0x7f
+}
+
+/// 给进程发信号; SIGCONT让停下的进程继续, SIGKILL也要先让它醒过来才能退出:
+/// 停下的线程自己会看到, 阻塞在等待队列里的线程要直接叫醒
+pub fn send_signal(process: &ProcessControlBlock, signal: SignalFlags) {
+    let mut inner = process.inner_exclusive_access();
+    if inner.is_zombie {
+        return;
+    }
+    if signal.contains(SignalFlags::SIGCONT) {
+        inner.signals -= SignalFlags::stop_signals();
+        if inner.stopped {
+            inner.stopped = false;
+            inner.job_status = Some(WAIT_CONTINUED);
+        }
+    }
+    if signal.contains(SignalFlags::SIGKILL) {
+        inner.stopped = false;
+    }
+    if signal.intersects(SignalFlags::stop_signals()) {
+        inner.signals -= SignalFlags::SIGCONT;
+    }
+    inner.signals |= signal;
+    if signal.contains(SignalFlags::SIGKILL) {
+        let tasks: Vec<_> = inner.tasks.iter().flatten().cloned().collect();
+        drop(inner);
+        for task in tasks {
+            wakeup_task(task);
+        }
+    }
+}
+
+/// 给进程组里的所有进程发信号, 组里没有进程时返回false
+pub fn send_signal_to_group(pgid: usize, signal: SignalFlags) -> bool {
+    let group = find_processes(|process| process.inner_exclusive_access().pgid == pgid);
+    for process in group.iter() {
+        send_signal(process, signal);
+    }
+    !group.is_empty()
+}
+
+/// 回用户态之前调用: 有未屏蔽的停止信号就停下, 停下的线程一直让出cpu
+pub fn handle_stop_signals_of_current() {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let stop = (inner.signals - inner.signal_mask) & SignalFlags::stop_signals();
+    if !stop.is_empty() {
+        inner.signals -= SignalFlags::stop_signals();
+        inner.stopped = true;
+        inner.job_status = Some(wait_stopped(stop));
+    }
+    while inner.stopped {
+        drop(inner);
+        suspend_current_and_run_next();
+        inner = process.inner_exclusive_access();
+    }
+}
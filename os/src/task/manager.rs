@@ -2,6 +2,7 @@ use super::{ProcessControlBlock, TaskControlBlock, TaskStatus};
 use crate::sync::UPIntrFreeCell;
 use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
 
 pub struct TaskManager {
@@ -19,7 +20,13 @@ impl TaskManager {
         self.ready_queue.push_back(task);
     }
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue.pop_front()
+        // 被exec或exit_group结束的线程可能还在队列里, 它们没有res了, 直接丢掉
+        while let Some(task) = self.ready_queue.pop_front() {
+            if task.inner_exclusive_access().res.is_some() {
+                return Some(task);
+            }
+        }
+        None
     }
 }
 
@@ -34,8 +41,12 @@ pub fn add_task(task: Arc<TaskControlBlock>) {
     TASK_MANAGER.exclusive_access().add(task);
 }
 
+/// 被SIGKILL提前叫醒的任务还留在原来的等待队列里, 之后再被唤醒时忽略
 pub fn wakeup_task(task: Arc<TaskControlBlock>) {
     let mut task_inner = task.inner_exclusive_access();
+    if task_inner.task_status != TaskStatus::Blocked {
+        return;
+    }
     task_inner.task_status = TaskStatus::Ready;
     drop(task_inner);
     add_task(task);
@@ -50,6 +61,18 @@ pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
     map.get(&pid).map(Arc::clone)
 }
 
+/// 满足条件的所有进程, 用来按进程组/会话查找
+pub fn find_processes<F>(filter: F) -> Vec<Arc<ProcessControlBlock>>
+where
+    F: Fn(&ProcessControlBlock) -> bool,
+{
+    let map = PID2PCB.exclusive_access();
+    map.values()
+        .filter(|process| filter(process))
+        .cloned()
+        .collect()
+}
+
 pub fn insert_into_pid2process(pid: usize, process: Arc<ProcessControlBlock>) {
     PID2PCB.exclusive_access().insert(pid, process);
 }
@@ -1,14 +1,19 @@
 mod context;
+mod cred;
 mod id;
+mod itimer;
+mod job;
 mod manager;
 mod process;
 mod processor;
+mod rlimit;
 mod signal;
 mod switch;
 #[allow(clippy::module_inception)]
 mod task;
 
 use self::id::TaskUserRes;
+use crate::config::CLOCK_FREQ;
 use crate::fs::{open_file, OpenFlags, FileType};
 use crate::sbi::shutdown;
 use alloc::{sync::Arc, vec::Vec};
@@ -18,15 +23,22 @@ use process::ProcessControlBlock;
 use switch::__switch;
 
 pub use context::TaskContext;
+pub use cred::{Credentials, MAY_EXEC, MAY_READ, MAY_WRITE, NGROUPS_MAX};
 pub use id::{kstack_alloc, pid_alloc, KernelStack, PidHandle, IDLE_PID};
-pub use manager::{add_task, pid2process, remove_from_pid2process, wakeup_task};
+pub use itimer::{arm_real_timer, ITimerVal, IntervalTimers, ITIMER_PROF};
+pub use job::{handle_stop_signals_of_current, send_signal, send_signal_to_group, WAIT_CONTINUED};
+pub use manager::{add_task, find_processes, pid2process, remove_from_pid2process, wakeup_task};
 pub use processor::{
     current_kstack_top, current_process, current_task, current_trap_cx, current_trap_cx_user_va,
     current_user_token, run_tasks, schedule, take_current_task,
 };
 pub use process::*;
+pub use rlimit::{
+    RLimit, ResourceLimits, RLIMIT_AS, RLIMIT_CORE, RLIMIT_CPU, RLIMIT_DATA, RLIMIT_FSIZE,
+    RLIMIT_NOFILE, RLIMIT_NPROC, RLIMIT_STACK, RLIM_INFINITY,
+};
 pub use signal::SignalFlags;
-pub use task::{TaskControlBlock, TaskStatus};
+pub use task::{CpuTimes, TaskControlBlock, TaskStatus};
 
 pub fn suspend_current_and_run_next() {
     // There must be an application running.
@@ -35,6 +47,7 @@ pub fn suspend_current_and_run_next() {
     // ---- access current TCB exclusively
     let mut task_inner = task.inner_exclusive_access();
     let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
+    task_inner.account_system_time();
     // Change status to Ready
     task_inner.task_status = TaskStatus::Ready;
     drop(task_inner);
@@ -50,6 +63,7 @@ pub fn suspend_current_and_run_next() {
 pub fn block_current_task() -> *mut TaskContext {
     let task = take_current_task().unwrap();
     let mut task_inner = task.inner_exclusive_access();
+    task_inner.account_system_time();
     task_inner.task_status = TaskStatus::Blocked;
     &mut task_inner.task_cx as *mut TaskContext
 }
@@ -60,74 +74,29 @@ pub fn block_current_and_run_next() {
 }
 
 /// Exit the current 'Running' task and run the next task in task list.
+/// 只结束当前线程, 进程的最后一个线程退出时整个进程才退出
 pub fn exit_current_and_run_next(exit_code: i32) {
     let task = take_current_task().unwrap();
     let mut task_inner = task.inner_exclusive_access();
     let process = task.process.upgrade().unwrap();
-    let tid = task_inner.res.as_ref().unwrap().tid;
+    task_inner.account_system_time();
     // record exit code
     task_inner.exit_code = Some(exit_code);
-    task_inner.res = None;
+    let res = task_inner.res.take();
     // here we do not remove the thread since we are still using the kstack
     // it will be deallocated when sys_waittid is called
     drop(task_inner);
     drop(task);
-    // however, if this is the main thread of current process
-    // the process should terminate at once
-    if tid == 0 {
-        let pid = process.getpid();
-        if pid == IDLE_PID {
-            println!(
-                "[kernel] Idle process exit with exit_code {} ...",
-                exit_code
-            );
-            if exit_code != 0 {
-                //crate::sbi::shutdown(255); //255 == -1 for err hint
-                shutdown(true);
-            } else {
-                //crate::sbi::shutdown(0); //0 for success hint
-                shutdown(false);
-            }
-        }
-        remove_from_pid2process(pid);
-        let mut process_inner = process.inner_exclusive_access();
-        // mark this process as a zombie process
-        process_inner.is_zombie = true;
-        // record exit code of main process
-        process_inner.exit_code = exit_code;
-
-        {
-            // move all child processes under init process
-            let mut initproc_inner = INITPROC.inner_exclusive_access();
-            for child in process_inner.children.iter() {
-                child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
-                initproc_inner.children.push(child.clone());
-            }
-        }
-
-        // deallocate user res (including tid/trap_cx/ustack) of all threads
-        // it has to be done before we dealloc the whole memory_set
-        // otherwise they will be deallocated twice
-        let mut recycle_res = Vec::<TaskUserRes>::new();
-        for task in process_inner.tasks.iter().filter(|t| t.is_some()) {
-            let task = task.as_ref().unwrap();
-            let mut task_inner = task.inner_exclusive_access();
-            if let Some(res) = task_inner.res.take() {
-                recycle_res.push(res);
-            }
-        }
-        // dealloc_tid and dealloc_user_res require access to PCB inner, so we
-        // need to collect those user res first, then release process_inner
-        // for now to avoid deadlock/double borrow problem.
-        drop(process_inner);
-        recycle_res.clear();
-
-        let mut process_inner = process.inner_exclusive_access();
-        process_inner.children.clear();
-        // deallocate other data in user space i.e. program code/data section
-        process_inner.memory_set.recycle_data_pages();
-        // drop file descriptors
-        process_inner.fd_table.clear();
+    // dealloc_tid and dealloc_user_res require access to PCB inner
+    drop(res);
+    let last_thread = process
+        .inner_exclusive_access()
+        .tasks
+        .iter()
+        .flatten()
+        .all(|task| task.inner_exclusive_access().res.is_none());
+    if last_thread {
+        exit_process(&process, exit_code);
     }
     drop(process);
     // we do not have to save task context
@@ -135,11 +104,85 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     schedule(&mut _unused as *mut _);
 }
 
+/// exit_group: 结束当前进程的所有线程
+pub fn exit_group_and_run_next(exit_code: i32) {
+    let task = take_current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    let process = task.process.upgrade().unwrap();
+    task_inner.account_system_time();
+    task_inner.exit_code = Some(exit_code);
+    drop(task_inner);
+    drop(task);
+    exit_process(&process, exit_code);
+    drop(process);
+    // we do not have to save task context
+    let mut _unused = TaskContext::zero_init();
+    schedule(&mut _unused as *mut _);
+}
+
+/// 进程变成僵尸, 回收所有线程的用户资源和地址空间.
+/// 其余还没退出的线程res被拿走了, 调度器不会再运行它们
+fn exit_process(process: &Arc<ProcessControlBlock>, exit_code: i32) {
+    let pid = process.getpid();
+    if pid == IDLE_PID {
+        println!(
+            "[kernel] Idle process exit with exit_code {} ...",
+            exit_code
+        );
+        if exit_code != 0 {
+            //crate::sbi::shutdown(255); //255 == -1 for err hint
+            shutdown(true);
+        } else {
+            //crate::sbi::shutdown(0); //0 for success hint
+            shutdown(false);
+        }
+    }
+    remove_from_pid2process(pid);
+    let mut process_inner = process.inner_exclusive_access();
+    // mark this process as a zombie process
+    process_inner.is_zombie = true;
+    // record exit code of main process
+    process_inner.exit_code = exit_code;
+
+    {
+        // move all child processes under init process
+        let mut initproc_inner = INITPROC.inner_exclusive_access();
+        for child in process_inner.children.iter() {
+            child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
+            initproc_inner.children.push(child.clone());
+        }
+    }
+
+    // deallocate user res (including tid/trap_cx/ustack) of all threads
+    // it has to be done before we dealloc the whole memory_set
+    // otherwise they will be deallocated twice
+    let mut recycle_res = Vec::<TaskUserRes>::new();
+    for task in process_inner.tasks.iter().flatten() {
+        let mut task_inner = task.inner_exclusive_access();
+        if let Some(res) = task_inner.res.take() {
+            recycle_res.push(res);
+            // 被exit_group结束的线程
+            task_inner.exit_code = Some(exit_code);
+        }
+    }
+    // dealloc_tid and dealloc_user_res require access to PCB inner, so we
+    // need to collect those user res first, then release process_inner
+    // for now to avoid deadlock/double borrow problem.
+    drop(process_inner);
+    recycle_res.clear();
+
+    let mut process_inner = process.inner_exclusive_access();
+    process_inner.children.clear();
+    // deallocate other data in user space i.e. program code/data section
+    process_inner.memory_set.recycle_data_pages();
+    // drop file descriptors
+    process_inner.fd_table.clear();
+}
+
 lazy_static! {
     pub static ref INITPROC: Arc<ProcessControlBlock> = {
         let inode = open_file("/", "initproc", OpenFlags::O_RDONLY, FileType::Regular).unwrap();
-        let v = inode.read_all();
-        ProcessControlBlock::new(v.as_slice())
+        ProcessControlBlock::new(&inode)
     };
 }
 
@@ -150,7 +193,40 @@ pub fn add_initproc() {
 pub fn check_signals_of_current() -> Option<(i32, &'static str)> {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
-    process_inner.signals.check_error()
+    (process_inner.signals - process_inner.signal_mask).check_error()
+}
+
+/// 陷入内核时记上用户态时间
+pub fn current_account_user_time() {
+    current_task().unwrap().inner_exclusive_access().account_user_time();
+}
+
+/// 回到用户态前记上内核态时间
+pub fn current_account_system_time() {
+    current_task().unwrap().inner_exclusive_access().account_system_time();
+}
+
+/// 时钟中断时按cpu时间推进ITIMER_VIRTUAL和ITIMER_PROF, 并检查RLIMIT_CPU:
+/// 超过软限制发SIGXCPU, 超过硬限制发SIGKILL
+pub fn current_charge_itimers() {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let cpu_limit = process_inner.rlimits.get(RLIMIT_CPU).unwrap();
+    let armed = process_inner.itimers.cpu_timer_armed();
+    if !armed && cpu_limit.rlim_cur == RLIM_INFINITY {
+        return;
+    }
+    let now = process_inner.cpu_times();
+    if armed {
+        let expired = process_inner.itimers.charge(now);
+        process_inner.signals |= expired;
+    }
+    let seconds = ((now.utime + now.stime) / CLOCK_FREQ) as u64;
+    if seconds >= cpu_limit.rlim_max {
+        process_inner.signals |= SignalFlags::SIGKILL;
+    } else if seconds >= cpu_limit.rlim_cur {
+        process_inner.signals |= SignalFlags::SIGXCPU;
+    }
 }
 
 pub fn current_add_signal(signal: SignalFlags) {
@@ -159,6 +235,32 @@ pub fn current_add_signal(signal: SignalFlags) {
     process_inner.signals |= signal;
 }
 
+/// 分配物理页失败过时, 在回用户态之前调用: 用SIGKILL杀掉驻留页最多的进程腾出内存.
+/// initproc和已经在等SIGKILL生效的进程不选
+pub fn oom_kill() {
+    let victim = find_processes(|process| {
+        process.getpid() != IDLE_PID
+            && !process
+                .inner_exclusive_access()
+                .signals
+                .contains(SignalFlags::SIGKILL)
+    })
+    .into_iter()
+    .map(|process| {
+        let rss = process.inner_exclusive_access().memory_set.resident_pages();
+        (rss, process)
+    })
+    .max_by_key(|(rss, _)| *rss);
+    if let Some((rss, process)) = victim {
+        println!(
+            "[kernel] Out of memory: killed process {} ({} pages resident)",
+            process.getpid(),
+            rss
+        );
+        send_signal(&process, SignalFlags::SIGKILL);
+    }
+}
+
 pub struct Utsname {
     pub sysname: [u8; 65],
     pub nodename: [u8; 65],
@@ -1,16 +1,19 @@
 use core::fmt::{Display, Formatter};
 
-use super::id::RecycleAllocator;
+use super::id::{map_user_res, remove_user_res, RecycleAllocator, TaskUserRes};
 use super::manager::insert_into_pid2process;
-use super::{add_task, SignalFlags};
-use super::{current_process, TaskControlBlock};
+use super::{add_task, CpuTimes, Credentials, IntervalTimers, SignalFlags};
+use super::{current_process, current_task, TaskControlBlock};
 use super::{pid_alloc, PidHandle};
-use crate::config::MEMORY_MAP_BASE;
-use crate::fs::{FileDescriptor, Stdin, Stdout};
+use super::{ResourceLimits, RLIMIT_AS, RLIMIT_NOFILE, RLIMIT_STACK};
+use crate::config::{MEMORY_MAP_BASE, PAGE_SIZE};
+use crate::fs::{FileDescriptor, OSInode, Stdin, Stdout};
 use crate::mm::{
-    translated_refmut, MapPermission, MemoryMapArea, MemorySet, VirtAddr, VirtPageNum, KERNEL_SPACE,
+    translated_refmut, FrameTracker, MapPermission, MemoryMapArea, MemorySet, VirtAddr,
+    VirtPageNum, AT_NULL, AT_RANDOM, KERNEL_SPACE,
 };
 use crate::sync::{Condvar, Mutex, Semaphore, UPIntrFreeCell, UPIntrRefMut};
+use crate::timer::get_time_ns;
 use crate::trap::{trap_handler, TrapContext};
 use alloc::string::{String, ToString};
 use alloc::sync::{Arc, Weak};
@@ -33,7 +36,26 @@ pub struct ProcessControlBlockInner {
     pub fd_table: Vec<Option<FileDescriptor>>,
     /// 文件描述符表
     pub signals: SignalFlags,
+    /// sigprocmask屏蔽的信号, 留在signals里等signalfd来读
+    pub signal_mask: SignalFlags,
+    /// 进程组和会话, 都用组长/首进程的pid表示
+    pub pgid: usize,
+    pub sid: usize,
+    /// 被SIGSTOP/SIGTSTP等停下, 所有线程在trap_handler里等SIGCONT
+    pub stopped: bool,
+    /// 停止/继续后还没被父进程wait到的状态, 格式同wait4的status
+    pub job_status: Option<i32>,
     pub tasks: Vec<Option<Arc<TaskControlBlock>>>,
+    /// 已经被waittid回收的线程的运行时间
+    pub exited_times: CpuTimes,
+    /// 已经被wait回收的子进程(连同它们的子进程)的运行时间
+    pub children_times: CpuTimes,
+    /// setitimer/alarm的定时器, fork时不继承
+    pub itimers: IntervalTimers,
+    /// 用户和组身份, fork时继承
+    pub cred: Credentials,
+    /// getrlimit/setrlimit的资源限制, fork时继承
+    pub rlimits: ResourceLimits,
     pub task_res_allocator: RecycleAllocator,
     pub mutex_list: Vec<Option<Arc<dyn Mutex>>>,
     pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
@@ -55,14 +77,20 @@ impl ProcessControlBlockInner {
         self.memory_set.token()
     }
     /// ### 查找空闲文件描述符下标
-    /// 从文件描述符表中 **由低到高** 查找空位，返回向量下标，没有空位则在最后插入一个空位
-    pub fn alloc_fd(&mut self) -> usize {
-        if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
-            fd
-        } else {
+    /// 从文件描述符表中 **由低到高** 查找空位，返回向量下标，没有空位则在最后插入一个空位.
+    /// 下标不能达到RLIMIT_NOFILE的软限制
+    pub fn alloc_fd(&mut self) -> Option<usize> {
+        let limit = self.rlimits.cur(RLIMIT_NOFILE);
+        let fd = (0..self.fd_table.len())
+            .find(|fd| self.fd_table[*fd].is_none())
+            .unwrap_or(self.fd_table.len());
+        if fd >= limit {
+            return None;
+        }
+        if fd == self.fd_table.len() {
             self.fd_table.push(None);
-            self.fd_table.len() - 1
         }
+        Some(fd)
     }
 
     // alloc a specific new_fd
@@ -82,6 +110,30 @@ impl ProcessControlBlockInner {
         self.task_res_allocator.dealloc(tid)
     }
 
+    /// 所有线程的运行时间之和
+    pub fn cpu_times(&self) -> CpuTimes {
+        let mut times = self.exited_times;
+        for task in self.tasks.iter().flatten() {
+            times += task.inner_exclusive_access().times;
+        }
+        times
+    }
+
+    /// 子进程被回收时记到父进程的children_times里
+    pub fn total_times(&self) -> CpuTimes {
+        self.cpu_times() + self.children_times
+    }
+
+    /// 地址空间大小, 按RLIMIT_AS检查: 已映射的区域加上brk得到的堆
+    pub fn vm_size(&self) -> usize {
+        self.memory_set.mapped_size() + (self.heap_end.0 - self.heap_base.0)
+    }
+
+    /// 再多映射`len`字节会不会超过RLIMIT_AS
+    pub fn may_grow_vm(&self, len: usize) -> bool {
+        self.vm_size().saturating_add(len) <= self.rlimits.cur(RLIMIT_AS)
+    }
+
     pub fn thread_count(&self) -> usize {
         self.tasks.len()
     }
@@ -102,7 +154,7 @@ impl ProcessControlBlockInner {
         let start_va = start.into();
         let end_va = (start + len).into();
         // 测例prot定义与MapPermission正好差一位
-        let map_perm = MapPermission::from_bits((prot << 1) as u8).unwrap() | MapPermission::U;
+        let map_perm = MapPermission::from_bits_truncate(((prot & 0x7) << 1) as u8) | MapPermission::U;
 
         self.memory_set.insert_mmap_area(MemoryMapArea::new(
             start_va, end_va, map_perm, fd, offset, flags,
@@ -110,10 +162,95 @@ impl ProcessControlBlockInner {
         self.mmap_area_end = end_va;
     }
 
+    /// 映射共享物理页, 用于shmat和/dev/shm文件的MAP_SHARED映射, 返回起始地址
+    pub fn mmap_shared(
+        &mut self,
+        start: Option<usize>,
+        frames: Vec<Arc<FrameTracker>>,
+        map_perm: MapPermission,
+    ) -> Option<usize> {
+        let len = frames.len() * PAGE_SIZE;
+        let at_area_end = start.is_none();
+        let start = match start {
+            Some(start) => {
+                let start_vpn = VirtAddr::from(start).floor();
+                let end_vpn = VirtAddr::from(start + len).ceil();
+                if self.memory_set.is_range_mapped(start_vpn, end_vpn) {
+                    return None;
+                }
+                start
+            }
+            None => self.mmap_area_end.0,
+        };
+        if !self
+            .memory_set
+            .insert_shared_area(start.into(), frames, map_perm | MapPermission::U)
+        {
+            return None;
+        }
+        if at_area_end {
+            self.mmap_area_end = (start + len).into();
+        }
+        Some(start)
+    }
+
     pub fn munmap(&mut self, start: usize, len: usize) -> bool {
         let start_vpn = VirtPageNum::from(VirtAddr::from(start));
         self.memory_set.remove_mmap_area(start_vpn)
+            || self.memory_set.remove_shared_area(start_vpn)
+    }
+}
+
+/// 按Linux的约定构造初始用户栈, 从sp往上依次是argc, argv[], NULL, envp[], NULL,
+/// auxv, AT_NULL, 再往上是字符串和AT_RANDOM的16个字节. 返回新的sp和argv的地址
+fn init_user_stack(
+    token: usize,
+    mut user_sp: usize,
+    args: &[String],
+    envs: &[String],
+    mut auxv: Vec<(usize, usize)>,
+) -> (usize, usize) {
+    let push_str = |sp: &mut usize, s: &str| {
+        *sp -= s.len() + 1;
+        let mut p = *sp;
+        for c in s.as_bytes() {
+            *translated_refmut(token, p as *mut u8) = *c;
+            p += 1;
+        }
+        *translated_refmut(token, p as *mut u8) = 0;
+        *sp
+    };
+    let argv: Vec<usize> = args.iter().map(|arg| push_str(&mut user_sp, arg)).collect();
+    let envp: Vec<usize> = envs.iter().map(|env| push_str(&mut user_sp, env)).collect();
+    // AT_RANDOM指向的16个随机字节, libc用来初始化stack canary
+    user_sp -= 16;
+    let mut seed = get_time_ns() as u64 ^ user_sp as u64;
+    for i in 0..16 {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        *translated_refmut(token, (user_sp + i) as *mut u8) = (seed >> 56) as u8;
+    }
+    auxv.push((AT_RANDOM, user_sp));
+    auxv.push((AT_NULL, 0));
+    // sp按16字节对齐
+    let words = 1 + argv.len() + 1 + envp.len() + 1 + auxv.len() * 2;
+    user_sp = (user_sp - words * core::mem::size_of::<usize>()) & !0xf;
+    let stack: Vec<usize> = [argv.len()]
+        .into_iter()
+        .chain(argv.into_iter())
+        .chain([0])
+        .chain(envp.into_iter())
+        .chain([0])
+        .chain(auxv.into_iter().flat_map(|(key, value)| [key, value]))
+        .collect();
+    for (i, word) in stack.into_iter().enumerate() {
+        *translated_refmut(
+            token,
+            (user_sp + i * core::mem::size_of::<usize>()) as *mut usize,
+        ) = word;
     }
+    (user_sp, user_sp + core::mem::size_of::<usize>())
 }
 
 impl ProcessControlBlock {
@@ -122,9 +259,10 @@ impl ProcessControlBlock {
     }
 
     //只有init proc调用,其他的线程从fork产生
-    pub fn new(elf_data: &[u8]) -> Arc<Self> {
+    pub fn new(elf_file: &OSInode) -> Arc<Self> {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, uheap_base, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, elf_info) = MemorySet::from_elf(elf_file).unwrap();
+        let token = memory_set.token();
         // allocate a pid
         let pid_handle = pid_alloc();
         let process = Arc::new(Self {
@@ -145,35 +283,46 @@ impl ProcessControlBlock {
                         Some(FileDescriptor::Abstract(Arc::new(Stdout))),
                     ],
                     signals: SignalFlags::empty(),
+                    signal_mask: SignalFlags::empty(),
+                    // initproc的pid是0, 自成一个进程组和会话
+                    pgid: 0,
+                    sid: 0,
+                    stopped: false,
+                    job_status: None,
                     tasks: Vec::new(),
+                    exited_times: CpuTimes::default(),
+                    children_times: CpuTimes::default(),
+                    itimers: IntervalTimers::default(),
+                    // initproc以root身份运行
+                    cred: Credentials::default(),
+                    rlimits: ResourceLimits::default(),
                     task_res_allocator: RecycleAllocator::new(),
                     mutex_list: Vec::new(),
                     semaphore_list: Vec::new(),
                     condvar_list: Vec::new(),
                     // 初始进程的工作目录当然是/了
                     work_path: WorkPath::new(),
-                    heap_base: uheap_base.into(),
-                    heap_end: uheap_base.into(),
+                    heap_base: elf_info.heap_base.into(),
+                    heap_end: elf_info.heap_base.into(),
                     mmap_area_base: MEMORY_MAP_BASE.into(),
                     mmap_area_end: MEMORY_MAP_BASE.into(),
                 })
             },
         });
         // create a main thread, we should allocate ustack and trap_cx here
-        let task = Arc::new(TaskControlBlock::new(
-            Arc::clone(&process),
-            ustack_base,
-            true,
-        ));
+        let task = Arc::new(
+            TaskControlBlock::new(Arc::clone(&process), elf_info.ustack_base, true).unwrap(),
+        );
         // prepare trap_cx of main thread
         let task_inner = task.inner_exclusive_access();
         let trap_cx = task_inner.get_trap_cx();
         let ustack_top = task_inner.res.as_ref().unwrap().ustack_top();
         let kstack_top = task.kstack.get_top();
         drop(task_inner);
+        let (user_sp, _) = init_user_stack(token, ustack_top, &[], &[], elf_info.auxv());
         *trap_cx = TrapContext::app_init_context(
-            entry_point,
-            ustack_top,
+            elf_info.entry,
+            user_sp,
             KERNEL_SPACE.exclusive_access().token(),
             kstack_top,
             trap_handler as usize,
@@ -188,55 +337,75 @@ impl ProcessControlBlock {
         process
     }
 
-    /// Only support processes with a single thread.
-    pub fn exec(self: &Arc<Self>, elf_data: &[u8], args: Vec<String>) {
-        assert_eq!(self.inner_exclusive_access().thread_count(), 1);
+    /// 其余线程都被结束掉. 返回argc, elf或者它的解释器不对时返回-1, 原来的进程保持不变
+    pub fn exec(
+        self: &Arc<Self>,
+        elf_file: &OSInode,
+        args: Vec<String>,
+        envs: Vec<String>,
+    ) -> isize {
+        // 同Linux, 参数和环境变量最多占栈大小限制的1/4
+        let strings: usize = args.iter().chain(envs.iter()).map(|s| s.len() + 1).sum();
+        let pointers = (args.len() + envs.len() + 2) * core::mem::size_of::<usize>();
+        let stack_limit = self.inner_exclusive_access().rlimits.cur(RLIMIT_STACK);
+        if strings + pointers > stack_limit / 4 {
+            return -1;
+        }
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, uheap_base, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (mut memory_set, elf_info) = match MemorySet::from_elf(elf_file) {
+            Some(loaded) => loaded,
+            None => return -1,
+        };
+        // 主线程(tid 0)的用户栈和trap上下文也先映射好, 物理页不够时原来的进程还能继续跑
+        if !map_user_res(&mut memory_set, elf_info.ustack_base, 0) {
+            return -1;
+        }
         let new_token = memory_set.token();
+        // 其余线程全部结束, 它们和当前线程的用户栈/trap上下文在旧的地址空间里回收
+        let task = current_task().unwrap();
+        let tasks: Vec<_> = self
+            .inner_exclusive_access()
+            .tasks
+            .drain(..)
+            .flatten()
+            .collect();
+        let mut recycle_res = Vec::new();
+        let mut exited_times = CpuTimes::default();
+        for other in tasks.iter().filter(|other| !Arc::ptr_eq(other, &task)) {
+            let mut other_inner = other.inner_exclusive_access();
+            other_inner.exit_code = Some(0);
+            exited_times += other_inner.times;
+            recycle_res.extend(other_inner.res.take());
+        }
+        recycle_res.extend(task.inner_exclusive_access().res.take());
+        drop(recycle_res);
+        drop(tasks);
+        let mut inner = self.inner_exclusive_access();
+        inner.exited_times += exited_times;
+        // 当前线程成为新程序的主线程, tid从0重新分配
+        inner.task_res_allocator = RecycleAllocator::new();
+        inner.tasks.push(Some(Arc::clone(&task)));
         // substitute memory_set
-        self.inner_exclusive_access().memory_set = memory_set;
+        inner.memory_set = memory_set;
         // 重新设置堆大小
-        self.inner.exclusive_access().heap_base = uheap_base.into();
-        self.inner.exclusive_access().heap_end = uheap_base.into();
+        inner.heap_base = elf_info.heap_base.into();
+        inner.heap_end = elf_info.heap_base.into();
         // 重新设置mmap_area
-        self.inner.exclusive_access().mmap_area_base = MEMORY_MAP_BASE.into();
-        self.inner.exclusive_access().mmap_area_end = MEMORY_MAP_BASE.into();
-        // then we alloc user resource for main thread again
-        // since memory_set has been changed
-        let task = self.inner_exclusive_access().get_task(0);
+        inner.mmap_area_base = MEMORY_MAP_BASE.into();
+        inner.mmap_area_end = MEMORY_MAP_BASE.into();
+        drop(inner);
+        // 用户栈和trap上下文已经映射在新的memory_set里了, 不会失败
+        let res = TaskUserRes::new(Arc::clone(self), elf_info.ustack_base, false).unwrap();
         let mut task_inner = task.inner_exclusive_access();
-        task_inner.res.as_mut().unwrap().ustack_base = ustack_base;
-        task_inner.res.as_mut().unwrap().alloc_user_res();
-        task_inner.trap_cx_ppn = task_inner.res.as_mut().unwrap().trap_cx_ppn();
-        // push arguments on user stack
-        let mut user_sp = task_inner.res.as_mut().unwrap().ustack_top();
-        user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
-        let argv_base = user_sp;
-        let mut argv: Vec<_> = (0..=args.len())
-            .map(|arg| {
-                translated_refmut(
-                    new_token,
-                    (argv_base + arg * core::mem::size_of::<usize>()) as *mut usize,
-                )
-            })
-            .collect();
-        *argv[args.len()] = 0;
-        for i in 0..args.len() {
-            user_sp -= args[i].len() + 1;
-            *argv[i] = user_sp;
-            let mut p = user_sp;
-            for c in args[i].as_bytes() {
-                *translated_refmut(new_token, p as *mut u8) = *c;
-                p += 1;
-            }
-            *translated_refmut(new_token, p as *mut u8) = 0;
-        }
-        // make the user_sp aligned to 8B for k210 platform
-        user_sp -= user_sp % core::mem::size_of::<usize>();
+        task_inner.trap_cx_ppn = res.trap_cx_ppn();
+        task_inner.res = Some(res);
+        // push argc/argv/envp/auxv on user stack
+        let user_sp = task_inner.res.as_mut().unwrap().ustack_top();
+        let (user_sp, argv_base) =
+            init_user_stack(new_token, user_sp, &args, &envs, elf_info.auxv());
         // initialize trap_cx
         let mut trap_cx = TrapContext::app_init_context(
-            entry_point,
+            elf_info.entry,
             user_sp,
             KERNEL_SPACE.exclusive_access().token(),
             task.kstack.get_top(),
@@ -245,14 +414,29 @@ impl ProcessControlBlock {
         trap_cx.x[10] = args.len();
         trap_cx.x[11] = argv_base;
         *task_inner.get_trap_cx() = trap_cx;
+        args.len() as isize
     }
 
-    /// Only support processes with a single thread.
-    pub fn fork(self: &Arc<Self>) -> Arc<Self> {
+    /// 子进程里只有调用fork的这一个线程, 沿用它的tid, 用户栈和trap上下文的位置都不变.
+    /// 物理页不够时返回None
+    pub fn fork(self: &Arc<Self>) -> Option<Arc<Self>> {
+        let (tid, ustack_base) = {
+            let current = current_task().unwrap();
+            let current_inner = current.inner_exclusive_access();
+            let res = current_inner.res.as_ref().unwrap();
+            (res.tid, res.ustack_base())
+        };
         let mut parent = self.inner_exclusive_access();
-        assert_eq!(parent.thread_count(), 1);
         // clone parent's memory_set completely including trampoline/ustacks/trap_cxs
-        let memory_set = MemorySet::from_existed_user(&parent.memory_set);
+        let mut memory_set = MemorySet::from_existed_user(&parent.memory_set)?;
+        // 其余线程的用户栈和trap上下文子进程用不到
+        for other in parent.tasks.iter().flatten() {
+            if let Some(res) = other.inner_exclusive_access().res.as_ref() {
+                if res.tid != tid {
+                    remove_user_res(&mut memory_set, ustack_base, res.tid);
+                }
+            }
+        }
         // alloc a pid
         let pid = pid_alloc();
         // copy fd table
@@ -277,8 +461,20 @@ impl ProcessControlBlock {
                     exit_code: 0,
                     fd_table: new_fd_table,
                     signals: SignalFlags::empty(),
+                    // 屏蔽字随fork继承
+                    signal_mask: parent.signal_mask,
+                    // 进程组和会话也继承
+                    pgid: parent.pgid,
+                    sid: parent.sid,
+                    stopped: false,
+                    job_status: None,
                     tasks: Vec::new(),
-                    task_res_allocator: RecycleAllocator::new(),
+                    exited_times: CpuTimes::default(),
+                    children_times: CpuTimes::default(),
+                    itimers: IntervalTimers::default(),
+                    cred: parent.cred.clone(),
+                    rlimits: parent.rlimits,
+                    task_res_allocator: RecycleAllocator::starting_at(tid),
                     mutex_list: Vec::new(),
                     semaphore_list: Vec::new(),
                     condvar_list: Vec::new(),
@@ -291,25 +487,20 @@ impl ProcessControlBlock {
                 })
             },
         });
-        // add child
-        parent.children.push(Arc::clone(&child));
         // create main thread of child process
         let task = Arc::new(TaskControlBlock::new(
             Arc::clone(&child),
-            parent
-                .get_task(0)
-                .inner_exclusive_access()
-                .res
-                .as_ref()
-                .unwrap()
-                .ustack_base(),
+            ustack_base,
             // here we do not allocate trap_cx or ustack again
             // but mention that we allocate a new kstack here
             false,
-        ));
-        // attach task to child process
+        )?);
+        // add child
+        parent.children.push(Arc::clone(&child));
+        // attach task to child process, tasks按tid索引
         let mut child_inner = child.inner_exclusive_access();
-        child_inner.tasks.push(Some(Arc::clone(&task)));
+        child_inner.tasks.resize(tid + 1, None);
+        child_inner.tasks[tid] = Some(Arc::clone(&task));
         drop(child_inner);
         // modify kstack_top in trap_cx of this thread
         let task_inner = task.inner_exclusive_access();
@@ -319,7 +510,7 @@ impl ProcessControlBlock {
         insert_into_pid2process(child.getpid(), Arc::clone(&child));
         // add this thread to scheduler
         add_task(task);
-        child
+        Some(child)
     }
 
     pub fn getpid(&self) -> usize {
@@ -2,6 +2,7 @@ use super::__switch;
 use super::{fetch_task, TaskStatus};
 use super::{ProcessControlBlock, TaskContext, TaskControlBlock};
 use crate::sync::UPIntrFreeCell;
+use crate::timer::get_time;
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
 use lazy_static::*;
@@ -42,6 +43,7 @@ pub fn run_tasks() {
             // access coming task TCB exclusively
             let next_task_cx_ptr = task.inner.exclusive_session(|task_inner| {
                 task_inner.task_status = TaskStatus::Running;
+                task_inner.time_mark = get_time();
                 &task_inner.task_cx as *const TaskContext
             });
             processor.current = Some(task);
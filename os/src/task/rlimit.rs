@@ -0,0 +1,90 @@
+//! getrlimit/setrlimit/prlimit64的资源限制, fork时继承
+use crate::config::USER_STACK_SIZE;
+
+pub const RLIMIT_CPU: usize = 0;
+pub const RLIMIT_FSIZE: usize = 1;
+pub const RLIMIT_DATA: usize = 2;
+pub const RLIMIT_STACK: usize = 3;
+/// 不生成core文件, 只是记下来
+pub const RLIMIT_CORE: usize = 4;
+pub const RLIMIT_NPROC: usize = 6;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_AS: usize = 9;
+const RLIM_NLIMITS: usize = 16;
+
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+/// 打开文件数的硬上限最多提到这么多
+pub const NR_OPEN: u64 = 1024;
+/// 打开文件数的默认软限制, 以前写死的FD_LIMIT
+const DEFAULT_NOFILE: u64 = 128;
+
+/// struct rlimit
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RLimit {
+    pub rlim_cur: u64,
+    pub rlim_max: u64,
+}
+
+impl RLimit {
+    const fn new(cur: u64, max: u64) -> Self {
+        Self {
+            rlim_cur: cur,
+            rlim_max: max,
+        }
+    }
+
+    /// 软限制换成usize, 无限大时是usize::MAX
+    pub fn cur(&self) -> usize {
+        self.rlim_cur.min(usize::MAX as u64) as usize
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ResourceLimits {
+    limits: [RLimit; RLIM_NLIMITS],
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        let mut limits = [RLimit::new(RLIM_INFINITY, RLIM_INFINITY); RLIM_NLIMITS];
+        limits[RLIMIT_NOFILE] = RLimit::new(DEFAULT_NOFILE, NR_OPEN);
+        // 用户栈大小是固定的, 只能调小
+        limits[RLIMIT_STACK] = RLimit::new(USER_STACK_SIZE as u64, USER_STACK_SIZE as u64);
+        limits[RLIMIT_CORE] = RLimit::new(0, RLIM_INFINITY);
+        Self { limits }
+    }
+}
+
+impl ResourceLimits {
+    pub fn get(&self, resource: usize) -> Option<RLimit> {
+        self.limits.get(resource).copied()
+    }
+
+    /// 软限制, 资源编号不对时当作无限
+    pub fn cur(&self, resource: usize) -> usize {
+        self.get(resource).map_or(usize::MAX, |limit| limit.cur())
+    }
+
+    /// 软限制不能超过硬限制; 只有特权进程能提高硬限制
+    pub fn set(&mut self, resource: usize, new: RLimit, privileged: bool) -> bool {
+        let old = match self.limits.get_mut(resource) {
+            Some(old) => old,
+            None => return false,
+        };
+        if new.rlim_cur > new.rlim_max || (new.rlim_max > old.rlim_max && !privileged) {
+            return false;
+        }
+        let ceiling = match resource {
+            RLIMIT_NOFILE => NR_OPEN,
+            RLIMIT_STACK => USER_STACK_SIZE as u64,
+            _ => RLIM_INFINITY,
+        };
+        if new.rlim_max > ceiling {
+            return false;
+        }
+        *old = new;
+        true
+    }
+}
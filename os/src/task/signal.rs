@@ -2,18 +2,72 @@ use bitflags::*;
 
 bitflags! {
     pub struct SignalFlags: u32 {
+        const SIGHUP    = 1 << 1;
         const SIGINT    = 1 << 2;
+        const SIGQUIT   = 1 << 3;
         const SIGILL    = 1 << 4;
         const SIGABRT   = 1 << 6;
         const SIGFPE    = 1 << 8;
+        const SIGKILL   = 1 << 9;
         const SIGSEGV   = 1 << 11;
+        const SIGALRM   = 1 << 14;
+        const SIGTERM   = 1 << 15;
+        const SIGCHLD   = 1 << 17;
+        const SIGCONT   = 1 << 18;
+        const SIGSTOP   = 1 << 19;
+        const SIGTSTP   = 1 << 20;
+        const SIGTTIN   = 1 << 21;
+        const SIGTTOU   = 1 << 22;
+        const SIGXCPU   = 1 << 24;
+        const SIGXFSZ   = 1 << 25;
+        const SIGVTALRM = 1 << 26;
+        const SIGPROF   = 1 << 27;
+        const SIGWINCH  = 1 << 28;
     }
 }
 
 impl SignalFlags {
+    /// 用户态sigset_t里信号n占第n-1位
+    pub fn from_sigset(set: u64) -> Self {
+        Self::from_bits_truncate((set << 1) as u32)
+    }
+
+    pub fn to_sigset(&self) -> u64 {
+        (self.bits() >> 1) as u64
+    }
+
+    /// kill等系统调用传进来的信号编号, 不支持的信号返回None
+    pub fn from_signum(signum: u32) -> Option<Self> {
+        if signum == 0 || signum >= 32 {
+            return None;
+        }
+        Self::from_bits(1 << signum)
+    }
+
+    /// 编号最小的那个信号
+    pub fn signum(&self) -> u32 {
+        self.bits().trailing_zeros()
+    }
+
+    /// 默认动作是停止进程的信号
+    pub fn stop_signals() -> Self {
+        Self::SIGSTOP | Self::SIGTSTP | Self::SIGTTIN | Self::SIGTTOU
+    }
+
+    /// 不能被屏蔽的信号
+    pub fn unblockable() -> Self {
+        Self::SIGKILL | Self::SIGSTOP
+    }
+
     pub fn check_error(&self) -> Option<(i32, &'static str)> {
-        if self.contains(Self::SIGINT) {
+        if self.contains(Self::SIGKILL) {
+            Some((-9, "Killed, SIGKILL=9"))
+        } else if self.contains(Self::SIGHUP) {
+            Some((-1, "Hangup, SIGHUP=1"))
+        } else if self.contains(Self::SIGINT) {
             Some((-2, "Killed, SIGINT=2"))
+        } else if self.contains(Self::SIGQUIT) {
+            Some((-3, "Quit, SIGQUIT=3"))
         } else if self.contains(Self::SIGILL) {
             Some((-4, "Illegal Instruction, SIGILL=4"))
         } else if self.contains(Self::SIGABRT) {
@@ -22,6 +76,18 @@ impl SignalFlags {
             Some((-8, "Erroneous Arithmetic Operation, SIGFPE=8"))
         } else if self.contains(Self::SIGSEGV) {
             Some((-11, "Segmentation Fault, SIGSEGV=11"))
+        } else if self.contains(Self::SIGALRM) {
+            Some((-14, "Alarm clock, SIGALRM=14"))
+        } else if self.contains(Self::SIGTERM) {
+            Some((-15, "Terminated, SIGTERM=15"))
+        } else if self.contains(Self::SIGXCPU) {
+            Some((-24, "CPU time limit exceeded, SIGXCPU=24"))
+        } else if self.contains(Self::SIGXFSZ) {
+            Some((-25, "File size limit exceeded, SIGXFSZ=25"))
+        } else if self.contains(Self::SIGVTALRM) {
+            Some((-26, "Virtual timer expired, SIGVTALRM=26"))
+        } else if self.contains(Self::SIGPROF) {
+            Some((-27, "Profiling timer expired, SIGPROF=27"))
         } else {
             None
         }
@@ -1,11 +1,13 @@
 use super::id::TaskUserRes;
 use super::{kstack_alloc, KernelStack, ProcessControlBlock, TaskContext};
+use crate::timer::get_time;
 use crate::trap::TrapContext;
 use crate::{
     mm::PhysPageNum,
     sync::{UPIntrFreeCell, UPIntrRefMut},
 };
 use alloc::sync::{Arc, Weak};
+use core::ops::{Add, AddAssign};
 
 pub struct TaskControlBlock {
     // immutable
@@ -33,6 +35,9 @@ pub struct TaskControlBlockInner {
     pub task_cx: TaskContext,
     pub task_status: TaskStatus,
     pub exit_code: Option<i32>,
+    pub times: CpuTimes,
+    /// 上次记账的时刻: 进入/离开用户态, 或者被调度上cpu
+    pub time_mark: usize,
 }
 
 impl TaskControlBlockInner {
@@ -40,6 +45,20 @@ impl TaskControlBlockInner {
         self.trap_cx_ppn.get_mut()
     }
 
+    /// 从上次记账到现在都在用户态, 陷入内核时调用
+    pub fn account_user_time(&mut self) {
+        let now = get_time();
+        self.times.utime += now - self.time_mark;
+        self.time_mark = now;
+    }
+
+    /// 从上次记账到现在都在内核里, 回到用户态或者让出cpu时调用
+    pub fn account_system_time(&mut self) {
+        let now = get_time();
+        self.times.stime += now - self.time_mark;
+        self.time_mark = now;
+    }
+
     #[allow(unused)]
     fn get_status(&self) -> TaskStatus {
         self.task_status
@@ -51,12 +70,13 @@ impl TaskControlBlock {
         process: Arc<ProcessControlBlock>,
         ustack_base: usize,
         alloc_user_res: bool,
-    ) -> Self {
-        let res = TaskUserRes::new(Arc::clone(&process), ustack_base, alloc_user_res);
+    ) -> Option<Self> {
+        // 物理页不够时返回None, 调用时不能借用着process的inner
+        let res = TaskUserRes::new(Arc::clone(&process), ustack_base, alloc_user_res)?;
         let trap_cx_ppn = res.trap_cx_ppn();
-        let kstack = kstack_alloc();
+        let kstack = kstack_alloc()?;
         let kstack_top = kstack.get_top();
-        Self {
+        Some(Self {
             process: Arc::downgrade(&process),
             kstack,
             inner: unsafe {
@@ -66,12 +86,37 @@ impl TaskControlBlock {
                     task_cx: TaskContext::goto_trap_return(kstack_top),
                     task_status: TaskStatus::Ready,
                     exit_code: None,
+                    times: CpuTimes::default(),
+                    time_mark: 0,
                 })
             },
+        })
+    }
+}
+
+/// 用户态和内核态的运行时间, 单位是time寄存器的计数
+#[derive(Copy, Clone, Default)]
+pub struct CpuTimes {
+    pub utime: usize,
+    pub stime: usize,
+}
+
+impl Add for CpuTimes {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self {
+            utime: self.utime + other.utime,
+            stime: self.stime + other.stime,
         }
     }
 }
 
+impl AddAssign for CpuTimes {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum TaskStatus {
     Ready,
@@ -1,16 +1,24 @@
 use core::cmp::Ordering;
 
 use crate::config::CLOCK_FREQ;
+use crate::drivers::rtc::{RtcDevice, RTC};
 use crate::sbi::set_timer;
 use crate::sync::UPIntrFreeCell;
 use crate::task::{wakeup_task, TaskControlBlock};
+use alloc::boxed::Box;
 use alloc::collections::BinaryHeap;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 use lazy_static::*;
 use riscv::register::time;
 
 const TICKS_PER_SEC: usize = 100;
 const MSEC_PER_SEC: usize = 1000;
+const USEC_PER_SEC: usize = 1_000_000;
+const NSEC_PER_SEC: usize = 1_000_000_000;
+/// times()用的时钟滴答, 和sysconf(_SC_CLK_TCK)一致
+pub const CLK_TCK: usize = 100;
 
 pub fn get_time() -> usize {
     time::read()
@@ -20,13 +28,134 @@ pub fn get_time_ms() -> usize {
     time::read() / (CLOCK_FREQ / MSEC_PER_SEC)
 }
 
+/// time寄存器的计数换算成纳秒, 先除后乘免得溢出
+pub fn ticks_to_ns(ticks: usize) -> usize {
+    ticks / CLOCK_FREQ * NSEC_PER_SEC + ticks % CLOCK_FREQ * NSEC_PER_SEC / CLOCK_FREQ
+}
+
+/// 纳秒换算成time寄存器的计数, 向上取整
+pub fn ns_to_ticks(ns: usize) -> usize {
+    ns / NSEC_PER_SEC * CLOCK_FREQ
+        + (ns % NSEC_PER_SEC * CLOCK_FREQ + NSEC_PER_SEC - 1) / NSEC_PER_SEC
+}
+
+pub fn ticks_to_clock_t(ticks: usize) -> usize {
+    ticks / (CLOCK_FREQ / CLK_TCK)
+}
+
+pub fn get_time_ns() -> usize {
+    ticks_to_ns(get_time())
+}
+
+/// time寄存器一个计数对应的纳秒数, clock_getres的结果
+pub fn clock_resolution_ns() -> usize {
+    (NSEC_PER_SEC + CLOCK_FREQ - 1) / CLOCK_FREQ
+}
+
+/// 开机时刻的墙上时间, 由init_realtime从rtc读出
+static BOOT_REALTIME_NS: AtomicUsize = AtomicUsize::new(0);
+
+/// 启动时读一次rtc, 之后的墙上时间都由time寄存器推算
+pub fn init_realtime() {
+    let boot_ns = (RTC.read_time_ns() as usize).saturating_sub(get_time_ns());
+    BOOT_REALTIME_NS.store(boot_ns, AtomicOrdering::Relaxed);
+    fatfs::set_time_source(realtime_secs);
+}
+
+/// 墙上时间, 1970-01-01以来的纳秒数
+pub fn get_realtime_ns() -> usize {
+    BOOT_REALTIME_NS.load(AtomicOrdering::Relaxed) + get_time_ns()
+}
+
+fn realtime_secs() -> u64 {
+    (get_realtime_ns() / NSEC_PER_SEC) as u64
+}
+
+/// struct timespec
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct TimeSpec {
+    pub tv_sec: usize,
+    pub tv_nsec: usize,
+}
+
+impl TimeSpec {
+    pub fn from_ms(ms: usize) -> Self {
+        Self {
+            tv_sec: ms / MSEC_PER_SEC,
+            tv_nsec: ms % MSEC_PER_SEC * 1_000_000,
+        }
+    }
+
+    pub fn from_ns(ns: usize) -> Self {
+        Self {
+            tv_sec: ns / NSEC_PER_SEC,
+            tv_nsec: ns % NSEC_PER_SEC,
+        }
+    }
+
+    pub fn to_ns(&self) -> usize {
+        self.tv_sec * NSEC_PER_SEC + self.tv_nsec
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.tv_nsec < NSEC_PER_SEC
+    }
+
+    /// 不足1ms的部分向上取整, 定时器不会提前到期
+    pub fn to_ms(&self) -> usize {
+        self.tv_sec * MSEC_PER_SEC + (self.tv_nsec + 999_999) / 1_000_000
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.tv_sec == 0 && self.tv_nsec == 0
+    }
+}
+
+/// struct timeval
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct TimeVal {
+    pub tv_sec: usize,
+    pub tv_usec: usize,
+}
+
+impl TimeVal {
+    pub fn from_ns(ns: usize) -> Self {
+        Self {
+            tv_sec: ns / NSEC_PER_SEC,
+            tv_usec: ns % NSEC_PER_SEC / (NSEC_PER_SEC / USEC_PER_SEC),
+        }
+    }
+
+    pub fn to_ns(&self) -> usize {
+        self.tv_sec * NSEC_PER_SEC + self.tv_usec * (NSEC_PER_SEC / USEC_PER_SEC)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.tv_usec < USEC_PER_SEC
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.tv_sec == 0 && self.tv_usec == 0
+    }
+}
+
 pub fn set_next_trigger() {
     set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
 }
 
+/// What to do when a timer in `TIMERS` expires.
+pub enum TimerAction {
+    /// wake up a blocked task, used by sleep
+    Wakeup(Arc<TaskControlBlock>),
+    /// run a kernel callback, used by protocol timers such as tcp retransmission
+    Callback(Box<dyn FnOnce() + Send>),
+}
+
 pub struct TimerCondVar {
     pub expire_ms: usize,
-    pub task: Arc<TaskControlBlock>,
+    pub action: TimerAction,
 }
 
 impl PartialEq for TimerCondVar {
@@ -56,19 +185,43 @@ lazy_static! {
 
 pub fn add_timer(expire_ms: usize, task: Arc<TaskControlBlock>) {
     let mut timers = TIMERS.exclusive_access();
-    timers.push(TimerCondVar { expire_ms, task });
+    timers.push(TimerCondVar {
+        expire_ms,
+        action: TimerAction::Wakeup(task),
+    });
+}
+
+/// Run `callback` in interrupt context once `expire_ms` has passed.
+/// The callback may add new timers itself.
+pub fn add_timer_callback<F>(expire_ms: usize, callback: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    let mut timers = TIMERS.exclusive_access();
+    timers.push(TimerCondVar {
+        expire_ms,
+        action: TimerAction::Callback(Box::new(callback)),
+    });
 }
 
 pub fn check_timer() {
     let current_ms = get_time_ms();
-    TIMERS.exclusive_session(|timers| {
+    // collect the expired timers first, callbacks may re-arm themselves
+    let expired = TIMERS.exclusive_session(|timers| {
+        let mut expired = Vec::new();
         while let Some(timer) = timers.peek() {
             if timer.expire_ms <= current_ms {
-                wakeup_task(Arc::clone(&timer.task));
-                timers.pop();
+                expired.push(timers.pop().unwrap());
             } else {
                 break;
             }
         }
+        expired
     });
+    for timer in expired {
+        match timer.action {
+            TimerAction::Wakeup(task) => wakeup_task(task),
+            TimerAction::Callback(callback) => callback(),
+        }
+    }
 }
@@ -1,10 +1,13 @@
 mod context;
 
 use crate::config::TRAMPOLINE;
+use crate::mm::take_out_of_memory;
 use crate::syscall::syscall;
 use crate::task::{
-    check_signals_of_current, current_add_signal, current_trap_cx, current_trap_cx_user_va,
-    current_user_token, exit_current_and_run_next, suspend_current_and_run_next, SignalFlags,
+    check_signals_of_current, current_account_system_time, current_account_user_time,
+    current_add_signal, current_charge_itimers, current_trap_cx, current_trap_cx_user_va,
+    current_user_token, exit_group_and_run_next, handle_stop_signals_of_current, oom_kill,
+    suspend_current_and_run_next, SignalFlags,
 };
 use crate::timer::{check_timer, set_next_trigger};
 use core::arch::{asm, global_asm};
@@ -59,6 +62,7 @@ fn disable_supervisor_interrupt() {
 #[no_mangle]
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
+    current_account_user_time();
     let scause = scause::read();
     let stval = stval::read();
     // println!("into {:?}", scause.cause());
@@ -98,6 +102,7 @@ pub fn trap_handler() -> ! {
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             set_next_trigger();
             check_timer();
+            current_charge_itimers();
             suspend_current_and_run_next();
         }
         Trap::Interrupt(Interrupt::SupervisorExternal) => {
@@ -111,10 +116,16 @@ pub fn trap_handler() -> ! {
             );
         }
     }
+    // 这次陷入内核期间物理页不够用过, 挑一个进程杀掉
+    if take_out_of_memory() {
+        oom_kill();
+    }
+    // 先处理停止信号, 被SIGKILL唤醒后再由下面退出
+    handle_stop_signals_of_current();
     // check signals
     if let Some((errno, msg)) = check_signals_of_current() {
         println!("[kernel] {}", msg);
-        exit_current_and_run_next(errno);
+        exit_group_and_run_next(errno);
     }
     trap_return();
 }
@@ -122,6 +133,7 @@ pub fn trap_handler() -> ! {
 #[no_mangle]
 pub fn trap_return() -> ! {
     disable_supervisor_interrupt();
+    current_account_system_time();
     set_user_trap_entry();
     let trap_cx_user_va = current_trap_cx_user_va();
     let user_satp = current_user_token();